@@ -0,0 +1,58 @@
+use structopt::StructOpt;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use crate::errors::*;
+use crate::model::archive::{ArchiveFormat, ArchiveOptions};
+use crate::model::repository::FileRepository;
+
+#[derive(Debug, StructOpt)]
+pub struct ArchiveOpt {
+        #[structopt(long = "format")]
+        ///Format of the resulting archive: tar, tgz, tar.gz or zip. Defaults to the
+        /// extension of --output, then tar.
+        format: Option<String>,
+
+        #[structopt(long = "prefix", default_value = "")]
+        ///Prepend <prefix>/ to paths in the archive.
+        prefix: String,
+
+        #[structopt(short = "o", long = "output", parse(from_os_str))]
+        ///Write the archive to <file> instead of stdout.
+        output: Option<PathBuf>,
+
+        #[structopt(short = "l", long = "list")]
+        ///Show all available formats.
+        list: bool,
+
+        #[structopt(required_unless = "list")]
+        ///The tree or commit to produce an archive for.
+        tree_ish: Option<String>,
+
+        ///Without an optional path parameter, all files and subdirectories of the current
+        /// working directory are included in the archive.
+        paths: Vec<String>,
+}
+
+pub fn archive(opt: ArchiveOpt) -> Result<()> {
+    if opt.list {
+        for format in ArchiveFormat::NAMES {
+            println!("{}", format);
+        }
+        return Ok(());
+    }
+    let format = match (&opt.format, &opt.output) {
+        (Some(format), _) => format.parse()?,
+        (None, Some(output)) => ArchiveFormat::from_file_name(&output.to_string_lossy()).unwrap_or(ArchiveFormat::Tar),
+        (None, None) => ArchiveFormat::Tar,
+    };
+    let repo = FileRepository::open(".")?;
+    let id = repo.rev_parse(opt.tree_ish.as_deref().unwrap_or_default())?;
+    let options = ArchiveOptions { format, prefix: opt.prefix, paths: opt.paths };
+    let mut out: Box<dyn Write> = match &opt.output {
+        Some(path) => Box::new(BufWriter::new(std::fs::File::create(path)?)),
+        None => Box::new(BufWriter::new(std::io::stdout())),
+    };
+    repo.archive(&id, &options, &mut out)?;
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,269 @@
+use structopt::StructOpt;
+use std::collections::{HashMap, HashSet};
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use crate::errors::*;
+use crate::fs::FileSystem;
+use crate::model::blame::{Blame, BlameHunk, BlameOptions};
+use crate::model::commit::Identity;
+use crate::model::diff::lines;
+use crate::model::id::Id;
+use crate::model::repository::{FileRepository, Repository};
+use crate::model::tree::EntryType;
+
+#[derive(Debug, StructOpt)]
+pub struct BlameOpt {
+        #[structopt(short = "L", raw(number_of_values = "1"))]
+        ///Annotate only the line range given by <start>,<end>, <start>,+<count> or <start>,-<count>.
+        /// May be specified multiple times.
+        ranges: Vec<String>,
+
+        #[structopt(short = "w")]
+        ///Ignore whitespace when comparing the parent's version and the child's to find where the
+        /// lines came from.
+        ignore_whitespace: bool,
+
+        #[structopt(short = "M")]
+        ///Detect moved or copied lines within a file.
+        moves: bool,
+
+        #[structopt(short = "C", parse(from_occurrences))]
+        ///In addition to -M, detect lines moved or copied from other files that were modified in the
+        /// same commit. Given twice, also look in every file of the commit that creates the file;
+        /// given three times, in every file of every commit.
+        copies: u8,
+
+        #[structopt(long = "ignore-rev", raw(number_of_values = "1"))]
+        ///Ignore changes made by the revision when assigning blame, as if the change never happened.
+        ignore_revs: Vec<String>,
+
+        #[structopt(long = "ignore-revs-file", raw(number_of_values = "1"))]
+        ///Ignore revisions listed in the file, one per line.
+        ignore_revs_file: Vec<String>,
+
+        #[structopt(short = "p", long = "porcelain")]
+        ///Show in a format designed for machine consumption.
+        porcelain: bool,
+
+        #[structopt(long = "line-porcelain")]
+        ///Show the porcelain format, but output commit information for each line.
+        line_porcelain: bool,
+
+        #[structopt(short = "l")]
+        ///Show long rev.
+        long_rev: bool,
+
+        #[structopt(short = "s")]
+        ///Suppress the author name and timestamp from the output.
+        no_author: bool,
+
+        #[structopt(short = "f", long = "show-name")]
+        ///Show the filename in the original commit.
+        show_name: bool,
+
+        #[structopt(short = "n", long = "show-number")]
+        ///Show the line number in the original commit.
+        show_number: bool,
+
+        ///The file to annotate, optionally followed or preceded by the revision to start from.
+        #[structopt(raw(required = "true", max_values = "2"))]
+        args: Vec<String>,
+}
+
+/// `<start>,<end>` and its shorthands, for `path` with `total` lines.
+fn parse_range(spec: &str, path: &str, total: usize) -> Result<(usize, usize)> {
+    let number = |s: &str| -> Result<usize> {
+        match s.parse::<usize>() {
+            Ok(n) if n > 0 => Ok(n),
+            _ => bail!("-L invalid line number: {}", s),
+        }
+    };
+    let (start, end) = match spec.find(',') {
+        Some(n) => (&spec[..n], &spec[n + 1..]),
+        None => (spec, ""),
+    };
+    let start = if start.is_empty() { 1 } else { number(start)? };
+    let (from, to) = if end.is_empty() {
+        (start, total.max(start))
+    } else if let Some(count) = end.strip_prefix('+') {
+        (start, start + number(count)? - 1)
+    } else if let Some(count) = end.strip_prefix('-') {
+        (start.saturating_sub(number(count)? - 1).max(1), start)
+    } else {
+        let end = number(end)?;
+        (start.min(end), start.max(end))
+    };
+    if from > total {
+        bail!("file {} has only {} line{}", path, total, if total == 1 { "" } else { "s" });
+    }
+    Ok((from, to))
+}
+
+fn ignore_revs<FS: FileSystem>(repo: &FileRepository<FS>, opt: &BlameOpt) -> Result<HashSet<Id>> {
+    let mut revs = opt.ignore_revs.clone();
+    let mut files: Vec<PathBuf> = opt.ignore_revs_file.iter().map(PathBuf::from).collect();
+    if let Some(path) = repo.config()?.get_path("blame.ignoreRevsFile")? {
+        files.insert(0, path);
+    }
+    for file in files {
+        let text = std::fs::read_to_string(&file)
+            .chain_err(|| format!("could not open object name list: {}", file.display()))?;
+        revs.extend(text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')).map(str::to_owned));
+    }
+    let mut ids = HashSet::new();
+    for rev in &revs {
+        match repo.rev_parse(rev).and_then(|id| repo.peel_to_commit(&id)) {
+            Ok(commit) => ids.insert(commit.id().clone()),
+            Err(_) => bail!("cannot find revision {} to ignore", rev),
+        };
+    }
+    Ok(ids)
+}
+
+pub fn blame(opt: BlameOpt) -> Result<()> {
+    let repo = FileRepository::open(".")?;
+    let (rev, path) = match opt.args.as_slice() {
+        [path] => ("HEAD", path),
+        [first, second] if repo.rev_parse(second).is_ok() => (second.as_str(), first),
+        [rev, path] => (rev.as_str(), path),
+        _ => unreachable!(),
+    };
+    let start = repo.rev_parse(rev).chain_err(|| format!("bad revision '{}'", rev))?;
+    let commit = repo.peel_to_commit(&start)?;
+    let total = match repo.find_tree(commit.tree())?.entry_at(&repo, path)? {
+        Some(entry) if entry.entry_type() == EntryType::BLOB => {
+            lines(&Repository::read_content(&repo, &repo.find_object(entry.id())?)?).len()
+        }
+        _ => bail!("no such path '{}' in {}", path, rev),
+    };
+    let options = BlameOptions {
+        ranges: opt.ranges.iter().map(|spec| parse_range(spec, path, total)).collect::<Result<_>>()?,
+        ignore_whitespace: opt.ignore_whitespace,
+        detect_moves: opt.moves || opt.copies > 0,
+        detect_copies: opt.copies,
+        ignore_revs: ignore_revs(&repo, &opt)?,
+    };
+    let blame = repo.blame(commit.id(), path, &options)?;
+
+    let mut out = BufWriter::new(std::io::stdout());
+    if opt.porcelain || opt.line_porcelain {
+        write_porcelain(&mut out, &blame, opt.line_porcelain)?;
+    } else {
+        let show_name = opt.show_name || blame.hunks.iter().any(|hunk| hunk.path != *path);
+        write_annotated(&mut out, &blame, &opt, show_name)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn line_of(blame: &Blame, n: usize) -> &[u8] {
+    let line = &blame.lines[n];
+    line.strip_suffix(b"\n").unwrap_or(line)
+}
+
+fn write_identity(out: &mut dyn Write, role: &str, identity: &Option<Identity>) -> Result<()> {
+    if let Some(identity) = identity {
+        writeln!(out, "{} {}", role, identity.name)?;
+        writeln!(out, "{}-mail <{}>", role, identity.email)?;
+        writeln!(out, "{}-time {}", role, identity.date.timestamp())?;
+        writeln!(out, "{}-tz {}", role, identity.date.format("%z"))?;
+    }
+    Ok(())
+}
+
+fn write_porcelain(out: &mut dyn Write, blame: &Blame, every_line: bool) -> Result<()> {
+    let mut paths: HashMap<&Id, HashSet<&str>> = HashMap::new();
+    for hunk in &blame.hunks {
+        paths.entry(hunk.commit.id()).or_default().insert(&hunk.path);
+    }
+    let mut shown = HashSet::new();
+    for hunk in &blame.hunks {
+        let id = hunk.commit.id();
+        let first = shown.insert(id);
+        for n in 0..hunk.lines {
+            write!(out, "{} {} {}", id, hunk.orig_start + n, hunk.final_start + n)?;
+            if n == 0 {
+                write!(out, " {}", hunk.lines)?;
+            }
+            writeln!(out)?;
+            let details = every_line || (n == 0 && first);
+            if details {
+                write_details(out, hunk)?;
+            }
+            // git names the file again for a commit seen under several
+            if details || (n == 0 && paths[id].len() > 1) {
+                if let Some((parent, path)) = &hunk.previous {
+                    writeln!(out, "previous {} {}", parent, path)?;
+                }
+                writeln!(out, "filename {}", hunk.path)?;
+            }
+            out.write_all(b"\t")?;
+            out.write_all(line_of(blame, hunk.final_start + n - 1))?;
+            writeln!(out)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_details(out: &mut dyn Write, hunk: &BlameHunk) -> Result<()> {
+    write_identity(out, "author", hunk.commit.author())?;
+    write_identity(out, "committer", hunk.commit.committer())?;
+    writeln!(out, "summary {}", hunk.commit.message().lines().next().unwrap_or_default())?;
+    if hunk.boundary {
+        writeln!(out, "boundary")?;
+    }
+    Ok(())
+}
+
+fn write_annotated(out: &mut dyn Write, blame: &Blame, opt: &BlameOpt, show_name: bool) -> Result<()> {
+    let author = |hunk: &BlameHunk| hunk.commit.author().as_ref().map_or(String::new(), |a| a.name.clone());
+    let width = |n: usize| n.to_string().len();
+    let longest_author = blame.hunks.iter().map(|hunk| author(hunk).chars().count()).max().unwrap_or(0);
+    let longest_path = blame.hunks.iter().map(|hunk| hunk.path.chars().count()).max().unwrap_or(0);
+    let final_digits = blame.hunks.iter().map(|hunk| width(hunk.final_start + hunk.lines - 1)).max().unwrap_or(1);
+    let orig_digits = blame.hunks.iter().map(|hunk| width(hunk.orig_start + hunk.lines - 1)).max().unwrap_or(1);
+    for hunk in &blame.hunks {
+        let hex = hunk.commit.id().to_string();
+        let length = if opt.long_rev { hex.len() } else { 8 };
+        let date = hunk.commit.author().as_ref().map_or(String::new(), |a| a.date.format("%Y-%m-%d %H:%M:%S %z").to_string());
+        for n in 0..hunk.lines {
+            if hunk.boundary {
+                write!(out, "^{}", &hex[..length - 1])?;
+            } else {
+                write!(out, "{}", &hex[..length])?;
+            }
+            if show_name {
+                write!(out, " {:<width$}", hunk.path, width = longest_path)?;
+            }
+            if opt.show_number {
+                write!(out, " {:>width$}", hunk.orig_start + n, width = orig_digits)?;
+            }
+            if !opt.no_author {
+                let name = author(hunk);
+                let pad = longest_author - name.chars().count();
+                write!(out, " ({}{:pad$} {}", name, "", date, pad = pad)?;
+            }
+            write!(out, " {:>width$}) ", hunk.final_start + n, width = final_digits)?;
+            out.write_all(line_of(blame, hunk.final_start + n - 1))?;
+            writeln!(out)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("2,4", "f", 10).unwrap(), (2, 4));
+        assert_eq!(parse_range("4,2", "f", 10).unwrap(), (2, 4));
+        assert_eq!(parse_range("3,+2", "f", 10).unwrap(), (3, 4));
+        assert_eq!(parse_range("4,-2", "f", 10).unwrap(), (3, 4));
+        assert_eq!(parse_range("5", "f", 10).unwrap(), (5, 10));
+        assert_eq!(parse_range(",2", "f", 10).unwrap(), (1, 2));
+        assert!(parse_range("0,1", "f", 10).is_err());
+        assert!(parse_range("11", "f", 10).is_err());
+    }
+}
@@ -0,0 +1,201 @@
+use structopt::StructOpt;
+use crate::errors::*;
+use crate::model::checkout::CheckoutOptions;
+use crate::model::commit::Commit;
+use crate::model::id::Id;
+use crate::model::refs::{RefChange, RefTarget, RefUpdate};
+use crate::model::repository::{FileRepository, Repository};
+
+const DETACHED_ADVICE: &str = "\
+You are in 'detached HEAD' state. You can look around, make experimental
+changes and commit them, and you can discard any commits you make in this
+state without impacting any branches by switching back to a branch.
+
+If you want to create a new branch to retain commits you create, you may
+do so (now or later) by using -c with the switch command. Example:
+
+  git switch -c <new-branch-name>
+
+Or undo this operation with:
+
+  git switch -
+
+Turn off this advice by setting config variable advice.detachedHead to false
+";
+
+#[derive(Debug, StructOpt)]
+pub struct CheckoutOpt {
+        #[structopt(short = "b", value_name = "new-branch")]
+        ///Create a new branch named <new-branch>, starting at <start-point>, and check it out.
+        new_branch: Option<String>,
+
+        #[structopt(short = "B", value_name = "new-branch", conflicts_with = "new_branch")]
+        ///Like -b, but reset <new-branch> to <start-point> if it already exists.
+        reset_branch: Option<String>,
+
+        #[structopt(long = "detach")]
+        ///Detach HEAD at the commit instead of checking out a branch.
+        detach: bool,
+
+        #[structopt(short = "f", long = "force")]
+        ///Throw away local changes and untracked files in the way.
+        force: bool,
+
+        #[structopt(short = "q", long = "quiet")]
+        ///Suppress feedback messages.
+        quiet: bool,
+
+        ///The branch or commit to check out, or the start point of a new branch.
+        target: Option<String>,
+}
+
+/// How a checkout or switch moves HEAD.
+pub(crate) struct Switch<'a> {
+    pub target: Option<&'a str>,
+    /// A branch to create, and whether it may already exist.
+    pub create: Option<(&'a str, bool)>,
+    pub detach: bool,
+    pub force: bool,
+    pub quiet: bool,
+    /// `git switch` wants a branch unless told to detach.
+    pub branch_only: bool,
+}
+
+fn summary(commit: &Commit) -> String {
+    format!("{} {}", &commit.id().to_string()[..7], commit.message().lines().next().unwrap_or(""))
+}
+
+pub(crate) fn switch_to(switch: Switch) -> Result<()> {
+    let repo = FileRepository::open(".")?;
+    let head = repo.read_ref("HEAD")?;
+    let current_branch = match &head {
+        Some(RefTarget::Symbolic(name)) => name.strip_prefix("refs/heads/").map(str::to_owned),
+        _ => None,
+    };
+    let head_id = repo.resolve_ref("HEAD")?;
+    let from = match (&current_branch, &head_id) {
+        (Some(branch), _) => branch.clone(),
+        (None, Some(id)) => id.to_string(),
+        (None, None) => "HEAD".to_owned(),
+    };
+
+    let mut branch = None;
+    let mut existed = false;
+    let (spec, commit) = match switch.create {
+        Some((name, reset)) => {
+            let ref_name = format!("refs/heads/{}", name);
+            existed = repo.resolve_ref(&ref_name)?.is_some();
+            if existed && !reset {
+                bail!("a branch named '{}' already exists", name);
+            }
+            branch = Some(name.to_owned());
+            let start = switch.target.unwrap_or("HEAD");
+            (start.to_owned(), repo.peel_to_commit(&repo.rev_parse(start)?)?)
+        }
+        None => {
+            let spec = switch.target.ok_or_else(|| Error::from("missing branch or commit argument"))?;
+            let as_branch = format!("refs/heads/{}", spec);
+            match repo.resolve_ref(&as_branch)? {
+                Some(id) if !switch.detach => {
+                    branch = Some(spec.to_owned());
+                    (spec.to_owned(), repo.peel_to_commit(&id)?)
+                }
+                _ => {
+                    let id = repo.rev_parse(spec)?;
+                    if switch.branch_only && !switch.detach {
+                        bail!("a branch is expected, got commit '{}'\n\
+                               hint: If you want to detach HEAD at the commit, try again with the --detach option.",
+                              spec);
+                    }
+                    (spec.to_owned(), repo.peel_to_commit(&id)?)
+                }
+            }
+        }
+    };
+
+    // everything that can refuse the switch is checked before any file
+    // changes, and the refs stay locked until the files are in place
+    let head_tree = match &head_id {
+        Some(id) => repo.peel_to_commit(id)?.tree().clone(),
+        None => repo.write_tree(&[])?,
+    };
+    let options = CheckoutOptions { force: switch.force, ..CheckoutOptions::default() };
+    let plan = repo.plan_checkout(head_id.as_ref().map(|_| &head_tree), commit.tree(), &options)?;
+
+    let message = format!("checkout: moving from {} to {}", from, branch.as_deref().unwrap_or(&spec));
+    let mut tx = repo.transaction();
+    if let Some((name, _)) = switch.create {
+        let verb = if existed { "Reset to" } else { "Created from" };
+        let mut update = RefUpdate::new(&format!("refs/heads/{}", name), RefChange::Update(commit.id().clone()))
+            .message(&format!("branch: {} {}", verb, spec));
+        if !existed {
+            update = update.old(repo.object_format().null_id());
+        }
+        tx.push(update);
+    }
+    match &branch {
+        Some(name) => tx.push(RefUpdate::new("HEAD", RefChange::Symbolic(format!("refs/heads/{}", name)))
+            .message(&message)),
+        None => tx.push(RefUpdate::new("HEAD", RefChange::Update(commit.id().clone())).no_deref().message(&message)),
+    };
+    let tx = tx.prepare()?;
+    repo.apply_checkout(plan)?;
+    if let Err(e) = tx.commit() {
+        // HEAD did not move, so the files go back to where it is
+        let back = repo.plan_checkout(Some(commit.tree()), &head_tree, &CheckoutOptions::default())
+            .and_then(|plan| repo.apply_checkout(plan));
+        match back {
+            Ok(_) => return Err(e),
+            Err(back) => bail!("{}\nunable to put the working tree back: {}", e, back),
+        }
+    }
+
+    if switch.quiet {
+        return Ok(());
+    }
+    let was_detached = current_branch.is_none() && head_id.is_some();
+    match branch {
+        Some(name) => {
+            if was_detached {
+                let previous: Id = head_id.unwrap();
+                eprintln!("Previous HEAD position was {}", summary(&repo.find_commit(&previous)?));
+            }
+            let same = current_branch.as_deref() == Some(name.as_str());
+            match switch.create {
+                Some(_) if existed && same => eprintln!("Reset branch '{}'", name),
+                Some(_) if existed => eprintln!("Switched to and reset branch '{}'", name),
+                Some(_) => eprintln!("Switched to a new branch '{}'", name),
+                None if same => eprintln!("Already on '{}'", name),
+                None => eprintln!("Switched to branch '{}'", name),
+            }
+        }
+        None => {
+            if was_detached {
+                if head_id.as_ref() != Some(commit.id()) {
+                    eprintln!("Previous HEAD position was {}", summary(&repo.find_commit(head_id.as_ref().unwrap())?));
+                }
+            } else if repo.config()?.get_bool("advice.detachedHead")? != Some(false) {
+                eprintln!("Note: switching to '{}'.\n\n{}", spec, DETACHED_ADVICE);
+            }
+            eprintln!("HEAD is now at {}", summary(&commit));
+        }
+    }
+    Ok(())
+}
+
+pub fn checkout(opt: CheckoutOpt) -> Result<()> {
+    let create = match (&opt.new_branch, &opt.reset_branch) {
+        (Some(name), _) => Some((name.as_str(), false)),
+        (None, Some(name)) => Some((name.as_str(), true)),
+        (None, None) => None,
+    };
+    let switch = Switch {
+        target: opt.target.as_deref(),
+        create,
+        detach: opt.detach,
+        force: opt.force,
+        quiet: opt.quiet,
+        branch_only: false,
+    };
+    switch_to(switch)
+}
@@ -0,0 +1,168 @@
+use structopt::StructOpt;
+use std::path::PathBuf;
+use std::io::{Read, Write};
+use crate::errors::*;
+use crate::config::{Config, ConfigEntry, ConfigFile, ConfigScope, IncludeContext, global_config_paths};
+use crate::config::value;
+use crate::fs::{FileSystem, LockFile, OsFs};
+use crate::model::repository::FileRepository;
+
+#[derive(Debug, StructOpt)]
+pub struct ConfigOpt {
+        #[structopt(long = "get", value_name = "name")]
+        ///Get the value for a given key.
+        get: Option<String>,
+
+        #[structopt(long = "get-all", value_name = "name")]
+        ///Like --get, but returns all values for a multi-valued key.
+        get_all: Option<String>,
+
+        #[structopt(short = "l", long = "list")]
+        ///List all variables set in config file, along with their values.
+        list: bool,
+
+        #[structopt(long = "show-origin")]
+        ///Augment the output of all queried config options with the origin type and the
+        /// actual origin (file or command line).
+        show_origin: bool,
+
+        #[structopt(long = "set", value_name = "name value", raw(number_of_values = "2"))]
+        ///Set the value for a given key in the selected config file.
+        set: Vec<String>,
+
+        #[structopt(long = "type", raw(possible_values = "&[\"bool\", \"int\", \"path\", \"color\"]"))]
+        ///Canonicalize values to the given type before printing them.
+        value_type: Option<String>,
+
+        #[structopt(long = "global")]
+        ///Use the per-user config file instead of the repository one.
+        global: bool,
+
+        #[structopt(long = "system")]
+        ///Use the system-wide config file instead of the repository one.
+        system: bool,
+
+        #[structopt(long = "local")]
+        ///Only use the repository config file. This is the default for writing.
+        local: bool,
+
+        #[structopt(short = "f", long = "file", parse(from_os_str))]
+        ///Use the given config file instead of the one specified by GIT_CONFIG.
+        file: Option<PathBuf>,
+}
+
+pub fn config(opt: ConfigOpt) -> Result<()> {
+    let fs = OsFs::new("/");
+    let repo = FileRepository::open(".").ok();
+    let git_dir = repo.as_ref().map(|repo| repo.git_dir().to_path_buf());
+    let common_dir = repo.as_ref().map(|repo| repo.common_dir().to_path_buf());
+
+    if !opt.set.is_empty() {
+        let path = target_file(&opt, &common_dir)?;
+        let lock = LockFile::acquire(&fs, &path)?;
+        // a missing file is created, but one that cannot be read is never
+        // replaced
+        let text = match fs.read_file(&path) {
+            Ok(mut reader) => {
+                let mut buf = vec![];
+                reader.read_to_end(&mut buf)?;
+                String::from_utf8(buf).chain_err(|| format!("bad config file {}", path.display()))?
+            }
+            Err(e) => match e.kind() {
+                ErrorKind::Io(io) if io.kind() == std::io::ErrorKind::NotFound => String::new(),
+                _ => return Err(e),
+            },
+        };
+        let mut file = ConfigFile::parse(&text)
+            .chain_err(|| format!("bad config file {}", path.display()))?;
+        file.set(&opt.set[0], &opt.set[1])?;
+        lock.write(file.to_string().as_bytes())?;
+        return lock.commit();
+    }
+
+    let config = if opt.global || opt.system || opt.local || opt.file.is_some() {
+        let path = target_file(&opt, &common_dir)?;
+        let scope = if opt.global {
+            ConfigScope::Global
+        } else if opt.system {
+            ConfigScope::System
+        } else {
+            ConfigScope::Local
+        };
+        let ctx = IncludeContext { git_dir: git_dir.clone(), branch: None };
+        let mut config = Config::default();
+        config.load_file(&fs, &path, scope, &ctx)?;
+        config
+    } else {
+        match &repo {
+            Some(repo) => repo.config()?,
+            None => Config::open(&fs, None)?,
+        }
+    };
+
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    if let Some(key) = &opt.get {
+        let entry = config.get_all(key)?.pop()
+            .ok_or_else(|| Error::from(ErrorKind::ConfigKeyNotFound(key.to_owned())))?;
+        print_entry(&mut out, &opt, entry, false)?;
+    } else if let Some(key) = &opt.get_all {
+        let entries = config.get_all(key)?;
+        if entries.is_empty() {
+            return Err(ErrorKind::ConfigKeyNotFound(key.to_owned()).into());
+        }
+        for entry in entries {
+            print_entry(&mut out, &opt, entry, false)?;
+        }
+    } else if opt.list {
+        for entry in config.entries() {
+            print_entry(&mut out, &opt, entry, true)?;
+        }
+    }
+    Ok(())
+}
+
+fn target_file(opt: &ConfigOpt, common_dir: &Option<PathBuf>) -> Result<PathBuf> {
+    if let Some(file) = &opt.file {
+        Ok(file.clone())
+    } else if opt.global {
+        let paths = global_config_paths();
+        let existing = paths.iter().rev().find(|p| p.is_file()).or_else(|| paths.last());
+        existing.cloned().ok_or_else(|| "can't determine global config file".into())
+    } else if opt.system {
+        Ok(std::env::var_os("GIT_CONFIG_SYSTEM")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("/etc/gitconfig")))
+    } else {
+        common_dir.as_ref()
+            .map(|d| d.join("config"))
+            .ok_or_else(|| ErrorKind::InvalidRepository(PathBuf::from(".")).into())
+    }
+}
+
+fn print_entry(out: &mut dyn Write, opt: &ConfigOpt, entry: &ConfigEntry, with_key: bool) -> Result<()> {
+    if opt.show_origin {
+        write!(out, "{}\t", entry.origin)?;
+    }
+    let key = entry.key.to_string();
+    let value = match (&opt.value_type, &entry.value) {
+        (Some(t), value) => Some(typed_value(t, &key, value.as_ref().map(|v| v.as_str()))?),
+        (None, value) => value.clone(),
+    };
+    match (with_key, value) {
+        (true, Some(value)) => writeln!(out, "{}={}", key, value)?,
+        (true, None) => writeln!(out, "{}", key)?,
+        (false, value) => writeln!(out, "{}", value.unwrap_or_default())?,
+    }
+    Ok(())
+}
+
+fn typed_value(value_type: &str, key: &str, raw: Option<&str>) -> Result<String> {
+    Ok(match value_type {
+        "bool" => value::parse_bool(key, raw)?.to_string(),
+        "int" => value::parse_int(key, raw.unwrap_or(""))?.to_string(),
+        "path" => value::parse_path(key, raw.unwrap_or(""))?.display().to_string(),
+        "color" => value::parse_color(key, raw.unwrap_or(""))?,
+        _ => raw.unwrap_or("").to_owned(),
+    })
+}
@@ -0,0 +1,77 @@
+use structopt::StructOpt;
+use crate::errors::*;
+use crate::model::describe::DescribeOptions;
+use crate::model::repository::{FileRepository, Repository};
+
+/// Hex digits of the commit id shown after the tag by default.
+const DEFAULT_ABBREV: usize = 7;
+
+#[derive(Debug, StructOpt)]
+pub struct DescribeOpt {
+        #[structopt(long = "tags")]
+        ///Use any tag found in refs/tags, not only annotated ones.
+        tags: bool,
+
+        #[structopt(long = "long")]
+        ///Always output the long format (the tag, the number of commits and the abbreviated commit
+        /// name) even when it matches a tag.
+        long: bool,
+
+        #[structopt(long = "dirty", value_name = "mark", raw(require_equals = "true"))]
+        ///Describe the working tree, appending <mark> (-dirty by default) if it has local changes.
+        dirty: Option<Option<String>>,
+
+        #[structopt(long = "match", value_name = "pattern", number_of_values = 1)]
+        ///Only consider tags matching the given glob pattern.
+        matches: Vec<String>,
+
+        #[structopt(long = "exclude", value_name = "pattern", number_of_values = 1)]
+        ///Do not consider tags matching the given glob pattern.
+        excludes: Vec<String>,
+
+        #[structopt(long = "abbrev", value_name = "n")]
+        ///Use <n> hexdigits of the commit name, or only the tag when 0.
+        abbrev: Option<usize>,
+
+        #[structopt(long = "first-parent")]
+        ///Follow only the first parent commit upon seeing a merge commit.
+        first_parent: bool,
+
+        #[structopt(long = "always")]
+        ///Show the abbreviated commit name as a fallback.
+        always: bool,
+
+        ///Commit-ish object names to describe. Defaults to HEAD.
+        commits: Vec<String>,
+}
+
+pub fn describe(opt: DescribeOpt) -> Result<()> {
+    if opt.dirty.is_some() && !opt.commits.is_empty() {
+        bail!("option '--dirty' and commit-ishes cannot be used together");
+    }
+    let repo = FileRepository::open(".")?;
+    let options = DescribeOptions {
+        tags: opt.tags,
+        matches: opt.matches.clone(),
+        excludes: opt.excludes.clone(),
+        first_parent: opt.first_parent,
+    };
+    let abbrev = opt.abbrev.unwrap_or(DEFAULT_ABBREV);
+    let commits = if opt.commits.is_empty() { vec!["HEAD".to_owned()] } else { opt.commits.clone() };
+    for spec in &commits {
+        let id = repo.rev_parse(spec).chain_err(|| format!("Not a valid object name {}", spec))?;
+        let id = repo.peel_to_commit(&id)?.id().clone();
+        let mut name = match repo.describe(&id, &options) {
+            Ok(description) => description.format(abbrev, opt.long),
+            Err(_) if opt.always => id.to_string()[..abbrev.clamp(4, id.to_string().len())].to_owned(),
+            Err(e) => return Err(e),
+        };
+        if let Some(mark) = &opt.dirty {
+            if repo.has_local_changes()? {
+                name.push_str(mark.as_deref().unwrap_or("-dirty"));
+            }
+        }
+        println!("{}", name);
+    }
+    Ok(())
+}
@@ -0,0 +1,86 @@
+use structopt::StructOpt;
+use std::io::{BufWriter, Write};
+use crate::errors::*;
+use crate::fs::FileSystem;
+use crate::model::id::Id;
+use crate::model::ref_filter::{sort_refs, RefFilter, RefFormat, SortKey, DEFAULT_FORMAT};
+use crate::model::repository::{FileRepository, Repository};
+
+#[derive(Debug, StructOpt)]
+pub struct ForEachRefOpt {
+        #[structopt(long = "sort", value_name = "key", number_of_values = 1)]
+        ///Sort on this field, prefixed with - for descending order and version: to compare numbers
+        /// as numbers. When given more than once the last key is the primary one. Defaults to
+        /// refname.
+        sort: Vec<String>,
+
+        #[structopt(long = "count", value_name = "count")]
+        ///Stop after showing this many refs.
+        count: Option<usize>,
+
+        #[structopt(long = "format", value_name = "format")]
+        ///Interpolate %(fieldname) from each ref shown, %% for a percent sign and %xx for a byte in
+        /// hex. Defaults to %(objectname) %(objecttype) TAB %(refname).
+        format: Option<String>,
+
+        #[structopt(long = "points-at", value_name = "object", number_of_values = 1)]
+        ///Only list refs which point at the given object, directly or through a tag.
+        points_at: Vec<String>,
+
+        #[structopt(long = "merged", value_name = "commit", raw(require_equals = "true"))]
+        ///Only list refs whose tips are reachable from the specified commit, HEAD if none.
+        merged: Option<Option<String>>,
+
+        #[structopt(long = "no-merged", value_name = "commit", raw(require_equals = "true"))]
+        ///Only list refs whose tips are not reachable from the specified commit, HEAD if none.
+        no_merged: Option<Option<String>>,
+
+        #[structopt(long = "contains", value_name = "commit", raw(require_equals = "true"))]
+        ///Only list refs which contain the specified commit, HEAD if none.
+        contains: Option<Option<String>>,
+
+        #[structopt(long = "no-contains", value_name = "commit", raw(require_equals = "true"))]
+        ///Only list refs which don't contain the specified commit, HEAD if none.
+        no_contains: Option<Option<String>>,
+
+        ///Only list refs matching a pattern: a full name, a prefix up to a slash, or a wildcard
+        /// pattern.
+        patterns: Vec<String>,
+}
+
+
+/// The commit an option names, HEAD when given without one.
+fn commit_arg<FS: FileSystem>(repo: &FileRepository<FS>, arg: &Option<Option<String>>) -> Result<Vec<Id>> {
+    let spec = match arg {
+        Some(spec) => spec.as_deref().unwrap_or("HEAD"),
+        None => return Ok(vec![]),
+    };
+    let id = repo.rev_parse(spec).chain_err(|| format!("malformed object name {}", spec))?;
+    Ok(vec![repo.peel_to_commit(&id).chain_err(|| format!("object {} is not a commit", spec))?.id().clone()])
+}
+
+pub fn for_each_ref(opt: ForEachRefOpt) -> Result<()> {
+    let repo = FileRepository::open(".")?;
+    let format: RefFormat = opt.format.as_deref().unwrap_or(DEFAULT_FORMAT).parse()?;
+    let keys = opt.sort.iter().map(|key| key.parse()).collect::<Result<Vec<SortKey>>>()?;
+    let points_at = opt.points_at.iter()
+        .map(|spec| repo.rev_parse(spec).chain_err(|| format!("malformed object name {}", spec)))
+        .collect::<Result<_>>()?;
+    let filter = RefFilter {
+        patterns: opt.patterns.clone(),
+        points_at,
+        merged: commit_arg(&repo, &opt.merged)?,
+        no_merged: commit_arg(&repo, &opt.no_merged)?,
+        contains: commit_arg(&repo, &opt.contains)?,
+        no_contains: commit_arg(&repo, &opt.no_contains)?,
+    };
+    let mut items = repo.filter_refs(&filter, &format)?;
+    sort_refs(&mut items, &keys);
+    let mut out = BufWriter::new(std::io::stdout());
+    for item in items.iter().take(opt.count.unwrap_or(usize::MAX)) {
+        out.write_all(&format.format(item))?;
+        writeln!(out)?;
+    }
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,27 @@
+use structopt::StructOpt;
+use crate::errors::*;
+use crate::model::fsck::FsckProblem;
+use crate::model::repository::FileRepository;
+
+#[derive(Debug, StructOpt)]
+pub struct FsckOpt {
+        #[structopt(long = "no-dangling")]
+        ///Do not print objects that exist but are never directly used.
+        no_dangling: bool,
+}
+
+pub fn fsck(opt: FsckOpt) -> Result<()> {
+    let repo = FileRepository::open(".")?;
+    let problems = repo.fsck()?;
+    for problem in &problems {
+        match problem {
+            FsckProblem::Dangling(..) if opt.no_dangling => {}
+            problem if problem.is_connectivity() => println!("{}", problem),
+            problem => eprintln!("{}", problem),
+        }
+    }
+    if problems.iter().any(FsckProblem::is_error) {
+        std::process::exit(1);
+    }
+    Ok(())
+}
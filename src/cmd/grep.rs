@@ -0,0 +1,207 @@
+use structopt::StructOpt;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use crate::errors::*;
+use crate::model::grep::{GrepExpr, GrepOptions, GrepOutput, GrepSource, PatternOptions, PatternSyntax, Term};
+use crate::model::repository::FileRepository;
+
+#[derive(Debug, StructOpt)]
+pub struct GrepOpt {
+        #[structopt(short = "e", value_name = "pattern", number_of_values = 1)]
+        ///The next parameter is the pattern. May be given more than once, combined with --and, --or,
+        /// --not and ( ).
+        patterns: Vec<String>,
+
+        #[structopt(long = "and", parse(from_occurrences))]
+        ///Both patterns around it must match the line.
+        and: u64,
+
+        #[structopt(long = "or", parse(from_occurrences))]
+        ///Either pattern around it may match the line, which is also what patterns without an
+        /// operator between them mean.
+        or: u64,
+
+        #[structopt(long = "not", parse(from_occurrences))]
+        ///The pattern after it must not match the line.
+        not: u64,
+
+        #[structopt(short = "G", long = "basic-regexp")]
+        ///Use POSIX basic regular expressions, the default.
+        basic: bool,
+
+        #[structopt(short = "E", long = "extended-regexp")]
+        ///Use POSIX extended regular expressions.
+        extended: bool,
+
+        #[structopt(short = "F", long = "fixed-strings")]
+        ///Match the patterns as fixed strings.
+        fixed: bool,
+
+        #[structopt(short = "i", long = "ignore-case")]
+        ///Ignore case differences between the patterns and the files.
+        ignore_case: bool,
+
+        #[structopt(short = "w", long = "word-regexp")]
+        ///Match the pattern only at word boundary.
+        word: bool,
+
+        #[structopt(short = "v", long = "invert-match")]
+        ///Select non-matching lines.
+        invert: bool,
+
+        #[structopt(short = "n", long = "line-number")]
+        ///Prefix the line number to matching lines.
+        line_number: bool,
+
+        #[structopt(short = "l", long = "files-with-matches", alias = "name-only")]
+        ///Show only the names of files that contain matches.
+        files_with_matches: bool,
+
+        #[structopt(short = "L", long = "files-without-match")]
+        ///Show only the names of files that do not contain matches.
+        files_without_match: bool,
+
+        #[structopt(short = "c", long = "count")]
+        ///Show the number of matching lines instead of the lines, for each file with any.
+        count: bool,
+
+        #[structopt(short = "A", long = "after-context", value_name = "num")]
+        ///Show <num> trailing lines after each match.
+        after: Option<usize>,
+
+        #[structopt(short = "B", long = "before-context", value_name = "num")]
+        ///Show <num> leading lines before each match.
+        before: Option<usize>,
+
+        #[structopt(short = "C", long = "context", value_name = "num")]
+        ///Show <num> leading and trailing lines around each match.
+        context: Option<usize>,
+
+        #[structopt(long = "threads", value_name = "num")]
+        ///Number of threads matching files. Defaults to grep.threads, or the number of CPUs.
+        threads: Option<usize>,
+
+        ///The pattern, unless given with -e, then the trees to search instead of the working tree.
+        args: Vec<String>,
+
+        #[structopt(raw(last = "true"))]
+        ///Only search files matching these paths.
+        paths: Vec<String>,
+}
+
+pub fn grep(opt: GrepOpt) -> Result<()> {
+    if !run(&opt)? {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// The pattern expression in command line order, which clap does not
+/// keep between different options.
+fn terms(args: &[String]) -> Vec<Term> {
+    let mut terms = vec![];
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--" => break,
+            "--and" => terms.push(Term::And),
+            "--or" => terms.push(Term::Or),
+            "--not" => terms.push(Term::Not),
+            "(" => terms.push(Term::Open),
+            ")" => terms.push(Term::Close),
+            "--after-context" | "--before-context" | "--context" | "--threads" => {
+                args.next();
+            }
+            short if short.starts_with('-') && !short.starts_with("--") => {
+                // the value of the first short option taking one ends the cluster
+                if let Some(n) = short.find(|c| "eABC".contains(c)) {
+                    let value = match &short[n + 1..] {
+                        "" => args.next().cloned(),
+                        value => Some(value.to_owned()),
+                    };
+                    if let (Some(value), true) = (value, short[n..].starts_with('e')) {
+                        terms.push(Term::Pattern(value));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    terms
+}
+
+fn run(opt: &GrepOpt) -> Result<bool> {
+    let repo = FileRepository::open(".")?;
+    let grouped = opt.args.iter().any(|arg| arg == "(" || arg == ")");
+    let mut terms = if opt.and + opt.or + opt.not == 0 && !grouped {
+        opt.patterns.iter().cloned().map(Term::Pattern).collect()
+    } else {
+        let command_line: Vec<String> = std::env::args().skip_while(|arg| arg != "grep").skip(1).collect();
+        terms(&command_line)
+    };
+    let mut args = opt.args.iter().filter(|arg| *arg != "(" && *arg != ")");
+    if opt.patterns.is_empty() {
+        if let Some(pattern) = args.next() {
+            terms.push(Term::Pattern(pattern.clone()));
+        }
+    }
+    let pattern_opts = PatternOptions {
+        syntax: if opt.fixed {
+            PatternSyntax::Fixed
+        } else if opt.extended && !opt.basic {
+            PatternSyntax::Extended
+        } else {
+            PatternSyntax::Basic
+        },
+        ignore_case: opt.ignore_case,
+        word: opt.word,
+    };
+    let expr = GrepExpr::parse(&terms, &pattern_opts)?;
+
+    // without a -- the trees end at the first argument that is not one,
+    // and what follows must be files
+    let separated = std::env::args().any(|arg| arg == "--");
+    let mut sources = vec![];
+    let mut paths = vec![];
+    for arg in args {
+        match repo.rev_parse(arg) {
+            Ok(id) if paths.is_empty() => sources.push(GrepSource::Tree(arg.clone(), id)),
+            Err(_) if separated => bail!("unable to resolve revision: {}", arg),
+            _ if !separated && !Path::new(arg).exists() => bail!(
+                "ambiguous argument '{}': unknown revision or path not in the working tree.\n\
+                 Use '--' to separate paths from revisions, like this:\n\
+                 'git <command> [<revision>...] -- [<file>...]'", arg),
+            _ => paths.push(arg.clone()),
+        }
+    }
+    if sources.is_empty() {
+        sources.push(GrepSource::WorkTree);
+    }
+    paths.extend(opt.paths.iter().cloned());
+
+    let output = if opt.files_with_matches {
+        GrepOutput::FilesWithMatches
+    } else if opt.files_without_match {
+        GrepOutput::FilesWithoutMatch
+    } else if opt.count {
+        GrepOutput::Count
+    } else {
+        GrepOutput::Lines
+    };
+    let grep_opts = GrepOptions {
+        invert: opt.invert,
+        line_number: opt.line_number,
+        output,
+        before: opt.before.or(opt.context).unwrap_or(0),
+        after: opt.after.or(opt.context).unwrap_or(0),
+        paths,
+        workers: opt.threads.unwrap_or(0),
+    };
+    let mut out = BufWriter::new(std::io::stdout());
+    let mut found = false;
+    for source in &sources {
+        found |= repo.grep(source, &expr, &grep_opts, &mut out)?;
+    }
+    out.flush()?;
+    Ok(found)
+}
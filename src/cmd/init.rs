@@ -0,0 +1,55 @@
+use structopt::StructOpt;
+use std::path::PathBuf;
+use crate::errors::*;
+use crate::model::init::InitOptions;
+use crate::model::repository::FileRepository;
+
+#[derive(Debug, StructOpt)]
+pub struct InitOpt {
+        #[structopt(long = "bare")]
+        ///Create a bare repository.
+        bare: bool,
+
+        #[structopt(short = "b", long = "initial-branch", value_name = "branch-name")]
+        ///Use the specified name for the initial branch in the newly created repository.
+        initial_branch: Option<String>,
+
+        #[structopt(long = "template", parse(from_os_str), value_name = "template-directory")]
+        ///Specify the directory from which templates will be used.
+        template: Option<PathBuf>,
+
+        #[structopt(long = "object-format", value_name = "format",
+                    raw(possible_values = "&[\"sha1\", \"sha256\"]"))]
+        ///Specify the given object format (hash algorithm) for the repository.
+        object_format: Option<String>,
+
+        #[structopt(short = "q", long = "quiet")]
+        ///Only print error and warning messages.
+        quiet: bool,
+
+        #[structopt(parse(from_os_str))]
+        directory: Option<PathBuf>,
+}
+
+pub fn init(opt: InitOpt) -> Result<()> {
+    let opts = InitOptions {
+        bare: opt.bare,
+        initial_branch: opt.initial_branch,
+        template: opt.template,
+        object_format: match &opt.object_format {
+            Some(format) => format.parse()?,
+            None => Default::default(),
+        },
+    };
+    let directory = opt.directory.unwrap_or_else(|| PathBuf::from("."));
+    let (repo, existed) = FileRepository::init(&directory, &opts)?;
+    if let (true, Some(branch)) = (existed, &opts.initial_branch) {
+        eprintln!("warning: re-init: ignored --initial-branch={}", branch);
+    }
+    if !opt.quiet {
+        println!("{} Git repository in {}/",
+                 if existed { "Reinitialized existing" } else { "Initialized empty" },
+                 repo.git_dir().display());
+    }
+    Ok(())
+}
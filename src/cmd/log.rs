@@ -0,0 +1,308 @@
+use structopt::StructOpt;
+use std::io::{BufWriter, IsTerminal, Write};
+use crate::errors::*;
+use crate::fs::FileSystem;
+use crate::model::commit::{Commit, Identity};
+use crate::model::patch::{write_combined, write_numstat, write_patch, write_shortstat, write_stat, CONTEXT};
+use crate::model::pretty::{show_commit, show_identity, DateMode, FormatContext, Pretty};
+use crate::model::repository::{FileRepository, Repository};
+use crate::model::signature::AllowedSigners;
+use crate::model::rev_walk::{RevWalk, Sorting};
+
+/// Renames are found between files at least this similar.
+const RENAME_THRESHOLD: usize = 50;
+
+/// How `log` and `show` format each commit and the diff after it.
+#[derive(Debug, StructOpt)]
+pub struct CommitFormatOpt {
+        #[structopt(short = "p", long = "patch")]
+        ///Generate a patch for each commit.
+        patch: bool,
+
+        #[structopt(short = "s", long = "no-patch")]
+        ///Suppress diff output.
+        no_patch: bool,
+
+        #[structopt(long = "stat")]
+        ///Generate a diffstat.
+        stat: bool,
+
+        #[structopt(long = "numstat")]
+        ///Show the number of added and deleted lines in decimal notation and the pathname.
+        numstat: bool,
+
+        #[structopt(long = "shortstat")]
+        ///Output only the last line of the --stat format.
+        shortstat: bool,
+
+        #[structopt(long = "cc")]
+        ///Show merges as a combined diff, leaving out hunks where the result took one parent's
+        /// version.
+        cc: bool,
+
+        #[structopt(long = "pretty", raw(alias = "\"format\""), value_name = "format")]
+        ///Pretty-print the commits: oneline, short, medium, full, fuller, raw, email,
+        /// format:<string> or tformat:<string>.
+        pretty: Option<Pretty>,
+
+        #[structopt(long = "oneline")]
+        ///Shorthand for --pretty=oneline --abbrev-commit.
+        oneline: bool,
+
+        #[structopt(long = "abbrev-commit")]
+        ///Show abbreviated commit ids in the header line.
+        abbrev_commit: bool,
+
+        #[structopt(long = "date", value_name = "format")]
+        ///The format of dates: relative, local, iso, iso-strict, rfc, short, raw, unix, default or
+        /// format:<strftime>.
+        date: Option<DateMode>,
+
+        #[structopt(long = "decorate")]
+        ///Print the ref names of the commits shown.
+        decorate: bool,
+
+        #[structopt(long = "color", value_name = "when", raw(require_equals = "true", possible_values = "&[\"always\", \"never\", \"auto\"]"))]
+        ///Color the output: always, which --color alone means, never, or auto, the default, to
+        /// color only a terminal.
+        color: Option<Option<String>>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct LogOpt {
+        #[structopt(flatten)]
+        format: CommitFormatOpt,
+
+        #[structopt(short = "n", long = "max-count", value_name = "number")]
+        ///Limit the number of commits to output.
+        max_count: Option<usize>,
+
+        #[structopt(long = "skip", value_name = "number")]
+        ///Skip the number of commits before starting to show the commit output.
+        skip: Option<usize>,
+
+        #[structopt(long = "first-parent")]
+        ///Follow only the first parent commit upon seeing a merge commit.
+        first_parent: bool,
+
+        #[structopt(long = "topo-order")]
+        ///Show no parents before all of its children are shown.
+        topo_order: bool,
+
+        ///Show the commits reachable from these, but not from those given as ^<rev>; <rev1>..<rev2>
+        /// and <rev1>...<rev2> are the usual shorthands. Defaults to HEAD.
+        revisions: Vec<String>,
+}
+
+/// Which diffs follow each commit.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct DiffFormat {
+    patch: bool,
+    stat: bool,
+    numstat: bool,
+    shortstat: bool,
+    /// Show merges too, as a combined diff.
+    combined: bool,
+}
+
+impl CommitFormatOpt {
+    /// The diffs asked for. `--cc`, which `show` implies, covers merges
+    /// and means a patch unless some other diff is asked for.
+    pub(crate) fn diff_format(&self, show: bool) -> DiffFormat {
+        if self.no_patch {
+            return DiffFormat::default();
+        }
+        let stats = self.stat || self.numstat || self.shortstat;
+        DiffFormat {
+            patch: self.patch || ((self.cc || show) && !stats),
+            stat: self.stat,
+            numstat: self.numstat,
+            shortstat: self.shortstat,
+            combined: show || self.cc,
+        }
+    }
+}
+
+impl CommitFormatOpt {
+    fn color(&self) -> bool {
+        match self.color.as_ref().map(|when| when.as_ref().map_or("always", String::as_str)) {
+            Some("always") => true,
+            Some("auto") | None => std::io::stdout().is_terminal(),
+            _ => false,
+        }
+    }
+}
+
+impl DiffFormat {
+    fn any(&self) -> bool {
+        self.patch || self.stat || self.numstat || self.shortstat
+    }
+}
+
+/// Writes commits one after another the way `log` separates them.
+pub(crate) struct LogWriter<'a, FS: FileSystem> {
+    repo: &'a FileRepository<FS>,
+    pretty: Pretty,
+    context: FormatContext,
+    /// Set when the format checks signatures, to the signers trusted.
+    signers: Option<Option<AllowedSigners>>,
+    diff: DiffFormat,
+    width: usize,
+    shown: bool,
+}
+
+impl<'a, FS: FileSystem> LogWriter<'a, FS> {
+    pub fn new(repo: &'a FileRepository<FS>, opt: &CommitFormatOpt, diff: DiffFormat) -> Result<Self> {
+        let pretty = match &opt.pretty {
+            Some(pretty) => pretty.clone(),
+            None if opt.oneline => Pretty::Oneline,
+            None => Pretty::Medium,
+        };
+        let mut context = FormatContext {
+            date_mode: opt.date.clone().unwrap_or_default(),
+            decorate: opt.decorate,
+            abbrev_commit: opt.abbrev_commit || opt.oneline,
+            color: opt.color(),
+            ..FormatContext::default()
+        };
+        let format = match &pretty {
+            Pretty::Format(format) | Pretty::TFormat(format) => format.as_str(),
+            _ => "",
+        };
+        if opt.decorate || format.contains("%d") || format.contains("%D") {
+            context.decorations = repo.decorations()?;
+        }
+        let signers = if format.contains("%G") { Some(repo.allowed_signers()?) } else { None };
+        let width = std::env::var("COLUMNS").ok().and_then(|columns| columns.parse().ok()).unwrap_or(80);
+        Ok(LogWriter { repo, pretty, context, signers, diff, width, shown: false })
+    }
+
+    /// The `Tagger:` lines of a tag shown before the commit it points at.
+    pub fn tagger(&self, tagger: &Identity) -> String {
+        show_identity("Tagger", tagger, &self.pretty, &self.context)
+    }
+
+    /// The line left after a tag's message, unless commits end themselves.
+    pub fn tag_separator(&self) -> &'static str {
+        if self.pretty.terminates() { "" } else { "\n" }
+    }
+
+    pub fn write(&mut self, out: &mut dyn Write, commit: &Commit) -> Result<()> {
+        if self.shown && !self.pretty.terminates() {
+            writeln!(out)?;
+        }
+        self.shown = true;
+        if let Some(signers) = &self.signers {
+            let check = self.repo.signature_check(commit.id(), signers.as_ref())?;
+            self.context.signatures.insert(commit.id().clone(), check);
+        }
+        out.write_all(show_commit(commit, &self.pretty, &self.context).as_bytes())?;
+        let diff = self.diff_of(commit)?;
+        // git separates a merge's combined diff even when it is empty
+        let combined = commit.parent().len() > 1 && self.diff.combined && self.diff.any();
+        if !diff.is_empty() || combined {
+            // oneline runs straight into the diff, but for a merge's
+            if self.pretty == Pretty::Oneline && !combined {
+                out.write_all(&diff)?;
+                return Ok(());
+            }
+            if self.diff.stat && self.diff.patch && !combined {
+                write!(out, "---")?;
+            }
+            writeln!(out)?;
+            out.write_all(&diff)?;
+        }
+        Ok(())
+    }
+
+    fn diff_of(&self, commit: &Commit) -> Result<Vec<u8>> {
+        let mut out = vec![];
+        let parents = commit.parent();
+        if !self.diff.any() || (parents.len() > 1 && !self.diff.combined) {
+            return Ok(out);
+        }
+        let repo = self.repo;
+        let trees = parents.iter().map(|parent| Ok(repo.find_commit(parent)?.tree().clone())).collect::<Result<Vec<_>>>()?;
+        // stats of a merge are against its first parent
+        let stats = self.diff.stat || self.diff.numstat || self.diff.shortstat;
+        let patches = if stats || (self.diff.patch && trees.len() < 2) {
+            repo.file_patches(repo.diff_trees(trees.first(), Some(commit.tree()), Some(RENAME_THRESHOLD))?)?
+        } else {
+            vec![]
+        };
+        if self.diff.numstat {
+            write_numstat(&mut out, &patches)?;
+        }
+        if self.diff.stat {
+            write_stat(&mut out, &patches, self.width)?;
+        }
+        if self.diff.shortstat {
+            write_shortstat(&mut out, &patches)?;
+        }
+        if self.diff.patch {
+            let mut patch = vec![];
+            if trees.len() > 1 {
+                write_combined(&mut patch, repo, &repo.combined_changes(&trees, commit.tree())?, true)?;
+            } else {
+                write_patch(&mut patch, &patches, CONTEXT)?;
+            }
+            if !out.is_empty() && !patch.is_empty() {
+                writeln!(out)?;
+            }
+            out.extend(patch);
+        }
+        Ok(out)
+    }
+}
+
+/// Pushes and hides the commits `revisions` name, HEAD if none is
+/// positive.
+fn push_revisions<FS: FileSystem>(repo: &FileRepository<FS>, walk: &mut RevWalk<FS>, revisions: &[String]) -> Result<()> {
+    let parse = |spec: &str| {
+        let spec = if spec.is_empty() { "HEAD" } else { spec };
+        repo.rev_parse(spec).chain_err(|| format!("bad revision '{}'", spec))
+    };
+    let mut positive = false;
+    for spec in revisions {
+        if let Some(n) = spec.find("...") {
+            let (a, b) = (parse(&spec[..n])?, parse(&spec[n + 3..])?);
+            let (a, b) = (repo.peel_to_commit(&a)?.id().clone(), repo.peel_to_commit(&b)?.id().clone());
+            for base in repo.merge_bases(&a, std::slice::from_ref(&b))? {
+                walk.hide(&base)?;
+            }
+            walk.push(&a)?;
+            walk.push(&b)?;
+            positive = true;
+        } else if let Some(n) = spec.find("..") {
+            walk.hide(&parse(&spec[..n])?)?;
+            walk.push(&parse(&spec[n + 2..])?)?;
+            positive = true;
+        } else if let Some(spec) = spec.strip_prefix('^') {
+            walk.hide(&parse(spec)?)?;
+        } else {
+            walk.push(&parse(spec)?)?;
+            positive = true;
+        }
+    }
+    if !positive {
+        walk.push(&parse("HEAD")?)?;
+    }
+    Ok(())
+}
+
+pub fn log(opt: LogOpt) -> Result<()> {
+    let repo = FileRepository::open(".")?;
+    let mut walk = repo.rev_walk();
+    walk.first_parent(opt.first_parent);
+    if opt.topo_order {
+        walk.sorting(Sorting::Topo);
+    }
+    push_revisions(&repo, &mut walk, &opt.revisions)?;
+    let mut writer = LogWriter::new(&repo, &opt.format, opt.format.diff_format(false))?;
+    let mut out = BufWriter::new(std::io::stdout());
+    for commit in walk.skip(opt.skip.unwrap_or(0)).take(opt.max_count.unwrap_or(usize::MAX)) {
+        writer.write(&mut out, &commit?)?;
+    }
+    out.flush()?;
+    Ok(())
+}
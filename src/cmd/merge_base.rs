@@ -0,0 +1,64 @@
+use structopt::StructOpt;
+use crate::errors::*;
+use crate::model::id::Id;
+use crate::model::repository::FileRepository;
+
+#[derive(Debug, StructOpt)]
+pub struct MergeBaseOpt {
+        #[structopt(short = "a", long = "all")]
+        ///Output all merge bases for the commits, instead of just one.
+        all: bool,
+
+        #[structopt(long = "octopus", raw(conflicts_with_all = "&[\"independent\", \"is_ancestor\"]"))]
+        ///Compute the best common ancestors of all supplied commits, in preparation for an n-way merge.
+        octopus: bool,
+
+        #[structopt(long = "independent", conflicts_with = "is_ancestor")]
+        ///Instead of printing merge bases, print a minimal subset of the supplied commits with the
+        /// same ancestors.
+        independent: bool,
+
+        #[structopt(long = "is-ancestor")]
+        ///Check if the first <commit> is an ancestor of the second <commit>, and exit with status 0
+        /// if true, or with status 1 if not.
+        is_ancestor: bool,
+
+        #[structopt(required = true)]
+        commits: Vec<String>,
+}
+
+pub fn merge_base(opt: MergeBaseOpt) -> Result<()> {
+    let repo = FileRepository::open(".")?;
+    let commits = opt.commits.iter()
+        .map(|spec| repo.rev_parse(spec))
+        .collect::<Result<Vec<Id>>>()?;
+    if opt.is_ancestor {
+        if commits.len() != 2 {
+            bail!("--is-ancestor takes exactly two commits");
+        }
+        if !repo.is_ancestor(&commits[0], &commits[1])? {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+    let mut bases = if opt.independent {
+        repo.independent(&commits)?
+    } else if opt.octopus {
+        repo.octopus_merge_bases(&commits)?
+    } else {
+        if commits.len() < 2 {
+            bail!("merge-base needs at least two commits");
+        }
+        repo.merge_bases(&commits[0], &commits[1..])?
+    };
+    if bases.is_empty() {
+        std::process::exit(1);
+    }
+    if !opt.all && !opt.independent {
+        bases.truncate(1);
+    }
+    for base in bases {
+        println!("{}", base);
+    }
+    Ok(())
+}
@@ -0,0 +1,97 @@
+use structopt::StructOpt;
+use crate::errors::*;
+use crate::model::id::Id;
+use crate::model::merge::{ConflictStyle, Labels, MergeOptions};
+use crate::model::repository::{FileRepository, Repository};
+
+#[derive(Debug, StructOpt)]
+pub struct MergeTreeOpt {
+        #[structopt(long = "write-tree")]
+        ///Do a real merge, write the resulting tree and print its id, followed by the conflicted files.
+        write_tree: bool,
+
+        #[structopt(long = "messages")]
+        ///Also show informational and conflict messages; by default they are shown only on conflicts.
+        messages: bool,
+
+        #[structopt(long = "no-messages", conflicts_with = "messages")]
+        ///Never show informational and conflict messages.
+        no_messages: bool,
+
+        #[structopt(long = "name-only")]
+        ///List only the names of conflicted files, without modes, ids and stages.
+        name_only: bool,
+
+        #[structopt(long = "allow-unrelated-histories")]
+        ///Merge commits without a common ancestor as if they had an empty one.
+        allow_unrelated: bool,
+
+        #[structopt(long = "merge-base")]
+        ///Use this commit as the merge base instead of computing it.
+        merge_base: Option<String>,
+
+        #[structopt(long = "conflict")]
+        ///How to write conflict hunks: merge, diff3 or zdiff3. Defaults to merge.conflictStyle.
+        conflict: Option<String>,
+
+        branch1: String,
+        branch2: String,
+}
+
+pub fn merge_tree(opt: MergeTreeOpt) -> Result<()> {
+    if !opt.write_tree {
+        bail!("only --write-tree merges are supported");
+    }
+    let repo = FileRepository::open(".")?;
+    let style = match &opt.conflict {
+        Some(style) => style.parse()?,
+        None => match repo.config()?.get("merge.conflictStyle")? {
+            Some(style) => style.parse()?,
+            None => ConflictStyle::Merge,
+        },
+    };
+    let options = MergeOptions {
+        style,
+        labels: Labels { ours: opt.branch1.clone(), base: String::new(), theirs: opt.branch2.clone() },
+        allow_unrelated: opt.allow_unrelated,
+        ..MergeOptions::default()
+    };
+    let ours = repo.rev_parse(&opt.branch1)?;
+    let theirs = repo.rev_parse(&opt.branch2)?;
+    let merge = match &opt.merge_base {
+        Some(base) => {
+            let base = repo.peel_to_commit(&repo.rev_parse(base)?)?.id().clone();
+            repo.merge_commits_with_bases(&[base], &ours, &theirs, &options)?
+        }
+        None => {
+            let ours: Id = repo.peel_to_commit(&ours)?.id().clone();
+            let theirs: Id = repo.peel_to_commit(&theirs)?.id().clone();
+            repo.merge_commits(&ours, &theirs, &options)?
+        }
+    };
+
+    println!("{}", merge.tree);
+    if opt.name_only {
+        let mut last = None;
+        for entry in &merge.entries {
+            if last != Some(&entry.path) {
+                println!("{}", entry.path);
+                last = Some(&entry.path);
+            }
+        }
+    } else {
+        for entry in &merge.entries {
+            println!("{} {} {}\t{}", entry.mode.as_str(), entry.id, entry.stage, entry.path);
+        }
+    }
+    if opt.messages || (!merge.is_clean() && !opt.no_messages) {
+        println!();
+        for message in &merge.messages {
+            println!("{}", message);
+        }
+    }
+    if !merge.is_clean() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
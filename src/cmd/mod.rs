@@ -1,4 +1,26 @@
 extern crate structopt;
 
 
-pub mod catfile;
\ No newline at end of file
+pub mod catfile;
+pub mod config;
+pub mod init;
+pub mod update_ref;
+pub mod symbolic_ref;
+pub mod reflog;
+pub mod fsck;
+pub mod verify_pack;
+pub mod verify_commit;
+pub mod verify_tag;
+pub mod merge_base;
+pub mod merge_tree;
+pub mod checkout;
+pub mod switch;
+pub mod archive;
+pub mod blame;
+pub mod log;
+pub mod show;
+pub mod for_each_ref;
+pub mod show_ref;
+pub mod describe;
+pub mod name_rev;
+pub mod grep;
@@ -0,0 +1,71 @@
+use structopt::StructOpt;
+use std::io::Write;
+use crate::errors::*;
+use crate::model::name_rev::NameRevOptions;
+use crate::model::repository::{FileRepository, Repository};
+
+#[derive(Debug, StructOpt)]
+pub struct NameRevOpt {
+        #[structopt(long = "tags")]
+        ///Do not use branch names, but only tags to name the commits.
+        tags: bool,
+
+        #[structopt(long = "refs", value_name = "pattern", number_of_values = 1)]
+        ///Only use refs whose names match a given shell pattern.
+        refs: Vec<String>,
+
+        #[structopt(long = "exclude", value_name = "pattern", number_of_values = 1)]
+        ///Do not use any ref whose name matches a given shell pattern.
+        excludes: Vec<String>,
+
+        #[structopt(long = "name-only")]
+        ///Print only the name rather than both the SHA-1 and the name.
+        name_only: bool,
+
+        #[structopt(long = "no-undefined")]
+        ///Die with error code != 0 when a reference is undefined, instead of printing undefined.
+        no_undefined: bool,
+
+        #[structopt(long = "always")]
+        ///Show uniquely abbreviated commit object as fallback.
+        always: bool,
+
+        ///The commits to name.
+        commits: Vec<String>,
+}
+
+pub fn name_rev(opt: NameRevOpt) -> Result<()> {
+    let repo = FileRepository::open(".")?;
+    let mut commits = vec![];
+    for spec in &opt.commits {
+        match repo.rev_parse(spec).and_then(|id| repo.peel_to_commit(&id)) {
+            Ok(commit) => commits.push((spec, commit.id().clone())),
+            Err(_) => eprintln!("Could not get sha1 for {}. Skipping.", spec),
+        }
+    }
+    let options = NameRevOptions {
+        tags: opt.tags,
+        abbreviate: opt.tags && opt.name_only,
+        refs: opt.refs.clone(),
+        excludes: opt.excludes.clone(),
+    };
+    let ids: Vec<_> = commits.iter().map(|(_, id)| id.clone()).collect();
+    let names = repo.name_rev(&ids, &options)?;
+    for (spec, id) in &commits {
+        if !opt.name_only {
+            print!("{} ", spec);
+        }
+        // --always only stands in for a name where --no-undefined would fail
+        let name = match names.name(id) {
+            Some(name) => name,
+            None if !opt.no_undefined => "undefined".to_owned(),
+            None if opt.always => id.to_string()[..7].to_owned(),
+            None => {
+                std::io::stdout().flush()?;
+                bail!("cannot describe '{}'", id)
+            }
+        };
+        println!("{}", name);
+    }
+    Ok(())
+}
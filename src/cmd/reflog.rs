@@ -0,0 +1,129 @@
+use structopt::StructOpt;
+use chrono::Local;
+use crate::errors::*;
+use crate::fs::FileSystem;
+use crate::model::date::expiry_date;
+use crate::model::refs::Reflog;
+use crate::model::repository::FileRepository;
+
+#[derive(Debug, StructOpt)]
+pub struct ReflogOpt {
+        #[structopt(subcommand)]
+        command: Option<ReflogCommand>,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum ReflogCommand {
+    #[structopt(name = "show")]
+    ///Show the log of the reference provided in the command-line (or HEAD, by default).
+    Show(ReflogShowOpt),
+    #[structopt(name = "expire")]
+    ///Prune older reflog entries.
+    Expire(ReflogExpireOpt),
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ReflogShowOpt {
+        #[structopt()]
+        reference: Option<String>,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct ReflogExpireOpt {
+        #[structopt(long = "expire", value_name = "time")]
+        ///Prune entries older than the specified time. Defaults to gc.reflogExpire, or 90 days.
+        expire: Option<String>,
+
+        #[structopt(long = "expire-unreachable", value_name = "time")]
+        ///Prune entries older than <time> that are not reachable from the current tip of the
+        /// branch. Defaults to gc.reflogExpireUnreachable, or 30 days.
+        expire_unreachable: Option<String>,
+
+        #[structopt(long = "all")]
+        ///Process the reflogs of all references.
+        all: bool,
+
+        #[structopt(short = "n", long = "dry-run")]
+        ///Do not actually prune any entries; just show what would have been pruned.
+        dry_run: bool,
+
+        #[structopt(long = "verbose")]
+        ///Print extra information on screen.
+        verbose: bool,
+
+        #[structopt()]
+        references: Vec<String>,
+}
+
+pub fn reflog(opt: ReflogOpt) -> Result<()> {
+    let repo = FileRepository::open(".")?;
+    match opt.command {
+        Some(ReflogCommand::Show(opt)) => show(&repo, opt.reference.as_deref().unwrap_or("HEAD")),
+        Some(ReflogCommand::Expire(opt)) => expire(&repo, &opt),
+        None => show(&repo, "HEAD"),
+    }
+}
+
+fn show<FS: FileSystem>(repo: &FileRepository<FS>, reference: &str) -> Result<()> {
+    let name = full_name(repo, reference)?;
+    for (n, entry) in repo.reflog(&name)?.iter().enumerate() {
+        let id = entry.new.to_string();
+        println!("{} {}@{{{}}}: {}", &id[..7], reference, n, entry.message);
+    }
+    Ok(())
+}
+
+fn expire<FS: FileSystem>(repo: &FileRepository<FS>, opt: &ReflogExpireOpt) -> Result<()> {
+    let config = repo.config()?;
+    let now = Local::now();
+    let now = now.with_timezone(now.offset());
+    let expire = match &opt.expire {
+        Some(expire) => expiry_date(expire, now)?,
+        None => expiry_date(config.get("gc.reflogExpire")?.unwrap_or("90.days.ago"), now)?,
+    };
+    let expire_unreachable = match &opt.expire_unreachable {
+        Some(expire) => expiry_date(expire, now)?,
+        None => expiry_date(config.get("gc.reflogExpireUnreachable")?.unwrap_or("30.days.ago"), now)?,
+    };
+
+    let names = if opt.all {
+        repo.reflog_names()
+    } else {
+        opt.references.iter()
+            .map(|r| full_name(repo, r))
+            .collect::<Result<Vec<_>>>()?
+    };
+    for name in names {
+        let mut reflog: Reflog = repo.reflog(&name)?;
+        let before = reflog.len();
+        let reachable = match (&expire_unreachable, repo.resolve_ref(&name)?) {
+            (Some(_), Some(tip)) => repo.reachable(&[tip])?,
+            _ => Default::default(),
+        };
+        reflog.retain(|entry| {
+            let date = &entry.identity.date;
+            let expired = expire.map(|e| *date <= e).unwrap_or(false)
+                || (expire_unreachable.map(|e| *date <= e).unwrap_or(false)
+                    && !reachable.contains(&entry.new));
+            if expired && opt.verbose {
+                println!("{}prune {}", if opt.dry_run { "would " } else { "" }, entry.message);
+            }
+            !expired
+        });
+        if !opt.dry_run && reflog.len() != before {
+            repo.write_reflog(&name, &reflog)?;
+        }
+    }
+    Ok(())
+}
+
+/// `HEAD`, a full ref name or a short name with a reflog.
+fn full_name<FS: FileSystem>(repo: &FileRepository<FS>, reference: &str) -> Result<String> {
+    if repo.has_reflog(reference) {
+        return Ok(reference.to_owned());
+    }
+    match repo.dwim_ref(reference)? {
+        Some((name, _)) => Ok(name),
+        None => Err(ErrorKind::BadRevision(reference.to_owned()).into()),
+    }
+}
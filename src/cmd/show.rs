@@ -0,0 +1,68 @@
+use structopt::StructOpt;
+use std::io::{BufWriter, Write};
+use crate::cmd::log::{CommitFormatOpt, LogWriter};
+use crate::errors::*;
+use crate::fs::FileSystem;
+use crate::model::object::Object;
+use crate::model::repository::{FileRepository, Repository};
+use crate::model::tree::FileMode;
+
+#[derive(Debug, StructOpt)]
+pub struct ShowOpt {
+        #[structopt(flatten)]
+        format: CommitFormatOpt,
+
+        ///The objects to show: commits with their diff, tags with the object they point at, trees as
+        /// a listing and blobs as their content. <rev>:<path> names a file in a commit. Defaults to
+        /// HEAD.
+        objects: Vec<String>,
+}
+
+pub fn show(opt: ShowOpt) -> Result<()> {
+    let repo = FileRepository::open(".")?;
+    let mut writer = LogWriter::new(&repo, &opt.format, opt.format.diff_format(true))?;
+    let mut out = BufWriter::new(std::io::stdout());
+    let objects = if opt.objects.is_empty() { vec!["HEAD".to_owned()] } else { opt.objects.clone() };
+    for spec in &objects {
+        show_object(&repo, &mut writer, &mut out, spec)?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn show_object<FS: FileSystem>(repo: &FileRepository<FS>, writer: &mut LogWriter<FS>, out: &mut dyn Write, spec: &str) -> Result<()> {
+    let bad = || format!("bad revision '{}'", spec);
+    let id = match spec.find(':') {
+        Some(n) => {
+            let commit = repo.rev_parse(&spec[..n]).and_then(|id| repo.peel_to_commit(&id)).chain_err(bad)?;
+            let path = &spec[n + 1..];
+            match repo.find_tree(commit.tree())?.entry_at(repo, path)? {
+                Some(entry) => entry.id().clone(),
+                None if path.is_empty() => commit.tree().clone(),
+                None => bail!("path '{}' does not exist in '{}'", path, &spec[..n]),
+            }
+        }
+        None => repo.rev_parse(spec).chain_err(bad)?,
+    };
+    match repo.read_object(&id)? {
+        Object::Commit(commit) => writer.write(out, &commit)?,
+        Object::Tag(tag) => {
+            writeln!(out, "tag {}", tag.tag())?;
+            if let Some(tagger) = tag.tagger() {
+                write!(out, "{}", writer.tagger(tagger))?;
+            }
+            writeln!(out, "\n{}{}", tag.message(), writer.tag_separator())?;
+            show_object(repo, writer, out, &tag.object().to_string())?;
+        }
+        Object::Tree(tree) => {
+            writeln!(out, "tree {}\n", spec)?;
+            for entry in tree.entries() {
+                writeln!(out, "{}{}", entry.name(), if entry.mode() == FileMode::DIR { "/" } else { "" })?;
+            }
+        }
+        Object::Blob(_) => {
+            out.write_all(&Repository::read_content(repo, &repo.find_object(&id)?)?)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,135 @@
+use structopt::StructOpt;
+use std::io::{BufWriter, Write};
+use crate::errors::*;
+use crate::model::id::Id;
+use crate::model::object::Object;
+use crate::model::repository::{FileRepository, Repository};
+
+#[derive(Debug, StructOpt)]
+pub struct ShowRefOpt {
+        #[structopt(long = "head")]
+        ///Show the HEAD reference, even if it would normally be filtered out.
+        head: bool,
+
+        #[structopt(long = "heads")]
+        ///Limit to refs/heads.
+        heads: bool,
+
+        #[structopt(long = "tags")]
+        ///Limit to refs/tags.
+        tags: bool,
+
+        #[structopt(short = "d", long = "dereference")]
+        ///Dereference tags into object IDs as well, shown with ^{} appended.
+        dereference: bool,
+
+        #[structopt(short = "s", long = "hash", value_name = "n", raw(require_equals = "true"))]
+        ///Only show the object ID, abbreviated to n hex digits if given.
+        hash: Option<Option<usize>>,
+
+        #[structopt(long = "abbrev", value_name = "n", raw(require_equals = "true"))]
+        ///Abbreviate the object IDs to n hex digits, 7 if not given.
+        abbrev: Option<Option<usize>>,
+
+        #[structopt(long = "verify")]
+        ///Enable stricter reference checking by requiring an exact ref path.
+        verify: bool,
+
+        #[structopt(short = "q", long = "quiet")]
+        ///Do not print any results to stdout.
+        quiet: bool,
+
+        ///Show refs matching these: by their full name with --verify, otherwise by their last
+        /// components.
+        patterns: Vec<String>,
+}
+
+pub fn show_ref(opt: ShowRefOpt) -> Result<()> {
+    if !run(&opt)? {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Whether `pattern` names `name` by its trailing components.
+fn matches(name: &str, pattern: &str) -> bool {
+    name == pattern || name.strip_suffix(pattern).is_some_and(|rest| rest.ends_with('/'))
+}
+
+/// Shows the refs asked for, returning whether there were any.
+fn run(opt: &ShowRefOpt) -> Result<bool> {
+    let repo = FileRepository::open(".")?;
+    let abbrev = match (opt.hash, opt.abbrev) {
+        (Some(Some(n)), _) | (_, Some(Some(n))) => Some(n.max(4)),
+        (_, Some(None)) => Some(7),
+        _ => None,
+    };
+    let mut out = BufWriter::new(std::io::stdout());
+    let mut show = |name: &str, id: &Id| -> Result<()> {
+        if opt.quiet {
+            return Ok(());
+        }
+        let hex: String = id.to_string().chars().take(abbrev.unwrap_or(usize::MAX)).collect();
+        if opt.hash.is_some() {
+            writeln!(out, "{}", hex)?;
+        } else {
+            writeln!(out, "{} {}", hex, name)?;
+        }
+        if opt.dereference {
+            if let Object::Tag(tag) = repo.read_object(id)? {
+                let mut peeled = tag.object().clone();
+                while let Object::Tag(tag) = repo.read_object(&peeled)? {
+                    peeled = tag.object().clone();
+                }
+                let hex: String = peeled.to_string().chars().take(abbrev.unwrap_or(usize::MAX)).collect();
+                if opt.hash.is_some() {
+                    writeln!(out, "{}", hex)?;
+                } else {
+                    writeln!(out, "{} {}^{{}}", hex, name)?;
+                }
+            }
+        }
+        Ok(())
+    };
+
+    if opt.verify {
+        for name in &opt.patterns {
+            let id = if name.starts_with("refs/") || name == "HEAD" {
+                repo.resolve_ref(name)?
+            } else {
+                None
+            };
+            match id {
+                Some(id) => show(name, &id)?,
+                None if opt.quiet => return Ok(false),
+                None => bail!("'{}' - not a valid ref", name),
+            }
+        }
+        out.flush()?;
+        return Ok(true);
+    }
+
+    let mut found = false;
+    if opt.head && (opt.patterns.is_empty() || opt.patterns.iter().any(|p| p == "HEAD")) {
+        if let Some(id) = repo.resolve_ref("HEAD")? {
+            show("HEAD", &id)?;
+            found = true;
+        }
+    }
+    for name in repo.ref_names()? {
+        if (opt.heads || opt.tags)
+            && !(opt.heads && name.starts_with("refs/heads/"))
+            && !(opt.tags && name.starts_with("refs/tags/")) {
+            continue;
+        }
+        if !opt.patterns.is_empty() && !opt.patterns.iter().any(|pattern| matches(&name, pattern)) {
+            continue;
+        }
+        if let Some(id) = repo.resolve_ref(&name)? {
+            show(&name, &id)?;
+            found = true;
+        }
+    }
+    out.flush()?;
+    Ok(found)
+}
@@ -0,0 +1,46 @@
+use structopt::StructOpt;
+use crate::errors::*;
+use crate::cmd::checkout::{switch_to, Switch};
+
+#[derive(Debug, StructOpt)]
+pub struct SwitchOpt {
+        #[structopt(short = "c", long = "create", value_name = "new-branch")]
+        ///Create a new branch named <new-branch> starting at <start-point> before switching to it.
+        create: Option<String>,
+
+        #[structopt(short = "C", long = "force-create", value_name = "new-branch", conflicts_with = "create")]
+        ///Like --create, but reset <new-branch> to <start-point> if it already exists.
+        force_create: Option<String>,
+
+        #[structopt(short = "d", long = "detach")]
+        ///Switch to a commit for inspection, detaching HEAD.
+        detach: bool,
+
+        #[structopt(short = "f", long = "force", raw(alias = "\"discard-changes\""))]
+        ///Throw away local changes and untracked files in the way.
+        force: bool,
+
+        #[structopt(short = "q", long = "quiet")]
+        ///Suppress feedback messages.
+        quiet: bool,
+
+        ///The branch to switch to, or the start point of a new branch.
+        target: Option<String>,
+}
+
+pub fn switch(opt: SwitchOpt) -> Result<()> {
+    let create = match (&opt.create, &opt.force_create) {
+        (Some(name), _) => Some((name.as_str(), false)),
+        (None, Some(name)) => Some((name.as_str(), true)),
+        (None, None) => None,
+    };
+    let switch = Switch {
+        target: opt.target.as_deref(),
+        create,
+        detach: opt.detach,
+        force: opt.force,
+        quiet: opt.quiet,
+        branch_only: true,
+    };
+    switch_to(switch)
+}
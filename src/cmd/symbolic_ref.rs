@@ -0,0 +1,65 @@
+use structopt::StructOpt;
+use crate::errors::*;
+use crate::model::refs::{shorten_ref_name, RefChange, RefTarget, RefUpdate};
+use crate::model::repository::FileRepository;
+
+#[derive(Debug, StructOpt)]
+pub struct SymbolicRefOpt {
+        #[structopt(short = "m", value_name = "reason")]
+        ///Update the reflog for <name> with <reason>.
+        message: Option<String>,
+
+        #[structopt(short = "d", long = "delete")]
+        ///Delete the symbolic ref <name>.
+        delete: bool,
+
+        #[structopt(short = "q", long = "quiet")]
+        ///Do not issue an error message if <name> is not a symbolic ref but a detached HEAD;
+        /// instead exit with non-zero status silently.
+        quiet: bool,
+
+        #[structopt(long = "short")]
+        ///When showing the value of <name> as a symbolic ref, try to shorten it.
+        short: bool,
+
+        #[structopt()]
+        name: String,
+
+        #[structopt()]
+        reference: Option<String>,
+}
+
+pub fn symbolic_ref(opt: SymbolicRefOpt) -> Result<()> {
+    let repo = FileRepository::open(".")?;
+    let message = opt.message.clone().unwrap_or_default();
+
+    if let Some(reference) = &opt.reference {
+        if opt.name == "HEAD" && !reference.starts_with("refs/") {
+            return Err(format!("Refusing to point HEAD outside of refs/: {}", reference).into());
+        }
+        let mut tx = repo.transaction();
+        tx.push(RefUpdate::new(&opt.name, RefChange::Symbolic(reference.to_owned())).message(&message));
+        return tx.commit();
+    }
+
+    let target = match repo.read_ref(&opt.name)? {
+        Some(RefTarget::Symbolic(target)) => target,
+        _ if opt.quiet => std::process::exit(1),
+        _ => return Err(format!("ref {} is not a symbolic ref", opt.name).into()),
+    };
+    if opt.delete {
+        let mut tx = repo.transaction();
+        tx.push(RefUpdate::new(&opt.name, RefChange::Delete).no_deref().message(&message));
+        return tx.commit();
+    }
+
+    let mut target = target;
+    for _ in 0..5 {
+        match repo.read_ref(&target)? {
+            Some(RefTarget::Symbolic(next)) => target = next,
+            _ => break,
+        }
+    }
+    println!("{}", if opt.short { shorten_ref_name(&target) } else { &target });
+    Ok(())
+}
@@ -0,0 +1,214 @@
+use structopt::StructOpt;
+use std::io::Read;
+use crate::errors::*;
+use crate::fs::FileSystem;
+use crate::model::id::Id;
+use crate::model::refs::{RefChange, RefTransaction, RefUpdate};
+use crate::model::repository::FileRepository;
+
+#[derive(Debug, StructOpt)]
+pub struct UpdateRefOpt {
+        #[structopt(short = "m", value_name = "reason")]
+        ///Use <reason> as the message of the reflog entry.
+        message: Option<String>,
+
+        #[structopt(short = "d")]
+        ///Delete the named reference after verifying it still contains <oldvalue>.
+        delete: bool,
+
+        #[structopt(long = "no-deref")]
+        ///Update the reference itself rather than the one it points at.
+        no_deref: bool,
+
+        #[structopt(long = "create-reflog")]
+        ///Create a reflog for the ref even if core.logAllRefUpdates would not.
+        create_reflog: bool,
+
+        #[structopt(long = "stdin")]
+        ///Read instructions from standard input and apply them as one transaction.
+        stdin: bool,
+
+        #[structopt(short = "z")]
+        ///With --stdin, read NUL-terminated instructions.
+        nul: bool,
+
+        #[structopt()]
+        reference: Option<String>,
+
+        #[structopt()]
+        newvalue: Option<String>,
+
+        #[structopt()]
+        oldvalue: Option<String>,
+}
+
+pub fn update_ref(opt: UpdateRefOpt) -> Result<()> {
+    let repo = FileRepository::open(".")?;
+    let message = opt.message.clone().unwrap_or_default();
+    if opt.stdin {
+        return update_from_stdin(&repo, &opt, &message);
+    }
+
+    let reference = opt.reference.as_ref()
+        .ok_or_else(|| Error::from("usage: git update-ref [<options>] <refname> <new-val> [<old-val>]"))?;
+    let (change, old) = if opt.delete {
+        (RefChange::Delete, opt.newvalue.as_ref())
+    } else {
+        let new = opt.newvalue.as_ref()
+            .ok_or_else(|| Error::from("usage: git update-ref [<options>] <refname> <new-val> [<old-val>]"))?;
+        (change_to(parse_value(&repo, new)?), opt.oldvalue.as_ref())
+    };
+    let mut update = RefUpdate::new(reference, change).message(&message);
+    if let Some(old) = old {
+        update = update.old(parse_value(&repo, old)?);
+    }
+    if opt.no_deref {
+        update = update.no_deref();
+    }
+    let mut tx = repo.transaction();
+    tx.create_reflog(opt.create_reflog).push(update);
+    tx.commit()
+}
+
+/// Applies `update`, `create`, `delete`, `verify` and `option` commands read
+/// from standard input. `start`, `prepare`, `commit` and `abort` delimit
+/// transactions and are acknowledged on standard output.
+fn update_from_stdin<FS: FileSystem>(repo: &FileRepository<FS>, opt: &UpdateRefOpt, message: &str) -> Result<()> {
+    let stdin = std::io::stdin();
+    let mut input = String::new();
+    stdin.lock().read_to_string(&mut input)?;
+    let mut commands = StdinCommands::new(&input, opt.nul);
+
+    let mut tx = new_transaction(repo, opt);
+    let mut no_deref = opt.no_deref;
+    while let Some((verb, args)) = commands.next_command()? {
+        if args.is_empty() && !["start", "prepare", "commit", "abort"].contains(&verb.as_str()) {
+            return Err(format!("{}: missing argument", verb).into());
+        }
+        let arg = |i: usize| args.get(i).map(String::as_str).filter(|s| !s.is_empty());
+        let update = match verb.as_str() {
+            "update" => {
+                let new = arg(1).ok_or_else(|| Error::from(format!("update {}: missing <new-oid>", args[0])))?;
+                let mut update = RefUpdate::new(&args[0], change_to(parse_value(repo, new)?));
+                if let Some(old) = arg(2) {
+                    update = update.old(parse_value(repo, old)?);
+                }
+                update
+            }
+            "create" => {
+                let new = arg(1).ok_or_else(|| Error::from(format!("create {}: missing <new-oid>", args[0])))?;
+                RefUpdate::new(&args[0], change_to(parse_value(repo, new)?)).old(repo.object_format().null_id())
+            }
+            "delete" => {
+                let mut update = RefUpdate::new(&args[0], RefChange::Delete);
+                if let Some(old) = arg(1) {
+                    update = update.old(parse_value(repo, old)?);
+                }
+                update
+            }
+            "verify" => {
+                let old = arg(1).map(|old| parse_value(repo, old)).transpose()?
+                    .unwrap_or_else(|| repo.object_format().null_id());
+                RefUpdate::new(&args[0], RefChange::Verify).old(old)
+            }
+            "option" => {
+                match args[0].as_str() {
+                    "no-deref" => no_deref = true,
+                    option => return Err(format!("option unknown: {}", option).into()),
+                }
+                continue;
+            }
+            "start" | "prepare" => {
+                println!("{}: ok", verb);
+                continue;
+            }
+            "commit" => {
+                std::mem::replace(&mut tx, new_transaction(repo, opt)).commit()?;
+                no_deref = opt.no_deref;
+                println!("commit: ok");
+                continue;
+            }
+            "abort" => {
+                tx = new_transaction(repo, opt);
+                no_deref = opt.no_deref;
+                println!("abort: ok");
+                continue;
+            }
+            _ => return Err(format!("unknown command: {}", verb).into()),
+        };
+        let mut update = update.message(message);
+        if no_deref {
+            update = update.no_deref();
+            no_deref = opt.no_deref;
+        }
+        tx.push(update);
+    }
+    if tx.is_empty() {
+        return Ok(());
+    }
+    tx.commit()
+}
+
+fn new_transaction<'r, FS: FileSystem>(repo: &'r FileRepository<FS>, opt: &UpdateRefOpt) -> RefTransaction<'r, FS> {
+    let mut tx = repo.transaction();
+    tx.create_reflog(opt.create_reflog);
+    tx
+}
+
+/// Splits `update-ref --stdin` input into commands and their arguments.
+/// Without `-z` arguments are separated by spaces, one command per line.
+/// With `-z` the verb and ref name are separated by a space and every other
+/// argument is terminated by NUL, an empty one meaning "not given".
+struct StdinCommands<'a> {
+    lines: Box<dyn Iterator<Item=&'a str> + 'a>,
+    nul: bool,
+}
+
+impl<'a> StdinCommands<'a> {
+    fn new(input: &'a str, nul: bool) -> Self {
+        let lines: Box<dyn Iterator<Item=&'a str>> = if nul {
+            Box::new(input.split('\0'))
+        } else {
+            Box::new(input.lines())
+        };
+        StdinCommands { lines, nul }
+    }
+
+    fn next_command(&mut self) -> Result<Option<(String, Vec<String>)>> {
+        let line = match self.lines.by_ref().find(|l| !l.trim().is_empty()) {
+            Some(line) => line,
+            None => return Ok(None),
+        };
+        let mut words = line.splitn(2, ' ');
+        let verb = words.next().unwrap_or_default().to_owned();
+        let rest = words.next().unwrap_or_default();
+        if !self.nul {
+            let args = rest.split(' ').filter(|s| !s.is_empty()).map(str::to_owned).collect();
+            return Ok(Some((verb, args)));
+        }
+        let extra = match verb.as_str() {
+            "update" => 2,
+            "create" | "delete" | "verify" => 1,
+            _ => 0,
+        };
+        let mut args = vec![rest.to_owned()];
+        for _ in 0..extra {
+            let arg = self.lines.next()
+                .ok_or_else(|| Error::from(format!("{} {}: unexpected end of input", verb, rest)))?;
+            args.push(arg.to_owned());
+        }
+        Ok(Some((verb, args)))
+    }
+}
+
+fn change_to(id: Id) -> RefChange {
+    if id.is_zero() {
+        RefChange::Delete
+    } else {
+        RefChange::Update(id)
+    }
+}
+
+fn parse_value<FS: FileSystem>(repo: &FileRepository<FS>, value: &str) -> Result<Id> {
+    repo.rev_parse(value).chain_err(|| format!("{}: not a valid SHA1", value))
+}
@@ -0,0 +1,88 @@
+use structopt::StructOpt;
+use std::io::Write;
+use crate::config::Config;
+use crate::errors::*;
+use crate::model::object::ObjectType;
+use crate::model::repository::{FileRepository, Repository};
+use crate::model::signature::{split_commit, verify_ssh, AllowedSigners, Signed, SignatureFormat, Verification};
+
+#[derive(Debug, StructOpt)]
+pub struct VerifyCommitOpt {
+        #[structopt(short = "v", long = "verbose")]
+        ///Print the contents of the commit object before validating it.
+        verbose: bool,
+
+        #[structopt(required = true)]
+        ///SHA-1 identifiers of Git commit objects.
+        commits: Vec<String>,
+}
+
+pub fn verify_commit(opt: VerifyCommitOpt) -> Result<()> {
+    let repo = FileRepository::open(".")?;
+    let config = repo.config()?;
+    let mut failed = false;
+    for spec in &opt.commits {
+        let obj = match repo.rev_parse(spec).and_then(|id| repo.find_object(&id)) {
+            Ok(obj) => obj,
+            Err(_) => {
+                eprintln!("error: commit '{}' not found.", spec);
+                failed = true;
+                continue;
+            }
+        };
+        if obj.object_type() != ObjectType::COMMIT {
+            eprintln!("error: {}: cannot verify a non-commit object of type {}.", spec, obj.object_type());
+            failed = true;
+            continue;
+        }
+        let content = repo.read_content(&obj)?;
+        // like git, an unsigned commit fails quietly
+        match split_commit(&content, repo.object_format()) {
+            Some(signed) => failed |= !check_signature(&config, &signed, opt.verbose)?,
+            None => failed = true,
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// Prints the payload when `verbose`, then checks its signature against
+/// `gpg.ssh.allowedSignersFile`, reporting on stderr the way git does.
+pub(crate) fn check_signature(config: &Config, signed: &Signed, verbose: bool) -> Result<bool> {
+    if verbose {
+        std::io::stdout().write_all(&signed.payload)?;
+    }
+    if signed.format != SignatureFormat::Ssh {
+        eprintln!("error: cannot verify {:?} signatures", signed.format);
+        return Ok(false);
+    }
+    let signers = match config.get_path("gpg.ssh.allowedSignersFile")? {
+        Some(path) => std::fs::read_to_string(path).ok(),
+        None => None,
+    };
+    let signers = match signers {
+        Some(signers) => AllowedSigners::parse(&signers)?,
+        None => {
+            eprintln!("error: gpg.ssh.allowedSignersFile needs to be configured and exist for ssh signature verification");
+            return Ok(false);
+        }
+    };
+    Ok(match verify_ssh(signed, &signers)? {
+        Verification::Good { principal, key } => {
+            eprintln!("Good \"git\" signature for {} with {}", principal, key);
+            true
+        }
+        Verification::Untrusted { key } => {
+            eprintln!("Good \"git\" signature with {}", key);
+            eprintln!("No principal matched.");
+            false
+        }
+        Verification::Bad { .. } => {
+            eprintln!("Could not verify signature.");
+            eprintln!("Signature verification failed: incorrect signature");
+            false
+        }
+    })
+}
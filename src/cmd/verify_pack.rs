@@ -0,0 +1,87 @@
+use structopt::StructOpt;
+use std::path::PathBuf;
+use crate::errors::*;
+use crate::fs::OsFs;
+use crate::fs::pack_file::{PackFile, PackStats};
+use crate::model::id::ObjectFormat;
+use crate::model::repository::FileRepository;
+
+#[derive(Debug, StructOpt)]
+pub struct VerifyPackOpt {
+        #[structopt(short = "v", long = "verbose")]
+        ///After verifying the pack, show the list of objects contained in the pack and a
+        /// histogram of delta chain length.
+        verbose: bool,
+
+        #[structopt(short = "s", long = "stat-only")]
+        ///Do not verify the pack contents; only show the histogram of delta chain length.
+        stat_only: bool,
+
+        #[structopt(parse(from_os_str), required = true)]
+        ///The idx files to verify.
+        packs: Vec<PathBuf>,
+}
+
+pub fn verify_pack(opt: VerifyPackOpt) -> Result<()> {
+    // outside a repository packs are assumed to be SHA-1
+    let format = FileRepository::open(".").map(|repo| repo.object_format())
+        .unwrap_or(ObjectFormat::Sha1);
+    let fs = OsFs::new("/");
+    let cwd = std::env::current_dir()?;
+    let mut failed = false;
+    for path in &opt.packs {
+        let pack_path = path.with_extension("pack");
+        let result = PackFile::open(&fs, &cwd.join(&pack_path), format)
+            .and_then(|pack| {
+                if !opt.stat_only {
+                    pack.verify()?;
+                }
+                pack.stats()
+            });
+        match result {
+            Ok(stats) => if opt.verbose || opt.stat_only {
+                show_pack_info(&stats, !opt.stat_only);
+                if !opt.stat_only {
+                    println!("{}: ok", pack_path.display());
+                }
+            },
+            Err(e) => {
+                failed = true;
+                match e.kind() {
+                    // these already name the pack
+                    ErrorKind::InvalidPackfile(..) | ErrorKind::CorruptPackEntry(..) | ErrorKind::ChecksumMismatch(_) =>
+                        eprintln!("error: {}", e),
+                    _ => eprintln!("error: {}: {}", pack_path.display(), e),
+                }
+                if opt.verbose || opt.stat_only {
+                    println!("{}: bad", pack_path.display());
+                }
+            }
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn show_pack_info(stats: &PackStats, list: bool) {
+    if list {
+        for entry in &stats.entries {
+            print!("{} {:<6} {} {} {}", entry.id, entry.object_type.as_str(), entry.size,
+                   entry.size_in_pack, entry.offset);
+            if let Some(base) = &entry.base {
+                print!(" {} {}", entry.depth, base);
+            }
+            println!();
+        }
+    }
+    for (length, count) in &stats.chain_lengths {
+        let objects = if *count == 1 { "object" } else { "objects" };
+        if *length == 0 {
+            println!("non delta: {} {}", count, objects);
+        } else {
+            println!("chain length = {}: {} {}", length, count, objects);
+        }
+    }
+}
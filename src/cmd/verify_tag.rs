@@ -0,0 +1,50 @@
+use structopt::StructOpt;
+use crate::cmd::verify_commit::check_signature;
+use crate::errors::*;
+use crate::model::object::ObjectType;
+use crate::model::repository::{FileRepository, Repository};
+use crate::model::signature::split_tag;
+
+#[derive(Debug, StructOpt)]
+pub struct VerifyTagOpt {
+        #[structopt(short = "v", long = "verbose")]
+        ///Print the contents of the tag object before validating it.
+        verbose: bool,
+
+        #[structopt(required = true)]
+        ///SHA-1 identifiers of Git tag objects.
+        tags: Vec<String>,
+}
+
+pub fn verify_tag(opt: VerifyTagOpt) -> Result<()> {
+    let repo = FileRepository::open(".")?;
+    let config = repo.config()?;
+    let mut failed = false;
+    for spec in &opt.tags {
+        let obj = match repo.rev_parse(spec).and_then(|id| repo.find_object(&id)) {
+            Ok(obj) => obj,
+            Err(_) => {
+                eprintln!("error: tag '{}' not found.", spec);
+                failed = true;
+                continue;
+            }
+        };
+        if obj.object_type() != ObjectType::TAG {
+            eprintln!("error: {}: cannot verify a non-tag object of type {}.", spec, obj.object_type());
+            failed = true;
+            continue;
+        }
+        let content = repo.read_content(&obj)?;
+        match split_tag(&content) {
+            Some(signed) => failed |= !check_signature(&config, &signed, opt.verbose)?,
+            None => {
+                eprintln!("error: no signature found");
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -0,0 +1,540 @@
+use crate::errors::*;
+use std::fmt::{Display, Formatter};
+
+/// A config key split into its parts. Section and variable names are
+/// case-insensitive and stored lowercased, the subsection is kept as is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Key {
+    pub section: String,
+    pub subsection: Option<String>,
+    pub name: String,
+}
+
+impl Key {
+    pub fn parse(key: &str) -> Result<Key> {
+        let first = key.find('.');
+        let last = key.rfind('.');
+        match (first, last) {
+            (Some(first), Some(last)) => {
+                let section = &key[..first];
+                let name = &key[last + 1..];
+                let subsection = if first < last {
+                    Some(key[first + 1..last].to_owned())
+                } else {
+                    None
+                };
+                if !is_valid_section(section) || !is_valid_name(name) {
+                    return Err(ErrorKind::InvalidConfigKey(key.to_owned()).into());
+                }
+                Ok(Key {
+                    section: section.to_lowercase(),
+                    subsection,
+                    name: name.to_lowercase(),
+                })
+            }
+            _ => Err(ErrorKind::InvalidConfigKey(key.to_owned()).into())
+        }
+    }
+
+    fn matches(&self, section: &str, subsection: &Option<String>) -> bool {
+        self.section == section && &self.subsection == subsection
+    }
+}
+
+impl Display for Key {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match &self.subsection {
+            Some(sub) => write!(f, "{}.{}.{}", self.section, sub, self.name),
+            None => write!(f, "{}.{}", self.section, self.name),
+        }
+    }
+}
+
+fn is_valid_section(s: &str) -> bool {
+    !s.is_empty() && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+}
+
+fn is_valid_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() => chars.all(|c| c.is_ascii_alphanumeric() || c == '-'),
+        _ => false,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Line {
+    Section {
+        raw: String,
+        section: String,
+        subsection: Option<String>,
+    },
+    Entry {
+        raw: String,
+        key: Key,
+        value: Option<String>,
+        line: usize,
+    },
+    Other(String),
+}
+
+impl Line {
+    fn raw(&self) -> &str {
+        match self {
+            Line::Section { raw, .. } => raw,
+            Line::Entry { raw, .. } => raw,
+            Line::Other(raw) => raw,
+        }
+    }
+}
+
+/// A single parsed config file. Every byte of the input is kept, so that
+/// writing the file back after an edit only touches the edited lines.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ConfigFile {
+    lines: Vec<Line>,
+}
+
+/// A variable as read from a file: key, value (`None` for `[core] bare`
+/// style entries without `=`) and the line it was found on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawEntry<'a> {
+    pub key: &'a Key,
+    pub value: Option<&'a str>,
+    pub line: usize,
+}
+
+impl ConfigFile {
+    pub fn parse(input: &str) -> Result<ConfigFile> {
+        Parser { input: input.as_bytes(), pos: 0, line: 1 }.parse()
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item=RawEntry<'_>> {
+        self.lines.iter().filter_map(|l| match l {
+            Line::Entry { key, value, line, .. } => Some(RawEntry {
+                key,
+                value: value.as_ref().map(|v| v.as_str()),
+                line: *line,
+            }),
+            _ => None
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<Option<&str>>> {
+        let key = Key::parse(key)?;
+        Ok(self.entries().filter(|e| e.key == &key).last().map(|e| e.value))
+    }
+
+    /// Sets `key` to `value`, replacing the last existing occurrence or
+    /// adding it to the end of its section.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        let key = Key::parse(key)?;
+        let pos = self.lines.iter().rposition(|l| match l {
+            Line::Entry { key: k, .. } => k == &key,
+            _ => false
+        });
+        if let Some(pos) = pos {
+            let indent: String = self.lines[pos].raw().chars()
+                .take_while(|c| *c == ' ' || *c == '\t')
+                .collect();
+            let line = match &self.lines[pos] {
+                Line::Entry { line, .. } => *line,
+                _ => 0,
+            };
+            self.lines[pos] = Line::Entry {
+                raw: format!("{}{} = {}\n", indent, key.name, quote_value(value)),
+                key,
+                value: Some(value.to_owned()),
+                line,
+            };
+            Ok(())
+        } else {
+            self.add(&key.to_string(), value)
+        }
+    }
+
+    /// Adds another value for `key`, keeping existing ones.
+    pub fn add(&mut self, key: &str, value: &str) -> Result<()> {
+        let key = Key::parse(key)?;
+        let raw = format!("\t{} = {}\n", key.name, quote_value(value));
+        let mut insert_at = None;
+        let mut in_section = false;
+        for (i, l) in self.lines.iter().enumerate() {
+            match l {
+                Line::Section { section, subsection, .. } => {
+                    in_section = key.matches(section, subsection);
+                    if in_section {
+                        insert_at = Some(i + 1);
+                    }
+                }
+                Line::Entry { .. } if in_section => insert_at = Some(i + 1),
+                _ => {}
+            }
+        }
+        let entry = Line::Entry { raw, key: key.clone(), value: Some(value.to_owned()), line: 0 };
+        match insert_at {
+            Some(i) => self.lines.insert(i, entry),
+            None => {
+                if let Some(last) = self.lines.last_mut() {
+                    let raw = match last {
+                        Line::Section { raw, .. } => raw,
+                        Line::Entry { raw, .. } => raw,
+                        Line::Other(raw) => raw,
+                    };
+                    if !raw.ends_with('\n') {
+                        raw.push('\n');
+                    }
+                }
+                let header = match &key.subsection {
+                    Some(sub) => format!("[{} \"{}\"]\n", key.section,
+                                         sub.replace('\\', "\\\\").replace('"', "\\\"")),
+                    None => format!("[{}]\n", key.section),
+                };
+                self.lines.push(Line::Section {
+                    raw: header,
+                    section: key.section.clone(),
+                    subsection: key.subsection.clone(),
+                });
+                self.lines.push(entry);
+            }
+        }
+        Ok(())
+    }
+
+    /// Removes every occurrence of `key`, returns how many were removed.
+    pub fn unset_all(&mut self, key: &str) -> Result<usize> {
+        let key = Key::parse(key)?;
+        let before = self.lines.len();
+        self.lines.retain(|l| match l {
+            Line::Entry { key: k, .. } => k != &key,
+            _ => true
+        });
+        Ok(before - self.lines.len())
+    }
+}
+
+impl Display for ConfigFile {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        for line in &self.lines {
+            f.write_str(line.raw())?;
+        }
+        Ok(())
+    }
+}
+
+fn quote_value(value: &str) -> String {
+    let needs_quotes = value.starts_with(' ') || value.ends_with(' ') ||
+        value.contains('#') || value.contains(';');
+    let mut out = String::with_capacity(value.len() + 2);
+    if needs_quotes {
+        out.push('"');
+    }
+    for c in value.chars() {
+        match c {
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\u{8}' => out.push_str("\\b"),
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            c => out.push(c),
+        }
+    }
+    if needs_quotes {
+        out.push('"');
+    }
+    out
+}
+
+struct Parser<'a> {
+    input: &'a [u8],
+    pos: usize,
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn parse(mut self) -> Result<ConfigFile> {
+        let mut lines = vec![];
+        let mut section: Option<(String, Option<String>)> = None;
+        while self.pos < self.input.len() {
+            let start = self.pos;
+            self.skip_blank();
+            match self.peek() {
+                None | Some(b'\n') | Some(b'#') | Some(b';') => {
+                    self.skip_line();
+                    lines.push(Line::Other(self.raw_from(start)));
+                }
+                Some(b'[') => {
+                    let (name, sub) = self.parse_section_header()?;
+                    lines.push(Line::Section {
+                        raw: self.raw_from(start),
+                        section: name.clone(),
+                        subsection: sub.clone(),
+                    });
+                    section = Some((name, sub));
+                    // git allows a variable after the header on the same line
+                    let rest = self.pos;
+                    self.skip_blank();
+                    match self.peek() {
+                        None | Some(b'\n') | Some(b'#') | Some(b';') => {
+                            self.skip_line();
+                            if let Some(Line::Section { raw, .. }) = lines.last_mut() {
+                                raw.push_str(&self.raw_from(rest));
+                            }
+                        }
+                        _ => {
+                            self.pos = rest;
+                        }
+                    }
+                }
+                Some(c) if c.is_ascii_alphabetic() => {
+                    let line = self.line;
+                    let (section, subsection) = match &section {
+                        Some(s) => s.clone(),
+                        None => return Err(self.error()),
+                    };
+                    let name = self.parse_name();
+                    let value = self.parse_value()?;
+                    lines.push(Line::Entry {
+                        raw: self.raw_from(start),
+                        key: Key { section, subsection, name },
+                        value,
+                        line,
+                    });
+                }
+                _ => return Err(self.error()),
+            }
+        }
+        Ok(ConfigFile { lines })
+    }
+
+    fn error(&self) -> Error {
+        ErrorKind::InvalidConfig(self.line).into()
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).cloned()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let c = self.peek();
+        if let Some(c) = c {
+            self.pos += 1;
+            if c == b'\n' {
+                self.line += 1;
+            }
+        }
+        c
+    }
+
+    fn raw_from(&self, start: usize) -> String {
+        String::from_utf8_lossy(&self.input[start..self.pos]).into_owned()
+    }
+
+    fn skip_blank(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == b' ' || c == b'\t' || c == b'\r' {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn skip_line(&mut self) {
+        while let Some(c) = self.next() {
+            if c == b'\n' {
+                break;
+            }
+        }
+    }
+
+    fn parse_section_header(&mut self) -> Result<(String, Option<String>)> {
+        self.next();
+        let mut name = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_ascii_alphanumeric() || c == b'-' || c == b'.' {
+                name.push(c.to_ascii_lowercase() as char);
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        if name.is_empty() {
+            return Err(self.error());
+        }
+        match self.next() {
+            Some(b']') => {
+                // deprecated [section.subsection] syntax
+                if let Some(dot) = name.find('.') {
+                    let sub = name[dot + 1..].to_owned();
+                    name.truncate(dot);
+                    Ok((name, Some(sub)))
+                } else {
+                    Ok((name, None))
+                }
+            }
+            Some(b' ') | Some(b'\t') => {
+                self.skip_blank();
+                if self.next() != Some(b'"') {
+                    return Err(self.error());
+                }
+                let mut sub = vec![];
+                loop {
+                    match self.next() {
+                        Some(b'"') => break,
+                        Some(b'\\') => match self.next() {
+                            Some(b'\n') | None => return Err(self.error()),
+                            Some(c) => sub.push(c),
+                        },
+                        Some(b'\n') | None => return Err(self.error()),
+                        Some(c) => sub.push(c),
+                    }
+                }
+                if self.next() != Some(b']') {
+                    return Err(self.error());
+                }
+                Ok((name, Some(String::from_utf8_lossy(&sub).into_owned())))
+            }
+            _ => Err(self.error())
+        }
+    }
+
+    fn parse_name(&mut self) -> String {
+        let mut name = String::new();
+        while let Some(c) = self.peek() {
+            if c.is_ascii_alphanumeric() || c == b'-' {
+                name.push(c.to_ascii_lowercase() as char);
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        name
+    }
+
+    fn parse_value(&mut self) -> Result<Option<String>> {
+        self.skip_blank();
+        match self.peek() {
+            None | Some(b'\n') | Some(b'#') | Some(b';') => {
+                self.skip_line();
+                return Ok(None);
+            }
+            Some(b'=') => {
+                self.next();
+            }
+            _ => return Err(self.error()),
+        }
+        self.skip_blank();
+        let mut value: Vec<u8> = vec![];
+        // length of value without trailing unquoted whitespace
+        let mut trimmed_len = 0;
+        let mut quoted = false;
+        loop {
+            let c = match self.next() {
+                None | Some(b'\n') => {
+                    if quoted {
+                        return Err(self.error());
+                    }
+                    break;
+                }
+                Some(c) => c,
+            };
+            match c {
+                b'#' | b';' if !quoted => {
+                    self.skip_line();
+                    break;
+                }
+                b'"' => {
+                    quoted = !quoted;
+                    trimmed_len = value.len();
+                }
+                b'\\' => {
+                    let escaped = match self.next() {
+                        Some(b'\n') => continue,
+                        Some(b'\r') if self.peek() == Some(b'\n') => {
+                            self.next();
+                            continue;
+                        }
+                        Some(b'n') => b'\n',
+                        Some(b't') => b'\t',
+                        Some(b'b') => 8u8,
+                        Some(b'\\') => b'\\',
+                        Some(b'"') => b'"',
+                        _ => return Err(self.error()),
+                    };
+                    value.push(escaped);
+                    trimmed_len = value.len();
+                }
+                b' ' | b'\t' | b'\r' if !quoted => {
+                    value.push(c);
+                }
+                c => {
+                    value.push(c);
+                    trimmed_len = value.len();
+                }
+            }
+        }
+        value.truncate(trimmed_len);
+        Ok(Some(String::from_utf8_lossy(&value).into_owned()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "# top comment
+[core]
+\trepositoryformatversion = 0
+\tbare ; no value means true
+[remote \"origin\"]
+\turl = https://example.com/a.git
+\tfetch = +refs/heads/*:refs/remotes/origin/*
+\tfetch = +refs/tags/*:refs/tags/*
+[Alias] lg = \"log --graph \\
+  --oneline\" # comment
+[branch.Main]
+\tremote = \" origin \"
+";
+
+    #[test]
+    fn test_parse_config() {
+        let file = ConfigFile::parse(SAMPLE).expect("parse failed");
+        let entries: Vec<(String, Option<&str>)> = file.entries()
+            .map(|e| (e.key.to_string(), e.value))
+            .collect();
+        assert_eq!(entries, vec![
+            ("core.repositoryformatversion".to_owned(), Some("0")),
+            ("core.bare".to_owned(), None),
+            ("remote.origin.url".to_owned(), Some("https://example.com/a.git")),
+            ("remote.origin.fetch".to_owned(), Some("+refs/heads/*:refs/remotes/origin/*")),
+            ("remote.origin.fetch".to_owned(), Some("+refs/tags/*:refs/tags/*")),
+            ("alias.lg".to_owned(), Some("log --graph   --oneline")),
+            ("branch.main.remote".to_owned(), Some(" origin ")),
+        ]);
+        assert_eq!(file.to_string(), SAMPLE);
+    }
+
+    #[test]
+    fn test_set_preserves_formatting() {
+        let mut file = ConfigFile::parse(SAMPLE).unwrap();
+        file.set("core.repositoryFormatVersion", "1").unwrap();
+        file.set("remote.origin.pushurl", "a#b").unwrap();
+        file.set("user.name", "space dragon").unwrap();
+        let text = file.to_string();
+        assert!(text.starts_with("# top comment\n[core]\n\trepositoryformatversion = 1\n\tbare ;"));
+        assert!(text.contains("+refs/tags/*:refs/tags/*\n\tpushurl = \"a#b\"\n[Alias]"));
+        assert!(text.ends_with("\" origin \"\n[user]\n\tname = space dragon\n"));
+        let reparsed = ConfigFile::parse(&text).unwrap();
+        assert_eq!(reparsed.get("remote.origin.pushurl").unwrap(), Some(Some("a#b")));
+    }
+
+    #[test]
+    fn test_parse_error_line() {
+        let err = ConfigFile::parse("[core]\n\tbare = true\n\t=oops\n").unwrap_err();
+        match err.kind() {
+            ErrorKind::InvalidConfig(line) => assert_eq!(*line, 3),
+            _ => panic!("unexpected error {}", err),
+        }
+    }
+}
@@ -0,0 +1,369 @@
+use std::path::{Path, PathBuf};
+use std::fmt::{Display, Formatter};
+use std::io::Read;
+use crate::errors::*;
+use crate::fs::FileSystem;
+use crate::wildmatch::wildmatch;
+
+pub mod file;
+pub mod value;
+
+pub use self::file::{ConfigFile, Key};
+
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ConfigScope {
+    System,
+    Global,
+    Local,
+    Worktree,
+    Env,
+}
+
+impl Display for ConfigScope {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            ConfigScope::System => "system",
+            ConfigScope::Global => "global",
+            ConfigScope::Local => "local",
+            ConfigScope::Worktree => "worktree",
+            ConfigScope::Env => "command",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigOrigin {
+    File(PathBuf),
+    CommandLine,
+}
+
+impl Display for ConfigOrigin {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            ConfigOrigin::File(path) => write!(f, "file:{}", path.display()),
+            ConfigOrigin::CommandLine => write!(f, "command line:"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigEntry {
+    pub key: Key,
+    pub value: Option<String>,
+    pub scope: ConfigScope,
+    pub origin: ConfigOrigin,
+}
+
+/// What `includeIf` conditions are evaluated against.
+#[derive(Debug, Clone, Default)]
+pub struct IncludeContext {
+    pub git_dir: Option<PathBuf>,
+    pub branch: Option<String>,
+}
+
+/// The merged view of every config file, in increasing order of priority.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    entries: Vec<ConfigEntry>,
+}
+
+impl Config {
+    /// Reads system, global, local, worktree and environment configuration,
+    /// honouring `GIT_CONFIG_NOSYSTEM`, `GIT_CONFIG_SYSTEM`,
+    /// `GIT_CONFIG_GLOBAL` and `GIT_CONFIG_COUNT`.
+    pub fn open<FS: FileSystem>(fs: &FS, git_dir: Option<&Path>) -> Result<Config> {
+        Config::open_layers(fs, git_dir.map(|d| (d, d)))
+    }
+
+    /// Like `open`, for a linked worktree whose shared `config` lives in
+    /// `common_dir` while `config.worktree` lives in `git_dir`.
+    pub fn open_repository<FS: FileSystem>(fs: &FS, git_dir: &Path, common_dir: &Path) -> Result<Config> {
+        Config::open_layers(fs, Some((git_dir, common_dir)))
+    }
+
+    fn open_layers<FS: FileSystem>(fs: &FS, dirs: Option<(&Path, &Path)>) -> Result<Config> {
+        let mut config = Config::default();
+        let ctx = IncludeContext {
+            git_dir: dirs.map(|(git_dir, _)| git_dir.to_path_buf()),
+            branch: dirs.and_then(|(git_dir, _)| current_branch(fs, git_dir)),
+        };
+        if std::env::var_os("GIT_CONFIG_NOSYSTEM").is_none() {
+            let system = std::env::var_os("GIT_CONFIG_SYSTEM")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| PathBuf::from("/etc/gitconfig"));
+            config.load_file(fs, &system, ConfigScope::System, &ctx)?;
+        }
+        for global in global_config_paths() {
+            config.load_file(fs, &global, ConfigScope::Global, &ctx)?;
+        }
+        if let Some((git_dir, common_dir)) = dirs {
+            config.load_file(fs, &common_dir.join("config"), ConfigScope::Local, &ctx)?;
+            if config.get_bool("extensions.worktreeConfig")?.unwrap_or(false) {
+                config.load_file(fs, &git_dir.join("config.worktree"), ConfigScope::Worktree, &ctx)?;
+            }
+        }
+        config.load_env()?;
+        Ok(config)
+    }
+
+    /// Adds the entries of `path`, following includes. Missing files are
+    /// silently ignored.
+    pub fn load_file<FS: FileSystem>(&mut self, fs: &FS, path: &Path, scope: ConfigScope,
+                                     ctx: &IncludeContext) -> Result<()> {
+        self.load_file_with_depth(fs, path, scope, ctx, 0)
+    }
+
+    fn load_file_with_depth<FS: FileSystem>(&mut self, fs: &FS, path: &Path, scope: ConfigScope,
+                                            ctx: &IncludeContext, depth: usize) -> Result<()> {
+        if depth > MAX_INCLUDE_DEPTH {
+            return Err(ErrorKind::IncludeDepthExceeded(path.to_path_buf()).into());
+        }
+        let text = match read_to_string(fs, path) {
+            Some(text) => text,
+            None => return Ok(()),
+        };
+        let file = ConfigFile::parse(&text)
+            .chain_err(|| format!("bad config file {}", path.display()))?;
+        for entry in file.entries() {
+            self.entries.push(ConfigEntry {
+                key: entry.key.clone(),
+                value: entry.value.map(|v| v.to_owned()),
+                scope,
+                origin: ConfigOrigin::File(path.to_path_buf()),
+            });
+            if entry.key.name != "path" {
+                continue;
+            }
+            let include = match (entry.key.section.as_str(), &entry.key.subsection, entry.value) {
+                ("include", None, Some(value)) => Some(value),
+                ("includeif", Some(condition), Some(value))
+                    if include_condition_matches(condition, path, ctx) => Some(value),
+                _ => None,
+            };
+            if let Some(value) = include {
+                let included = resolve_include(path, &entry.key.to_string(), value)?;
+                self.load_file_with_depth(fs, &included, scope, ctx, depth + 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds `GIT_CONFIG_KEY_<n>`/`GIT_CONFIG_VALUE_<n>` pairs.
+    pub fn load_env(&mut self) -> Result<()> {
+        let count = match std::env::var("GIT_CONFIG_COUNT") {
+            Ok(count) => value::parse_int("GIT_CONFIG_COUNT", &count)?,
+            Err(_) => return Ok(()),
+        };
+        for i in 0..count {
+            let key = std::env::var(format!("GIT_CONFIG_KEY_{}", i))
+                .chain_err(|| format!("missing config key GIT_CONFIG_KEY_{}", i))?;
+            let value = std::env::var(format!("GIT_CONFIG_VALUE_{}", i))
+                .chain_err(|| format!("missing config value GIT_CONFIG_VALUE_{}", i))?;
+            self.push_env(&key, &value)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn push_env(&mut self, key: &str, value: &str) -> Result<()> {
+        self.entries.push(ConfigEntry {
+            key: Key::parse(key)?,
+            value: Some(value.to_owned()),
+            scope: ConfigScope::Env,
+            origin: ConfigOrigin::CommandLine,
+        });
+        Ok(())
+    }
+
+    pub fn entries(&self) -> &[ConfigEntry] {
+        self.entries.as_slice()
+    }
+
+    /// Returns the raw value of the last occurrence of `key`. A variable
+    /// present without a value is returned as `Some(None)`.
+    pub fn get_raw(&self, key: &str) -> Result<Option<Option<&str>>> {
+        let key = Key::parse(key)?;
+        Ok(self.entries.iter().rev()
+            .find(|e| e.key == key)
+            .map(|e| e.value.as_deref()))
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<&str>> {
+        Ok(self.get_raw(key)?.map(|v| v.unwrap_or("")))
+    }
+
+    pub fn get_all(&self, key: &str) -> Result<Vec<&ConfigEntry>> {
+        let key = Key::parse(key)?;
+        Ok(self.entries.iter().filter(|e| e.key == key).collect())
+    }
+
+    pub fn get_bool(&self, key: &str) -> Result<Option<bool>> {
+        match self.get_raw(key)? {
+            Some(v) => value::parse_bool(key, v).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_int(&self, key: &str) -> Result<Option<i64>> {
+        match self.get(key)? {
+            Some(v) => value::parse_int(key, v).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_path(&self, key: &str) -> Result<Option<PathBuf>> {
+        match self.get(key)? {
+            Some(v) => value::parse_path(key, v).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub fn get_color(&self, key: &str) -> Result<Option<String>> {
+        match self.get(key)? {
+            Some(v) => value::parse_color(key, v).map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+/// The global config files, `$XDG_CONFIG_HOME/git/config` then
+/// `~/.gitconfig`, unless `GIT_CONFIG_GLOBAL` is set.
+pub fn global_config_paths() -> Vec<PathBuf> {
+    if let Some(global) = std::env::var_os("GIT_CONFIG_GLOBAL") {
+        return vec![PathBuf::from(global)];
+    }
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let xdg = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| home.as_ref().map(|h| h.join(".config")));
+    let mut paths = vec![];
+    if let Some(xdg) = xdg {
+        paths.push(xdg.join("git").join("config"));
+    }
+    if let Some(home) = home {
+        paths.push(home.join(".gitconfig"));
+    }
+    paths
+}
+
+fn read_to_string<FS: FileSystem>(fs: &FS, path: &Path) -> Option<String> {
+    let mut reader = fs.read_file(path).ok()?;
+    let mut buf = vec![];
+    reader.read_to_end(&mut buf).ok()?;
+    Some(String::from_utf8_lossy(&buf).into_owned())
+}
+
+fn current_branch<FS: FileSystem>(fs: &FS, git_dir: &Path) -> Option<String> {
+    let head = read_to_string(fs, &git_dir.join("HEAD"))?;
+    head.trim().strip_prefix("ref: refs/heads/").map(|b| b.to_owned())
+}
+
+fn resolve_include(including: &Path, key: &str, value: &str) -> Result<PathBuf> {
+    let path = value::parse_path(key, value)?;
+    if path.is_absolute() {
+        Ok(path)
+    } else {
+        Ok(including.parent().unwrap_or_else(|| Path::new("")).join(path))
+    }
+}
+
+fn include_condition_matches(condition: &str, including: &Path, ctx: &IncludeContext) -> bool {
+    let (kind, pattern) = match condition.find(':') {
+        Some(i) => (&condition[..i], &condition[i + 1..]),
+        None => return false,
+    };
+    match kind {
+        "gitdir" | "gitdir/i" => {
+            let git_dir = match &ctx.git_dir {
+                Some(dir) => dir.to_string_lossy().into_owned(),
+                None => return false,
+            };
+            let mut pattern = if let Some(relative) = pattern.strip_prefix("./") {
+                let base = including.parent().unwrap_or_else(|| Path::new(""));
+                base.join(relative).to_string_lossy().into_owned()
+            } else if pattern.starts_with('~') {
+                match value::parse_path("includeif", pattern) {
+                    Ok(p) => p.to_string_lossy().into_owned(),
+                    Err(_) => return false,
+                }
+            } else if pattern.starts_with('/') {
+                pattern.to_owned()
+            } else {
+                format!("**/{}", pattern)
+            };
+            if pattern.ends_with('/') {
+                pattern.push_str("**");
+            }
+            let git_dir = git_dir.trim_end_matches('/');
+            wildmatch(&pattern, git_dir, true, kind == "gitdir/i") ||
+                wildmatch(&pattern, &format!("{}/", git_dir), true, kind == "gitdir/i")
+        }
+        "onbranch" => {
+            let branch = match &ctx.branch {
+                Some(branch) => branch,
+                None => return false,
+            };
+            let mut pattern = pattern.to_owned();
+            if pattern.ends_with('/') {
+                pattern.push_str("**");
+            }
+            wildmatch(&pattern, branch, true, false)
+        }
+        _ => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::MemFs;
+
+    #[test]
+    fn test_layered_config() {
+        let mut fs = MemFs::default();
+        fs.add_file("/home/me/.gitconfig", b"[user]\n\tname = Global\n\temail = g@example.com\n\
+            [include]\n\tpath = extra.inc\n\
+            [includeIf \"gitdir:work/\"]\n\tpath = /home/me/work.inc\n\
+            [includeIf \"onbranch:release/**\"]\n\tpath = /home/me/release.inc\n".to_vec());
+        fs.add_file("/home/me/extra.inc", b"[core]\n\tbigFileThreshold = 2m\n".to_vec());
+        fs.add_file("/home/me/work.inc", b"[user]\n\temail = me@work.example\n".to_vec());
+        fs.add_file("/home/me/release.inc", b"[color]\n\tui = bold red\n".to_vec());
+        fs.add_file("/work/repo/.git/HEAD", b"ref: refs/heads/release/1.0\n".to_vec());
+        fs.add_file("/work/repo/.git/config", b"[user]\n\tname = Local\n\
+            [remote \"origin\"]\n\tfetch = a\n\tfetch = b\n[core]\n\tbare\n".to_vec());
+
+        let git_dir = Path::new("/work/repo/.git");
+        let ctx = IncludeContext {
+            git_dir: Some(git_dir.to_path_buf()),
+            branch: current_branch(&fs, git_dir),
+        };
+        let mut config = Config::default();
+        config.load_file(&fs, Path::new("/home/me/.gitconfig"), ConfigScope::Global, &ctx).unwrap();
+        config.load_file(&fs, &git_dir.join("config"), ConfigScope::Local, &ctx).unwrap();
+        config.push_env("user.name", "Env").unwrap();
+
+        assert_eq!(config.get("user.name").unwrap(), Some("Env"));
+        assert_eq!(config.get("user.email").unwrap(), Some("me@work.example"));
+        assert_eq!(config.get_int("core.bigfilethreshold").unwrap(), Some(2 * 1024 * 1024));
+        assert_eq!(config.get_bool("core.bare").unwrap(), Some(true));
+        assert_eq!(config.get_color("color.ui").unwrap(), Some("\x1b[1;31m".to_owned()));
+        let fetch: Vec<&str> = config.get_all("remote.origin.fetch").unwrap().iter()
+            .filter_map(|e| e.value.as_deref())
+            .collect();
+        assert_eq!(fetch, vec!["a", "b"]);
+        let origin = &config.get_all("core.bigFileThreshold").unwrap()[0].origin;
+        assert_eq!(origin, &ConfigOrigin::File(PathBuf::from("/home/me/extra.inc")));
+        assert_eq!(config.get("user.missing").unwrap(), None);
+    }
+
+    #[test]
+    fn test_include_loop() {
+        let mut fs = MemFs::default();
+        fs.add_file("/a", b"[include]\n\tpath = /a\n".to_vec());
+        let mut config = Config::default();
+        let result = config.load_file(&fs, Path::new("/a"), ConfigScope::Local,
+                                      &IncludeContext::default());
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,167 @@
+use crate::errors::*;
+use std::path::PathBuf;
+
+fn invalid(key: &str, value: &str) -> Error {
+    ErrorKind::InvalidConfigValue(key.to_owned(), value.to_owned()).into()
+}
+
+/// Interprets a value the way `git config --type=bool` does. A variable
+/// without `=` is true, an empty value is false.
+pub fn parse_bool(key: &str, value: Option<&str>) -> Result<bool> {
+    let value = match value {
+        None => return Ok(true),
+        Some(v) => v,
+    };
+    match value.to_lowercase().as_str() {
+        "true" | "yes" | "on" => Ok(true),
+        "false" | "no" | "off" | "" => Ok(false),
+        _ => parse_int(key, value).map(|i| i != 0).map_err(|_| invalid(key, value)),
+    }
+}
+
+/// Parses an integer with an optional `k`, `m` or `g` suffix.
+pub fn parse_int(key: &str, value: &str) -> Result<i64> {
+    let trimmed = value.trim();
+    let (digits, factor) = match trimmed.chars().last() {
+        Some('k') | Some('K') => (&trimmed[..trimmed.len() - 1], 1024i64),
+        Some('m') | Some('M') => (&trimmed[..trimmed.len() - 1], 1024 * 1024),
+        Some('g') | Some('G') => (&trimmed[..trimmed.len() - 1], 1024 * 1024 * 1024),
+        _ => (trimmed, 1),
+    };
+    digits.parse::<i64>().ok()
+        .and_then(|i| i.checked_mul(factor))
+        .ok_or_else(|| invalid(key, value))
+}
+
+/// Expands a leading `~/` to the home directory.
+pub fn parse_path(key: &str, value: &str) -> Result<PathBuf> {
+    if value == "~" || value.starts_with("~/") {
+        let home = std::env::var_os("HOME").ok_or_else(|| invalid(key, value))?;
+        let mut path = PathBuf::from(home);
+        if value.len() > 2 {
+            path.push(&value[2..]);
+        }
+        Ok(path)
+    } else if value.starts_with('~') {
+        Err(invalid(key, value))
+    } else if value.starts_with("%(prefix)/") {
+        Ok(PathBuf::from(&value["%(prefix)".len()..]))
+    } else {
+        Ok(PathBuf::from(value))
+    }
+}
+
+const COLORS: [&str; 8] = ["black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"];
+const ATTRIBUTES: [(&str, u8); 7] = [
+    ("bold", 1), ("dim", 2), ("italic", 3), ("ul", 4), ("blink", 5), ("reverse", 7), ("strike", 9)
+];
+
+/// Converts a color description such as `bold red` or `#ff0000 ul` into an
+/// ANSI escape sequence. An empty description yields an empty string.
+pub fn parse_color(key: &str, value: &str) -> Result<String> {
+    if value.trim().eq_ignore_ascii_case("reset") {
+        return Ok("\x1b[m".to_owned());
+    }
+    let mut attributes: Vec<String> = vec![];
+    let mut colors: Vec<Option<String>> = vec![];
+    for word in value.split_whitespace() {
+        let word = word.to_lowercase();
+        if let Some(code) = parse_color_word(&word, colors.is_empty()) {
+            if colors.len() == 2 {
+                return Err(invalid(key, value));
+            }
+            colors.push(code);
+            continue;
+        }
+        let (negate, attr) = match word.strip_prefix("no-").or_else(|| word.strip_prefix("no")) {
+            Some(attr) => (true, attr),
+            None => (false, word.as_str()),
+        };
+        let attr = if attr == "underline" { "ul" } else { attr };
+        match ATTRIBUTES.iter().find(|(name, _)| *name == attr) {
+            Some((_, code)) => {
+                let code = match (negate, code) {
+                    (false, code) => *code,
+                    (true, 1) => 22,
+                    (true, code) => 20 + code,
+                };
+                attributes.push(code.to_string());
+            }
+            None => return Err(invalid(key, value)),
+        }
+    }
+    let codes: Vec<String> = attributes.into_iter()
+        .chain(colors.into_iter().flatten())
+        .collect();
+    if codes.is_empty() {
+        Ok(String::new())
+    } else {
+        Ok(format!("\x1b[{}m", codes.join(";")))
+    }
+}
+
+/// Returns `Some(None)` for colors that emit no code (`normal`), `None` if
+/// the word is not a color at all.
+fn parse_color_word(word: &str, foreground: bool) -> Option<Option<String>> {
+    let base = if foreground { 30 } else { 40 };
+    match word {
+        "normal" => return Some(None),
+        "default" => return Some(Some((base + 9).to_string())),
+        _ => {}
+    }
+    if let Some(i) = COLORS.iter().position(|c| *c == word) {
+        return Some(Some((base + i).to_string()));
+    }
+    if word.starts_with("bright") {
+        if let Some(i) = COLORS.iter().position(|c| *c == &word[6..]) {
+            return Some(Some((base + 60 + i).to_string()));
+        }
+    }
+    if word.starts_with('#') && word.len() == 7 {
+        let rgb = u32::from_str_radix(&word[1..], 16).ok()?;
+        return Some(Some(format!("{};2;{};{};{}", base + 8,
+                                 rgb >> 16, (rgb >> 8) & 0xff, rgb & 0xff)));
+    }
+    if let Ok(n) = word.parse::<u8>() {
+        return Some(Some(format!("{};5;{}", base + 8, n)));
+    }
+    if word == "-1" {
+        return Some(None);
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bool() {
+        assert!(parse_bool("a.b", None).unwrap());
+        assert!(!parse_bool("a.b", Some("")).unwrap());
+        assert!(parse_bool("a.b", Some("Yes")).unwrap());
+        assert!(!parse_bool("a.b", Some("off")).unwrap());
+        assert!(parse_bool("a.b", Some("2")).unwrap());
+        assert!(parse_bool("a.b", Some("maybe")).is_err());
+    }
+
+    #[test]
+    fn test_parse_int() {
+        assert_eq!(parse_int("a.b", "12").unwrap(), 12);
+        assert_eq!(parse_int("a.b", "1k").unwrap(), 1024);
+        assert_eq!(parse_int("a.b", "2M").unwrap(), 2 * 1024 * 1024);
+        assert_eq!(parse_int("a.b", "1g").unwrap(), 1024 * 1024 * 1024);
+        assert!(parse_int("a.b", "1t").is_err());
+    }
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("a.b", "red").unwrap(), "\x1b[31m");
+        assert_eq!(parse_color("a.b", "bold red blue").unwrap(), "\x1b[1;31;44m");
+        assert_eq!(parse_color("a.b", "#ff0000 ul").unwrap(), "\x1b[4;38;2;255;0;0m");
+        assert_eq!(parse_color("a.b", "brightgreen nobold").unwrap(), "\x1b[22;92m");
+        assert_eq!(parse_color("a.b", "normal").unwrap(), "");
+        assert!(parse_color("a.b", "red blue green").is_err());
+        assert!(parse_color("a.b", "sparkly").is_err());
+    }
+}
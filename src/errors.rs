@@ -1,24 +1,109 @@
 use std::path::PathBuf;
+use crate::model::id::Id;
+use crate::model::object::ObjectType;
 
 error_chain! {
 
     foreign_links {
         Io(::std::io::Error);
         Encoding(::std::str::Utf8Error);
+        Regex(::regex::Error);
     }
 
     errors {
-        BadId
+        BadId(input: String) {
+            display("invalid object id '{}'", input)
+        }
         InvalidRepository(path: PathBuf){
             display("{:?} is not a valid repository", path)
         }
-        InvalidObjectType
-        InvalidPackfile
-        ParseError
-        BadMode
-        UnsupportedPackIndexVersion
-        ChecksumMismatch
+        NotFound(id: Id) {
+            display("object {} not found", id)
+        }
+        InvalidObjectType(id: Id, expected: ObjectType, actual: ObjectType) {
+            display("object {} is a {}, not a {}", id, actual, expected)
+        }
+        UnknownObjectType(name: String) {
+            display("invalid object type '{}'", name)
+        }
+        CorruptObject(object_type: ObjectType, id: Id, offset: usize, reason: String) {
+            display("{} {}: {} at byte {}", object_type, id, reason, offset)
+        }
+        CorruptLooseObject(id: Id, path: PathBuf, reason: String) {
+            display("loose object {} ({}): {}", id, path.display(), reason)
+        }
+        InvalidPackfile(path: PathBuf, reason: String) {
+            display("{}: {}", path.display(), reason)
+        }
+        CorruptPackEntry(path: PathBuf, offset: usize, reason: String) {
+            display("{}: {} at offset {}", path.display(), reason, offset)
+        }
+        InvalidPackIndex(reason: String) {
+            display("invalid pack index: {}", reason)
+        }
+        InvalidCommitGraph(reason: String) {
+            display("invalid commit-graph: {}", reason)
+        }
+        BadMode(mode: String) {
+            display("invalid file mode '{}'", mode)
+        }
+        UnsupportedPackIndexVersion(version: u32) {
+            display("unsupported pack index version {}", version)
+        }
+        ChecksumMismatch(path: PathBuf) {
+            display("{}: checksum mismatch", path.display())
+        }
+        InvalidIndex(reason: String) {
+            display("invalid index: {}", reason)
+        }
+        WouldOverwrite(paths: Vec<String>) {
+            display("Your local changes to the following files would be overwritten by checkout:\n\t{}\n\
+                     Please commit your changes or stash them before you switch branches.", paths.join("\n\t"))
+        }
+        UntrackedWouldBeOverwritten(paths: Vec<String>) {
+            display("The following untracked working tree files would be overwritten by checkout:\n\t{}\n\
+                     Please move or remove them before you switch branches.", paths.join("\n\t"))
+        }
+        InvalidSignature(reason: String) {
+            display("invalid signature: {}", reason)
+        }
+        InvalidReflogEntry(line: String) {
+            display("invalid reflog entry '{}'", line)
+        }
         NotBelongThisRepo
+        LockExists(path: PathBuf) {
+            display("unable to create {:?}: File exists", path)
+        }
+        InvalidRefName(name: String) {
+            display("'{}' is not a valid ref name", name)
+        }
+        BadRevision(spec: String) {
+            display("bad revision '{}'", spec)
+        }
+        BrokenRef(name: String) {
+            display("ref '{}' is broken", name)
+        }
+        RefUpdateRejected(name: String, reason: String) {
+            display("cannot lock ref '{}': {}", name, reason)
+        }
+        UnsupportedObjectFormat(format: String) {
+            display("unknown object format '{}'", format)
+        }
+        InvalidConfig(line: usize) {
+            display("invalid config at line {}", line)
+        }
+        InvalidConfigKey(key: String) {
+            display("invalid config key: {}", key)
+        }
+        InvalidConfigValue(key: String, value: String) {
+            display("bad config value '{}' for '{}'", value, key)
+        }
+        ConfigKeyNotFound(key: String) {
+            display("config key not found: {}", key)
+        }
+        IncludeDepthExceeded(path: PathBuf) {
+            display("exceeded maximum include depth while including {:?}", path)
+        }
     }
 }
 
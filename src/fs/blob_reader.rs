@@ -0,0 +1,295 @@
+use std::fs::File;
+use std::io::{Cursor, Error, ErrorKind, Read, Seek, SeekFrom, Write};
+use miniz_oxide::inflate::stream::{inflate, InflateState};
+use miniz_oxide::{DataFormat, MZError, MZFlush, MZStatus};
+use crate::fs::SeekRead;
+use crate::model::id::Id;
+
+/// How much inflated output lies between two zlib checkpoints. Each one
+/// holds a copy of the inflater and its 32KiB window.
+pub const CHECKPOINT_INTERVAL: u64 = 4 << 20;
+
+/// Reconstructed deltas larger than this go to a temporary file.
+pub const SPILL_THRESHOLD: usize = 32 << 20;
+
+const INPUT_BUFFER: usize = 64 << 10;
+
+/// A blob's content as `Read + Seek`, without holding it in memory.
+/// Zlib streams are inflated on demand with checkpoints for seeking
+/// back; deltas are reconstructed once, in memory or spilled to disk.
+pub struct BlobReader<'a> {
+    id: Id,
+    size: u64,
+    source: Source<'a>,
+}
+
+enum Source<'a> {
+    Zlib(ZlibSeeker<Box<dyn SeekRead + 'a>>),
+    Memory(Cursor<Vec<u8>>),
+    Spilled(File),
+}
+
+impl<'a> BlobReader<'a> {
+    /// Streams `size` bytes of content that start `skip` bytes into the
+    /// zlib stream at the current position of `source`.
+    pub fn inflate(id: &Id, source: Box<dyn SeekRead + 'a>, skip: u64, size: u64) -> std::io::Result<Self> {
+        Ok(BlobReader {
+            id: id.to_owned(),
+            size,
+            source: Source::Zlib(ZlibSeeker::new(source, skip, size, CHECKPOINT_INTERVAL)?),
+        })
+    }
+
+    /// Reads `content` to the end once, keeping it in memory up to
+    /// `threshold` bytes and in an anonymous temporary file beyond that.
+    pub fn materialize(id: &Id, mut content: impl Read, threshold: usize) -> std::io::Result<Self> {
+        let mut buf = Vec::new();
+        content.by_ref().take(threshold as u64 + 1).read_to_end(&mut buf)?;
+        if buf.len() <= threshold {
+            return Ok(BlobReader { id: id.to_owned(), size: buf.len() as u64, source: Source::Memory(Cursor::new(buf)) });
+        }
+        let mut file = tempfile::tempfile()?;
+        file.write_all(&buf)?;
+        let size = buf.len() as u64 + std::io::copy(&mut content, &mut file)?;
+        file.seek(SeekFrom::Start(0))?;
+        Ok(BlobReader { id: id.to_owned(), size, source: Source::Spilled(file) })
+    }
+
+    pub fn id(&self) -> &Id {
+        &self.id
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn is_spilled(&self) -> bool {
+        matches!(self.source, Source::Spilled(_))
+    }
+}
+
+impl<'a> Read for BlobReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match &mut self.source {
+            Source::Zlib(reader) => reader.read(buf),
+            Source::Memory(reader) => reader.read(buf),
+            Source::Spilled(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl<'a> Seek for BlobReader<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        match &mut self.source {
+            Source::Zlib(reader) => reader.seek(pos),
+            Source::Memory(reader) => reader.seek(pos),
+            Source::Spilled(reader) => reader.seek(pos),
+        }
+    }
+}
+
+/// Inflater state taken at `output` bytes of output, after reading `input`
+/// bytes of the compressed stream.
+struct Checkpoint {
+    state: Box<InflateState>,
+    input: u64,
+    output: u64,
+}
+
+/// Inflates a zlib stream from a seekable source. Seeking back restarts
+/// from the nearest checkpoint instead of the start of the stream.
+pub(crate) struct ZlibSeeker<R> {
+    source: R,
+    /// Where the stream starts in `source`.
+    start: u64,
+    /// Inflated bytes before the content, e.g. a loose object's header.
+    skip: u64,
+    size: u64,
+    interval: u64,
+    state: Box<InflateState>,
+    buffer: Vec<u8>,
+    buffered: std::ops::Range<usize>,
+    input: u64,
+    output: u64,
+    finished: bool,
+    checkpoints: Vec<Checkpoint>,
+}
+
+impl<R: Read + Seek> ZlibSeeker<R> {
+    pub(crate) fn new(mut source: R, skip: u64, size: u64, interval: u64) -> std::io::Result<Self> {
+        let start = source.stream_position()?;
+        let state = InflateState::new_boxed(DataFormat::Zlib);
+        let mut seeker = ZlibSeeker {
+            source,
+            start,
+            skip,
+            size,
+            interval: interval.max(1),
+            checkpoints: vec![Checkpoint { state: state.clone(), input: 0, output: 0 }],
+            state,
+            buffer: vec![0u8; INPUT_BUFFER],
+            buffered: 0..0,
+            input: 0,
+            output: 0,
+            finished: false,
+        };
+        seeker.discard(skip)?;
+        if seeker.output < skip {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "zlib stream ends inside the header"));
+        }
+        Ok(seeker)
+    }
+
+    fn position(&self) -> u64 {
+        self.output - self.skip
+    }
+
+    fn inflate_some(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        if self.finished || out.is_empty() {
+            return Ok(0);
+        }
+        let last = self.checkpoints.last().map(|c| c.output).unwrap_or(0);
+        if self.output >= last + self.interval {
+            self.checkpoints.push(Checkpoint { state: self.state.clone(), input: self.input, output: self.output });
+        }
+        loop {
+            let mut eof = false;
+            if self.buffered.is_empty() {
+                let n = self.source.read(&mut self.buffer)?;
+                self.buffered = 0..n;
+                eof = n == 0;
+            }
+            let result = inflate(&mut self.state, &self.buffer[self.buffered.clone()], out, MZFlush::None);
+            self.buffered.start += result.bytes_consumed;
+            self.input += result.bytes_consumed as u64;
+            self.output += result.bytes_written as u64;
+            match result.status {
+                Ok(MZStatus::StreamEnd) => {
+                    self.finished = true;
+                    return Ok(result.bytes_written);
+                }
+                Ok(_) | Err(MZError::Buf) if result.bytes_written > 0 => return Ok(result.bytes_written),
+                Ok(_) | Err(MZError::Buf) if eof => return Err(Error::new(ErrorKind::UnexpectedEof, "truncated zlib stream")),
+                Ok(_) | Err(MZError::Buf) => {}
+                Err(e) => return Err(Error::new(ErrorKind::InvalidData, format!("corrupt zlib stream: {:?}", e))),
+            }
+        }
+    }
+
+    fn discard(&mut self, mut count: u64) -> std::io::Result<()> {
+        let mut scratch = [0u8; 8192];
+        while count > 0 {
+            let len = scratch.len().min(count as usize);
+            let n = self.inflate_some(&mut scratch[..len])?;
+            if n == 0 {
+                break;
+            }
+            count -= n as u64;
+        }
+        Ok(())
+    }
+
+    /// Moves to `target` bytes of inflated output, going back to the last
+    /// checkpoint before it if needed.
+    fn restart_at(&mut self, target: u64) -> std::io::Result<()> {
+        if target < self.output {
+            let checkpoint = self.checkpoints.iter().rev()
+                .find(|c| c.output <= target)
+                .unwrap_or(&self.checkpoints[0]);
+            self.state = checkpoint.state.clone();
+            self.input = checkpoint.input;
+            self.output = checkpoint.output;
+            self.source.seek(SeekFrom::Start(self.start + checkpoint.input))?;
+            self.buffered = 0..0;
+            self.finished = false;
+        }
+        self.discard(target - self.output)
+    }
+}
+
+impl<R: Read + Seek> Read for ZlibSeeker<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let remain = self.size.saturating_sub(self.position());
+        let len = buf.len().min(remain as usize);
+        self.inflate_some(&mut buf[..len])
+    }
+}
+
+impl<R: Read + Seek> Seek for ZlibSeeker<R> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(delta) => offset(self.size, delta),
+            SeekFrom::Current(delta) => offset(self.position(), delta),
+        };
+        let target = target.ok_or_else(|| Error::new(ErrorKind::InvalidInput, "seek before the start of the blob"))?;
+        // past the end there is nothing to inflate
+        self.restart_at(self.skip + target.min(self.size))?;
+        Ok(target.min(self.size))
+    }
+}
+
+fn offset(base: u64, delta: i64) -> Option<u64> {
+    if delta < 0 {
+        base.checked_sub(delta.unsigned_abs())
+    } else {
+        base.checked_add(delta as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+
+    fn content() -> Vec<u8> {
+        (0..200_000u32).flat_map(|n| format!("{} ", n * 7919 % 10007).into_bytes()).collect()
+    }
+
+    #[test]
+    fn test_zlib_seeker() {
+        let content = content();
+        let mut stream = b"blob 99\0".to_vec();
+        stream.extend_from_slice(&content);
+        let mut encoder = ZlibEncoder::new(b"junk".to_vec(), Compression::default());
+        encoder.write_all(&stream).unwrap();
+        let mut source = Cursor::new(encoder.finish().unwrap());
+        source.seek(SeekFrom::Start(4)).unwrap();
+
+        let mut reader = ZlibSeeker::new(source, 8, content.len() as u64, 100_000).unwrap();
+        let mut all = vec![];
+        reader.read_to_end(&mut all).unwrap();
+        assert_eq!(all, content);
+        assert!(reader.checkpoints.len() > 5);
+
+        for &start in &[700_000u64, 12, 250_001, 970_000, 0] {
+            assert_eq!(reader.seek(SeekFrom::Start(start)).unwrap(), start);
+            let mut buf = [0u8; 1000];
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf[..], &content[start as usize..start as usize + 1000]);
+        }
+        assert_eq!(reader.seek(SeekFrom::End(-3)).unwrap(), content.len() as u64 - 3);
+        let mut tail = vec![];
+        reader.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, &content[content.len() - 3..]);
+        assert!(reader.seek(SeekFrom::Current(-(content.len() as i64) - 1)).is_err());
+    }
+
+    #[test]
+    fn test_materialize_spills() {
+        let content = content();
+        let id = Id::new(&[1u8; 20]);
+        let mut small = BlobReader::materialize(&id, &content[..], content.len()).unwrap();
+        assert!(!small.is_spilled());
+        let mut large = BlobReader::materialize(&id, &content[..], 1000).unwrap();
+        assert!(large.is_spilled());
+        assert_eq!(large.size(), content.len() as u64);
+        for reader in &mut [&mut small, &mut large] {
+            reader.seek(SeekFrom::Start(5000)).unwrap();
+            let mut buf = [0u8; 10];
+            reader.read_exact(&mut buf).unwrap();
+            assert_eq!(&buf[..], &content[5000..5010]);
+        }
+    }
+}
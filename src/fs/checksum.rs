@@ -1,28 +1,84 @@
 use sha1::Sha1;
+use sha2::{Digest, Sha256};
 use std::io::Read;
+use crate::model::id::{Id, ObjectFormat};
 
 
-pub struct Sha1Reader<T> {
+/// Hashes with the algorithm of a repository's object format.
+#[derive(Clone)]
+pub enum Hasher {
+    Sha1(Sha1),
+    Sha256(Sha256),
+}
+
+impl Hasher {
+    pub fn new(format: ObjectFormat) -> Self {
+        match format {
+            ObjectFormat::Sha1 => Hasher::Sha1(Sha1::new()),
+            ObjectFormat::Sha256 => Hasher::Sha256(Sha256::new()),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha1(sha1) => sha1.update(data),
+            Hasher::Sha256(sha256) => sha256.update(data),
+        }
+    }
+
+    /// The digest of everything hashed so far.
+    pub fn digest(&self) -> Id {
+        match self {
+            Hasher::Sha1(sha1) => Id::new(&sha1.digest().bytes()),
+            Hasher::Sha256(sha256) => Id::new(&sha256.clone().finalize()),
+        }
+    }
+}
+
+/// Hashes an object the way git names it: `<type> <len>\0<content>`.
+pub fn hash_object(format: ObjectFormat, object_type: &str, content: &[u8]) -> Id {
+    let mut hasher = Hasher::new(format);
+    hasher.update(format!("{} {}\0", object_type, content.len()).as_bytes());
+    hasher.update(content);
+    hasher.digest()
+}
+
+/// Hashes everything read through it, to check the trailing checksum of
+/// pack and index files.
+pub struct HashReader<T> {
     reader: T,
-    sha1: Sha1
+    hasher: Hasher,
 }
 
-impl<T: Read> Sha1Reader<T> {
-    pub fn new(reader: T) -> Self{
-        Sha1Reader {
+impl<T: Read> HashReader<T> {
+    pub fn new(reader: T, format: ObjectFormat) -> Self{
+        HashReader {
             reader,
-            sha1: Sha1::new()
+            hasher: Hasher::new(format)
         }
     }
-    pub fn digest(&self) -> [u8; 20] {
-        self.sha1.digest().bytes()
+    pub fn digest(&self) -> Id {
+        self.hasher.digest()
     }
 }
 
-impl<T: Read> Read for Sha1Reader<T> {
+impl<T: Read> Read for HashReader<T> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         let result = self.reader.read(buf)?;
-        self.sha1.update(buf);
+        self.hasher.update(&buf[..result]);
         Ok(result)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_empty_blob() {
+        assert_eq!(hash_object(ObjectFormat::Sha1, "blob", b"").to_string(),
+                   "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391");
+        assert_eq!(hash_object(ObjectFormat::Sha256, "blob", b"").to_string(),
+                   "473a0f4c3be8a93681a267e3b1e9a7dcda1185436fe141f7749120a303721813");
+    }
+}
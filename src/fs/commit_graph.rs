@@ -0,0 +1,206 @@
+use std::convert::TryInto;
+use std::io::Read;
+use std::path::Path;
+use crate::errors::*;
+use crate::fs::FileSystem;
+use crate::model::id::{Id, ObjectFormat};
+
+const SIGNATURE: &[u8] = b"CGPH";
+const OID_FANOUT: &[u8] = b"OIDF";
+const OID_LOOKUP: &[u8] = b"OIDL";
+const COMMIT_DATA: &[u8] = b"CDAT";
+const EXTRA_EDGES: &[u8] = b"EDGE";
+
+const PARENT_NONE: u32 = 0x7000_0000;
+const EDGE_LIST: u32 = 0x8000_0000;
+
+/// Generation of commits a graph does not know about, which sorts them
+/// by date alone.
+pub const GENERATION_INFINITY: u32 = u32::MAX;
+
+/// A commit as recorded in the commit-graph. Parents are positions in the
+/// same graph.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GraphCommit {
+    pub tree: Id,
+    pub parents: Vec<u32>,
+    /// The topological level: one more than the highest parent's.
+    pub generation: u32,
+    pub commit_time: u64,
+}
+
+/// `objects/info/commit-graph`, the single-file form `git commit-graph
+/// write` produces. Split graph chains are not read.
+pub struct CommitGraph {
+    ids: Vec<Id>,
+    commits: Vec<GraphCommit>,
+}
+
+fn invalid(reason: impl Into<String>) -> Error {
+    ErrorKind::InvalidCommitGraph(reason.into()).into()
+}
+
+fn be_u32(data: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+fn be_u64(data: &[u8], at: usize) -> u64 {
+    u64::from_be_bytes(data[at..at + 8].try_into().unwrap())
+}
+
+impl CommitGraph {
+    /// The repository's commit-graph, `None` when it has none.
+    pub fn open<FS: FileSystem>(fs: &FS, object_dir: &Path, format: ObjectFormat) -> Result<Option<CommitGraph>> {
+        let path = object_dir.join("info").join("commit-graph");
+        if !fs.is_file(&path) {
+            return Ok(None);
+        }
+        let mut data = vec![];
+        fs.read_file(&path)?.read_to_end(&mut data)?;
+        CommitGraph::parse(&data, format).map(Some)
+    }
+
+    pub fn parse(data: &[u8], format: ObjectFormat) -> Result<CommitGraph> {
+        let hash_len = format.hash_len();
+        if data.len() < 8 || &data[..4] != SIGNATURE {
+            return Err(invalid("bad signature"));
+        }
+        if data[4] != 1 {
+            return Err(invalid(format!("unsupported version {}", data[4])));
+        }
+        let hash_version = match format {
+            ObjectFormat::Sha1 => 1,
+            ObjectFormat::Sha256 => 2,
+        };
+        if data[5] != hash_version {
+            return Err(invalid("hash version does not match the repository"));
+        }
+        if data[7] != 0 {
+            return Err(invalid("split commit-graphs are not supported"));
+        }
+        let chunk_count = data[6] as usize;
+        // the table ends with an entry for where the last chunk ends
+        let table_end = 8 + (chunk_count + 1) * 12;
+        let body_end = data.len().checked_sub(hash_len).ok_or_else(|| invalid("truncated"))?;
+        if table_end > body_end {
+            return Err(invalid("truncated chunk table"));
+        }
+        let mut chunks = vec![];
+        for i in 0..chunk_count {
+            let entry = 8 + i * 12;
+            let start = be_u64(data, entry + 4) as usize;
+            let end = be_u64(data, entry + 16) as usize;
+            if start < table_end || start > end || end > body_end {
+                return Err(invalid("chunk out of bounds"));
+            }
+            chunks.push((&data[entry..entry + 4], &data[start..end]));
+        }
+        let chunk = |id: &[u8]| chunks.iter().find(|(found, _)| *found == id).map(|(_, chunk)| *chunk);
+        let missing = |id: &[u8]| invalid(format!("missing {} chunk", String::from_utf8_lossy(id)));
+
+        let fanout = chunk(OID_FANOUT).ok_or_else(|| missing(OID_FANOUT))?;
+        if fanout.len() != 256 * 4 {
+            return Err(invalid("bad fanout size"));
+        }
+        let count = be_u32(fanout, 255 * 4) as usize;
+        let lookup = chunk(OID_LOOKUP).ok_or_else(|| missing(OID_LOOKUP))?;
+        let commit_data = chunk(COMMIT_DATA).ok_or_else(|| missing(COMMIT_DATA))?;
+        let entry_len = hash_len + 16;
+        if lookup.len() != count * hash_len || commit_data.len() != count * entry_len {
+            return Err(invalid("chunk sizes do not match the commit count"));
+        }
+        let edges = chunk(EXTRA_EDGES).unwrap_or_default();
+
+        let ids: Vec<Id> = lookup.chunks(hash_len).map(Id::new).collect();
+        if ids.windows(2).any(|w| w[0].bytes() >= w[1].bytes()) {
+            return Err(invalid("object ids are not sorted"));
+        }
+        let position = |value: u32| -> Result<u32> {
+            if (value as usize) < count { Ok(value) } else { Err(invalid("parent out of range")) }
+        };
+        let mut commits = Vec::with_capacity(count);
+        for entry in commit_data.chunks(entry_len) {
+            let tree = Id::new(&entry[..hash_len]);
+            let first = be_u32(entry, hash_len);
+            let second = be_u32(entry, hash_len + 4);
+            let mut parents = vec![];
+            if first != PARENT_NONE {
+                parents.push(position(first)?);
+            }
+            if second & EDGE_LIST != 0 {
+                let mut at = (second & !EDGE_LIST) as usize * 4;
+                loop {
+                    if at + 4 > edges.len() {
+                        return Err(invalid("extra edge out of range"));
+                    }
+                    let edge = be_u32(edges, at);
+                    parents.push(position(edge & !EDGE_LIST)?);
+                    if edge & EDGE_LIST != 0 {
+                        break;
+                    }
+                    at += 4;
+                }
+            } else if second != PARENT_NONE {
+                parents.push(position(second)?);
+            }
+            let word = be_u64(entry, hash_len + 8);
+            commits.push(GraphCommit {
+                tree,
+                parents,
+                generation: (word >> 34) as u32,
+                commit_time: word & ((1 << 34) - 1),
+            });
+        }
+        Ok(CommitGraph { ids, commits })
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    pub fn position(&self, id: &Id) -> Option<u32> {
+        self.ids.binary_search_by(|probe| probe.bytes().cmp(id.bytes())).ok().map(|n| n as u32)
+    }
+
+    pub fn id(&self, position: u32) -> &Id {
+        &self.ids[position as usize]
+    }
+
+    pub fn commit(&self, position: u32) -> &GraphCommit {
+        &self.commits[position as usize]
+    }
+
+    pub fn lookup(&self, id: &Id) -> Option<&GraphCommit> {
+        self.position(id).map(|position| self.commit(position))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::OsFs;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_read_commit_graph() {
+        let fs = OsFs::new(".");
+        let graph = CommitGraph::open(&fs, Path::new("tests/fixture_graph/objects"), ObjectFormat::Sha1)
+            .unwrap().unwrap();
+        assert_eq!(graph.len(), 10);
+        let root = graph.lookup(&Id::from_str("7c83c34b51e2cf9a6b9ba2a6828d35ec83235031").unwrap()).unwrap();
+        assert_eq!((root.generation, root.commit_time, root.parents.len()), (1, 1_500_000_060, 0));
+        // Y2, a merge
+        let merge = graph.lookup(&Id::from_str("0973279d3eae28b50f4ae4a38d80e8c1fef6dfcc").unwrap()).unwrap();
+        assert_eq!(merge.generation, 3);
+        let parents: Vec<String> = merge.parents.iter().map(|p| graph.id(*p).to_string()).collect();
+        assert_eq!(parents, ["391853c40ea1bf267ac56d1888af8a03b10f46d1", "8b04b07bcbb719e7c01ed06e28b9a32d6e005bc5"]);
+
+        let data = fs.map_file("tests/fixture_graph/objects/info/commit-graph").unwrap();
+        assert!(CommitGraph::parse((*data).as_ref(), ObjectFormat::Sha256).is_err());
+        assert!(CommitGraph::parse(&(*data).as_ref()[..100], ObjectFormat::Sha1).is_err());
+    }
+}
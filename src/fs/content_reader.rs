@@ -1,13 +1,14 @@
 use crate::fs::pack_file::PackZlibReader;
 use crate::fs::delta::DeltaReader;
-use std::io::{Read};
+use std::io::{Cursor, Error, ErrorKind, Read};
 use crate::fs::loose_file::LooseFileReader;
 use crate::fs::SeekRead;
 
 pub enum Source<'a> {
     FromPack(PackZlibReader<'a>),
     FromLooseFile(LooseFileReader),
-    Delta(DeltaReader<'a>)
+    Delta(DeltaReader<'a>),
+    Memory(Cursor<Vec<u8>>),
 }
 
 pub struct ContentReader<'a> {
@@ -32,19 +33,26 @@ impl <'a> ContentReader<'a> {
         }
     }
 
-    pub fn from_loose_file(file_reader: Box<dyn SeekRead>, offset: usize, size: usize) -> Self {
-        let reader = LooseFileReader::new(file_reader, offset, size);
+    pub fn from_loose_file(file_reader: Box<dyn SeekRead>, offset: usize, size: usize) -> std::io::Result<Self> {
+        let reader = LooseFileReader::new(file_reader, offset, size)?;
         let source = Source::FromLooseFile(reader);
-        ContentReader {
+        Ok(ContentReader {
             source,
             pos: 0
+        })
+    }
+
+    pub fn from_bytes(content: Vec<u8>) -> Self {
+        ContentReader {
+            source: Source::Memory(Cursor::new(content)),
+            pos: 0
         }
     }
 
     pub fn forward(&mut self, offset: usize) -> std::io::Result<usize> {
 
         if offset < self.pos {
-            self.reset();
+            self.reset()?;
         }
 
         let forward = (offset - self.pos) as u64;
@@ -58,7 +66,10 @@ impl <'a> ContentReader<'a> {
         let mut byte = self.read_byte()?;
         let mut result = u64::from(byte & 0b0111_1111);
         let mut shift = 7;
-        while byte > 128u8 {
+        while byte >= 128u8 {
+            if shift > 63 {
+                return Err(Error::new(ErrorKind::InvalidData, "varint overflows 64 bits"));
+            }
             byte = self.read_byte()?;
             result += u64::from(byte & 0b0111_1111) << shift;
             shift += 7;
@@ -71,19 +82,23 @@ impl <'a> ContentReader<'a> {
         Ok(byte[0])
     }
 
-    pub(crate) fn reset(&mut self){
+    pub(crate) fn reset(&mut self) -> std::io::Result<()> {
         match &mut self.source {
             Source::FromLooseFile(reader) => {
-                reader.reset();
+                reader.reset()?;
             }
             Source::FromPack(reader) => {
                 reader.reset()
             }
             Source::Delta(reader) => {
-                reader.reset()
+                reader.reset()?;
+            }
+            Source::Memory(reader) => {
+                reader.set_position(0)
             }
         };
         self.pos = 0;
+        Ok(())
     }
 }
 
@@ -99,6 +114,9 @@ impl <'a> Read for ContentReader<'a> {
             Source::Delta(reader) => {
                 reader.read(buf)?
             }
+            Source::Memory(reader) => {
+                reader.read(buf)?
+            }
         };
         self.pos += size;
         Ok(size)
@@ -1,7 +1,8 @@
-use std::io::Read;
+use std::io::{Error, ErrorKind, Read};
 use crate::fs::content_reader::ContentReader;
 
 enum State {
+    Header,
     NEXT,
     COPY(usize, usize),
     INSERT(usize),
@@ -9,7 +10,9 @@ enum State {
 }
 
 pub struct DeltaReader<'a> {
-    base: Box<ContentReader<'a>>,
+    /// Copy instructions jump around in the base, so it is read once up
+    /// front instead of re-inflating it for every backward copy.
+    base: Vec<u8>,
     delta: Box<ContentReader<'a>>,
     state: State,
     size: u64,
@@ -17,30 +20,46 @@ pub struct DeltaReader<'a> {
 }
 
 impl<'a> DeltaReader<'a> {
-    pub fn new(base: ContentReader<'a>, mut delta: ContentReader<'a>, _size: usize) -> std::io::Result<Self> {
-        let _base_size =  delta.read_varint()?;
+    pub fn new(mut base: ContentReader<'a>, mut delta: ContentReader<'a>, _size: usize) -> std::io::Result<Self> {
+        let base_size =  delta.read_varint()?;
         let output_size =  delta.read_varint()?;
+        let mut base_content = Vec::new();
+        base.read_to_end(&mut base_content)?;
+        if base_content.len() as u64 != base_size {
+            return Err(invalid("delta base size mismatch"));
+        }
         Ok(Self {
             delta: Box::new(delta),
-            base: Box::new(base),
+            base: base_content,
             state: State::NEXT,
             size: output_size,
             pos: 0
         })
     }
 
-    pub(crate) fn reset(&mut self) {
-        self.base.reset();
-        self.delta.reset();
+    pub(crate) fn reset(&mut self) -> std::io::Result<()> {
+        self.delta.reset()?;
+        // the sizes at the start of the delta are skipped again on the next read
+        self.state = State::Header;
         self.pos = 0;
+        Ok(())
     }
 }
 
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
 impl<'a> Read for DeltaReader<'a> {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
         loop {
             let remain = self.size - self.pos;
             match self.state {
+                State::Header => {
+                    self.delta.read_varint()?;
+                    self.delta.read_varint()?;
+                    self.state = State::NEXT;
+                },
                 State::NEXT => {
                     if remain == 0 {
                         self.state = State::DONE;
@@ -68,26 +87,33 @@ impl<'a> Read for DeltaReader<'a> {
                                length += value << (8 * i) as usize;
                            } 
                         }
+                        // a zero length stands for 0x10000
+                        if length == 0 {
+                            length = 0x10000;
+                        }
+                        if length as u64 > remain {
+                            return Err(invalid("delta writes past its result size"));
+                        }
                         self.state = State::COPY(offset, length)
+                    } else if instruction == 0 {
+                        return Err(invalid("unexpected delta opcode 0"));
                     } else {
-                        self.state = State::INSERT((instruction & 0b0111_1111) as usize);
+                        let length = (instruction & 0b0111_1111) as usize;
+                        if length as u64 > remain {
+                            return Err(invalid("delta writes past its result size"));
+                        }
+                        self.state = State::INSERT(length);
                     }
                 },
                 State::COPY(offset, length) => {
-                    if buf.len() >= length {
-                        self.base.forward(offset)?;
-                        self.base.read_exact(&mut buf[..length])?;
-                        self.state = State::NEXT;
-                        self.pos += length as u64;
-                        return Ok(length);
-                    } else {
-                        self.base.forward(offset)?;
-                        self.base.read_exact(buf)?;
-                        let len = buf.len();
-                        self.state = State::COPY(offset + len, length - len);
-                        self.pos += len as u64;
-                        return Ok(len);
-                    }
+                    let len = length.min(buf.len());
+                    let source = offset.checked_add(len)
+                        .and_then(|end| self.base.get(offset..end))
+                        .ok_or_else(|| invalid("delta copies outside its base"))?;
+                    buf[..len].copy_from_slice(source);
+                    self.state = if len == length { State::NEXT } else { State::COPY(offset + len, length - len) };
+                    self.pos += len as u64;
+                    return Ok(len);
                 },
                 State::INSERT(length) => {
                     if buf.len() >= length as usize {
@@ -96,7 +122,7 @@ impl<'a> Read for DeltaReader<'a> {
                         self.pos += length as u64;
                         return Ok(length);
                     } else {
-                        self.base.read_exact(buf)?;
+                        self.delta.read_exact(buf)?;
                         let len = buf.len();
                         self.state = State::INSERT(length - len);
                         self.pos += len as u64;
@@ -109,4 +135,34 @@ impl<'a> Read for DeltaReader<'a> {
             }
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply(base: &[u8], delta: &[u8]) -> std::io::Result<Vec<u8>> {
+        let base = ContentReader::from_bytes(base.to_vec());
+        let mut reader = DeltaReader::new(base, ContentReader::from_bytes(delta.to_vec()), 0)?;
+        let mut out = vec![];
+        reader.read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn test_apply_delta() {
+        let delta = b"\x0b\x11\x90\x05\x06 there\x91\x05\x06";
+        assert_eq!(apply(b"hello world", delta).unwrap(), b"hello there world");
+    }
+
+    #[test]
+    fn test_reject_bad_delta() {
+        // copies past the end of the base
+        assert!(apply(b"hello world", b"\x0b\x05\x91\x08\x05").is_err());
+        // the reserved opcode
+        assert!(apply(b"hello world", b"\x0b\x05\x00").is_err());
+        // writes more than the result size
+        assert!(apply(b"hello world", b"\x0b\x02\x90\x05").is_err());
+        // wrong base size
+        assert!(apply(b"hello", b"\x0b\x05\x90\x05").is_err());
+    }
+}
@@ -5,9 +5,7 @@
 use std::path::PathBuf;
 use crate::model::id::Id;
 
-
-
-
+#[derive(Debug, Clone, PartialEq)]
 pub enum Locator {
     LooseObject(PathBuf, usize),
     Packfile(Id, usize),
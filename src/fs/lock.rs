@@ -0,0 +1,112 @@
+use std::path::{Path, PathBuf};
+use crate::errors::*;
+use crate::fs::FileSystem;
+
+/// A `<path>.lock` file, created exclusively so that only one writer can
+/// update `path` at a time. The new content is written to the lock file and
+/// renamed over `path` on `commit`. Dropping an uncommitted lock removes it.
+pub struct LockFile<'a, FS: FileSystem> {
+    fs: &'a FS,
+    path: PathBuf,
+    lock_path: PathBuf,
+    done: bool,
+}
+
+impl<'a, FS: FileSystem> LockFile<'a, FS> {
+    pub fn acquire<P: AsRef<Path>>(fs: &'a FS, path: P) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut name = path.file_name().map(|n| n.to_os_string()).unwrap_or_default();
+        name.push(".lock");
+        let lock_path = path.with_file_name(name);
+        if let Err(e) = fs.create_new(&lock_path) {
+            if let ErrorKind::Io(io) = e.kind() {
+                if io.kind() == std::io::ErrorKind::AlreadyExists {
+                    return Err(ErrorKind::LockExists(lock_path).into());
+                }
+            }
+            return Err(e);
+        }
+        Ok(LockFile { fs, path, lock_path, done: false })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.path.as_path()
+    }
+
+    pub fn lock_path(&self) -> &Path {
+        self.lock_path.as_path()
+    }
+
+    /// Replaces the content of the lock file.
+    pub fn write(&self, content: &[u8]) -> Result<()> {
+        self.fs.write_file(&self.lock_path, content)
+    }
+
+    /// Flushes the lock file and renames it over the locked path.
+    pub fn commit(mut self) -> Result<()> {
+        self.fs.sync(&self.lock_path)?;
+        self.fs.rename(&self.lock_path, &self.path)?;
+        self.done = true;
+        Ok(())
+    }
+
+    /// Removes the lock file, leaving the locked path untouched.
+    pub fn rollback(mut self) -> Result<()> {
+        self.done = true;
+        self.fs.remove_file(&self.lock_path)
+    }
+}
+
+impl<'a, FS: FileSystem> Drop for LockFile<'a, FS> {
+    fn drop(&mut self) {
+        if !self.done {
+            let _ = self.fs.remove_file(&self.lock_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::MemFs;
+    use std::io::Read;
+
+    fn read(fs: &MemFs, path: &str) -> String {
+        let mut s = String::new();
+        fs.read_file(path).unwrap().read_to_string(&mut s).unwrap();
+        s
+    }
+
+    #[test]
+    fn test_lock_commit() {
+        let mut fs = MemFs::default();
+        fs.add_file("/repo/HEAD", b"old".to_vec());
+        let lock = LockFile::acquire(&fs, "/repo/HEAD").unwrap();
+        assert!(fs.is_file("/repo/HEAD.lock"));
+        match LockFile::acquire(&fs, "/repo/HEAD") {
+            Err(e) => match e.kind() {
+                ErrorKind::LockExists(path) => assert_eq!(path, Path::new("/repo/HEAD.lock")),
+                _ => panic!("unexpected error {}", e),
+            },
+            Ok(_) => panic!("lock acquired twice"),
+        }
+        lock.write(b"new").unwrap();
+        assert_eq!(read(&fs, "/repo/HEAD"), "old");
+        lock.commit().unwrap();
+        assert_eq!(read(&fs, "/repo/HEAD"), "new");
+        assert!(!fs.is_file("/repo/HEAD.lock"));
+    }
+
+    #[test]
+    fn test_lock_dropped() {
+        let mut fs = MemFs::default();
+        fs.add_file("/repo/HEAD", b"old".to_vec());
+        {
+            let lock = LockFile::acquire(&fs, "/repo/HEAD").unwrap();
+            lock.write(b"new").unwrap();
+        }
+        assert!(!fs.is_file("/repo/HEAD.lock"));
+        assert_eq!(read(&fs, "/repo/HEAD"), "old");
+        assert!(LockFile::acquire(&fs, "/repo/HEAD").is_ok());
+    }
+}
@@ -25,39 +25,42 @@ impl Read for Empty {
 }
 
 impl LooseFileReader {
-    pub fn new(mut file_reader: Box<dyn SeekRead>, init_offset: usize, size: usize) -> Self {
-        let init_file_pos = file_reader.seek(SeekFrom::Current(0)).expect("file reader can't seek?");
+    pub fn new(mut file_reader: Box<dyn SeekRead>, init_offset: usize, size: usize) -> std::io::Result<Self> {
+        let init_file_pos = file_reader.seek(SeekFrom::Current(0))?;
         let mut reader = ZlibDecoder::new(file_reader);
         if init_offset > 0 {
-            reader.forward(init_offset as u64).expect("");
+            reader.forward(init_offset as u64)?;
         }
-        Self {
+        Ok(Self {
             reader,
             init_file_pos,
             init_offset,
             pos: 0,
             size
-        }
+        })
     }
 
-    pub(crate) fn reset(&mut self) {
+    pub(crate) fn reset(&mut self) -> std::io::Result<()> {
         let empty : Box<dyn SeekRead> = Box::new(Empty);
         let empty_reader = ZlibDecoder::new(empty);
         let reader = mem::replace(&mut self.reader, empty_reader);
         let mut file_reader = reader.into_inner();
-        file_reader.seek(SeekFrom::Start(self.init_file_pos)).expect("");
-        let new_reader =  ZlibDecoder::new(file_reader);
-        mem::replace(&mut self.reader, new_reader);
+        file_reader.seek(SeekFrom::Start(self.init_file_pos))?;
+        self.reader = ZlibDecoder::new(file_reader);
         if self.init_offset > 0 {
-            self.reader.forward(self.init_offset as u64).expect("");
+            self.reader.forward(self.init_offset as u64)?;
         }
         self.pos = 0;
+        Ok(())
     }
 }
 
 impl Read for LooseFileReader {
     fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        let len = self.reader.read(buf)?;
+        // a file holding more than its header claims is cut at the size
+        let remain = (self.size as u64).saturating_sub(self.pos);
+        let len = buf.len().min(remain as usize);
+        let len = self.reader.read(&mut buf[..len])?;
         self.pos += len as u64;
         Ok(len)
     }
@@ -73,7 +76,7 @@ impl Seek for LooseFileReader {
         if new_pos > self.pos {
             self.forward(new_pos - self.pos)?
         } else {
-            self.reset();
+            self.reset()?;
             self.forward(new_pos)?;
         }
         Ok(self.pos)
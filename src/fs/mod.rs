@@ -1,8 +1,11 @@
 use std::path::{Path, PathBuf};
 
 use os_str_generic::OsStrGenericExt;
-use std::collections::HashMap;
-use std::fs::File;
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+use std::fs::{File, OpenOptions};
+use std::time::{SystemTime, UNIX_EPOCH, Duration};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use crate::errors::*;
 use memmap::{MmapOptions};
 use std::io::{Read, Cursor, Seek, BufReader};
@@ -14,6 +17,11 @@ pub mod locator;
 pub mod delta;
 pub mod content_reader;
 pub mod loose_file;
+pub mod lock;
+pub mod blob_reader;
+pub mod commit_graph;
+
+pub use self::lock::LockFile;
 
 pub trait SeekRead: Seek + Read {}
 impl<T: Seek + Read> SeekRead for T {}
@@ -31,8 +39,36 @@ impl <T: Read> Forwardable for T {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileType {
+    File,
+    Dir,
+    Symlink,
+}
+
+/// The subset of `stat` git cares about.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metadata {
+    pub file_type: FileType,
+    pub len: u64,
+    pub mode: u32,
+    pub modified: SystemTime,
+    /// When the inode last changed (`st_ctime`).
+    pub changed: SystemTime,
+    pub device: u64,
+    pub inode: u64,
+    pub uid: u32,
+    pub gid: u32,
+}
+
 pub trait FileSystem {
     fn is_dir<P: AsRef<Path>>(&self, path: P) -> bool;
+    fn is_file<P: AsRef<Path>>(&self, path: P) -> bool;
+    /// The device a path lives on, used to stop repository discovery at
+    /// file system boundaries. `None` if unknown.
+    fn device<P: AsRef<Path>>(&self, path: P) -> Option<u64> {
+        self.metadata(path).ok().map(|m| m.device)
+    }
     fn read_dir<P: AsRef<Path>>(&self, path: P) -> Box<dyn Iterator<Item= PathBuf>>;
     fn ls_files<P: AsRef<Path>>(&self, path: P) -> Box<dyn Iterator<Item= PathBuf>> {
         let path = path.as_ref().to_path_buf();
@@ -57,6 +93,52 @@ pub trait FileSystem {
     fn read_file<P: AsRef<Path>>(&self, path: P) -> Result<Box<dyn SeekRead>>;
     fn map_file<P: AsRef<Path>>(&self, path: P) -> Result<Box<dyn AsRef<[u8]>>>;
     fn check_file<P: AsRef<Path>>(&self, path: P) -> Result<()>;
+    /// Does not follow symlinks.
+    fn metadata<P: AsRef<Path>>(&self, path: P) -> Result<Metadata>;
+    fn create_dir_all<P: AsRef<Path>>(&self, path: P) -> Result<()>;
+    /// Creates or truncates `path`. Use `write_atomic` for files other
+    /// processes may be reading.
+    fn write_file<P: AsRef<Path>>(&self, path: P, content: &[u8]) -> Result<()>;
+    /// Appends to `path`, creating it if needed (`O_APPEND`).
+    fn append_file<P: AsRef<Path>>(&self, path: P, content: &[u8]) -> Result<()>;
+    /// Creates an empty file, failing if it already exists (`O_EXCL`).
+    fn create_new<P: AsRef<Path>>(&self, path: P) -> Result<()>;
+    /// Creates `path` holding `content`, failing if it already exists. An
+    /// executable file gets the execute bits the umask allows.
+    fn create_file<P: AsRef<Path>>(&self, path: P, content: &[u8], executable: bool) -> Result<()>;
+    /// Creates a symlink at `path` pointing to `target`, or where symlinks
+    /// are not supported a plain file holding it, as git does.
+    fn symlink<P: AsRef<Path>>(&self, target: &[u8], path: P) -> Result<()>;
+    /// The target of the symlink at `path`.
+    fn read_link<P: AsRef<Path>>(&self, path: P) -> Result<Vec<u8>>;
+    fn rename<P: AsRef<Path>, Q: AsRef<Path>>(&self, from: P, to: Q) -> Result<()>;
+    fn remove_file<P: AsRef<Path>>(&self, path: P) -> Result<()>;
+    /// Removes an empty directory.
+    fn remove_dir<P: AsRef<Path>>(&self, path: P) -> Result<()>;
+    /// Flushes a file or directory to stable storage.
+    fn sync<P: AsRef<Path>>(&self, path: P) -> Result<()>;
+
+    /// Writes `content` to a temporary file next to `path`, then renames it
+    /// into place so readers never see a partially written file.
+    fn write_atomic<P: AsRef<Path>>(&self, path: P, content: &[u8]) -> Result<()> {
+        let path = path.as_ref();
+        let tmp = temp_path(path);
+        self.create_new(&tmp)?;
+        let result = self.write_file(&tmp, content)
+            .and_then(|_| self.sync(&tmp))
+            .and_then(|_| self.rename(&tmp, path));
+        if result.is_err() {
+            let _ = self.remove_file(&tmp);
+        }
+        result
+    }
+}
+
+fn temp_path(path: &Path) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let tmp = format!(".tmp-{}-{}-{}", std::process::id(), COUNTER.fetch_add(1, Ordering::SeqCst), name);
+    path.with_file_name(tmp)
 }
 
 #[derive(Debug)]
@@ -77,6 +159,10 @@ impl FileSystem for OsFs {
         path.as_ref().is_dir()
     }
 
+    fn is_file<P: AsRef<Path>>(&self, path: P) -> bool {
+        path.as_ref().is_file()
+    }
+
     fn read_dir<P: AsRef<Path>>(&self, path: P) -> Box<dyn Iterator<Item=PathBuf>>{
         let path = path.as_ref();
         if self.is_dir(path) {
@@ -107,81 +193,374 @@ impl FileSystem for OsFs {
         p.strip_prefix(&self.prefix).chain_err(|| ErrorKind::NotBelongThisRepo)?;
         Ok(())
     }
+
+    fn metadata<P: AsRef<Path>>(&self, path: P) -> Result<Metadata> {
+        let meta = std::fs::symlink_metadata(path)?;
+        let file_type = if meta.file_type().is_symlink() {
+            FileType::Symlink
+        } else if meta.is_dir() {
+            FileType::Dir
+        } else {
+            FileType::File
+        };
+        let modified = meta.modified()?;
+        #[cfg(unix)]
+        let (mode, changed, device, inode, uid, gid) = {
+            use std::os::unix::fs::MetadataExt;
+            let changed = UNIX_EPOCH + Duration::new(meta.ctime().max(0) as u64, meta.ctime_nsec() as u32);
+            (meta.mode(), changed, meta.dev(), meta.ino(), meta.uid(), meta.gid())
+        };
+        #[cfg(not(unix))]
+        let (mode, changed, device, inode, uid, gid) = {
+            let mode = match file_type {
+                FileType::Dir => 0o40755,
+                FileType::Symlink => 0o120777,
+                FileType::File => if meta.permissions().readonly() { 0o100444 } else { 0o100644 },
+            };
+            (mode, modified, 0, 0, 0, 0)
+        };
+        Ok(Metadata {
+            file_type,
+            len: meta.len(),
+            mode,
+            modified,
+            changed,
+            device,
+            inode,
+            uid,
+            gid,
+        })
+    }
+
+    fn create_dir_all<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        std::fs::create_dir_all(path)?;
+        Ok(())
+    }
+
+    fn write_file<P: AsRef<Path>>(&self, path: P, content: &[u8]) -> Result<()> {
+        std::fs::write(path, content)?;
+        Ok(())
+    }
+
+    fn append_file<P: AsRef<Path>>(&self, path: P, content: &[u8]) -> Result<()> {
+        use std::io::Write;
+        OpenOptions::new().append(true).create(true).open(path)?.write_all(content)?;
+        Ok(())
+    }
+
+    fn create_new<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        OpenOptions::new().write(true).create_new(true).open(path)?;
+        Ok(())
+    }
+
+    fn create_file<P: AsRef<Path>>(&self, path: P, content: &[u8], executable: bool) -> Result<()> {
+        use std::io::Write;
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(if executable { 0o777 } else { 0o666 });
+        }
+        #[cfg(not(unix))]
+        let _ = executable;
+        options.open(path)?.write_all(content)?;
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn symlink<P: AsRef<Path>>(&self, target: &[u8], path: P) -> Result<()> {
+        use std::os::unix::ffi::OsStrExt;
+        std::os::unix::fs::symlink(std::ffi::OsStr::from_bytes(target), path)?;
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn symlink<P: AsRef<Path>>(&self, target: &[u8], path: P) -> Result<()> {
+        self.create_file(path, target, false)
+    }
+
+    #[cfg(unix)]
+    fn read_link<P: AsRef<Path>>(&self, path: P) -> Result<Vec<u8>> {
+        use std::os::unix::ffi::OsStrExt;
+        Ok(std::fs::read_link(path)?.as_os_str().as_bytes().to_vec())
+    }
+
+    #[cfg(not(unix))]
+    fn read_link<P: AsRef<Path>>(&self, path: P) -> Result<Vec<u8>> {
+        Ok(std::fs::read(path)?)
+    }
+
+    fn rename<P: AsRef<Path>, Q: AsRef<Path>>(&self, from: P, to: Q) -> Result<()> {
+        std::fs::rename(from, to)?;
+        Ok(())
+    }
+
+    fn remove_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        std::fs::remove_file(path)?;
+        Ok(())
+    }
+
+    fn remove_dir<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        std::fs::remove_dir(path)?;
+        Ok(())
+    }
+
+    fn sync<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        File::open(path)?.sync_all()?;
+        Ok(())
+    }
 }
 
 
 
 #[derive(Debug, Default)]
-pub struct MemFs (HashMap<PathBuf, Vec<u8>>);
+struct MemFsInner {
+    files: HashMap<PathBuf, Vec<u8>>,
+    dirs: HashSet<PathBuf>,
+    links: HashMap<PathBuf, Vec<u8>>,
+    executable: HashSet<PathBuf>,
+    modified: HashMap<PathBuf, SystemTime>,
+    clock: u64,
+}
+
+impl MemFsInner {
+    fn touch(&mut self, path: &Path) {
+        self.clock += 1;
+        self.modified.insert(path.to_path_buf(), UNIX_EPOCH + Duration::from_secs(self.clock));
+    }
+}
+
+/// An in-memory file system for tests. Directories exist either because
+/// they were created explicitly or because a file lives below them.
+#[derive(Debug, Default)]
+pub struct MemFs(RwLock<MemFsInner>);
 
 impl MemFs {
     pub fn add_file<P: AsRef<Path>>(&mut self, file_name: P, content: Vec<u8>) {
-        self.0.insert(file_name.as_ref().to_path_buf(), content);
+        self.0.get_mut().expect("poisoned lock")
+            .files.insert(file_name.as_ref().to_path_buf(), content);
+    }
+
+    fn inner(&self) -> std::sync::RwLockReadGuard<'_, MemFsInner> {
+        self.0.read().expect("poisoned lock")
+    }
+
+    fn inner_mut(&self) -> std::sync::RwLockWriteGuard<'_, MemFsInner> {
+        self.0.write().expect("poisoned lock")
+    }
+
+    fn not_found(path: &Path) -> Error {
+        MemFs::io_error(std::io::ErrorKind::NotFound, format!("{:?} not found!", path))
+    }
+
+    fn io_error(kind: std::io::ErrorKind, message: String) -> Error {
+        ErrorKind::Io(std::io::Error::new(kind, message)).into()
+    }
+
+    fn check_parent(&self, path: &Path) -> Result<()> {
+        match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() && !self.is_dir(parent) =>
+                Err(MemFs::not_found(parent)),
+            _ => Ok(())
+        }
     }
 }
 
 impl FileSystem for MemFs {
     fn is_dir<P: AsRef<Path>>(&self, path: P) -> bool {
         let path = path.as_ref();
-        for p in self.0.keys() {
-            let mut dir = p.parent();
-            while let Some(p) = dir {
-                if p == path {
-                    return true
-                }
-                dir = p.parent();
-            }
+        let inner = self.inner();
+        if inner.dirs.contains(path) {
+            return true;
         }
-        false
+        inner.files.keys().chain(inner.dirs.iter()).chain(inner.links.keys())
+            .any(|p| p != path && p.starts_with(path))
+    }
+
+    fn is_file<P: AsRef<Path>>(&self, path: P) -> bool {
+        self.inner().files.contains_key(path.as_ref())
     }
 
     fn read_dir<P: AsRef<Path>>(&self, path: P) -> Box<dyn Iterator<Item=PathBuf>> {
         let path = path.as_ref();
-        if self.is_dir(path) {
-            let keys: Vec<PathBuf> = self.0.keys()
-                .map(|k| k.to_path_buf())
-                .filter( |p| {
-                     if let Some(parent) = p.parent() {
-                         return parent == path;
-                     }
-                    false
-                })
-                .collect();
-            Box::new(keys.into_iter())
-        } else {
-            Box::new(std::iter::empty::<PathBuf>())
-        }
+        let inner = self.inner();
+        let mut children: Vec<PathBuf> = inner.files.keys().chain(inner.dirs.iter()).chain(inner.links.keys())
+            .filter(|p| p.starts_with(path) && *p != path)
+            .filter_map(|p| p.strip_prefix(path).ok()
+                .and_then(|rest| rest.components().next())
+                .map(|c| path.join(c)))
+            .collect();
+        children.sort();
+        children.dedup();
+        Box::new(children.into_iter())
     }
 
     fn read_file<P: AsRef<Path>>(& self, path: P) -> Result<Box<dyn SeekRead>> {
-        let path = path.as_ref().to_path_buf();
-        if let Some(content) = self.0.get(&path) {
+        let path = path.as_ref();
+        if let Some(content) = self.inner().files.get(path) {
             Ok(Box::new(Cursor::new(content.to_owned())))
         } else {
-            Err(ErrorKind::Io(std::io::Error::new(
-                std::io::ErrorKind::NotFound, format!("{:?} not found!", path)
-            )).into())
+            Err(MemFs::not_found(path))
         }
     }
 
     fn map_file<P: AsRef<Path>>(&self, path: P) -> Result<Box<dyn AsRef<[u8]>>> {
-        let path = path.as_ref().to_path_buf();
-        if let Some(content) = self.0.get(&path) {
+        let path = path.as_ref();
+        if let Some(content) = self.inner().files.get(path) {
             Ok(Box::new(content.to_owned()))
         } else {
-            Err(ErrorKind::Io(std::io::Error::new(
-                std::io::ErrorKind::NotFound, format!("{:?} not found!", path)
-            )).into())
+            Err(MemFs::not_found(path))
         }
     }
 
     fn check_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
-        if self.0.contains_key(path.as_ref()) {
+        if self.inner().files.contains_key(path.as_ref()) {
            Ok(())
         } else {
             Err(ErrorKind::NotBelongThisRepo.into())
         }
     }
+
+    fn metadata<P: AsRef<Path>>(&self, path: P) -> Result<Metadata> {
+        let path = path.as_ref();
+        let is_dir = self.is_dir(path);
+        let inner = self.inner();
+        let (file_type, len, mode) = if let Some(content) = inner.files.get(path) {
+            let mode = if inner.executable.contains(path) { 0o100755 } else { 0o100644 };
+            (FileType::File, content.len() as u64, mode)
+        } else if let Some(target) = inner.links.get(path) {
+            (FileType::Symlink, target.len() as u64, 0o120777)
+        } else if is_dir {
+            (FileType::Dir, 0, 0o40755)
+        } else {
+            return Err(MemFs::not_found(path));
+        };
+        let modified = inner.modified.get(path).cloned().unwrap_or(UNIX_EPOCH);
+        Ok(Metadata { file_type, len, mode, modified, changed: modified, device: 0, inode: 0, uid: 0, gid: 0 })
+    }
+
+    fn create_dir_all<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        if self.is_file(path) || self.inner().links.contains_key(path) {
+            return Err(MemFs::io_error(std::io::ErrorKind::AlreadyExists,
+                                       format!("{:?} is a file", path)));
+        }
+        self.inner_mut().dirs.insert(path.to_path_buf());
+        Ok(())
+    }
+
+    fn write_file<P: AsRef<Path>>(&self, path: P, content: &[u8]) -> Result<()> {
+        let path = path.as_ref();
+        self.check_parent(path)?;
+        let mut inner = self.inner_mut();
+        inner.files.insert(path.to_path_buf(), content.to_vec());
+        inner.touch(path);
+        Ok(())
+    }
+
+    fn append_file<P: AsRef<Path>>(&self, path: P, content: &[u8]) -> Result<()> {
+        let path = path.as_ref();
+        self.check_parent(path)?;
+        let mut inner = self.inner_mut();
+        inner.files.entry(path.to_path_buf()).or_default().extend_from_slice(content);
+        inner.touch(path);
+        Ok(())
+    }
+
+    fn create_new<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        self.check_parent(path)?;
+        let mut inner = self.inner_mut();
+        if inner.files.contains_key(path) || inner.dirs.contains(path) || inner.links.contains_key(path) {
+            return Err(MemFs::io_error(std::io::ErrorKind::AlreadyExists,
+                                       format!("{:?} already exists", path)));
+        }
+        inner.files.insert(path.to_path_buf(), vec![]);
+        inner.touch(path);
+        Ok(())
+    }
+
+    fn create_file<P: AsRef<Path>>(&self, path: P, content: &[u8], executable: bool) -> Result<()> {
+        let path = path.as_ref();
+        self.create_new(path)?;
+        self.write_file(path, content)?;
+        if executable {
+            self.inner_mut().executable.insert(path.to_path_buf());
+        }
+        Ok(())
+    }
+
+    fn symlink<P: AsRef<Path>>(&self, target: &[u8], path: P) -> Result<()> {
+        let path = path.as_ref();
+        self.check_parent(path)?;
+        if self.metadata(path).is_ok() {
+            return Err(MemFs::io_error(std::io::ErrorKind::AlreadyExists,
+                                       format!("{:?} already exists", path)));
+        }
+        let mut inner = self.inner_mut();
+        inner.links.insert(path.to_path_buf(), target.to_vec());
+        inner.touch(path);
+        Ok(())
+    }
+
+    fn read_link<P: AsRef<Path>>(&self, path: P) -> Result<Vec<u8>> {
+        let path = path.as_ref();
+        self.inner().links.get(path).cloned().ok_or_else(|| MemFs::not_found(path))
+    }
+
+    fn rename<P: AsRef<Path>, Q: AsRef<Path>>(&self, from: P, to: Q) -> Result<()> {
+        let (from, to) = (from.as_ref(), to.as_ref());
+        self.check_parent(to)?;
+        let mut inner = self.inner_mut();
+        match inner.files.remove(from) {
+            Some(content) => {
+                inner.files.insert(to.to_path_buf(), content);
+                if inner.executable.remove(from) {
+                    inner.executable.insert(to.to_path_buf());
+                } else {
+                    inner.executable.remove(to);
+                }
+                inner.modified.remove(from);
+                inner.touch(to);
+                Ok(())
+            }
+            None => Err(MemFs::not_found(from))
+        }
+    }
+
+    fn remove_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let mut inner = self.inner_mut();
+        inner.modified.remove(path);
+        inner.executable.remove(path);
+        match inner.files.remove(path).or_else(|| inner.links.remove(path)) {
+            Some(_) => Ok(()),
+            None => Err(MemFs::not_found(path))
+        }
+    }
+
+    fn remove_dir<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        if self.read_dir(path).next().is_some() {
+            return Err(MemFs::io_error(std::io::ErrorKind::Other,
+                                       format!("{:?} is not empty", path)));
+        }
+        if self.inner_mut().dirs.remove(path) {
+            Ok(())
+        } else {
+            Err(MemFs::not_found(path))
+        }
+    }
+
+    fn sync<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        if self.is_file(path) || self.is_dir(path) {
+            Ok(())
+        } else {
+            Err(MemFs::not_found(path))
+        }
+    }
 }
 
 #[cfg(test)]
@@ -210,4 +589,64 @@ mod tests {
         assert_eq!(fs.ls_files("/test/2/1").count(), 1);
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn fs_mem_fs_write() {
+        let fs = MemFs::default();
+        assert!(fs.write_file("/repo/config", b"").is_err());
+        fs.create_dir_all("/repo/refs/heads").unwrap();
+        assert!(fs.is_dir("/repo/refs"));
+        fs.write_atomic("/repo/refs/heads/master", b"1234\n").unwrap();
+        assert_eq!(fs.read_dir("/repo/refs/heads").collect::<Vec<_>>(),
+                   vec![PathBuf::from("/repo/refs/heads/master")]);
+        let meta = fs.metadata("/repo/refs/heads/master").unwrap();
+        assert_eq!(meta.file_type, FileType::File);
+        assert_eq!(meta.len, 5);
+        assert!(fs.create_new("/repo/refs/heads/master").is_err());
+        assert!(fs.remove_dir("/repo/refs/heads").is_err());
+        fs.rename("/repo/refs/heads/master", "/repo/refs/heads/main").unwrap();
+        fs.remove_file("/repo/refs/heads/main").unwrap();
+        fs.remove_dir("/repo/refs/heads").unwrap();
+        assert!(!fs.is_dir("/repo/refs/heads"));
+
+        fs.create_dir_all("/repo").unwrap();
+        fs.create_file("/repo/hook", b"#!/bin/sh\n", true).unwrap();
+        assert_eq!(fs.metadata("/repo/hook").unwrap().mode, 0o100755);
+        assert!(fs.create_file("/repo/hook", b"", false).is_err());
+        fs.symlink(b"hook", "/repo/link").unwrap();
+        assert_eq!(fs.metadata("/repo/link").unwrap().file_type, FileType::Symlink);
+        assert_eq!(fs.read_link("/repo/link").unwrap(), b"hook");
+        assert!(!fs.is_file("/repo/link"));
+        fs.remove_file("/repo/link").unwrap();
+        assert!(fs.read_link("/repo/link").is_err());
+    }
+
+    #[test]
+    fn fs_os_fs_write() {
+        let dir = std::env::temp_dir().join(format!("rust-git-fs-{}", std::process::id()));
+        let fs = OsFs::new(&dir);
+        fs.create_dir_all(dir.join("refs")).unwrap();
+        let file = dir.join("refs").join("master");
+        fs.write_atomic(&file, b"abc").unwrap();
+        assert!(fs.create_new(&file).is_err());
+        let meta = fs.metadata(&file).unwrap();
+        assert_eq!(meta.file_type, FileType::File);
+        assert_eq!(meta.len, 3);
+        assert_eq!(fs.read_dir(dir.join("refs")).count(), 1);
+        fs.sync(&file).unwrap();
+        #[cfg(unix)]
+        {
+            let hook = dir.join("hook");
+            fs.create_file(&hook, b"#!/bin/sh\n", true).unwrap();
+            assert_ne!(fs.metadata(&hook).unwrap().mode & 0o100, 0);
+            let link = dir.join("link");
+            fs.symlink(b"hook", &link).unwrap();
+            assert_eq!(fs.metadata(&link).unwrap().file_type, FileType::Symlink);
+            assert_eq!(fs.read_link(&link).unwrap(), b"hook");
+            fs.remove_file(&link).unwrap();
+            fs.remove_file(&hook).unwrap();
+        }
+        fs.remove_file(&file).unwrap();
+        fs.remove_dir(dir.join("refs")).unwrap();
+        fs.remove_dir(&dir).unwrap();
+    }
+}
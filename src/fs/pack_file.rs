@@ -3,39 +3,96 @@ use nom::IResult;
 use nom::bytes::complete::{tag, take};
 use nom::number::complete::be_u32;
 use crate::errors::*;
-use crate::model::id::Id;
+use crate::model::id::{Id, ObjectFormat};
 use crate::model::object::{GitObject, ObjectType, ObjectHeader};
 use std::io::{BufReader, Read, Write};
 use flate2::read::ZlibDecoder;
+use flate2::Crc;
+use crate::fs::checksum::Hasher;
 use crate::fs::locator::Locator;
 
-use crate::model::tree::parse_id;
+use crate::model::tree::parse_id_of;
 use std::cmp::min;
 use crate::fs::content_reader::ContentReader;
-use std::mem;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use crate::fs::FileSystem;
+
+/// One object of a pack as `git verify-pack -v` lists it. `size` is the
+/// inflated size of the entry, which for deltas is the size of the delta.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackEntry {
+    pub id: Id,
+    pub object_type: ObjectType,
+    pub size: usize,
+    pub size_in_pack: usize,
+    pub offset: usize,
+    /// The length of the delta chain, 0 for whole objects.
+    pub depth: usize,
+    pub base: Option<Id>,
+}
+
+/// Every entry in pack order and how many objects have each delta chain
+/// length, whole objects counted under 0.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PackStats {
+    pub entries: Vec<PackEntry>,
+    pub chain_lengths: BTreeMap<usize, usize>,
+}
 
 pub struct PackFile {
     mmap: Box<dyn AsRef<[u8]>>,
     idx: Option<PackIdx>,
     version: u32,
     count: u32,
+    format: ObjectFormat,
+    /// Names the pack in errors.
+    path: PathBuf,
 }
 
 impl PackFile {
     pub fn try_from(mmap: Box<dyn AsRef<[u8]>>) -> Result<Self> {
+        PackFile::with_format(mmap, ObjectFormat::Sha1)
+    }
+
+    /// Opens a pack whose ids and trailing checksum use `format`.
+    pub fn with_format(mmap: Box<dyn AsRef<[u8]>>, format: ObjectFormat) -> Result<Self> {
+        let mut packfile = PackFile::parse(mmap, format, PathBuf::from("pack"))?;
+        packfile.path = PathBuf::from(format!("pack-{}.pack", packfile.id()));
+        Ok(packfile)
+    }
+
+    fn parse(mmap: Box<dyn AsRef<[u8]>>, format: ObjectFormat, path: PathBuf) -> Result<Self> {
         let input = (*mmap).as_ref();
-        let (_, (version, count)) = parse_header(input)
-            .map_err(|_| ErrorKind::InvalidPackfile)?;
-        let len = input.len();
-        let bytes = &input[(len - 20)..len];
-        let _id = Id::new(bytes);
+        let (_, (version, count)) = match parse_header(input) {
+            Ok(header) => header,
+            Err(_) => return Err(ErrorKind::InvalidPackfile(path, "bad pack header".to_owned()).into()),
+        };
+        if input.len() < 12 + format.hash_len() {
+            return Err(ErrorKind::InvalidPackfile(path, "pack too short".to_owned()).into());
+        }
         Ok(PackFile {
             mmap,
             version,
             count,
+            format,
+            path,
             idx: None,
         })
     }
+
+    /// Maps the pack at `path` and loads the `.idx` next to it, if any.
+    pub fn open<FS: FileSystem>(fs: &FS, path: &Path, format: ObjectFormat) -> Result<Self> {
+        let mut packfile = PackFile::parse(fs.map_file(path)?, format, path.to_path_buf())?;
+        let idx_path = path.with_extension("idx");
+        if fs.is_file(&idx_path) {
+            let idx = PackIdx::read(fs.read_file(&idx_path)?, format)
+                .chain_err(|| format!("{}: bad pack index", idx_path.display()))?;
+            packfile.load_idx(idx);
+        }
+        Ok(packfile)
+    }
+
     pub fn load_idx(&mut self, idx: PackIdx) {
         self.idx = Some(idx)
     }
@@ -45,35 +102,154 @@ impl PackFile {
     pub fn count(&self) -> u32 {
         self.count
     }
+    pub fn format(&self) -> ObjectFormat {
+        self.format
+    }
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
     pub fn id(&self) -> Id {
         let input = (*(self.mmap)).as_ref();
         let len = input.len();
-        let bytes = &input[(len - 20)..len];
+        let bytes = &input[(len - self.format.hash_len())..len];
         Id::new(bytes)
     }
 
+    /// Recomputes the checksum over the pack contents and compares it with
+    /// the trailing one.
+    pub fn verify_checksum(&self) -> Result<()> {
+        let input = (*(self.mmap)).as_ref();
+        let mut hasher = Hasher::new(self.format);
+        hasher.update(&input[..input.len() - self.format.hash_len()]);
+        if hasher.digest() != self.id() {
+            return Err(ErrorKind::ChecksumMismatch(self.path.clone()).into());
+        }
+        Ok(())
+    }
+
+    /// Checks the trailing checksum, that the index belongs to this pack and
+    /// the CRC32 the index records for every entry.
+    pub fn verify(&self) -> Result<()> {
+        self.verify_checksum()?;
+        let idx = self.idx.as_ref().ok_or_else(|| self.invalid("pack has no index"))?;
+        if idx.pack_id() != &self.id() {
+            return Err(self.invalid("pack checksum does not match its index"));
+        }
+        let mut entries: Vec<(&Id, usize, Option<u32>)> = idx.iter().collect();
+        entries.sort_by_key(|entry| entry.1);
+        for (i, (id, offset, crc)) in entries.iter().enumerate() {
+            let end = entries.get(i + 1).map(|entry| entry.1).unwrap_or_else(|| self.data_end());
+            if let Some(crc) = crc {
+                if self.crc32(*offset, end) != Some(*crc) {
+                    return Err(self.corrupt(*offset, &format!("index CRC mismatch for object {}", id)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The CRC32 of the raw bytes between two offsets, as recorded in
+    /// version 2 indexes for every entry.
+    pub fn crc32(&self, start: usize, end: usize) -> Option<u32> {
+        let input = (*(self.mmap)).as_ref();
+        let mut crc = Crc::new();
+        crc.update(input.get(start..end)?);
+        Some(crc.sum())
+    }
+
+    /// The raw bytes of the pack.
+    pub(crate) fn data(&self) -> &[u8] {
+        (*(self.mmap)).as_ref()
+    }
+
+    /// Where the object entries end and the trailing checksum begins.
+    pub fn data_end(&self) -> usize {
+        (*(self.mmap)).as_ref().len() - self.format.hash_len()
+    }
+
+    /// Lists every entry with its delta chain, like `git verify-pack -v`.
+    /// Needs the index to know where entries start.
+    pub fn stats(&self) -> Result<PackStats> {
+        let idx = self.idx.as_ref().ok_or_else(|| self.invalid("pack has no index"))?;
+        let mut offsets: Vec<(usize, &Id)> = idx.iter().map(|(id, offset, _)| (offset, id)).collect();
+        offsets.sort_by_key(|entry| entry.0);
+        let ids: HashMap<usize, &Id> = offsets.iter().cloned().collect();
+
+        let mut entries: Vec<PackEntry> = Vec::with_capacity(offsets.len());
+        let mut depths: HashMap<usize, usize> = HashMap::new();
+        for (i, (offset, id)) in offsets.iter().enumerate() {
+            let end = offsets.get(i + 1).map(|next| next.0).unwrap_or_else(|| self.data_end());
+            let (locator, object_type, size) = self.read_object(*offset)?;
+            let base_offset = match &locator {
+                Locator::PackOfs(_, _, base_offset) => Some(*base_offset),
+                Locator::PackRef(_, _, base) => idx.lookup(base).map(|found| found.1),
+                _ => None,
+            };
+            let base = match &locator {
+                Locator::PackRef(_, _, base) => Some(base.clone()),
+                _ => base_offset.and_then(|base_offset| ids.get(&base_offset)).map(|base| (*base).clone()),
+            };
+            entries.push(PackEntry {
+                id: (*id).clone(),
+                object_type,
+                size,
+                size_in_pack: end.saturating_sub(*offset),
+                offset: *offset,
+                depth: 0,
+                base,
+            });
+            if let Some(base_offset) = base_offset {
+                depths.insert(*offset, base_offset);
+            }
+        }
+
+        let mut stats = PackStats::default();
+        for mut entry in entries {
+            let mut offset = entry.offset;
+            while let Some(base_offset) = depths.get(&offset) {
+                entry.depth += 1;
+                offset = *base_offset;
+                if entry.depth > depths.len() {
+                    return Err(self.corrupt(entry.offset, "delta chain loop"));
+                }
+            }
+            if entry.depth == 0 && entry.base.is_some() {
+                // a base outside the pack still makes a delta
+                entry.depth = 1;
+            }
+            *stats.chain_lengths.entry(entry.depth).or_default() += 1;
+            stats.entries.push(entry);
+        }
+        Ok(stats)
+    }
+
     pub fn idx(&self)  -> &Option<PackIdx> {
         &self.idx
     }
 
     pub fn find_object(&self, id: &Id) -> Option<GitObject> {
+        self.lookup_object(id).ok().flatten()
+    }
+
+    /// Like `find_object`, but tells an entry that fails to parse from one
+    /// that is not in the pack.
+    pub fn lookup_object(&self, id: &Id) -> Result<Option<GitObject>> {
         if let Some(idx) = &self.idx {
             if let Some((id, offset)) = idx.lookup(&id) {
-                let (locator, object_type, object_length)
-                    = self.read_object(offset).expect("parse object failed");
+                let (locator, object_type, object_length) = self.read_object(offset)?;
                 let header = ObjectHeader {
                     object_type,
                     length: object_length,
                 };
-                return Some(GitObject::new(&id.clone(), header, locator));
+                return Ok(Some(GitObject::new(&id.clone(), header, locator)));
             }
         }
-        None
+        Ok(None)
     }
 
     pub fn write_object(&self, offset: usize, range: (usize, usize), writer: &mut dyn Write) -> Result<u64> {
         let mmap = (*(self.mmap)).as_ref();
-        let input = &mmap[offset..];
+        let input = mmap.get(offset..).ok_or_else(|| self.corrupt(offset, "entry out of range"))?;
         let mut reader = BufReader::new(
             ZlibDecoder::new(BufReader::new(input))
         );
@@ -86,41 +262,81 @@ impl PackFile {
 
     pub fn read_object(&self, from_offset: usize) -> Result<(Locator, ObjectType, usize)> {
         let mmap = (*(self.mmap)).as_ref();
-        let input = &mmap[from_offset..];
-        match parse_object_header(input) {
-            Ok((input, (pack_object_type, object_length))) => {
-                let object_type = match pack_object_type {
-                    PackObjectType::COMMIT => ObjectType::COMMIT,
-                    PackObjectType::TAG => ObjectType::TAG,
-                    PackObjectType::TREE => ObjectType::TREE,
-                    _ => ObjectType::BLOB,
-                };
-                let locator = match pack_object_type {
-                    PackObjectType::OFS_DELTA => {
-                        let (input, delta_offset) = parse_offset(input)
-                            .map_err(|_|ErrorKind::ParseError)?;
-                        let data_offset = input.as_ptr() as usize - mmap.as_ptr() as usize;
-                        Locator::PackOfs(self.id(), data_offset, from_offset - delta_offset)
-                    },
-                    PackObjectType::REF_DELTA => {
-                        let (input, ref_id) = parse_id(input)
-                            .map_err(|_|ErrorKind::ParseError)?;
-                        let data_offset = input.as_ptr() as usize - mmap.as_ptr() as usize;
-                        Locator::PackRef(self.id(), data_offset, ref_id)
-                    }
-                    _ => {
-                        let data_offset = input.as_ptr() as usize - mmap.as_ptr() as usize;
-                        Locator::Packfile(self.id(), data_offset)
-                    }
-                };
-                Ok((locator, object_type, object_length))
+        let (input, (pack_object_type, object_length)) = self.parse_entry(from_offset)?;
+        let locator = match pack_object_type {
+            PackObjectType::OFS_DELTA => {
+                let (input, delta_offset) = parse_offset(input)
+                    .map_err(|_| self.corrupt(from_offset, "truncated delta base offset"))?;
+                let data_offset = input.as_ptr() as usize - mmap.as_ptr() as usize;
+                let base_offset = from_offset.checked_sub(delta_offset)
+                    .filter(|_| delta_offset > 0)
+                    .ok_or_else(|| self.corrupt(from_offset, "delta base offset out of range"))?;
+                Locator::PackOfs(self.id(), data_offset, base_offset)
             },
-            Err(_e) => Err(ErrorKind::ParseError.into())
+            PackObjectType::REF_DELTA => {
+                let (input, ref_id) = parse_id_of(self.format)(input)
+                    .map_err(|_| self.corrupt(from_offset, "truncated delta base id"))?;
+                let data_offset = input.as_ptr() as usize - mmap.as_ptr() as usize;
+                Locator::PackRef(self.id(), data_offset, ref_id)
+            }
+            _ => {
+                let data_offset = input.as_ptr() as usize - mmap.as_ptr() as usize;
+                Locator::Packfile(self.id(), data_offset)
+            }
+        };
+        let object_type = self.resolve_type(from_offset)?;
+        Ok((locator, object_type, object_length))
+    }
+
+    fn parse_entry(&self, offset: usize) -> Result<(&[u8], (PackObjectType, usize))> {
+        let mmap = (*(self.mmap)).as_ref();
+        let input = mmap.get(offset..).ok_or_else(|| self.corrupt(offset, "entry out of range"))?;
+        parse_object_header(input).map_err(|_| self.corrupt(offset, "invalid entry header"))
+    }
+
+    /// The type of the object at `offset`, following delta bases within
+    /// this pack: a delta has the type of the object it is based on.
+    fn resolve_type(&self, mut offset: usize) -> Result<ObjectType> {
+        for _ in 0..=self.count {
+            let (input, (pack_object_type, _)) = self.parse_entry(offset)?;
+            offset = match pack_object_type {
+                PackObjectType::COMMIT => return Ok(ObjectType::COMMIT),
+                PackObjectType::TREE => return Ok(ObjectType::TREE),
+                PackObjectType::BLOB => return Ok(ObjectType::BLOB),
+                PackObjectType::TAG => return Ok(ObjectType::TAG),
+                PackObjectType::OFS_DELTA => {
+                    let (_, delta_offset) = parse_offset(input)
+                        .map_err(|_| self.corrupt(offset, "truncated delta base offset"))?;
+                    offset.checked_sub(delta_offset)
+                        .filter(|_| delta_offset > 0)
+                        .ok_or_else(|| self.corrupt(offset, "delta base offset out of range"))?
+                }
+                PackObjectType::REF_DELTA => {
+                    let (_, ref_id) = parse_id_of(self.format)(input)
+                        .map_err(|_| self.corrupt(offset, "truncated delta base id"))?;
+                    // like git, only thin packs in transit may lean on
+                    // objects elsewhere
+                    match self.idx.as_ref().and_then(|idx| idx.lookup(&ref_id)) {
+                        Some((_, base_offset)) => base_offset,
+                        None => return Err(self.corrupt(offset, &format!("delta base {} is not in the pack", ref_id))),
+                    }
+                }
+            };
         }
+        Err(self.corrupt(offset, "delta chain loop"))
+    }
+
+    fn corrupt(&self, offset: usize, reason: &str) -> Error {
+        ErrorKind::CorruptPackEntry(self.path.clone(), offset, reason.to_owned()).into()
+    }
+
+    fn invalid(&self, reason: &str) -> Error {
+        ErrorKind::InvalidPackfile(self.path.clone(), reason.to_owned()).into()
     }
 
     pub fn read_object_content(&self, from_offset: usize, size: usize) -> Result<ContentReader> {
-        let pack_reader = PackZlibReader::new(self, from_offset, size);
+        let pack_reader = PackZlibReader::new(self, from_offset, size)
+            .ok_or_else(|| self.corrupt(from_offset, "entry out of range"))?;
         Ok(ContentReader::from_pack(pack_reader))
     }
 }
@@ -133,20 +349,19 @@ pub struct PackZlibReader<'a> {
 }
 
 impl <'a> PackZlibReader<'a> {
-    fn new(pack: &'a PackFile, offset: usize, size: usize) -> Self {
+    fn new(pack: &'a PackFile, offset: usize, size: usize) -> Option<Self> {
         let mmap = (*(pack.mmap)).as_ref();
-        let input = &mmap[offset..];
+        let input = mmap.get(offset..)?;
         let reader = ZlibDecoder::new(input);
-        Self {
+        Some(Self {
             input,
             reader,
             size,
             pos: 0
-        }
+        })
     }
     pub(crate) fn reset(&mut self) {
-        let input = self.input;
-        mem::replace(&mut self.reader, ZlibDecoder::new(input));
+        self.reader = ZlibDecoder::new(self.input);
         self.pos = 0;
     }
 }
@@ -173,7 +388,7 @@ fn parse_header(input: &[u8]) -> IResult<&[u8], (u32, u32)> {
     Ok((input, (version, count)))
 }
 
-fn parse_object_header(input: &[u8]) -> IResult<&[u8], (PackObjectType, usize)> {
+pub(crate) fn parse_object_header(input: &[u8]) -> IResult<&[u8], (PackObjectType, usize)> {
     let _header_bytes: Vec<u8> = vec![];
     let _size = 0u64;
     let (mut input, byte) = take(1u8)(input)?;
@@ -193,6 +408,9 @@ fn parse_object_header(input: &[u8]) -> IResult<&[u8], (PackObjectType, usize)>
     let mut size = (byte & 0b0000_1111) as usize;
     let mut shift = 4;
     while byte >= 128u8 {
+        if shift > 57 {
+            return Err(nom::Err::Failure(nom::error::make_error(input, nom::error::ErrorKind::TooLarge)));
+        }
         let (rest, b) = take(1u8)(input)?;
         byte = b[0];
         input = rest;
@@ -209,7 +427,9 @@ fn parse_offset(input: &[u8]) -> IResult<&[u8], usize> {
     let mut byte = byte[0];    
     let mut offset = (byte & 0b0111_1111) as usize;
     while byte >= 128u8 {
-        offset += 1;
+        offset = offset.checked_add(1)
+            .filter(|offset| offset.leading_zeros() >= 7)
+            .ok_or_else(|| nom::Err::Failure(nom::error::make_error(input, nom::error::ErrorKind::TooLarge)))?;
         offset <<= 7;
         let (rest, b) = take(1u8)(input)?;
         byte = b[0];
@@ -220,7 +440,7 @@ fn parse_offset(input: &[u8]) -> IResult<&[u8], usize> {
     Ok((input, offset))
 }
 
-enum PackObjectType {
+pub(crate) enum PackObjectType {
     COMMIT = 1,
     TREE = 2,
     BLOB = 3,
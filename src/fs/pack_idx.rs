@@ -5,9 +5,9 @@ use crate::fs::SeekRead;
 use nom::IResult;
 use nom::multi::count;
 use nom::number::complete::{be_u32, be_u64};
-use crate::model::id::Id;
-use crate::model::tree::parse_id;
-use crate::fs::checksum::Sha1Reader;
+use crate::model::id::{Id, ObjectFormat};
+use crate::model::tree::parse_id_of;
+use crate::fs::checksum::HashReader;
 
 use std::cmp::Ordering;
 
@@ -47,16 +47,48 @@ impl PackIdx {
         }
     }
 
+    pub fn len(&self) -> usize {
+        match self {
+            PackIdx::V1(a) => a.objects.len(),
+            PackIdx::V2(a) => a.objects.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Every object in id order with its pack offset and, for version 2,
+    /// the CRC32 of its packed bytes.
+    pub fn iter(&self) -> impl Iterator<Item=(&Id, usize, Option<u32>)> + '_ {
+        let (offsets, objects) = match self {
+            PackIdx::V1(a) => (&a.offsets, &a.objects),
+            PackIdx::V2(a) => (&a.offsets, &a.objects),
+        };
+        objects.iter().zip(offsets.iter()).enumerate()
+            .filter_map(move |(i, (id, offset))| {
+                let crc = match self {
+                    PackIdx::V2(a) => a.crcs.get(i).cloned(),
+                    PackIdx::V1(_) => None,
+                };
+                Some((id, self.offset(*offset)?, crc))
+            })
+    }
+
     pub fn lookup(&self, id: &Id) -> Option<(Id, usize)> {
         let (fanout, offsets, objects) = match self {
             PackIdx::V1(a) => (&a.fanout, &a.offsets, &a.objects),
             PackIdx::V2(a) => (&a.fanout, &a.offsets, &a.objects),
         };
-        let first_byte = id.bytes()[0] as usize;
+        let first_byte = *id.bytes().first()? as usize;
         let mut lo = if first_byte > 0 {
             fanout[first_byte - 1] as usize
         } else { 0usize };
-        let mut hi = fanout[first_byte] as usize;
+        let mut hi = if first_byte < fanout.len() {
+            fanout[first_byte] as usize
+        } else {
+            objects.len()
+        };
         let len = objects.len();
         loop {
             let mid = ((hi + lo) / 2) as usize;
@@ -68,8 +100,7 @@ impl PackIdx {
                 Some(Ordering::Less) => { hi = mid }
                 Some(Ordering::Greater) => { lo = mid + 1 }
                 Some(Ordering::Equal) =>  {
-                    let offset = offsets[mid] as usize;
-                    return Some((mid_id.clone(), offset));
+                    return Some((mid_id.clone(), self.offset(offsets[mid])?));
                 },
                 _ => return None,
             }
@@ -80,40 +111,72 @@ impl PackIdx {
     }
 }
 
-fn verify_checksum(f: &mut Sha1Reader<Box<dyn SeekRead>>) -> Result<()> {
-    let sha1_checksum = f.digest();
-    let mut checksum = [0u8; 20];
-    f.read_exact(&mut checksum)?;
-    if sha1_checksum != checksum {
-        return Err(ErrorKind::ChecksumMismatch.into());
+fn verify_checksum(f: &mut HashReader<Box<dyn SeekRead>>, format: ObjectFormat) -> Result<()> {
+    let digest = f.digest();
+    let checksum = read_id(f, format)?;
+    if digest != checksum {
+        return Err(invalid("checksum mismatch"));
     }
     Ok(())
 }
 
-fn get_pack_id(f: &mut Sha1Reader<Box<dyn SeekRead>>) -> Result<Id> {
-    let mut buf = [0u8; 20];
+fn invalid(reason: &str) -> Error {
+    ErrorKind::InvalidPackIndex(reason.to_owned()).into()
+}
+
+/// Reads `len` bytes without allocating them up front, so a corrupt count
+/// fails on the short read instead of exhausting memory.
+fn read_bytes(f: &mut HashReader<Box<dyn SeekRead>>, len: usize) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    f.take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() != len {
+        return Err(invalid("truncated index"));
+    }
+    Ok(buf)
+}
+
+fn read_id(f: &mut HashReader<Box<dyn SeekRead>>, format: ObjectFormat) -> Result<Id> {
+    let mut buf = vec![0u8; format.hash_len()];
     f.read_exact(&mut buf)?;
     Ok(Id::new(&buf))
 }
 
-impl TryFrom<Box<dyn SeekRead>> for PackIdx where {
-    type Error = Error;
-
-    fn try_from(f: Box<dyn SeekRead>) -> Result<Self> {
-        let mut f = Sha1Reader::new(f);
+impl PackIdx {
+    /// Parses an index whose ids are hashed with `format`. SHA-256
+    /// repositories use the same version 2 layout with 32-byte ids.
+    pub fn read(f: Box<dyn SeekRead>, format: ObjectFormat) -> Result<Self> {
+        let mut f = HashReader::new(f, format);
         let mut magic = [0u8; 4];
         f.read_exact(&mut magic)?;
         let mut version = [0u8; 4];
         f.read_exact(&mut version)?;
 
         if &magic != b"\xfftOc" {
-            return parse_idx_v1(&mut f, magic, version);
+            return parse_idx_v1(&mut f, magic, version, format);
         }
 
         if version != [0u8, 0u8, 0u8, 2u8] {
-            return Err(ErrorKind::UnsupportedPackIndexVersion.into());
+            return Err(ErrorKind::UnsupportedPackIndexVersion(u32::from_be_bytes(version)).into());
+        }
+        parse_idx_v2(&mut f, format)
+    }
+
+    /// Resolves an offset table entry, which in version 2 may point into
+    /// the table of 64-bit offsets.
+    fn offset(&self, offset: u32) -> Option<usize> {
+        match self {
+            PackIdx::V2(idx) if offset & 0x8000_0000 != 0 =>
+                idx.large_offsets.get((offset & 0x7fff_ffff) as usize).map(|o| *o as usize),
+            _ => Some(offset as usize),
         }
-        parse_idx_v2(&mut f)
+    }
+}
+
+impl TryFrom<Box<dyn SeekRead>> for PackIdx where {
+    type Error = Error;
+
+    fn try_from(f: Box<dyn SeekRead>) -> Result<Self> {
+        PackIdx::read(f, ObjectFormat::Sha1)
     }
 }
 
@@ -133,23 +196,18 @@ fn parse_crc(input: &[u8]) -> IResult<&[u8], u32> {
     be_u32(input)
 }
 
-fn parse_entry(input: &[u8]) -> IResult<&[u8], (u32, Id)> {
-    let (input, offset) = parse_offset(input)?;
-    let (input, id) = parse_id(input)?;
-    Ok((input, (offset, id)))
-}
-
-fn parse_entries(input: &[u8], size: usize) -> IResult<&[u8], (Vec<u32>, Vec<Id>)> {
+fn parse_entries(input: &[u8], size: usize, format: ObjectFormat) -> IResult<&[u8], (Vec<u32>, Vec<Id>)> {
     let mut rest = input;
     let mut objects = Vec::with_capacity(size);
     let mut offsets = Vec::with_capacity(size);
-    for i in 0..size {
-        let (input, (offset, id)) = parse_entry(rest)?;
-        objects[i] = id;
-        offsets[i] = offset;
+    for _ in 0..size {
+        let (input, offset) = parse_offset(rest)?;
+        let (input, id) = parse_id_of(format)(input)?;
+        objects.push(id);
+        offsets.push(offset);
         rest = input;
     }
-    Ok((input, (offsets, objects)))
+    Ok((rest, (offsets, objects)))
 }
 
 fn parse_fanout(input: &[u8]) -> IResult<&[u8], ([u32; 255], usize)> {
@@ -161,26 +219,28 @@ fn parse_fanout(input: &[u8]) -> IResult<&[u8], ([u32; 255], usize)> {
         rest = input;
     }
     let (input, size) = be_u32(rest)?;
+    // lookups index the object table with these, so they must not decrease
+    if fanout.windows(2).any(|w| w[0] > w[1]) || fanout[254] > size {
+        return Err(nom::Err::Failure(nom::error::make_error(input, nom::error::ErrorKind::Verify)));
+    }
     Ok((input, (fanout, size as usize)))
 }
 
 
-fn parse_idx_v1(mut f: &mut Sha1Reader<Box<dyn SeekRead>>, magic: [u8; 4], version: [u8; 4]) -> Result<PackIdx> {
+fn parse_idx_v1(mut f: &mut HashReader<Box<dyn SeekRead>>, magic: [u8; 4], version: [u8; 4],
+                format: ObjectFormat) -> Result<PackIdx> {
     // parse v1
     let mut fanout_buf = [0u8; 256 * 4];
     fanout_buf[0..4].clone_from_slice(&magic);
     fanout_buf[4..8].clone_from_slice(&version);
     f.read_exact(&mut fanout_buf[8..])?;
     let (_, (fanout, size)) = parse_fanout(&fanout_buf)
-        .map_err(|_| ErrorKind::ParseError)?;
-    let mut buf = Vec::with_capacity(24 * size);
-    f.read_exact(buf.as_mut_slice())?;
-    let (_, (offsets, objects)) = parse_entries(buf.as_slice(), size)
-        .map_err(|_| ErrorKind::ParseError)?;
-    let mut buf = [0u8; 20];
-    f.read_exact(&mut buf)?;
-    let pack_id = get_pack_id(&mut f)?;
-    verify_checksum(&mut f)?;
+        .map_err(|_| invalid("bad fanout table"))?;
+    let buf = read_bytes(f, (4 + format.hash_len()) * size)?;
+    let (_, (offsets, objects)) = parse_entries(buf.as_slice(), size, format)
+        .map_err(|_| invalid("bad object table"))?;
+    let pack_id = read_id(&mut f, format)?;
+    verify_checksum(&mut f, format)?;
     Ok(PackIdx::V1(PackIdxV1 {
         pack_id,
         fanout,
@@ -190,35 +250,32 @@ fn parse_idx_v1(mut f: &mut Sha1Reader<Box<dyn SeekRead>>, magic: [u8; 4], versi
 }
 
 
-fn parse_idx_v2(mut f: &mut Sha1Reader<Box<dyn SeekRead>>) -> Result<PackIdx> {
+fn parse_idx_v2(mut f: &mut HashReader<Box<dyn SeekRead>>, format: ObjectFormat) -> Result<PackIdx> {
 // parse v2
     let mut fanout_buf = [0u8; 256 * 4];
     f.read_exact(&mut fanout_buf)?;
     let (_, (fanout, size)) = parse_fanout(&fanout_buf)
-        .map_err(|_| ErrorKind::ParseError)?;
-    let mut buf = vec![0u8; 20 * size];
-    f.read_exact(&mut buf)?;
-    let (_, objects) = count(parse_id, size)(&buf)
-        .map_err(|_| ErrorKind::ParseError)?;
-    let mut buf = vec![0u8; 4 * size];
-    f.read_exact(&mut buf)?;
+        .map_err(|_| invalid("bad fanout table"))?;
+    let buf = read_bytes(f, format.hash_len() * size)?;
+    let (_, objects) = count(parse_id_of(format), size)(&buf)
+        .map_err(|_| invalid("bad object name table"))?;
+    let buf = read_bytes(f, 4 * size)?;
     let (_, crcs) = count(parse_crc, size)(&buf)
-        .map_err(|_| ErrorKind::ParseError)?;
-    f.read_exact(&mut buf)?;
+        .map_err(|_| invalid("bad CRC table"))?;
+    let buf = read_bytes(f, 4 * size)?;
     let (_, offsets) = count(parse_offset, size)(buf.as_slice())
-        .map_err(|_| ErrorKind::ParseError)?;
+        .map_err(|_| invalid("bad offset table"))?;
     let large_offset_count: usize = offsets.iter().filter(|o| *o & 0x8000_0000 > 0).count();
     let large_offsets: Vec<u64> = if large_offset_count > 0 {
-        let mut buf = vec![0u8; 8 * size];
-        f.read_exact(&mut buf)?;
+        let buf = read_bytes(f, 8 * large_offset_count)?;
         let (_, offsets) = count(parse_large_offset, large_offset_count)(&buf)
-            .map_err(|_| ErrorKind::ParseError)?;
+            .map_err(|_| invalid("bad large offset table"))?;
         offsets
     } else {
         vec![]
     };
-    let pack_id = get_pack_id(&mut f)?;
-    verify_checksum(&mut f)?;
+    let pack_id = read_id(&mut f, format)?;
+    verify_checksum(&mut f, format)?;
     Ok(PackIdx::V2(PackIdxV2 {
         pack_id,
         fanout,
@@ -0,0 +1,102 @@
+//! Entry points for the fuzz targets under `fuzz/`. Each hands arbitrary
+//! bytes to one reader or parser; returning errors is fine, panicking is
+//! the bug being looked for. Run one with `cargo +nightly fuzz run delta`
+//! from the repository root.
+
+use std::io::{Cursor, Read};
+use crate::fs::commit_graph::CommitGraph;
+use crate::fs::content_reader::ContentReader;
+use crate::fs::locator::Locator;
+use crate::fs::pack_file::{parse_object_header, PackFile};
+use crate::fs::pack_idx::PackIdx;
+use crate::model::commit::{parse_commit, parse_identity};
+use crate::model::id::{Id, ObjectFormat};
+use crate::model::object::{parse_header, ParseMode};
+use crate::model::refs::ReflogEntry;
+use crate::model::repository::{FileRepository, Repository};
+use crate::model::tag::parse_tag_object;
+use crate::model::tree::parse_tree;
+
+/// Bytes read from any one object, so sizes claimed by the input cannot
+/// make a run allocate or spin without bound.
+const READ_LIMIT: u64 = 1 << 20;
+
+/// Entry offsets tried per pack.
+const MAX_ENTRIES: usize = 256;
+
+fn drain(reader: impl Read) {
+    let _ = std::io::copy(&mut reader.take(READ_LIMIT), &mut std::io::sink());
+}
+
+/// `data` as a pack: every entry header from offset 12 on, and the content
+/// of whole objects.
+pub fn pack_entries(data: &[u8]) {
+    let _ = parse_object_header(data);
+    let pack = match PackFile::with_format(Box::new(data.to_vec()), ObjectFormat::Sha1) {
+        Ok(pack) => pack,
+        Err(_) => return,
+    };
+    let _ = pack.stats();
+    for offset in (12..pack.data_end()).take(MAX_ENTRIES) {
+        if let Ok((Locator::Packfile(_, data_offset), _, size)) = pack.read_object(offset) {
+            if let Ok(content) = pack.read_object_content(data_offset, size) {
+                drain(content);
+            }
+        }
+    }
+}
+
+/// `data` as a 2-byte big endian base length, the base and a delta on it.
+pub fn delta(data: &[u8]) {
+    if data.len() < 2 {
+        return;
+    }
+    let base_len = (usize::from(data[0]) << 8 | usize::from(data[1])).min(data.len() - 2);
+    let (base, delta) = data[2..].split_at(base_len);
+    let base = ContentReader::from_bytes(base.to_vec());
+    if let Ok(mut content) = ContentReader::from_bytes(delta.to_vec()).attach_base(base, 0) {
+        drain(&mut content);
+        if content.forward(0).is_ok() {
+            drain(content);
+        }
+    }
+}
+
+/// `data` through the parsers for object headers, commits, tags, trees,
+/// identities, reflog lines, pack indexes and commit-graphs.
+pub fn object_parsers(data: &[u8]) {
+    let sha1 = Id::new(&[0u8; 20]);
+    let sha256 = Id::new(&[0u8; 32]);
+    let _ = parse_header(data);
+    for &mode in &[ParseMode::Strict, ParseMode::Lenient] {
+        let _ = parse_commit(data, &sha1, mode);
+        let _ = parse_tag_object(data, &sha1, mode);
+    }
+    let _ = parse_tree(data, &sha1);
+    let _ = parse_tree(data, &sha256);
+    let _ = parse_identity(data);
+    let _ = ReflogEntry::parse(data);
+    let _ = PackIdx::read(Box::new(Cursor::new(data.to_vec())), ObjectFormat::Sha1);
+    let _ = CommitGraph::parse(data, ObjectFormat::Sha1);
+}
+
+/// `data` as the zlib stream of a loose object, read through the
+/// repository as a typed object and as a blob stream.
+pub fn loose_object(data: &[u8]) {
+    let id = "1234567890123456789012345678901234567890";
+    let mut repo = FileRepository::default();
+    repo.add_file(format!("objects/{}/{}", &id[..2], &id[2..]), data.to_vec());
+    let id: Id = match id.parse() {
+        Ok(id) => id,
+        Err(_) => return,
+    };
+    if let Ok(obj) = repo.find_object(&id) {
+        if let Ok(content) = repo.read_content_by_id(obj.id()) {
+            drain(content);
+        }
+    }
+    if let Ok(blob) = repo.open_blob(&id) {
+        drain(blob);
+    }
+    let _ = repo.read_object(&id);
+}
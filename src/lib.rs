@@ -1,3 +1,4 @@
+#![recursion_limit = "256"]
 #[macro_use]
 extern crate error_chain;
 extern crate nom;
@@ -6,5 +7,9 @@ pub mod errors;
 pub mod cmd;
 pub mod model;
 pub mod fs;
+pub mod config;
+pub mod wildmatch;
+#[doc(hidden)]
+pub mod fuzz;
 
 
@@ -3,24 +3,101 @@ extern crate structopt;
 use structopt::StructOpt;
 
 use rust_git::cmd::catfile::*;
+use rust_git::cmd::config::*;
+use rust_git::cmd::init::*;
+use rust_git::cmd::update_ref::*;
+use rust_git::cmd::symbolic_ref::*;
+use rust_git::cmd::reflog::*;
+use rust_git::cmd::fsck::*;
+use rust_git::cmd::verify_pack::*;
+use rust_git::cmd::verify_commit::*;
+use rust_git::cmd::verify_tag::*;
+use rust_git::cmd::merge_base::*;
+use rust_git::cmd::merge_tree::*;
+use rust_git::cmd::checkout::*;
+use rust_git::cmd::switch::*;
+use rust_git::cmd::archive::*;
+use rust_git::cmd::blame::*;
+use rust_git::cmd::log::*;
+use rust_git::cmd::show::*;
+use rust_git::cmd::for_each_ref::*;
+use rust_git::cmd::show_ref::*;
+use rust_git::cmd::describe::*;
+use rust_git::cmd::name_rev::*;
+use rust_git::cmd::grep::*;
 use rust_git::errors::*;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "git", about = "the rust git command")]
 enum Opt {
     #[structopt(name = "cat-file")]
-    CatFile(CatFileOpt)
+    CatFile(CatFileOpt),
+    #[structopt(name = "config")]
+    Config(ConfigOpt),
+    #[structopt(name = "init")]
+    Init(InitOpt),
+    #[structopt(name = "update-ref")]
+    UpdateRef(UpdateRefOpt),
+    #[structopt(name = "symbolic-ref")]
+    SymbolicRef(SymbolicRefOpt),
+    #[structopt(name = "reflog")]
+    Reflog(ReflogOpt),
+    #[structopt(name = "fsck")]
+    Fsck(FsckOpt),
+    #[structopt(name = "verify-pack")]
+    VerifyPack(VerifyPackOpt),
+    #[structopt(name = "verify-commit")]
+    VerifyCommit(VerifyCommitOpt),
+    #[structopt(name = "verify-tag")]
+    VerifyTag(VerifyTagOpt),
+    #[structopt(name = "merge-base")]
+    MergeBase(MergeBaseOpt),
+    #[structopt(name = "merge-tree")]
+    MergeTree(MergeTreeOpt),
+    #[structopt(name = "checkout")]
+    Checkout(CheckoutOpt),
+    #[structopt(name = "switch")]
+    Switch(SwitchOpt),
+    #[structopt(name = "archive")]
+    Archive(ArchiveOpt),
+    #[structopt(name = "blame")]
+    Blame(BlameOpt),
+    #[structopt(name = "log")]
+    Log(LogOpt),
+    #[structopt(name = "show")]
+    Show(ShowOpt),
+    #[structopt(name = "for-each-ref")]
+    ForEachRef(ForEachRefOpt),
+    #[structopt(name = "show-ref")]
+    ShowRef(ShowRefOpt),
+    #[structopt(name = "describe")]
+    Describe(DescribeOpt),
+    #[structopt(name = "name-rev")]
+    NameRev(NameRevOpt),
+    #[structopt(name = "grep")]
+    Grep(GrepOpt),
 }
 
 fn main() {
     if let Err(ref e) = run() {
-        use std::io::Write;
-        use error_chain::ChainedError; // trait which holds `display_chain`
-        let stderr = &mut ::std::io::stderr();
-        let errmsg = "Error writing to stderr";
+        ::std::process::exit(report(e));
+    }
+}
 
-        writeln!(stderr, "{}", e.display_chain()).expect(errmsg);
-        ::std::process::exit(1);
+/// Reports the error of a command the way git does and returns its exit
+/// code: a missing config key is a silent 1, refusing to overwrite work
+/// an `error:` with 1, anything else `fatal:` with 128.
+fn report(e: &Error) -> i32 {
+    match e.kind() {
+        ErrorKind::ConfigKeyNotFound(_) => 1,
+        ErrorKind::WouldOverwrite(_) | ErrorKind::UntrackedWouldBeOverwritten(_) => {
+            eprintln!("error: {}\nAborting", e);
+            1
+        }
+        _ => {
+            eprintln!("fatal: {}", e);
+            128
+        }
     }
 }
 
@@ -30,6 +107,28 @@ fn run() -> Result<()> {
     
     match opt {
         Opt::CatFile(opt) => cat_file(&mut clap, opt),
+        Opt::Config(opt) => config(opt),
+        Opt::Init(opt) => init(opt),
+        Opt::UpdateRef(opt) => update_ref(opt),
+        Opt::SymbolicRef(opt) => symbolic_ref(opt),
+        Opt::Reflog(opt) => reflog(opt),
+        Opt::Fsck(opt) => fsck(opt),
+        Opt::VerifyPack(opt) => verify_pack(opt),
+        Opt::VerifyCommit(opt) => verify_commit(opt),
+        Opt::VerifyTag(opt) => verify_tag(opt),
+        Opt::MergeBase(opt) => merge_base(opt),
+        Opt::MergeTree(opt) => merge_tree(opt),
+        Opt::Checkout(opt) => checkout(opt),
+        Opt::Switch(opt) => switch(opt),
+        Opt::Archive(opt) => archive(opt),
+        Opt::Blame(opt) => blame(opt),
+        Opt::Log(opt) => log(opt),
+        Opt::Show(opt) => show(opt),
+        Opt::ForEachRef(opt) => for_each_ref(opt),
+        Opt::ShowRef(opt) => show_ref(opt),
+        Opt::Describe(opt) => describe(opt),
+        Opt::NameRev(opt) => name_rev(opt),
+        Opt::Grep(opt) => grep(opt),
     }
 }
 
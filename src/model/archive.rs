@@ -0,0 +1,600 @@
+use std::io::Write;
+use std::str::FromStr;
+use chrono::{Local, TimeZone, Datelike, Timelike};
+use flate2::Compression;
+use flate2::write::{DeflateEncoder, GzEncoder};
+use crate::errors::*;
+use crate::fs::FileSystem;
+use crate::model::attributes::{AttrValue, Attributes};
+use crate::model::commit::Commit;
+use crate::model::id::Id;
+use crate::model::object::{Object, ObjectType};
+use crate::model::patch::is_binary;
+use crate::model::pretty::{format_commit, FormatContext};
+use crate::model::repository::{FileRepository, Repository};
+use crate::model::tree::{FileMode, Tree};
+use crate::wildmatch::wildmatch;
+
+const BLOCK: usize = 512;
+const RECORD: usize = 20 * BLOCK;
+/// The largest size and mtime a ustar header field holds.
+const USTAR_MAX: u64 = 0o77777777777;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArchiveFormat {
+    Tar,
+    TarGz,
+    Zip,
+}
+
+impl FromStr for ArchiveFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "tar" => Ok(ArchiveFormat::Tar),
+            "tgz" | "tar.gz" => Ok(ArchiveFormat::TarGz),
+            "zip" => Ok(ArchiveFormat::Zip),
+            _ => bail!("Unknown archive format '{}'", s),
+        }
+    }
+}
+
+impl ArchiveFormat {
+    pub const NAMES: &'static [&'static str] = &["tar", "tgz", "tar.gz", "zip"];
+
+    /// The format an output file name asks for, by its extension.
+    pub fn from_file_name(name: &str) -> Option<ArchiveFormat> {
+        ArchiveFormat::NAMES.iter()
+            .filter(|format| name.ends_with(&format!(".{}", format)))
+            .find_map(|format| format.parse().ok())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ArchiveOptions {
+    pub format: ArchiveFormat,
+    /// Prepended to every path; a trailing slash makes it a directory.
+    pub prefix: String,
+    /// Pathspecs limiting what goes in, everything when empty.
+    pub paths: Vec<String>,
+}
+
+impl Default for ArchiveOptions {
+    fn default() -> Self {
+        ArchiveOptions { format: ArchiveFormat::Tar, prefix: String::new(), paths: vec![] }
+    }
+}
+
+/// What goes into an archive entry: nothing for a directory, otherwise
+/// the blob, or its content after `export-subst`.
+enum EntryData<'a> {
+    Empty,
+    Blob(&'a Id),
+    Bytes(Vec<u8>),
+}
+
+trait ArchiveWriter {
+    /// `binary` is what the attributes say about the entry being binary,
+    /// `None` leaving it to the content.
+    fn entry(&mut self, repo: &dyn Repository, path: &str, mode: &FileMode, id: &Id, data: EntryData,
+             binary: Option<bool>) -> Result<()>;
+}
+
+fn read_data(repo: &dyn Repository, data: EntryData) -> Result<Vec<u8>> {
+    match data {
+        EntryData::Empty => Ok(vec![]),
+        EntryData::Blob(id) => repo.read_content(&repo.find_object(id)?),
+        EntryData::Bytes(bytes) => Ok(bytes),
+    }
+}
+
+/// A ustar stream in git's layout: owned by root, mtime from the commit,
+/// long names and the commit id in pax headers.
+struct TarWriter<W: Write> {
+    out: W,
+    time: i64,
+    umask: u32,
+    written: usize,
+}
+
+fn octal(field: &mut [u8], value: u64) {
+    let text = format!("{:0width$o}", value, width = field.len() - 1);
+    field[..text.len()].copy_from_slice(text.as_bytes());
+    field[text.len()] = 0;
+}
+
+/// A pax record, `<len> <key>=<value>\n` where len counts itself.
+fn pax_record(records: &mut Vec<u8>, key: &str, value: &[u8]) {
+    let mut len = 1 + 1 + key.len() + 1 + value.len() + 1;
+    let mut digits = 1;
+    while len / 10 >= digits {
+        len += 1;
+        digits *= 10;
+    }
+    records.extend_from_slice(format!("{} {}=", len, key).as_bytes());
+    records.extend_from_slice(value);
+    records.push(b'\n');
+}
+
+/// Where a long path splits into the ustar prefix and name fields.
+fn path_prefix(path: &[u8], max: usize) -> usize {
+    let mut i = path.len();
+    if i > 1 && path[i - 1] == b'/' {
+        i -= 1;
+    }
+    i = i.min(max);
+    loop {
+        if i == 0 {
+            return 0;
+        }
+        i -= 1;
+        if i == 0 || path[i] == b'/' {
+            return i;
+        }
+    }
+}
+
+impl<W: Write> TarWriter<W> {
+    fn new(out: W, time: i64, umask: u32) -> Self {
+        TarWriter { out, time, umask, written: 0 }
+    }
+
+    fn write_blocked(&mut self, data: &[u8]) -> Result<()> {
+        self.out.write_all(data)?;
+        self.written += data.len();
+        self.pad()
+    }
+
+    fn pad(&mut self) -> Result<()> {
+        let tail = (BLOCK - self.written % BLOCK) % BLOCK;
+        self.out.write_all(&[0; BLOCK][..tail])?;
+        self.written += tail;
+        Ok(())
+    }
+
+    fn header(&self, typeflag: u8, name: &[u8], prefix: &[u8], link: &[u8], mode: u32, size: u64) -> [u8; BLOCK] {
+        let mut header = [0u8; BLOCK];
+        header[..name.len()].copy_from_slice(name);
+        octal(&mut header[100..108], u64::from(mode & 0o7777));
+        octal(&mut header[108..116], 0);
+        octal(&mut header[116..124], 0);
+        octal(&mut header[124..136], size);
+        octal(&mut header[136..148], self.time.max(0) as u64);
+        header[156] = typeflag;
+        header[157..157 + link.len()].copy_from_slice(link);
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        header[265..269].copy_from_slice(b"root");
+        header[297..301].copy_from_slice(b"root");
+        octal(&mut header[329..337], 0);
+        octal(&mut header[337..345], 0);
+        header[345..345 + prefix.len()].copy_from_slice(prefix);
+        header[148..156].copy_from_slice(b"        ");
+        let sum: u32 = header.iter().map(|&b| u32::from(b)).sum();
+        octal(&mut header[148..156], u64::from(sum));
+        header
+    }
+
+    fn extended_header(&mut self, typeflag: u8, name: &[u8], records: &[u8]) -> Result<()> {
+        let header = self.header(typeflag, name, b"", b"", 0o100666, records.len() as u64);
+        self.write_blocked(&header)?;
+        self.write_blocked(records)
+    }
+
+    fn global_header(&mut self, commit: Option<&Id>) -> Result<()> {
+        let mut records = vec![];
+        if let Some(id) = commit {
+            pax_record(&mut records, "comment", id.to_string().as_bytes());
+        }
+        if self.time as u64 > USTAR_MAX {
+            pax_record(&mut records, "mtime", self.time.to_string().as_bytes());
+        }
+        if records.is_empty() {
+            return Ok(());
+        }
+        self.extended_header(b'g', b"pax_global_header", &records)
+    }
+
+    fn finish(mut self) -> Result<W> {
+        let mut tail = RECORD - self.written % RECORD;
+        if tail < 2 * BLOCK {
+            tail += RECORD;
+        }
+        self.out.write_all(&vec![0; tail])?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+impl<W: Write> ArchiveWriter for TarWriter<W> {
+    fn entry(&mut self, repo: &dyn Repository, path: &str, mode: &FileMode, id: &Id, data: EntryData,
+             _: Option<bool>) -> Result<()> {
+        let (typeflag, mode) = match mode {
+            FileMode::DIR | FileMode::SUBMODULE => (b'5', (0o40000 | 0o777) & !self.umask),
+            FileMode::LINK => (b'2', 0o120777),
+            FileMode::EXE => (b'0', (0o100000 | 0o777) & !self.umask),
+            FileMode::FILE => (b'0', (0o100000 | 0o666) & !self.umask),
+        };
+        let mut records = vec![];
+        let (link, data) = match typeflag {
+            b'2' => (read_data(repo, data)?, EntryData::Empty),
+            _ => (vec![], data),
+        };
+        let link = if link.len() > 100 {
+            pax_record(&mut records, "linkpath", &link);
+            format!("see {}.paxheader", id).into_bytes()
+        } else {
+            link
+        };
+        let path = path.as_bytes();
+        let (prefix, name) = if path.len() > 100 {
+            let split = path_prefix(path, 155);
+            if split > 0 && path.len() - split - 1 <= 100 {
+                (path[..split].to_vec(), path[split + 1..].to_vec())
+            } else {
+                pax_record(&mut records, "path", path);
+                (vec![], format!("{}.data", id).into_bytes())
+            }
+        } else {
+            (vec![], path.to_vec())
+        };
+
+        let (object, bytes) = match data {
+            EntryData::Blob(id) if typeflag == b'0' => (Some(repo.find_object(id)?), None),
+            EntryData::Bytes(bytes) if typeflag == b'0' => (None, Some(bytes)),
+            _ => (None, None),
+        };
+        let size = match (&object, &bytes) {
+            (Some(object), _) => object.size() as u64,
+            (_, Some(bytes)) => bytes.len() as u64,
+            _ => 0,
+        };
+        let size_field = if size > USTAR_MAX {
+            pax_record(&mut records, "size", size.to_string().as_bytes());
+            0
+        } else {
+            size
+        };
+        if !records.is_empty() {
+            self.extended_header(b'x', format!("{}.paxheader", id).as_bytes(), &records)?;
+        }
+        let header = self.header(typeflag, &name, &prefix, &link, mode, size_field);
+        self.write_blocked(&header)?;
+        if let Some(object) = object {
+            self.written += repo.write_content(&object, &mut self.out)? as usize;
+            self.pad()?;
+        } else if let Some(bytes) = bytes {
+            self.write_blocked(&bytes)?;
+        }
+        Ok(())
+    }
+}
+
+/// A zip file in git's layout: every entry stamped with the commit time,
+/// regular files deflated when that makes them smaller, and the commit id
+/// as the archive comment.
+struct ZipWriter<W: Write> {
+    out: W,
+    time: i64,
+    offset: u64,
+    central: Vec<u8>,
+    entries: usize,
+}
+
+fn le16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn le32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+impl<W: Write> ZipWriter<W> {
+    fn new(out: W, time: i64) -> Self {
+        ZipWriter { out, time, offset: 0, central: vec![], entries: 0 }
+    }
+
+    /// The MS-DOS date and time of the archive time, in local time.
+    fn dos_time(&self) -> (u16, u16) {
+        let time = Local.timestamp(self.time, 0);
+        let date = time.day() + time.month() * 32 + (time.year() as u32).saturating_sub(1980) * 512;
+        let clock = time.second() / 2 + time.minute() * 32 + time.hour() * 2048;
+        (date as u16, clock as u16)
+    }
+
+    fn finish(mut self, comment: Option<&Id>) -> Result<W> {
+        if self.entries > usize::from(u16::MAX) || self.offset > u64::from(u32::MAX) {
+            bail!("archive too large for a zip file");
+        }
+        let comment = comment.map(Id::to_string).unwrap_or_default();
+        let mut end = vec![];
+        le32(&mut end, 0x0605_4b50);
+        le16(&mut end, 0);
+        le16(&mut end, 0);
+        le16(&mut end, self.entries as u16);
+        le16(&mut end, self.entries as u16);
+        le32(&mut end, self.central.len() as u32);
+        le32(&mut end, self.offset as u32);
+        le16(&mut end, comment.len() as u16);
+        end.extend_from_slice(comment.as_bytes());
+        self.out.write_all(&self.central)?;
+        self.out.write_all(&end)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+impl<W: Write> ArchiveWriter for ZipWriter<W> {
+    fn entry(&mut self, repo: &dyn Repository, path: &str, mode: &FileMode, _: &Id, data: EntryData,
+             binary: Option<bool>) -> Result<()> {
+        let content = read_data(repo, data)?;
+        // the internal attributes mark text files, as git's entry_is_binary
+        // finds them
+        let text = match mode {
+            FileMode::DIR | FileMode::SUBMODULE => false,
+            _ => !binary.unwrap_or_else(|| is_binary(&content)),
+        };
+        let (creator, attributes) = match mode {
+            FileMode::DIR | FileMode::SUBMODULE => (0, 0x10),
+            FileMode::LINK => (0x0317, (0o120000 | 0o777) << 16),
+            FileMode::EXE => (0x0317, mode.bits() << 16),
+            FileMode::FILE => (0, 0),
+        };
+        let mut crc = flate2::Crc::new();
+        crc.update(&content);
+        let mut method = 0u16;
+        let mut stored = None;
+        if (*mode == FileMode::FILE || *mode == FileMode::EXE) && !content.is_empty() {
+            let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+            encoder.write_all(&content)?;
+            let deflated = encoder.finish()?;
+            if deflated.len() < content.len() {
+                method = 8;
+                stored = Some(deflated);
+            }
+        }
+        let stored = stored.as_ref().unwrap_or(&content);
+        let flags: u16 = if path.is_ascii() { 0 } else { 0x800 };
+        let (date, clock) = self.dos_time();
+        let mut extra = vec![];
+        le16(&mut extra, 0x5455);
+        le16(&mut extra, 5);
+        extra.push(1);
+        le32(&mut extra, self.time as u32);
+
+        let mut common = vec![];
+        le16(&mut common, 10);
+        le16(&mut common, flags);
+        le16(&mut common, method);
+        le16(&mut common, clock);
+        le16(&mut common, date);
+        le32(&mut common, crc.sum());
+        le32(&mut common, stored.len() as u32);
+        le32(&mut common, content.len() as u32);
+        le16(&mut common, path.len() as u16);
+        le16(&mut common, extra.len() as u16);
+
+        let mut local = vec![];
+        le32(&mut local, 0x0403_4b50);
+        local.extend_from_slice(&common);
+        local.extend_from_slice(path.as_bytes());
+        local.extend_from_slice(&extra);
+        self.out.write_all(&local)?;
+        self.out.write_all(stored)?;
+
+        le32(&mut self.central, 0x0201_4b50);
+        le16(&mut self.central, creator);
+        self.central.extend_from_slice(&common);
+        le16(&mut self.central, 0);
+        le16(&mut self.central, 0);
+        le16(&mut self.central, u16::from(text));
+        le32(&mut self.central, attributes);
+        le32(&mut self.central, self.offset as u32);
+        self.central.extend_from_slice(path.as_bytes());
+        self.central.extend_from_slice(&extra);
+
+        self.offset += (local.len() + stored.len()) as u64;
+        self.entries += 1;
+        Ok(())
+    }
+}
+
+/// Replaces every `$Format:...$` in `content` with the commit formatted.
+fn format_subst(commit: &Commit, content: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(content.len());
+    let mut rest = content;
+    while let Some(start) = rest.windows(8).position(|w| w == b"$Format:") {
+        let end = match rest[start + 8..].iter().position(|&b| b == b'$') {
+            Some(end) => start + 8 + end,
+            None => break,
+        };
+        out.extend_from_slice(&rest[..start]);
+        let format = String::from_utf8_lossy(&rest[start + 8..end]);
+        out.extend_from_slice(format_commit(commit, &format, &FormatContext::default()).as_bytes());
+        rest = &rest[end + 1..];
+    }
+    out.extend_from_slice(rest);
+    out
+}
+
+pub(crate) fn pathspec_matches(spec: &str, path: &str) -> bool {
+    path == spec || path.starts_with(&format!("{}/", spec.trim_end_matches('/'))) || wildmatch(spec, path, false, false)
+}
+
+/// Whether a directory may hold paths `spec` matches.
+fn pathspec_leads_into(spec: &str, dir: &str) -> bool {
+    let literal = &spec[..spec.find(['*', '?', '[', '\\']).unwrap_or(spec.len())];
+    let dir = format!("{}/", dir);
+    literal.starts_with(&dir) || (literal.len() < spec.len() && dir.starts_with(literal))
+}
+
+impl<FS: FileSystem> FileRepository<FS> {
+    fn gitattributes(&self, tree: &Tree) -> Result<Option<String>> {
+        match tree.entries().iter().find(|e| e.name() == ".gitattributes" && e.mode() != FileMode::DIR) {
+            Some(entry) => {
+                let content = Repository::read_content(self, &self.find_object(entry.id())?)?;
+                Ok(Some(String::from_utf8_lossy(&content).into_owned()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn write_entries(&self, writer: &mut dyn ArchiveWriter, tree: &Tree, commit: Option<&Commit>,
+                     opts: &ArchiveOptions) -> Result<()> {
+        let mut attributes: Attributes = self.attributes()?;
+        if let Some(text) = self.gitattributes(tree)? {
+            attributes.add("", &text);
+        }
+        let prefix = &opts.prefix;
+        if prefix.ends_with('/') {
+            let dir = format!("{}/", prefix.trim_end_matches('/'));
+            writer.entry(self, &dir, &FileMode::DIR, tree.id(), EntryData::Empty, None)?;
+        }
+        // directories are written only once something inside them is
+        let mut pending: Vec<(String, Id)> = vec![];
+        tree.walk(self, &mut |path, entry| {
+            let mode = entry.mode();
+            let is_dir = mode == FileMode::DIR;
+            let selected = opts.paths.is_empty() || opts.paths.iter().any(|spec| pathspec_matches(spec, path));
+            let leads_into = is_dir && opts.paths.iter().any(|spec| pathspec_leads_into(spec, path));
+            if !selected && !leads_into {
+                return Ok(false);
+            }
+            let attr_path = if is_dir || mode == FileMode::SUBMODULE { format!("{}/", path) } else { path.to_owned() };
+            pending.retain(|(dir, _)| attr_path.starts_with(dir.as_str()));
+            if is_dir {
+                if attributes.is_set(&attr_path, "export-ignore") {
+                    return Ok(false);
+                }
+                pending.push((attr_path, entry.id().clone()));
+                if let Some(text) = self.gitattributes(&self.find_tree(entry.id())?)? {
+                    attributes.add(path, &text);
+                }
+                return Ok(true);
+            }
+            for (dir, id) in pending.drain(..) {
+                writer.entry(self, &format!("{}{}", prefix, dir), &FileMode::DIR, &id, EntryData::Empty, None)?;
+            }
+            if attributes.is_set(&attr_path, "export-ignore") {
+                return Ok(false);
+            }
+            let data = match (&mode, commit) {
+                (FileMode::SUBMODULE, _) => EntryData::Empty,
+                (FileMode::FILE, Some(commit)) | (FileMode::EXE, Some(commit))
+                    if attributes.is_set(&attr_path, "export-subst") => {
+                    let content = Repository::read_content(self, &self.find_object(entry.id())?)?;
+                    EntryData::Bytes(format_subst(commit, &content))
+                }
+                _ => EntryData::Blob(entry.id()),
+            };
+            // `binary` is the macro for `-diff`
+            let binary = match attributes.get(&attr_path, "diff") {
+                Some(AttrValue::Unset) => Some(true),
+                _ if attributes.is_set(&attr_path, "binary") => Some(true),
+                _ => None,
+            };
+            writer.entry(self, &format!("{}{}", prefix, attr_path), &mode, entry.id(), data, binary)?;
+            Ok(false)
+        })
+    }
+
+    fn check_pathspecs(&self, tree: &Tree, paths: &[String]) -> Result<()> {
+        let mut matched = vec![false; paths.len()];
+        tree.walk(self, &mut |path, entry| {
+            for (spec, matched) in paths.iter().zip(matched.iter_mut()) {
+                *matched |= pathspec_matches(spec, path);
+            }
+            Ok(entry.mode() == FileMode::DIR && paths.iter().any(|spec| pathspec_leads_into(spec, path)))
+        })?;
+        match matched.iter().position(|m| !m) {
+            Some(n) => bail!("pathspec '{}' did not match any files", paths[n]),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the tree of `treeish` to `out` as an archive. A commit, or a
+    /// tag of one, also gives the entries its committer time and goes into
+    /// the archive comment; `export-ignore` leaves paths out and
+    /// `export-subst` expands `$Format:...$` in files.
+    pub fn archive(&self, treeish: &Id, opts: &ArchiveOptions, out: &mut dyn Write) -> Result<()> {
+        let commit = match self.peel_to_kind(treeish, ObjectType::COMMIT) {
+            Ok(Object::Commit(commit)) => Some(commit),
+            Ok(_) => None,
+            Err(e) => match e.kind() {
+                ErrorKind::InvalidObjectType(..) => None,
+                _ => return Err(e),
+            },
+        };
+        let tree = self.peel_to_tree(treeish)?;
+        self.check_pathspecs(&tree, &opts.paths)?;
+        let time = commit.as_ref()
+            .and_then(|commit| commit.committer().as_ref())
+            .map_or_else(|| Local::now().timestamp(), |identity| identity.date.timestamp());
+        let commit_id = commit.as_ref().map(Commit::id);
+        let umask = match self.config()?.get("tar.umask")? {
+            Some("user") => 0o002,
+            Some(umask) => u32::from_str_radix(umask, 8).chain_err(|| format!("invalid tar.umask '{}'", umask))?,
+            None => 0o002,
+        };
+        match opts.format {
+            ArchiveFormat::Tar => {
+                let mut tar = TarWriter::new(out, time, umask);
+                tar.global_header(commit_id)?;
+                self.write_entries(&mut tar, &tree, commit.as_ref(), opts)?;
+                tar.finish()?;
+            }
+            ArchiveFormat::TarGz => {
+                let mut tar = TarWriter::new(GzEncoder::new(out, Compression::default()), time, umask);
+                tar.global_header(commit_id)?;
+                self.write_entries(&mut tar, &tree, commit.as_ref(), opts)?;
+                tar.finish()?.finish()?;
+            }
+            ArchiveFormat::Zip => {
+                let mut zip = ZipWriter::new(out, time);
+                self.write_entries(&mut zip, &tree, commit.as_ref(), opts)?;
+                zip.finish(commit_id)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::commit::parse_commit;
+    use crate::model::object::ParseMode;
+
+    #[test]
+    fn test_tar_header_fields() {
+        let mut records = vec![];
+        pax_record(&mut records, "comment", b"26e30e73560e29029a578514aff4380ac4606b04");
+        assert_eq!(records, b"52 comment=26e30e73560e29029a578514aff4380ac4606b04\n".to_vec());
+
+        let path = format!("{}/{}", "d".repeat(60), "e".repeat(60));
+        assert_eq!(path_prefix(path.as_bytes(), 155), 60);
+        assert_eq!(path_prefix("f".repeat(130).as_bytes(), 155), 0);
+
+        let tar = TarWriter::new(vec![], 1577930645, 0o002);
+        let header = tar.header(b'0', b"a.txt", b"", b"", 0o100664, 2);
+        assert_eq!(&header[100..108], b"0000664\0");
+        assert_eq!(&header[124..136], b"00000000002\0");
+        assert_eq!(&header[136..148], b"13603247625\0");
+        let sum: u32 = header.iter().enumerate()
+            .map(|(n, &b)| if (148..156).contains(&n) { u32::from(b' ') } else { u32::from(b) })
+            .sum();
+        assert_eq!(&header[148..156], format!("{:07o}\0", sum).as_bytes());
+    }
+
+    #[test]
+    fn test_format_subst() {
+        let id: Id = "26e30e73560e29029a578514aff4380ac4606b04".parse().unwrap();
+        let content = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+                        author A U Thor <a@x> 1577930645 +0100\n\
+                        committer C <c@x> 1577930645 +0100\n\nfirst\ncommit\n\nbody\n";
+        let commit = parse_commit(content, &id, ParseMode::Strict).unwrap();
+        assert_eq!(format_subst(&commit, b"v $Format:%h %an <%ae> %ad%n%s$ $Format:%x %% %b$ $Format:"),
+                   b"v 26e30e7 A U Thor <a@x> Thu Jan 2 03:04:05 2020 +0100\nfirst commit %x % body\n $Format:".to_vec());
+    }
+}
@@ -0,0 +1,158 @@
+use crate::errors::*;
+use crate::fs::FileSystem;
+use crate::model::refs::read_to_string;
+use crate::model::repository::FileRepository;
+use crate::wildmatch::wildmatch;
+
+/// The state a gitattributes line gives an attribute.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttrValue {
+    /// `attr`
+    Set,
+    /// `-attr`
+    Unset,
+    /// `attr=value`
+    Value(String),
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    /// The directory of the file the rule came from, with a trailing slash.
+    base: String,
+    pattern: String,
+    /// No slash in the pattern: it matches the basename at any depth.
+    basename: bool,
+    must_be_dir: bool,
+    /// `None` for `!attr`, which makes the attribute unspecified again.
+    attrs: Vec<(String, Option<AttrValue>)>,
+}
+
+impl Rule {
+    fn matches(&self, path: &str) -> bool {
+        let is_dir = path.ends_with('/');
+        if self.must_be_dir && !is_dir {
+            return false;
+        }
+        let path = match path.strip_prefix(self.base.as_str()) {
+            Some(path) => path.trim_end_matches('/'),
+            None => return false,
+        };
+        if self.basename {
+            let name = path.rsplit('/').next().unwrap_or(path);
+            wildmatch(&self.pattern, name, false, false)
+        } else {
+            wildmatch(&self.pattern, path, true, false)
+        }
+    }
+}
+
+fn parse_rules(base: &str, text: &str) -> Vec<Rule> {
+    let mut rules = vec![];
+    for line in text.lines() {
+        let mut words = line.split_whitespace();
+        let pattern = match words.next() {
+            Some(pattern) if !pattern.starts_with('#') && !pattern.starts_with("[attr]") => pattern,
+            _ => continue,
+        };
+        // negative patterns are forbidden in gitattributes
+        if pattern.starts_with('!') {
+            continue;
+        }
+        let must_be_dir = pattern.ends_with('/') && pattern.len() > 1;
+        let pattern = pattern.trim_end_matches('/');
+        let basename = !pattern.contains('/');
+        let attrs = words.map(|word| {
+            if let Some(name) = word.strip_prefix('-') {
+                (name.to_owned(), Some(AttrValue::Unset))
+            } else if let Some(name) = word.strip_prefix('!') {
+                (name.to_owned(), None)
+            } else if let Some(n) = word.find('=') {
+                (word[..n].to_owned(), Some(AttrValue::Value(word[n + 1..].to_owned())))
+            } else {
+                (word.to_owned(), Some(AttrValue::Set))
+            }
+        }).collect();
+        rules.push(Rule {
+            base: base.to_owned(),
+            pattern: pattern.trim_start_matches('/').to_owned(),
+            basename,
+            must_be_dir,
+            attrs,
+        });
+    }
+    rules
+}
+
+/// Attributes from gitattributes files, looked up the way git does:
+/// `info/attributes` first, then the deepest `.gitattributes`, then
+/// `core.attributesFile`, the last matching line of each deciding.
+#[derive(Debug, Clone, Default)]
+pub struct Attributes {
+    fallback: Vec<Rule>,
+    dirs: Vec<Rule>,
+    overrides: Vec<Rule>,
+}
+
+impl Attributes {
+    /// Adds the `.gitattributes` of directory `dir`, `""` for the top
+    /// level. Directories must be added parents first.
+    pub fn add(&mut self, dir: &str, text: &str) {
+        let base = if dir.is_empty() { String::new() } else { format!("{}/", dir.trim_end_matches('/')) };
+        self.dirs.extend(parse_rules(&base, text));
+    }
+
+    /// The attribute `name` for `path`, which ends with a slash for a
+    /// directory; `None` when unspecified.
+    pub fn get(&self, path: &str, name: &str) -> Option<&AttrValue> {
+        self.overrides.iter().rev()
+            .chain(self.dirs.iter().rev())
+            .chain(self.fallback.iter().rev())
+            .filter(|rule| rule.matches(path))
+            .flat_map(|rule| rule.attrs.iter().rev())
+            .find(|(attr, _)| attr == name)
+            .and_then(|(_, value)| value.as_ref())
+    }
+
+    pub fn is_set(&self, path: &str, name: &str) -> bool {
+        self.get(path, name) == Some(&AttrValue::Set)
+    }
+}
+
+impl<FS: FileSystem> FileRepository<FS> {
+    /// The attributes from `core.attributesFile` and `info/attributes`;
+    /// the caller adds the `.gitattributes` files of whichever tree it
+    /// works on.
+    pub fn attributes(&self) -> Result<Attributes> {
+        let mut attributes = Attributes::default();
+        if let Some(path) = self.config()?.get_path("core.attributesFile")? {
+            if let Some(text) = read_to_string(&self.fs, &path)? {
+                attributes.fallback = parse_rules("", &text);
+            }
+        }
+        if let Some(text) = read_to_string(&self.fs, &self.git_dir().join("info").join("attributes"))? {
+            attributes.overrides = parse_rules("", &text);
+        }
+        Ok(attributes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_attribute_lookup() {
+        let mut attributes = Attributes::default();
+        attributes.add("", "*.txt text\n/docs/ export-ignore\nversion export-subst\n*.bin -text\n");
+        attributes.add("src", "*.txt -text !export-subst\nversion !export-subst\n");
+        assert!(attributes.is_set("a.txt", "text"));
+        assert!(attributes.is_set("lib/b.txt", "text"));
+        assert_eq!(attributes.get("src/b.txt", "text"), Some(&AttrValue::Unset));
+        assert_eq!(attributes.get("x.bin", "text"), Some(&AttrValue::Unset));
+        assert!(attributes.is_set("docs/", "export-ignore"));
+        assert!(!attributes.is_set("docs", "export-ignore"));
+        assert!(!attributes.is_set("lib/docs/", "export-ignore"));
+        assert!(attributes.is_set("lib/version", "export-subst"));
+        assert_eq!(attributes.get("src/version", "export-subst"), None);
+    }
+}
@@ -0,0 +1,490 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use crate::errors::*;
+use crate::fs::FileSystem;
+use crate::model::commit::Commit;
+use crate::model::diff::{diff, lines, Edit, TreeChange};
+use crate::model::id::Id;
+use crate::model::repository::{FileRepository, Repository};
+use crate::model::tree::FileMode;
+
+/// How much a block of lines must be worth, one plus its alphanumeric
+/// characters, to be blamed on a move within the file or a copy from
+/// another one.
+pub const MOVE_SCORE: usize = 20;
+pub const COPY_SCORE: usize = 40;
+
+/// How similar, in percent, a file must be to follow it across a rename.
+const RENAME_THRESHOLD: usize = 50;
+
+#[derive(Debug, Clone, Default)]
+pub struct BlameOptions {
+    /// 1-based inclusive line ranges, the whole file when empty.
+    pub ranges: Vec<(usize, usize)>,
+    pub ignore_whitespace: bool,
+    /// Look for lines moved within the file, `-M`.
+    pub detect_moves: bool,
+    /// Look for lines copied from other files: 1 for files changed in the
+    /// same commit, 2 also for any file when the blamed one was created,
+    /// 3 for any file in every commit.
+    pub detect_copies: u8,
+    /// Commits whose changes are passed on to their parent as best it can.
+    pub ignore_revs: HashSet<Id>,
+}
+
+/// Consecutive lines that came from the same place.
+#[derive(Debug, Clone)]
+pub struct BlameHunk {
+    pub commit: Commit,
+    /// The path of the file in `commit`.
+    pub path: String,
+    /// The first line in `commit`'s version of the file, 1-based.
+    pub orig_start: usize,
+    /// The first line in the blamed version, 1-based.
+    pub final_start: usize,
+    pub lines: usize,
+    /// `commit` is a root commit, so blame stopped there.
+    pub boundary: bool,
+    /// The parent commit and path blame was passed on to from `commit`.
+    pub previous: Option<(Id, String)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Blame {
+    /// The lines of the blamed version, each keeping its newline.
+    pub lines: Vec<Vec<u8>>,
+    /// The hunks in line order, covering the requested ranges.
+    pub hunks: Vec<BlameHunk>,
+}
+
+struct Origin {
+    commit: Commit,
+    path: String,
+    blob: Id,
+    lines: Vec<Vec<u8>>,
+    previous: Option<usize>,
+}
+
+/// Lines of the blamed version still looking for their origin: `len`
+/// lines from `final_start` there, which are lines from `start` in the
+/// suspected origin.
+#[derive(Debug, Clone)]
+struct Entry {
+    final_start: usize,
+    start: usize,
+    len: usize,
+}
+
+impl Entry {
+    /// The lines `range` of the suspect, which must lie within this entry.
+    fn part(&self, range: Range<usize>) -> Entry {
+        Entry { final_start: self.final_start + range.start - self.start, start: range.start, len: range.len() }
+    }
+}
+
+fn score(lines: &[Vec<u8>]) -> usize {
+    1 + lines.iter().flatten().filter(|b| b.is_ascii_alphanumeric()).count()
+}
+
+struct Blamer<'a, FS: FileSystem> {
+    repo: &'a FileRepository<FS>,
+    opts: &'a BlameOptions,
+    origins: Vec<Origin>,
+    index: HashMap<(Id, String), usize>,
+    suspects: HashMap<usize, Vec<Entry>>,
+    /// Origins with suspects, by the commit that must come out of the
+    /// walk before they are looked at.
+    waiting: HashMap<Id, Vec<usize>>,
+    processed: HashSet<Id>,
+    /// Origins that got suspects after their commit was walked, which
+    /// clock skew makes possible.
+    late: Vec<usize>,
+    blamed: Vec<(usize, Entry)>,
+}
+
+impl<'a, FS: FileSystem> Blamer<'a, FS> {
+    fn origin(&mut self, commit: &Commit, path: &str, blob: &Id) -> Result<usize> {
+        let key = (commit.id().clone(), path.to_owned());
+        if let Some(&n) = self.index.get(&key) {
+            return Ok(n);
+        }
+        let content = Repository::read_content(self.repo, &self.repo.find_object(blob)?)?;
+        self.origins.push(Origin {
+            commit: commit.clone(),
+            path: path.to_owned(),
+            blob: blob.clone(),
+            lines: lines(&content).into_iter().map(<[u8]>::to_vec).collect(),
+            previous: None,
+        });
+        self.index.insert(key, self.origins.len() - 1);
+        Ok(self.origins.len() - 1)
+    }
+
+    fn suspect(&mut self, origin: usize, entry: Entry) {
+        let suspects = self.suspects.entry(origin).or_default();
+        suspects.push(entry);
+        if suspects.len() > 1 {
+            return;
+        }
+        let commit = self.origins[origin].commit.id();
+        if self.processed.contains(commit) {
+            self.late.push(origin);
+        } else {
+            self.waiting.entry(commit.clone()).or_default().push(origin);
+        }
+    }
+
+    /// The lines as compared: without whitespace under `-w`.
+    fn keys(&self, origin: usize) -> Vec<Vec<u8>> {
+        let lines = &self.origins[origin].lines;
+        if self.opts.ignore_whitespace {
+            lines.iter().map(|line| line.iter().filter(|b| !b.is_ascii_whitespace()).cloned().collect()).collect()
+        } else {
+            lines.clone()
+        }
+    }
+
+    fn edits(&self, parent: usize, origin: usize) -> Vec<Edit> {
+        diff(&self.keys(parent), &self.keys(origin))
+    }
+
+    fn is_file(mode: &FileMode) -> bool {
+        *mode == FileMode::FILE || *mode == FileMode::EXE || *mode == FileMode::LINK
+    }
+
+    /// The file in `parent` that became `origin`: the same path, or the
+    /// file renamed to it.
+    fn parent_origin(&mut self, parent: &Commit, origin: usize) -> Result<Option<usize>> {
+        let (path, tree) = (self.origins[origin].path.clone(), self.origins[origin].commit.tree().clone());
+        if let Some(entry) = self.repo.find_tree(parent.tree())?.entry_at(self.repo, &path)? {
+            if Self::is_file(&entry.mode()) {
+                return self.origin(parent, &path, entry.id()).map(Some);
+            }
+        }
+        for change in self.repo.diff_trees(Some(parent.tree()), Some(&tree), Some(RENAME_THRESHOLD))? {
+            if let TreeChange::Renamed(old, new, _) = change {
+                if new.path == path && Self::is_file(&old.mode) {
+                    return self.origin(parent, &old.path, &old.id).map(Some);
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Hands lines the diff leaves unchanged to `parent`, returning the
+    /// rest.
+    fn pass_unchanged(&mut self, parent: usize, origin: usize, entries: Vec<Entry>) -> Vec<Entry> {
+        let edits = self.edits(parent, origin);
+        let mut unchanged = vec![];
+        let (mut old_at, mut new_at) = (0, 0);
+        for edit in edits.iter().chain(std::iter::once(&Edit {
+            old: self.origins[parent].lines.len()..self.origins[parent].lines.len(),
+            new: self.origins[origin].lines.len()..self.origins[origin].lines.len(),
+        })) {
+            if edit.new.start > new_at {
+                unchanged.push((new_at..edit.new.start, old_at));
+            }
+            old_at = edit.old.end;
+            new_at = edit.new.end;
+        }
+        let mut rest = vec![];
+        for entry in entries {
+            let mut at = entry.start;
+            let end = entry.start + entry.len;
+            for (range, old_start) in &unchanged {
+                let (from, to) = (at.max(range.start), end.min(range.end));
+                if from >= to {
+                    continue;
+                }
+                if at < from {
+                    rest.push(entry.part(at..from));
+                }
+                let part = entry.part(from..to);
+                self.suspect(parent, Entry { start: old_start + from - range.start, ..part });
+                at = to;
+            }
+            if at < end {
+                rest.push(entry.part(at..end));
+            }
+        }
+        rest
+    }
+
+    /// Hands the best matching blocks worth at least `threshold` to
+    /// `target`, returning the lines left.
+    fn pass_copies(&mut self, target: usize, origin: usize, entries: Vec<Entry>, threshold: usize) -> Vec<Entry> {
+        let (source, target_keys) = (self.keys(origin), self.keys(target));
+        let mut work = entries;
+        let mut rest = vec![];
+        while let Some(entry) = work.pop() {
+            // the longest run of lines the entry shares with the target
+            let lines = &source[entry.start..entry.start + entry.len];
+            let mut best = (0, 0, 0);
+            let mut previous = vec![0; target_keys.len() + 1];
+            for (i, line) in lines.iter().enumerate() {
+                let mut current = vec![0; target_keys.len() + 1];
+                for (j, other) in target_keys.iter().enumerate() {
+                    if line == other {
+                        current[j + 1] = previous[j] + 1;
+                        if current[j + 1] > best.0 {
+                            best = (current[j + 1], i + 1 - current[j + 1], j + 1 - current[j + 1]);
+                        }
+                    }
+                }
+                previous = current;
+            }
+            let (len, i, j) = best;
+            let start = entry.start + i;
+            if len == 0 || score(&self.origins[origin].lines[start..start + len]) < threshold {
+                rest.push(entry);
+                continue;
+            }
+            if i > 0 {
+                work.push(entry.part(entry.start..start));
+            }
+            if start + len < entry.start + entry.len {
+                work.push(entry.part(start + len..entry.start + entry.len));
+            }
+            let part = entry.part(start..start + len);
+            self.suspect(target, Entry { start: j, ..part });
+        }
+        rest
+    }
+
+    /// The files in `parent` lines may have been copied from.
+    fn copy_sources(&mut self, parent: &Commit, origin: usize, parent_origin: Option<usize>) -> Result<Vec<usize>> {
+        let own_path = parent_origin.map(|p| self.origins[p].path.clone());
+        let mut files = vec![];
+        if self.opts.detect_copies >= 3 || (self.opts.detect_copies >= 2 && parent_origin.is_none()) {
+            self.repo.find_tree(parent.tree())?.walk(self.repo, &mut |path, entry| {
+                if Self::is_file(&entry.mode()) {
+                    files.push((path.to_owned(), entry.id().clone()));
+                }
+                Ok(true)
+            })?;
+        } else {
+            let tree = self.origins[origin].commit.tree().clone();
+            for change in self.repo.diff_trees(Some(parent.tree()), Some(&tree), None)? {
+                match change {
+                    TreeChange::Modified(old, _) | TreeChange::Deleted(old) if Self::is_file(&old.mode) => {
+                        files.push((old.path, old.id));
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let mut sources = vec![];
+        for (path, id) in files {
+            if Some(&path) != own_path.as_ref() {
+                sources.push(self.origin(parent, &path, &id)?);
+            }
+        }
+        Ok(sources)
+    }
+
+    /// Maps the lines an ignored commit changed onto the lines its parent
+    /// had in their place, in proportion. Lines it only added stay.
+    fn pass_ignored(&mut self, parent: usize, origin: usize, entries: Vec<Entry>) -> Vec<Entry> {
+        let edits = self.edits(parent, origin);
+        let mut rest = vec![];
+        for entry in entries {
+            for line in entry.start..entry.start + entry.len {
+                let edit = edits.iter().find(|edit| edit.new.contains(&line) && !edit.old.is_empty());
+                match edit {
+                    Some(edit) => {
+                        let start = edit.old.start + (line - edit.new.start) * edit.old.len() / edit.new.len();
+                        let part = entry.part(line..line + 1);
+                        self.suspect(parent, Entry { start, ..part });
+                    }
+                    None => rest.push(entry.part(line..line + 1)),
+                }
+            }
+        }
+        rest
+    }
+
+    fn process(&mut self, origin: usize) -> Result<()> {
+        let mut entries = match self.suspects.remove(&origin) {
+            Some(entries) => entries,
+            None => return Ok(()),
+        };
+        let commit = self.origins[origin].commit.clone();
+        let mut parents = vec![];
+        for id in commit.parent() {
+            let parent = self.repo.find_commit(id)?;
+            let parent_origin = self.parent_origin(&parent, origin)?;
+            parents.push((parent, parent_origin));
+        }
+        if self.origins[origin].previous.is_none() {
+            self.origins[origin].previous = parents.iter().find_map(|(_, p)| *p);
+        }
+
+        for parent_origin in parents.iter().filter_map(|(_, p)| *p) {
+            if entries.is_empty() {
+                break;
+            }
+            if self.origins[parent_origin].blob == self.origins[origin].blob {
+                for entry in entries.drain(..) {
+                    self.suspect(parent_origin, entry);
+                }
+                break;
+            }
+            entries = self.pass_unchanged(parent_origin, origin, entries);
+        }
+        if self.opts.detect_moves {
+            for parent_origin in parents.iter().filter_map(|(_, p)| *p) {
+                if !entries.is_empty() {
+                    entries = self.pass_copies(parent_origin, origin, entries, MOVE_SCORE);
+                }
+            }
+        }
+        if self.opts.detect_copies > 0 {
+            for (parent, parent_origin) in &parents {
+                if entries.is_empty() {
+                    break;
+                }
+                for source in self.copy_sources(parent, origin, *parent_origin)? {
+                    if !entries.is_empty() {
+                        entries = self.pass_copies(source, origin, entries, COPY_SCORE);
+                    }
+                }
+            }
+        }
+        if self.opts.ignore_revs.contains(commit.id()) {
+            if let Some(parent_origin) = parents.iter().find_map(|(_, p)| *p) {
+                entries = self.pass_ignored(parent_origin, origin, entries);
+            }
+        }
+        self.blamed.extend(entries.into_iter().map(|entry| (origin, entry)));
+        Ok(())
+    }
+
+    fn hunks(mut self) -> Vec<BlameHunk> {
+        self.blamed.sort_by_key(|(_, entry)| entry.final_start);
+        let mut merged: Vec<(usize, Entry)> = vec![];
+        for (origin, entry) in self.blamed.drain(..) {
+            match merged.last_mut() {
+                Some((last_origin, last)) if *last_origin == origin
+                    && last.final_start + last.len == entry.final_start && last.start + last.len == entry.start => {
+                    last.len += entry.len;
+                }
+                _ => merged.push((origin, entry)),
+            }
+        }
+        merged.into_iter().map(|(origin, entry)| {
+            let origin = &self.origins[origin];
+            BlameHunk {
+                commit: origin.commit.clone(),
+                path: origin.path.clone(),
+                orig_start: entry.start + 1,
+                final_start: entry.final_start + 1,
+                lines: entry.len,
+                boundary: origin.commit.parent().is_empty(),
+                previous: origin.previous.map(|p| (self.origins[p].commit.id().clone(), self.origins[p].path.clone())),
+            }
+        }).collect()
+    }
+}
+
+impl<FS: FileSystem> FileRepository<FS> {
+    /// Finds the commit each line of `path` as of `start` came from,
+    /// following the file across renames.
+    pub fn blame(&self, start: &Id, path: &str, opts: &BlameOptions) -> Result<Blame> {
+        let commit = self.peel_to_commit(start)?;
+        let blob = match self.find_tree(commit.tree())?.entry_at(self, path)? {
+            Some(entry) if Blamer::<FS>::is_file(&entry.mode()) => entry.id().clone(),
+            _ => bail!("no such path {} in {}", path, start),
+        };
+        let mut blamer = Blamer {
+            repo: self,
+            opts,
+            origins: vec![],
+            index: HashMap::new(),
+            suspects: HashMap::new(),
+            waiting: HashMap::new(),
+            processed: HashSet::new(),
+            late: vec![],
+            blamed: vec![],
+        };
+        let origin = blamer.origin(&commit, path, &blob)?;
+        let total = blamer.origins[origin].lines.len();
+        let mut ranges = opts.ranges.clone();
+        if ranges.is_empty() && total > 0 {
+            ranges.push((1, total));
+        }
+        ranges.sort_unstable();
+        let mut covered = 0;
+        for (from, to) in ranges {
+            if from == 0 || from > total {
+                bail!("file {} has only {} line{}", path, total, if total == 1 { "" } else { "s" });
+            }
+            let (from, to) = (from.max(covered + 1), to.min(total));
+            if from <= to {
+                blamer.suspect(origin, Entry { final_start: from - 1, start: from - 1, len: to + 1 - from });
+                covered = to;
+            }
+        }
+
+        let mut walk = self.rev_walk();
+        walk.push(commit.id())?;
+        loop {
+            while let Some(origin) = blamer.late.pop() {
+                blamer.process(origin)?;
+            }
+            if blamer.suspects.is_empty() {
+                break;
+            }
+            let commit = match walk.next() {
+                Some(commit) => commit?,
+                None => break,
+            };
+            blamer.processed.insert(commit.id().clone());
+            for origin in blamer.waiting.remove(commit.id()).unwrap_or_default() {
+                blamer.process(origin)?;
+            }
+        }
+        let lines = blamer.origins[origin].lines.clone();
+        Ok(Blame { lines, hunks: blamer.hunks() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::MemFs;
+    use crate::model::object::ObjectType;
+    use crate::model::tree::TreeEntry;
+
+    fn commit(repo: &mut FileRepository<MemFs>, parents: &[&Id], files: &[(&str, &str)], time: i64) -> Id {
+        let entries: Vec<TreeEntry> = files.iter().map(|(name, content)| {
+            TreeEntry::new(FileMode::FILE, repo.write_object(ObjectType::BLOB, content.as_bytes()).unwrap(), *name)
+        }).collect();
+        let tree = repo.write_tree(&entries).unwrap();
+        repo.add_commit(None, Some(&tree), parents, time, &format!("c{}", time))
+    }
+
+    /// Each hunk as `<commit message>:<orig_start>+<lines>`.
+    fn owners(blame: &Blame) -> Vec<String> {
+        blame.hunks.iter().map(|h| format!("{}:{}+{}", h.commit.message(), h.orig_start, h.lines)).collect()
+    }
+
+    #[test]
+    fn test_blame_follows_renames_and_moves() {
+        let mut repo = FileRepository::<MemFs>::default();
+        let block = "fn block_with_enough_characters() {}\n";
+        let head = "the first line both versions share\nand the second line they share\n";
+        let one = commit(&mut repo, &[], &[("a", &format!("{}one\ntwo\n{}", head, block))], 1);
+        let two = commit(&mut repo, &[&one], &[("b", &format!("{}{}one\ntwo\nthree\n", head, block))], 2);
+
+        let blame = repo.blame(&two, "b", &BlameOptions::default()).unwrap();
+        assert_eq!(owners(&blame), ["c1:1+2", "c2:3+1", "c1:3+2", "c2:6+1"]);
+        assert!(blame.hunks[0].boundary);
+        assert_eq!(blame.hunks[1].previous, Some((one.clone(), "a".to_owned())));
+
+        let opts = BlameOptions { detect_moves: true, ..BlameOptions::default() };
+        assert_eq!(owners(&repo.blame(&two, "b", &opts).unwrap()), ["c1:1+2", "c1:5+1", "c1:3+2", "c2:6+1"]);
+
+        let opts = BlameOptions { ranges: vec![(3, 4)], ignore_revs: vec![two.clone()].into_iter().collect(), ..BlameOptions::default() };
+        assert_eq!(owners(&repo.blame(&two, "b", &opts).unwrap()), ["c2:3+1", "c1:3+1"]);
+    }
+}
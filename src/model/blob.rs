@@ -27,7 +27,7 @@ impl Blob {
                 content
             })
         } else {
-            Err(ErrorKind::InvalidObjectType.into())
+            Err(obj.not_a(ObjectType::BLOB))
         }
     }
 }
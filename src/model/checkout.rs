@@ -0,0 +1,494 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Read;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::errors::*;
+use crate::fs::{FileSystem, FileType, Metadata};
+use crate::fs::checksum::hash_object;
+use crate::model::id::Id;
+use crate::model::index::{Index, IndexEntry};
+use crate::model::repository::{FileRepository, Repository};
+use crate::model::tree::FileMode;
+use crate::model::workers::run_workers;
+
+/// Fewer files than this are written without worker threads, as git's
+/// `checkout.thresholdForParallelism` defaults to.
+const PARALLEL_THRESHOLD: usize = 100;
+
+#[derive(Debug, Clone, Default)]
+pub struct CheckoutOptions {
+    /// Throw away local changes and untracked files in the way.
+    pub force: bool,
+    /// Threads writing files; 0 reads `checkout.workers`, and uses every
+    /// CPU when that is unset or below 1.
+    pub workers: usize,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CheckoutStats {
+    pub written: usize,
+    pub removed: usize,
+}
+
+pub(crate) type Files = BTreeMap<String, (FileMode, Id)>;
+
+/// Git refuses paths that could escape the working tree or write into
+/// the repository.
+fn verify_path(path: &str) -> Result<()> {
+    let bad = path.split('/').any(|part| part.is_empty() || part == "." || part == ".." || part.eq_ignore_ascii_case(".git"));
+    if bad {
+        bail!("invalid path '{}'", path);
+    }
+    Ok(())
+}
+
+fn same(entry: &IndexEntry, file: &(FileMode, Id)) -> bool {
+    entry.mode == file.0.bits() && entry.id == file.1
+}
+
+fn stat_time(time: SystemTime) -> (u32, u32) {
+    time.duration_since(UNIX_EPOCH).map_or((0, 0), |d| (d.as_secs() as u32, d.subsec_nanos()))
+}
+
+fn record_stat(entry: &mut IndexEntry, meta: &Metadata) {
+    entry.ctime = stat_time(meta.changed);
+    entry.mtime = stat_time(meta.modified);
+    entry.dev = meta.device as u32;
+    entry.ino = meta.inode as u32;
+    entry.uid = meta.uid;
+    entry.gid = meta.gid;
+    entry.size = meta.len as u32;
+}
+
+fn is_executable(meta: &Metadata) -> bool {
+    meta.mode & 0o100 != 0
+}
+
+/// Replaces whatever is at `path` with a file, symlink or, for a
+/// submodule, an empty directory.
+fn write_file<FS: FileSystem>(fs: &FS, path: &Path, mode: &FileMode, content: &[u8]) -> Result<Metadata> {
+    if let Ok(meta) = fs.metadata(path) {
+        if meta.file_type != FileType::Dir {
+            fs.remove_file(path)?;
+        } else if *mode != FileMode::SUBMODULE {
+            fs.remove_dir(path)?;
+        }
+    }
+    match mode {
+        FileMode::SUBMODULE => fs.create_dir_all(path)?,
+        FileMode::LINK => fs.symlink(content, path)?,
+        _ => fs.create_file(path, content, *mode == FileMode::EXE)?,
+    }
+    fs.metadata(path)
+}
+
+/// Removes the directories between `path` and `stop` that are now empty.
+fn prune_empty_dirs<FS: FileSystem>(fs: &FS, path: &Path, stop: &Path) {
+    let mut dir = path.parent();
+    while let Some(current) = dir {
+        if current == stop || fs.remove_dir(current).is_err() {
+            break;
+        }
+        dir = current.parent();
+    }
+}
+
+/// The first leading directory of `path` that is a symlink or a file, as
+/// git's `check_leading_path` finds, with its type. Anything below it
+/// is not in the working tree, whatever a stat through it would say.
+/// `None` when they are all directories, or one is missing.
+fn blocked_leading_path<FS: FileSystem>(fs: &FS, work_tree: &Path, path: &str) -> Option<(String, FileType)> {
+    let mut end = 0;
+    while let Some(slash) = path[end..].find('/') {
+        end += slash;
+        match fs.metadata(work_tree.join(&path[..end])) {
+            Ok(meta) if meta.file_type == FileType::Dir => {}
+            Ok(meta) => return Some((path[..end].to_owned(), meta.file_type)),
+            Err(_) => return None,
+        }
+        end += 1;
+    }
+    None
+}
+
+/// Makes the leading directories of `path`, replacing symlinks and files
+/// in their place rather than writing through them, as git does.
+fn create_leading_dirs<FS: FileSystem>(fs: &FS, work_tree: &Path, path: &str) -> Result<()> {
+    let mut end = 0;
+    while let Some(slash) = path[end..].find('/') {
+        end += slash;
+        let dir = work_tree.join(&path[..end]);
+        match fs.metadata(&dir) {
+            Ok(meta) if meta.file_type == FileType::Dir => {}
+            Ok(_) => {
+                fs.remove_file(&dir)?;
+                fs.create_dir_all(&dir)?;
+            }
+            Err(_) => fs.create_dir_all(&dir)?,
+        }
+        end += 1;
+    }
+    Ok(())
+}
+
+/// What `checkout_tree` will change, once every precondition has been
+/// checked and before anything is touched.
+pub struct CheckoutPlan {
+    index: Index,
+    /// Entries that stay as they are, or only change in the index.
+    entries: Vec<IndexEntry>,
+    removals: Vec<String>,
+    writes: Vec<(String, FileMode, Id)>,
+    workers: usize,
+}
+
+impl<FS: FileSystem> FileRepository<FS> {
+    pub(crate) fn tree_files(&self, tree: Option<&Id>) -> Result<Files> {
+        let mut files = BTreeMap::new();
+        let mut pending: Vec<(String, Id)> = tree.map(|id| (String::new(), id.clone())).into_iter().collect();
+        while let Some((prefix, id)) = pending.pop() {
+            for entry in self.find_tree(&id)?.entries() {
+                let path = format!("{}{}", prefix, entry.name());
+                if entry.mode() == FileMode::DIR {
+                    pending.push((format!("{}/", path), entry.id().clone()));
+                } else {
+                    verify_path(&path)?;
+                    files.insert(path, (entry.mode(), entry.id().clone()));
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    /// Whether the working tree copy of `entry` matches it, `None` when
+    /// there is none. The stat data decides unless it changed or the entry
+    /// could have been written in the same second as the index.
+    fn worktree_matches(&self, work_tree: &Path, entry: &IndexEntry, index_mtime: (u32, u32)) -> Result<Option<bool>> {
+        if blocked_leading_path(&self.fs, work_tree, &entry.path).is_some() {
+            return Ok(None);
+        }
+        let path = work_tree.join(&entry.path);
+        let meta = match self.fs.metadata(&path) {
+            Ok(meta) => meta,
+            Err(e) => match e.kind() {
+                ErrorKind::Io(io) if io.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+                _ => return Err(e),
+            },
+        };
+        let file_type = meta.file_type;
+        match FileMode::from_bits(entry.mode) {
+            Some(FileMode::SUBMODULE) => return Ok(Some(file_type == FileType::Dir)),
+            Some(FileMode::LINK) if file_type != FileType::Symlink => return Ok(Some(false)),
+            Some(FileMode::FILE) | Some(FileMode::EXE) if file_type != FileType::File => return Ok(Some(false)),
+            Some(FileMode::EXE) if cfg!(unix) && !is_executable(&meta) => return Ok(Some(false)),
+            Some(FileMode::FILE) if is_executable(&meta) => return Ok(Some(false)),
+            _ => {}
+        }
+        let mut current = entry.clone();
+        record_stat(&mut current, &meta);
+        let racy = entry.mtime >= index_mtime;
+        if !racy && current.mtime == entry.mtime && current.size == entry.size && current.ino == entry.ino {
+            return Ok(Some(true));
+        }
+        let content = if file_type == FileType::Symlink {
+            self.fs.read_link(&path)?
+        } else {
+            let mut content = vec![];
+            self.fs.read_file(&path)?.read_to_end(&mut content)?;
+            content
+        };
+        Ok(Some(hash_object(self.object_format(), "blob", &content) == entry.id))
+    }
+
+    /// When the index was last written, which makes entries stamped at
+    /// or after it racy.
+    fn index_mtime(&self) -> (u32, u32) {
+        self.fs.metadata(self.index_path()).map_or((0, 0), |meta| stat_time(meta.modified))
+    }
+
+    /// Whether the index or the working tree differ from `HEAD` in any
+    /// tracked file, as `describe --dirty` asks.
+    pub fn has_local_changes(&self) -> Result<bool> {
+        let work_tree = self.work_tree()
+            .ok_or_else(|| Error::from("this operation must be run in a work tree"))?
+            .to_path_buf();
+        let head = self.tree_files(self.head_tree()?.as_ref())?;
+        let index = self.read_index()?;
+        let index_mtime = self.index_mtime();
+        for entry in index.entries() {
+            let unchanged = entry.stage == 0 && head.get(&entry.path).is_some_and(|file| same(entry, file));
+            if !unchanged {
+                return Ok(true);
+            }
+            if !entry.skip_worktree && self.worktree_matches(&work_tree, entry, index_mtime)? != Some(true) {
+                return Ok(true);
+            }
+        }
+        Ok(index.entries().len() != head.len())
+    }
+
+    fn head_tree(&self) -> Result<Option<Id>> {
+        Ok(match self.resolve_ref("HEAD")? {
+            Some(head) => Some(self.peel_to_commit(&head)?.tree().clone()),
+            None => None,
+        })
+    }
+
+    /// Works out how to move the working tree and index from `from`,
+    /// usually the tree of `HEAD`, to `tree`, keeping local changes to files
+    /// the two trees agree on and refusing to lose any others unless forced.
+    /// Paths outside the sparse-checkout stay in the index, marked
+    /// skip-worktree, but not on disk. Nothing is changed yet.
+    pub fn plan_checkout(&self, from: Option<&Id>, tree: &Id, opts: &CheckoutOptions) -> Result<CheckoutPlan> {
+        let work_tree = self.work_tree()
+            .ok_or_else(|| Error::from("this operation must be run in a work tree"))?
+            .to_path_buf();
+        let old = self.tree_files(from)?;
+        let new = self.tree_files(Some(tree))?;
+        let index = self.read_index()?;
+        if !opts.force && index.entries().iter().any(|e| e.stage > 0) {
+            bail!("you need to resolve your current index first");
+        }
+        let index_mtime = self.index_mtime();
+        let sparse = self.sparse_checkout()?;
+        let included = |path: &str| sparse.as_ref().is_none_or(|s| s.includes(path));
+
+        let paths: BTreeSet<String> = old.keys().chain(new.keys())
+            .chain(index.entries().iter().map(|e| &e.path))
+            .cloned()
+            .collect();
+        let mut local = vec![];
+        let mut untracked: Vec<String> = vec![];
+        let mut entries: Vec<IndexEntry> = vec![];
+        let mut removals: Vec<String> = vec![];
+        let mut writes: Vec<(String, FileMode, Id)> = vec![];
+        for path in &paths {
+            let (o, n) = (old.get(path), new.get(path));
+            let current = index.entry(path).filter(|_| !index.is_conflicted(path));
+            let clean = |entry: &IndexEntry| -> Result<bool> {
+                Ok(entry.skip_worktree || self.worktree_matches(&work_tree, entry, index_mtime)? != Some(false))
+            };
+            let n = match n {
+                Some(n) => n,
+                None => {
+                    match (o, current) {
+                        (_, None) => {}
+                        (None, Some(entry)) => entries.push(entry.clone()),
+                        (Some(o), Some(entry)) => {
+                            if !opts.force && (!same(entry, o) || !clean(entry)?) {
+                                local.push(path.clone());
+                            } else if !entry.skip_worktree {
+                                removals.push(path.clone());
+                            }
+                        }
+                    }
+                    continue;
+                }
+            };
+            let include = included(path);
+            match current {
+                // the trees agree, or the change is already staged: keep
+                // the entry and any local changes
+                Some(entry) if !opts.force && (o == Some(n) || same(entry, n)) => {
+                    let mut entry = entry.clone();
+                    if include && entry.skip_worktree {
+                        writes.push((path.clone(), n.0.clone(), n.1.clone()));
+                    } else if !include && !entry.skip_worktree && clean(&entry)? {
+                        entry.skip_worktree = true;
+                        removals.push(path.clone());
+                        entries.push(entry);
+                    } else {
+                        entries.push(entry);
+                    }
+                    continue;
+                }
+                Some(entry) if !opts.force && (o.is_none_or(|o| !same(entry, o)) || !clean(entry)?) => {
+                    local.push(path.clone());
+                    continue;
+                }
+                Some(entry) if opts.force && same(entry, n) && include && !entry.skip_worktree
+                    && clean(entry)? => {
+                    entries.push(entry.clone());
+                    continue;
+                }
+                None if !opts.force && include => {
+                    // an untracked symlink or file where a directory goes
+                    // is in the way just as much as one at the path
+                    let in_the_way = match blocked_leading_path(&self.fs, &work_tree, path) {
+                        Some((leading, _)) if index.entry(&leading).is_none() => Some(leading),
+                        Some(_) => None,
+                        None => match self.fs.metadata(work_tree.join(path)) {
+                            Ok(meta) if meta.file_type == FileType::Dir => {
+                                let dir = format!("{}/", path);
+                                Some(path.clone()).filter(|_| !index.entries().iter().any(|e| e.path.starts_with(&dir)))
+                            }
+                            Ok(_) => Some(path.clone()),
+                            Err(_) => None,
+                        },
+                    };
+                    if let Some(in_the_way) = in_the_way {
+                        if !untracked.contains(&in_the_way) {
+                            untracked.push(in_the_way);
+                        }
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+            if include {
+                writes.push((path.clone(), n.0.clone(), n.1.clone()));
+            } else {
+                if current.is_some_and(|e| !e.skip_worktree) {
+                    removals.push(path.clone());
+                }
+                entries.push(IndexEntry { skip_worktree: true, ..IndexEntry::new(path, n.0.bits(), n.1.clone()) });
+            }
+        }
+        if !local.is_empty() {
+            return Err(ErrorKind::WouldOverwrite(local).into());
+        }
+        if !untracked.is_empty() {
+            return Err(ErrorKind::UntrackedWouldBeOverwritten(untracked).into());
+        }
+        let workers = self.workers(opts.workers, "checkout.workers")?;
+        Ok(CheckoutPlan { index, entries, removals, writes, workers })
+    }
+}
+
+impl<FS: FileSystem + Sync> FileRepository<FS> {
+    /// Writes the files, reading blobs on this thread and handing them to
+    /// `workers` threads to write once there are enough of them. Returns
+    /// what each written file looks like now, and the first error, after
+    /// which the remaining files may not have been written.
+    fn write_files(&self, work_tree: &Path, files: &[(String, FileMode, Id)], workers: usize)
+                   -> (Vec<Option<Metadata>>, Result<()>) {
+        // only the file system is shared with the workers, not the packs
+        let fs = &self.fs;
+        let write = |n: usize, content: &[u8]| -> Result<Metadata> {
+            let (path, mode, _) = &files[n];
+            write_file(fs, &work_tree.join(path), mode, content)
+                .chain_err(|| format!("unable to write '{}'", path))
+        };
+        let read = |n: usize| -> Result<Option<Vec<u8>>> {
+            let (_, mode, id) = &files[n];
+            Ok(Some(match mode {
+                // a submodule's commit is not in this repository, only its directory is written
+                FileMode::SUBMODULE => vec![],
+                _ => Repository::read_content(self, &self.find_object(id)?)?,
+            }))
+        };
+        let workers = if files.len() < PARALLEL_THRESHOLD { 1 } else { workers };
+        run_workers(files.len(), workers, read, write)
+    }
+
+    /// Carries out a plan from `plan_checkout`. Should a file fail to be
+    /// removed or written, the index is still written for what did change,
+    /// so it keeps describing the working tree.
+    pub fn apply_checkout(&self, plan: CheckoutPlan) -> Result<CheckoutStats> {
+        let work_tree = self.work_tree()
+            .ok_or_else(|| Error::from("this operation must be run in a work tree"))?
+            .to_path_buf();
+        let CheckoutPlan { mut index, mut entries, removals, writes, workers } = plan;
+
+        let mut removed = 0;
+        let mut result = Ok(());
+        for path in &removals {
+            // a file behind a symlinked directory is not ours to remove
+            let full = work_tree.join(path);
+            let removal = match self.fs.metadata(&full) {
+                _ if blocked_leading_path(&self.fs, &work_tree, path).is_some() => Ok(()),
+                Ok(meta) if meta.file_type == FileType::Dir => self.fs.remove_dir(&full),
+                Ok(_) => self.fs.remove_file(&full),
+                Err(_) => Ok(()),
+            };
+            if let Err(e) = removal {
+                result = Err(e).chain_err(|| format!("unable to remove '{}'", path));
+                break;
+            }
+            prune_empty_dirs(&self.fs, &full, &work_tree);
+            removed += 1;
+        }
+        let written = if result.is_ok() {
+            result = writes.iter().try_for_each(|(path, _, _)| {
+                create_leading_dirs(&self.fs, &work_tree, path)
+                    .chain_err(|| format!("unable to create directories for '{}'", path))
+            });
+            match result {
+                Ok(()) => {
+                    let (written, outcome) = self.write_files(&work_tree, &writes, workers);
+                    result = outcome;
+                    written
+                }
+                Err(_) => vec![None; writes.len()],
+            }
+        } else {
+            vec![None; writes.len()]
+        };
+
+        for path in &removals[removed..] {
+            if let Some(entry) = index.entry(path).filter(|_| !entries.iter().any(|e| &e.path == path)) {
+                entries.push(entry.clone());
+            }
+        }
+        for ((path, mode, id), meta) in writes.iter().zip(&written) {
+            match meta {
+                Some(meta) => {
+                    let mut entry = IndexEntry::new(path, mode.bits(), id.clone());
+                    record_stat(&mut entry, meta);
+                    entries.push(entry);
+                }
+                None => entries.extend(index.entry(path).cloned()),
+            }
+        }
+        index.set_entries(entries);
+        self.write_index(&index)?;
+        result?;
+        Ok(CheckoutStats { written: writes.len(), removed: removals.len() })
+    }
+
+    /// Moves the working tree and index from the tree of `HEAD` to `tree`,
+    /// see `plan_checkout`.
+    pub fn checkout_tree(&self, tree: &Id, opts: &CheckoutOptions) -> Result<CheckoutStats> {
+        let plan = self.plan_checkout(self.head_tree()?.as_ref(), tree, opts)?;
+        self.apply_checkout(plan)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::MemFs;
+    use crate::model::init::InitOptions;
+    use crate::model::object::ObjectType;
+    use crate::model::tree::TreeEntry;
+
+    #[test]
+    fn test_checkout_on_mem_fs() {
+        let (repo, _) = FileRepository::init_with(MemFs::default(), Path::new("/work"), &InitOptions::default()).unwrap();
+        let blob = |content: &[u8]| repo.write_object(ObjectType::BLOB, content).unwrap();
+        let tree = repo.write_tree(&[
+            TreeEntry::new(FileMode::LINK, blob(b"run.sh"), "link"),
+            TreeEntry::new(FileMode::FILE, blob(b"text\n"), "plain"),
+            TreeEntry::new(FileMode::EXE, blob(b"#!/bin/sh\n"), "run.sh"),
+        ]).unwrap();
+        let stats = repo.checkout_tree(&tree, &CheckoutOptions::default()).unwrap();
+        assert_eq!(stats, CheckoutStats { written: 3, removed: 0 });
+
+        let fs = &repo.fs;
+        assert_eq!(fs.read_link("/work/link").unwrap(), b"run.sh");
+        assert_eq!(fs.metadata("/work/run.sh").unwrap().mode, 0o100755);
+        assert_eq!(fs.metadata("/work/plain").unwrap().mode, 0o100644);
+        let index = repo.read_index().unwrap();
+        let index_mtime = repo.index_mtime();
+        for entry in index.entries() {
+            assert_eq!(repo.worktree_matches(Path::new("/work"), entry, index_mtime).unwrap(), Some(true));
+        }
+
+        fs.write_file("/work/plain", b"changed\n").unwrap();
+        let entry = index.entry("plain").unwrap();
+        assert_eq!(repo.worktree_matches(Path::new("/work"), entry, index_mtime).unwrap(), Some(false));
+        fs.remove_file("/work/link").unwrap();
+        let entry = index.entry("link").unwrap();
+        assert_eq!(repo.worktree_matches(Path::new("/work"), entry, index_mtime).unwrap(), None);
+    }
+}
@@ -1,24 +1,21 @@
 
-use std::collections::HashMap;
 use crate::model::id::Id;
-use chrono::{DateTime, FixedOffset};
+use chrono::{DateTime, FixedOffset, Local, TimeZone};
 use nom::IResult;
-use nom::bytes::complete::{take_until, tag, take_while, take_till};
-use nom::multi::{separated_list};
+use nom::bytes::complete::{take_until, tag};
 use nom::sequence::{delimited};
-use nom::character::{is_space};
 
 use std::str;
 use std::str::FromStr;
 use crate::errors::*;
-use nom::combinator::{map_res, rest};
-use nom::branch::alt;
+use nom::combinator::map_res;
 use std::fmt::{Display, Formatter};
 
-use nom::character::complete::{not_line_ending, line_ending};
+use nom::character::complete::not_line_ending;
 use super::object::*;
 
 use crate::model::repository::Repository;
+use crate::config::Config;
 
 #[derive(Debug, Clone, PartialEq)]
 pub struct Commit {
@@ -27,11 +24,15 @@ pub struct Commit {
     parent: Vec<Id>,
     author: Option<Identity>,
     message: String,
+    raw_message: Vec<u8>,
     tree: Id,
-    other: HashMap<String, String>,
+    headers: Vec<(String, String)>,
 }
 
 impl Commit {
+    pub fn id(&self) -> &Id {
+        &self.id
+    }
     pub fn committer(&self) -> &Option<Identity> {
         &self.committer
     }
@@ -47,15 +48,32 @@ impl Commit {
     pub fn tree(&self) -> &Id {
         &self.tree
     }
+    /// The message bytes as stored, in the commit's `encoding`.
+    pub fn raw_message(&self) -> &[u8] {
+        &self.raw_message
+    }
+    /// Headers other than tree, parents, author and committer, in order.
+    pub fn extra_headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
+    pub fn encoding(&self) -> Option<&str> {
+        self.header("encoding")
+    }
 
+    /// Reads a commit leniently.
     pub fn from(repo: &dyn Repository, obj: &GitObject) -> Result<Self> {
+        Commit::with_mode(repo, obj, ParseMode::Lenient)
+    }
+
+    pub fn with_mode(repo: &dyn Repository, obj: &GitObject, mode: ParseMode) -> Result<Self> {
         if obj.object_type() == ObjectType::COMMIT {
             let buf = repo.read_content(&obj)?;
-            let commit: Commit = parse_commit(&buf, obj.id()).map(|res| res.1)
-                .map_err(|_|ErrorKind::ParseError)?;
-            Ok(commit)
+            parse_commit(&buf, obj.id(), mode)
         } else {
-            Err(ErrorKind::InvalidObjectType.into())
+            Err(obj.not_a(ObjectType::COMMIT))
         }
     }
 }
@@ -67,42 +85,68 @@ pub struct Identity {
     pub date: DateTime<FixedOffset>,
 }
 
+impl Identity {
+    /// The identity git would record for a committer right now:
+    /// `GIT_COMMITTER_NAME`, `GIT_COMMITTER_EMAIL` and `GIT_COMMITTER_DATE`,
+    /// falling back to `user.name`, `user.email` and the current time.
+    pub fn committer(config: &Config) -> Result<Identity> {
+        Identity::from_env(config, "COMMITTER")
+    }
+
+    pub fn author(config: &Config) -> Result<Identity> {
+        Identity::from_env(config, "AUTHOR")
+    }
+
+    fn from_env(config: &Config, role: &str) -> Result<Identity> {
+        let var = |name: &str| std::env::var(format!("GIT_{}_{}", role, name)).ok();
+        let user = std::env::var("USER").unwrap_or_else(|_| "unknown".to_owned());
+        let name = match var("NAME") {
+            Some(name) => name,
+            None => config.get("user.name")?.map(str::to_owned).unwrap_or_else(|| user.clone()),
+        };
+        let email = match var("EMAIL").or(std::env::var("EMAIL").ok()) {
+            Some(email) => email,
+            None => config.get("user.email")?.map(str::to_owned)
+                .unwrap_or_else(|| format!("{}@localhost", user)),
+        };
+        let date = match var("DATE") {
+            Some(date) => parse_date(&date)?,
+            None => {
+                let now = Local::now();
+                now.with_timezone(now.offset())
+            }
+        };
+        Ok(Identity { name, email, date })
+    }
+}
+
+/// Parses the date formats git accepts in `GIT_*_DATE`: its internal
+/// `<seconds> <offset>` form (optionally prefixed with `@`), RFC 2822 and
+/// ISO 8601.
+pub fn parse_date(input: &str) -> Result<DateTime<FixedOffset>> {
+    let input = input.trim();
+    let raw = input.strip_prefix('@').unwrap_or(input);
+    DateTime::parse_from_str(raw, "%s %z")
+        .or_else(|_| DateTime::parse_from_rfc2822(input))
+        .or_else(|_| DateTime::parse_from_rfc3339(input))
+        .or_else(|_| DateTime::parse_from_str(input, "%Y-%m-%d %H:%M:%S %z"))
+        .chain_err(|| format!("invalid date format: {}", input))
+}
+
 impl Display for Identity {
     fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
         write!(f, "{} <{}> {}", self.name, self.email, self.date.format("%s %z"))
     }
 }
 
-pub enum Attr {
-    Tree(Id),
-    Parent(Id),
-    Author(Identity),
-    Committer(Identity),
-    Unknown(String, String),
-}
-
 pub(crate) fn id_from_str_bytes(input: &[u8]) -> Result<Id> {
     let str = str::from_utf8(input)?;
     Id::from_str(str)
 }
 
-fn parse_tree(input: &[u8]) -> IResult<&[u8], Attr> {
-    let (input, _key) = tag("tree")(input)?;
-    let (input, _) = take_while(is_space)(input)?;
-    let (input, id) = map_res(not_line_ending, id_from_str_bytes)(input)?;
-    Ok((input, Attr::Tree(id)))
-}
-
-fn parse_parent(input: &[u8]) -> IResult<&[u8], Attr> {
-    let (input, _key) = tag("parent")(input)?;
-    let (input, _) = take_while(is_space)(input)?;
-    let (input, id) = map_res(not_line_ending, id_from_str_bytes)(input)?;
-    Ok((input, Attr::Parent(id)))
-}
-
 fn time_from_bytes(input: &[u8]) -> Result<DateTime<FixedOffset>> {
     let str = str::from_utf8(input)?;
-    DateTime::parse_from_str(str, "%s %z").chain_err(|| ErrorKind::ParseError)
+    DateTime::parse_from_str(str, "%s %z").chain_err(|| format!("invalid date '{}'", str))
 }
 
 fn parse_datetime(input: &[u8]) -> IResult<&[u8], DateTime<FixedOffset>> {
@@ -126,67 +170,133 @@ pub fn parse_identity(input: &[u8]) -> IResult<&[u8], Identity> {
     Ok((input, Identity { name: name.to_owned(), email: email.to_owned(), date }))
 }
 
-fn parse_author(input: &[u8]) -> IResult<&[u8], Attr> {
-    let (input, _key) = tag("author")(input)?;
-    let (input, _) = tag(" ")(input)?;
-    let (input, identity) = parse_identity(input)?;
-    Ok((input, Attr::Author(identity)))
+/// Parses an identity header. Lenient mode also takes a missing name,
+/// timezone or date, the last reading as the epoch.
+pub(crate) fn identity_value(value: &[u8], mode: ParseMode) -> Option<Identity> {
+    match parse_identity(value) {
+        Ok(([], identity)) => return Some(identity),
+        _ if mode == ParseMode::Strict => return None,
+        _ => {}
+    }
+    let open = value.iter().position(|b| *b == b'<')?;
+    let close = open + value[open..].iter().position(|b| *b == b'>')?;
+    let date = str::from_utf8(&value[close + 1..]).ok()?.trim();
+    let utc = FixedOffset::east(0);
+    let date = match date.split_whitespace().count() {
+        0 => utc.timestamp(0, 0),
+        1 => utc.timestamp_opt(date.parse().ok()?, 0).single()?,
+        _ => DateTime::parse_from_str(date, "%s %z").ok()?,
+    };
+    Some(Identity {
+        name: String::from_utf8_lossy(&value[..open]).trim().to_owned(),
+        email: String::from_utf8_lossy(&value[open + 1..close]).into_owned(),
+        date,
+    })
 }
 
-fn parse_committer(input: &[u8]) -> IResult<&[u8], Attr> {
-    let (input, _key) = tag("committer")(input)?;
-    let (input, _) = tag(" ")(input)?;
-    let (input, identity) = parse_identity(input)?;
-    Ok((input, Attr::Committer(identity)))
+/// Parses a header value holding an object id.
+pub(crate) fn id_value(value: &[u8]) -> Option<Id> {
+    id_from_str_bytes(value).ok()
 }
 
-fn parse_attrs(input: &[u8]) -> IResult<&[u8], Attr> {
-    let (input, attr) = alt((
-        parse_committer,
-        parse_author,
-        parse_tree,
-        parse_parent,
-        parse_attr
-    ))(input)?;
-    Ok((input, attr))
+/// Checks that known headers come in `order` (an index per known header,
+/// unknown ones last) and that only parents and unknown headers repeat.
+/// Returns the reason a strict parse rejects `header`.
+pub(crate) fn check_header(header: &Header, order: usize, last: &mut Option<usize>, repeatable: &[usize]) -> Option<&'static str> {
+    if header.key.is_empty() || !header.spaced {
+        return Some("invalid");
+    }
+    let misplaced = match *last {
+        Some(last) => order < last || (order == last && !repeatable.contains(&order)),
+        None => false,
+    };
+    *last = Some(order);
+    if misplaced { Some("unexpected") } else { None }
 }
 
-fn parse_attr(input: &[u8]) -> IResult<&[u8], Attr> {
-    let (input, key) = map_res(take_till(is_space), str::from_utf8)(input)?;
-    let (input, _) = take_while(is_space)(input)?;
-    let (input, value) = map_res(not_line_ending, str::from_utf8)(input)?;
-    Ok((input, Attr::Unknown(key.to_owned(), value.to_owned())))
+/// The message as text: lenient mode and messages with an `encoding`
+/// header fall back to a lossy conversion.
+pub(crate) fn message_text(raw: &[u8], mode: ParseMode, encoded: bool) -> Option<String> {
+    match str::from_utf8(raw) {
+        Ok(message) => Some(message.trim().to_owned()),
+        Err(_) if mode == ParseMode::Strict && !encoded => None,
+        Err(_) => Some(String::from_utf8_lossy(raw).trim().to_owned()),
+    }
 }
 
-fn parse_commit<'a>(input: &'a[u8], id: &Id) -> IResult<&'a[u8], Commit> {
-    let (input, attrs_str) = take_until("\n\n")(input)?;
-    let (_, attrs) = separated_list(line_ending, parse_attrs)(attrs_str)?;
-    let (input, message) = map_res(rest, str::from_utf8)(input)?;
-    let mut commit = Commit {
-        id: id.to_owned(),
-        committer: None,
-        parent: vec![],
-        author: None,
-        message: message.trim().to_owned(),
-        tree: Id::default(),
-        other: HashMap::new()
+const TREE: usize = 0;
+const PARENT: usize = 1;
+const AUTHOR: usize = 2;
+const COMMITTER: usize = 3;
+const OTHER: usize = 4;
+
+/// Parses commit content. Strict mode wants `tree`, `parent`s, `author`
+/// and `committer` in that order before any other header.
+pub fn parse_commit(content: &[u8], id: &Id, mode: ParseMode) -> Result<Commit> {
+    let corrupt = |offset: usize, reason: String| -> Error {
+        ErrorKind::CorruptObject(ObjectType::COMMIT, id.to_owned(), offset, reason).into()
+    };
+    let strict = mode == ParseMode::Strict;
+    let (headers, message) = split_headers(content);
+    let message = match message {
+        Some(message) => message,
+        None if strict => return Err(corrupt(content.len(), "missing blank line after header".to_owned())),
+        None => &[],
     };
-    for attr in attrs {
-        match attr {
-            Attr::Author(identity) => commit.author = Some(identity),
-            Attr::Committer(identity) => commit.committer = Some(identity),
-            Attr::Tree(id) => commit.tree = id,
-            Attr::Parent(id) => commit.parent.push(id),
-            Attr::Unknown(k, v) => { commit.other.insert(k, v); }
+    let (mut tree, mut author, mut committer) = (None, None, None);
+    let mut parent = vec![];
+    let mut extra = vec![];
+    let mut last = None;
+    for header in headers {
+        let order = match header.key {
+            b"tree" => TREE,
+            b"parent" => PARENT,
+            b"author" => AUTHOR,
+            b"committer" => COMMITTER,
+            _ => OTHER,
+        };
+        if strict {
+            if let Some(problem) = check_header(&header, order, &mut last, &[PARENT, OTHER]) {
+                return Err(corrupt(header.offset, format!("{} {} line", problem, header.name())));
+            }
+        }
+        let invalid = || corrupt(header.offset, format!("invalid {} line", header.name()));
+        match order {
+            TREE if tree.is_none() => tree = Some(id_value(&header.value).ok_or_else(invalid)?),
+            PARENT => parent.push(id_value(&header.value).ok_or_else(invalid)?),
+            AUTHOR if author.is_none() => author = Some(identity_value(&header.value, mode).ok_or_else(invalid)?),
+            COMMITTER if committer.is_none() => committer = Some(identity_value(&header.value, mode).ok_or_else(invalid)?),
+            _ if header.key.is_empty() => {}
+            _ => extra.push((header.name(), String::from_utf8_lossy(&header.value).into_owned())),
         }
     }
-    Ok((input, commit))
+    let tree = tree.ok_or_else(|| corrupt(0, "missing tree line".to_owned()))?;
+    if strict {
+        if author.is_none() {
+            return Err(corrupt(0, "missing author line".to_owned()));
+        }
+        if committer.is_none() {
+            return Err(corrupt(0, "missing committer line".to_owned()));
+        }
+    }
+    let encoded = extra.iter().any(|(key, _)| key == "encoding");
+    let text = message_text(message, mode, encoded)
+        .ok_or_else(|| corrupt(content.len() - message.len(), "message is not valid UTF-8".to_owned()))?;
+    Ok(Commit {
+        id: id.to_owned(),
+        committer,
+        parent,
+        author,
+        message: text,
+        raw_message: message.to_vec(),
+        tree,
+        headers: extra,
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use chrono::TimeZone;
 
     #[test]
     fn test_parse_identity() {
@@ -231,10 +341,80 @@ committer space dragon <allendragon@gmail.com> 1500000000 +0800
                          Id::from_str("1a8f251087b9d51b18f1a821cd87dae0acee2936").unwrap()],
             author: Some(author),
             message: str::from_utf8(message).unwrap().to_owned(),
+            raw_message: message.to_vec(),
             tree: Id::from_str("b2a72b0fe6f44f4839db41106ca11ad6db372327").unwrap(),
-            other: HashMap::new()
+            headers: vec![],
         };
-        let result = parse_commit(&str, &Id::default());
-        assert_eq!(result.unwrap(), ("".as_bytes() ,expected));
+        for &mode in &[ParseMode::Strict, ParseMode::Lenient] {
+            assert_eq!(parse_commit(&str, &Id::default(), mode).unwrap(), expected);
+        }
+    }
+
+    const TREE_LINE: &[u8] = b"tree b2a72b0fe6f44f4839db41106ca11ad6db372327\n";
+
+    fn commit(headers: &[u8], message: &[u8]) -> Vec<u8> {
+        let mut content = TREE_LINE.to_vec();
+        content.extend_from_slice(headers);
+        content.push(b'\n');
+        content.extend_from_slice(message);
+        content
+    }
+
+    fn reason(content: &[u8], mode: ParseMode) -> String {
+        parse_commit(content, &Id::default(), mode).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_parse_commit_extra_headers() {
+        let content = commit(b"author A <a@x> 1 +0000
+committer C <c@x> 2 +0100
+encoding ISO-8859-1
+gpgsig -----BEGIN PGP SIGNATURE-----
+ 
+ iQEz
+ -----END PGP SIGNATURE-----
+x-custom value
+", b"caf\xe9\n");
+        for &mode in &[ParseMode::Strict, ParseMode::Lenient] {
+            let commit = parse_commit(&content, &Id::default(), mode).unwrap();
+            let keys: Vec<_> = commit.extra_headers().iter().map(|(k, _)| k.as_str()).collect();
+            assert_eq!(keys, ["encoding", "gpgsig", "x-custom"]);
+            assert_eq!(commit.encoding(), Some("ISO-8859-1"));
+            assert_eq!(commit.header("gpgsig"),
+                       Some("-----BEGIN PGP SIGNATURE-----\n\niQEz\n-----END PGP SIGNATURE-----"));
+            assert_eq!(commit.raw_message(), b"caf\xe9\n");
+            assert_eq!(commit.message(), "caf\u{fffd}");
+        }
+    }
+
+    #[test]
+    fn test_parse_commit_strict_and_lenient() {
+        let id = Id::default();
+        let content = commit(b"committer C <c@x> 2 +0000\nauthor A <a@x> 1 +0000\n", b"msg\n");
+        assert_eq!(reason(&content, ParseMode::Strict),
+                   format!("commit {}: unexpected author line at byte 72", id));
+        let commit_ = parse_commit(&content, &id, ParseMode::Lenient).unwrap();
+        assert_eq!(commit_.author().as_ref().unwrap().name, "A");
+
+        let content = commit(b"author A <a@x>\ncommitter <c@x> 2\n", b"msg\n");
+        assert_eq!(reason(&content, ParseMode::Strict),
+                   format!("commit {}: invalid author line at byte 46", id));
+        let commit_ = parse_commit(&content, &id, ParseMode::Lenient).unwrap();
+        let author = commit_.author().as_ref().unwrap();
+        assert_eq!((author.name.as_str(), author.date.timestamp()), ("A", 0));
+        let committer = commit_.committer().as_ref().unwrap();
+        assert_eq!((committer.name.as_str(), committer.date.timestamp()), ("", 2));
+
+        let content = commit(b"author A <a@x> 1 +0000\ncommitter C <c@x> 1 +0000\n", b"\xff\n");
+        assert_eq!(reason(&content, ParseMode::Strict),
+                   format!("commit {}: message is not valid UTF-8 at byte 96", id));
+        assert!(parse_commit(&content, &id, ParseMode::Lenient).is_ok());
+
+        let content = commit(b"author A <a@x> 1 +0000\n", b"msg\n");
+        assert_eq!(reason(&content, ParseMode::Strict), format!("commit {}: missing committer line at byte 0", id));
+        assert!(parse_commit(&content, &id, ParseMode::Lenient).is_ok());
+
+        let content = b"author A <a@x> 1 +0000\n\nmsg\n";
+        assert_eq!(reason(content, ParseMode::Lenient), format!("commit {}: missing tree line at byte 0", id));
     }
 }
\ No newline at end of file
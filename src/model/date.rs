@@ -0,0 +1,99 @@
+use chrono::{DateTime, Duration, FixedOffset, NaiveDate, NaiveDateTime, TimeZone};
+use crate::errors::*;
+use crate::model::commit::parse_date;
+
+/// Parses the loose dates git accepts in `@{<date>}` and `--expire`:
+/// `now`, `yesterday`, `<n> <unit>[s] [ago]` (also dot separated, as in
+/// `2.weeks.ago`), `YYYY-MM-DD[ HH:MM[:SS]]` and anything `parse_date`
+/// understands. Relative dates count back from `now`.
+pub fn approxidate(input: &str, now: DateTime<FixedOffset>) -> Result<DateTime<FixedOffset>> {
+    let text = input.trim().to_lowercase();
+    match text.as_str() {
+        "now" => return Ok(now),
+        "yesterday" => return Ok(now - Duration::days(1)),
+        _ => {}
+    }
+    if let Some(date) = relative_date(&text, now) {
+        return Ok(date);
+    }
+    if let Some(date) = NaiveDate::parse_from_str(&text, "%Y-%m-%d").ok().and_then(|d| d.and_hms_opt(0, 0, 0)) {
+        return local(now, date, input);
+    }
+    for format in &["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%dt%H:%M:%S"] {
+        if let Ok(date) = NaiveDateTime::parse_from_str(&text, format) {
+            return local(now, date, input);
+        }
+    }
+    if let Ok(seconds) = text.parse::<i64>() {
+        if let Some(date) = now.timezone().timestamp_opt(seconds, 0).single() {
+            return Ok(date);
+        }
+    }
+    parse_date(input)
+}
+
+/// `--expire` style values: `never` and `false` disable expiry, `all` and
+/// `now` expire everything, anything else is an `approxidate`.
+pub fn expiry_date(input: &str, now: DateTime<FixedOffset>) -> Result<Option<DateTime<FixedOffset>>> {
+    match input.trim().to_lowercase().as_str() {
+        "never" | "false" => Ok(None),
+        "all" | "now" => Ok(Some(now)),
+        _ => approxidate(input, now).map(Some),
+    }
+}
+
+fn relative_date(text: &str, now: DateTime<FixedOffset>) -> Option<DateTime<FixedOffset>> {
+    let words: Vec<&str> = text.split(|c: char| c == '.' || c.is_whitespace())
+        .filter(|w| !w.is_empty())
+        .collect();
+    let words = match words.last() {
+        Some(&"ago") => &words[..words.len() - 1],
+        _ => &words[..],
+    };
+    if words.is_empty() || words.len() % 2 != 0 {
+        return None;
+    }
+    let mut date = now;
+    for pair in words.chunks(2) {
+        let count: i64 = pair[0].parse().ok()?;
+        let unit = pair[1].strip_suffix('s').unwrap_or(pair[1]);
+        let span = match unit {
+            "second" | "sec" => Duration::seconds(count),
+            "minute" | "min" => Duration::minutes(count),
+            "hour" => Duration::hours(count),
+            "day" => Duration::days(count),
+            "week" => Duration::weeks(count),
+            "month" => Duration::days(30 * count),
+            "year" => Duration::days(365 * count),
+            _ => return None,
+        };
+        date = date - span;
+    }
+    Some(date)
+}
+
+fn local(now: DateTime<FixedOffset>, date: NaiveDateTime, input: &str) -> Result<DateTime<FixedOffset>> {
+    now.timezone().from_local_datetime(&date).single()
+        .ok_or_else(|| format!("invalid date format: {}", input).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_approxidate() {
+        let now = FixedOffset::east(3600).timestamp(1_500_000_000, 0);
+        let ago = |s: &str| now.timestamp() - approxidate(s, now).unwrap().timestamp();
+        assert_eq!(ago("now"), 0);
+        assert_eq!(ago("yesterday"), 86400);
+        assert_eq!(ago("2.weeks.ago"), 14 * 86400);
+        assert_eq!(ago("3 hours ago"), 3 * 3600);
+        assert_eq!(ago("1 day 2 hours ago"), 86400 + 7200);
+        assert_eq!(approxidate("2017-07-14", now).unwrap().to_rfc3339(), "2017-07-14T00:00:00+01:00");
+        assert_eq!(approxidate("1500000000 +0100", now).unwrap(), now);
+        assert!(approxidate("someday", now).is_err());
+        assert_eq!(expiry_date("never", now).unwrap(), None);
+        assert_eq!(expiry_date("all", now).unwrap(), Some(now));
+    }
+}
@@ -0,0 +1,250 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use crate::errors::*;
+use crate::fs::FileSystem;
+use crate::model::commit::Commit;
+use crate::model::id::Id;
+use crate::model::object::Object;
+use crate::model::repository::{FileRepository, Repository};
+use crate::model::rev_walk::commit_time;
+use crate::wildmatch::wildmatch;
+
+/// How many tags are weighed before settling on the closest, as git's
+/// `--candidates` defaults to.
+const MAX_CANDIDATES: usize = 10;
+
+/// The commit flag for having been queued; candidate `n` marks what it
+/// reaches with bit `n + 1`.
+const SEEN: u32 = 1;
+
+#[derive(Debug, Clone, Default)]
+pub struct DescribeOptions {
+    /// Use lightweight tags too, not only annotated ones.
+    pub tags: bool,
+    /// Only tags whose names match one of these, if any.
+    pub matches: Vec<String>,
+    pub excludes: Vec<String>,
+    pub first_parent: bool,
+}
+
+/// A tag that can name a commit.
+#[derive(Debug, Clone)]
+struct Name {
+    /// The name the tag object gives itself, or the ref's for a
+    /// lightweight tag.
+    name: String,
+    annotated: bool,
+    /// The tagger's date, which picks between annotated tags of one
+    /// commit.
+    date: i64,
+}
+
+/// The nearest tag behind a commit and how far behind it is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Description {
+    pub tag: String,
+    /// Commits reachable from the described one but not from the tag.
+    pub depth: usize,
+    pub id: Id,
+}
+
+impl Description {
+    /// `v1.4.2-17-gabc1234`, or the tag alone when on it and not `long`.
+    /// An `abbrev` of 0 leaves the suffix out.
+    pub fn format(&self, abbrev: usize, long: bool) -> String {
+        if abbrev == 0 || (self.depth == 0 && !long) {
+            return self.tag.clone();
+        }
+        let hex = self.id.to_string();
+        format!("{}-{}-g{}", self.tag, self.depth, &hex[..abbrev.clamp(4, hex.len())])
+    }
+}
+
+struct Candidate<'a> {
+    name: &'a Name,
+    depth: usize,
+    flag: u32,
+}
+
+/// Walks commits newest first, spreading each commit's flags to its
+/// parents.
+struct Walk<'a, FS: FileSystem> {
+    repo: &'a FileRepository<FS>,
+    first_parent: bool,
+    /// Commit dates and indexes into `queued`, the later queued the
+    /// lower on equal dates.
+    queue: BinaryHeap<(i64, Reverse<usize>)>,
+    queued: Vec<Id>,
+    flags: HashMap<Id, u32>,
+}
+
+impl<'a, FS: FileSystem> Walk<'a, FS> {
+    fn push(&mut self, commit: &Commit) {
+        self.queue.push((commit_time(commit), Reverse(self.queued.len())));
+        self.queued.push(commit.id().clone());
+    }
+
+    fn pop(&mut self) -> Option<(i64, Reverse<usize>, Id)> {
+        self.queue.pop().map(|(time, seq)| (time, seq, self.queued[seq.0].clone()))
+    }
+
+    fn flags(&self, id: &Id) -> u32 {
+        self.flags.get(id).copied().unwrap_or(0)
+    }
+
+    /// Queues the parents of `id` not seen yet and gives them its flags.
+    fn add_parents(&mut self, id: &Id) -> Result<()> {
+        let flags = self.flags(id);
+        let commit = self.repo.find_commit(id)?;
+        let parents = if self.first_parent { &commit.parent()[..commit.parent().len().min(1)] } else { commit.parent() };
+        for parent in parents {
+            if self.flags(parent) & SEEN == 0 {
+                let parent_commit = self.repo.find_commit(parent)?;
+                self.push(&parent_commit);
+            }
+            *self.flags.entry(parent.clone()).or_default() |= flags;
+        }
+        Ok(())
+    }
+}
+
+fn matches_any(patterns: &[String], name: &str) -> bool {
+    patterns.iter().any(|pattern| wildmatch(pattern, name, false, false))
+}
+
+impl<FS: FileSystem> FileRepository<FS> {
+    /// The tags that may describe commits, by the commit each peels to.
+    /// Where several tag one commit, annotated tags win over lightweight
+    /// ones and newer annotated tags over older.
+    fn describe_names(&self, opts: &DescribeOptions) -> Result<HashMap<Id, Name>> {
+        let mut names: HashMap<Id, Name> = HashMap::new();
+        for path in self.ref_names()? {
+            let short = match path.strip_prefix("refs/tags/") {
+                Some(short) => short,
+                None => continue,
+            };
+            if (!opts.matches.is_empty() && !matches_any(&opts.matches, short)) || matches_any(&opts.excludes, short) {
+                continue;
+            }
+            let id = match self.resolve_ref(&path)? {
+                Some(id) => id,
+                None => continue,
+            };
+            let name = match self.read_object(&id)? {
+                Object::Tag(tag) => Name {
+                    name: tag.tag().to_owned(),
+                    annotated: true,
+                    date: tag.tagger().as_ref().map_or(0, |tagger| tagger.date.timestamp()),
+                },
+                _ => Name { name: short.to_owned(), annotated: false, date: 0 },
+            };
+            let commit = match self.peel_to_commit(&id) {
+                Ok(commit) => commit.id().clone(),
+                Err(_) => continue,
+            };
+            let replace = match names.get(&commit) {
+                None => true,
+                Some(old) if old.annotated && name.annotated => old.date == 0 || old.date < name.date,
+                Some(old) => !old.annotated && name.annotated,
+            };
+            if replace {
+                names.insert(commit, name);
+            }
+        }
+        Ok(names)
+    }
+
+    /// Finds the tag nearest to commit `id`: of the first few tags met
+    /// walking back from it, the one the fewest commits lie after. Fails
+    /// saying why when no tag is behind it.
+    pub fn describe(&self, id: &Id, opts: &DescribeOptions) -> Result<Description> {
+        let names = self.describe_names(opts)?;
+        if names.is_empty() {
+            bail!("No names found, cannot describe anything.");
+        }
+        let usable = |name: &Name| opts.tags || name.annotated;
+        if let Some(name) = names.get(id).filter(|name| usable(name)) {
+            return Ok(Description { tag: name.name.clone(), depth: 0, id: id.clone() });
+        }
+
+        let mut walk = Walk { repo: self, first_parent: opts.first_parent, queue: BinaryHeap::new(), queued: vec![], flags: HashMap::new() };
+        walk.push(&self.find_commit(id)?);
+        walk.flags.insert(id.clone(), SEEN);
+        let mut candidates: Vec<Candidate> = vec![];
+        let mut unannotated = false;
+        let mut annotated = false;
+        let mut seen_commits = 0;
+        let mut gave_up_on = None;
+        while let Some((time, seq, commit)) = walk.pop() {
+            seen_commits += 1;
+            if let Some(name) = names.get(&commit) {
+                if !usable(name) {
+                    unannotated = true;
+                } else if candidates.len() < MAX_CANDIDATES {
+                    let flag = 1 << (candidates.len() + 1);
+                    candidates.push(Candidate { name, depth: seen_commits - 1, flag });
+                    *walk.flags.entry(commit.clone()).or_default() |= flag;
+                    annotated |= name.annotated;
+                } else {
+                    gave_up_on = Some((time, seq));
+                    break;
+                }
+            }
+            let flags = walk.flags(&commit);
+            for candidate in candidates.iter_mut() {
+                if flags & candidate.flag == 0 {
+                    candidate.depth += 1;
+                }
+            }
+            // what is left is all behind a tag already
+            if annotated && walk.queue.is_empty() {
+                break;
+            }
+            walk.add_parents(&commit)?;
+        }
+        if candidates.is_empty() {
+            if unannotated {
+                bail!("No annotated tags can describe '{}'.\nHowever, there were unannotated tags: try --tags.", id);
+            }
+            bail!("No tags can describe '{}'.\nTry --always, or create some tags.", id);
+        }
+
+        // the sort is stable, so equally deep tags stay in the order found
+        candidates.sort_by_key(|candidate| candidate.depth);
+        if let Some(gave_up_on) = gave_up_on {
+            walk.queue.push(gave_up_on);
+        }
+        let best = &mut candidates[0];
+        // commits still queued that the best tag does not reach lie
+        // between it and the described commit too
+        while let Some((_, _, commit)) = walk.pop() {
+            let flags = walk.flags(&commit);
+            if flags & best.flag != 0 {
+                if walk.queue.iter().all(|(_, seq)| walk.flags(&walk.queued[seq.0]) & best.flag != 0) {
+                    break;
+                }
+            } else {
+                best.depth += 1;
+            }
+            walk.add_parents(&commit)?;
+        }
+        Ok(Description { tag: best.name.name.clone(), depth: best.depth, id: id.clone() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_description_format() {
+        let id: Id = "6434ccd6a0d4f1dc0d6e438fd2dc2828ee91dc23".parse().unwrap();
+        let description = Description { tag: "v1.10".to_owned(), depth: 1, id: id.clone() };
+        assert_eq!(description.format(7, false), "v1.10-1-g6434ccd");
+        assert_eq!(description.format(12, false), "v1.10-1-g6434ccd6a0d4");
+        assert_eq!(description.format(0, true), "v1.10");
+        let exact = Description { depth: 0, ..description };
+        assert_eq!(exact.format(7, false), "v1.10");
+        assert_eq!(exact.format(7, true), "v1.10-0-g6434ccd");
+    }
+}
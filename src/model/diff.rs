@@ -0,0 +1,391 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use crate::errors::*;
+use crate::fs::FileSystem;
+use crate::model::id::Id;
+use crate::model::repository::{FileRepository, Repository};
+use crate::model::tree::{FileMode, TreeEntry};
+
+/// Past this many candidates on either side only exact renames are found.
+const RENAME_LIMIT: usize = 1000;
+
+/// A region where `old` lines were replaced by `new` lines. Either range
+/// may be empty, for a pure insertion or deletion.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edit {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+/// Splits `content` into lines, each keeping its `\n`; the last may lack
+/// one.
+pub fn lines(content: &[u8]) -> Vec<&[u8]> {
+    let mut lines = vec![];
+    let mut start = 0;
+    for (i, b) in content.iter().enumerate() {
+        if *b == b'\n' {
+            lines.push(&content[start..=i]);
+            start = i + 1;
+        }
+    }
+    if start < content.len() {
+        lines.push(&content[start..]);
+    }
+    lines
+}
+
+/// The edits turning `old` into `new`, in order, found with Myers' linear
+/// space algorithm.
+pub fn diff<T: PartialEq>(old: &[T], new: &[T]) -> Vec<Edit> {
+    let max = old.len() + new.len() + 1;
+    let mut forward = V::new(max);
+    let mut backward = V::new(max);
+    let mut edits = vec![];
+    conquer(old, 0..old.len(), new, 0..new.len(), &mut forward, &mut backward, &mut edits);
+    // join the pieces the recursion split
+    let mut joined: Vec<Edit> = vec![];
+    for edit in edits {
+        match joined.last_mut() {
+            Some(last) if last.old.end == edit.old.start && last.new.end == edit.new.start => {
+                last.old.end = edit.old.end;
+                last.new.end = edit.new.end;
+            }
+            _ => joined.push(edit),
+        }
+    }
+    joined
+}
+
+/// Furthest reaching x per diagonal k, indexed from -max to max.
+struct V {
+    offset: isize,
+    v: Vec<usize>,
+}
+
+impl V {
+    fn new(max: usize) -> V {
+        V { offset: max as isize, v: vec![0; 2 * max + 2] }
+    }
+}
+
+impl std::ops::Index<isize> for V {
+    type Output = usize;
+    fn index(&self, k: isize) -> &usize {
+        &self.v[(k + self.offset) as usize]
+    }
+}
+
+impl std::ops::IndexMut<isize> for V {
+    fn index_mut(&mut self, k: isize) -> &mut usize {
+        &mut self.v[(k + self.offset) as usize]
+    }
+}
+
+fn common_prefix<T: PartialEq>(old: &[T], new: &[T]) -> usize {
+    old.iter().zip(new).take_while(|(a, b)| a == b).count()
+}
+
+fn common_suffix<T: PartialEq>(old: &[T], new: &[T]) -> usize {
+    old.iter().rev().zip(new.iter().rev()).take_while(|(a, b)| a == b).count()
+}
+
+/// The start of a snake on an optimal path through the middle of the
+/// edit graph, searching from both ends at once.
+fn middle_snake<T: PartialEq>(old: &[T], old_range: Range<usize>, new: &[T], new_range: Range<usize>,
+                              vf: &mut V, vb: &mut V) -> Option<(usize, usize)> {
+    let n = old_range.len();
+    let m = new_range.len();
+    let delta = n as isize - m as isize;
+    let odd = delta & 1 == 1;
+    vf[1] = 0;
+    vb[1] = 0;
+    let d_max = ((n + m).div_ceil(2) + 1) as isize;
+    for d in 0..d_max {
+        for k in (-d..=d).rev().step_by(2) {
+            let mut x = if k == -d || (k != d && vf[k - 1] < vf[k + 1]) { vf[k + 1] } else { vf[k - 1] + 1 };
+            let y = (x as isize - k) as usize;
+            let start = (x, y);
+            if x < n && y < m {
+                x += common_prefix(&old[old_range.start + x..old_range.end], &new[new_range.start + y..new_range.end]);
+            }
+            vf[k] = x;
+            if odd && (k - delta).abs() < d && vf[k] + vb[-(k - delta)] >= n {
+                return Some((start.0 + old_range.start, start.1 + new_range.start));
+            }
+        }
+        for k in (-d..=d).rev().step_by(2) {
+            let mut x = if k == -d || (k != d && vb[k - 1] < vb[k + 1]) { vb[k + 1] } else { vb[k - 1] + 1 };
+            let mut y = (x as isize - k) as usize;
+            if x < n && y < m {
+                let advance = common_suffix(&old[old_range.start..old_range.start + n - x],
+                                            &new[new_range.start..new_range.start + m - y]);
+                x += advance;
+                y += advance;
+            }
+            vb[k] = x;
+            if !odd && (k - delta).abs() <= d && vb[k] + vf[-(k - delta)] >= n {
+                return Some((n - x + old_range.start, m - y + new_range.start));
+            }
+        }
+    }
+    None
+}
+
+fn conquer<T: PartialEq>(old: &[T], mut old_range: Range<usize>, new: &[T], mut new_range: Range<usize>,
+                         vf: &mut V, vb: &mut V, edits: &mut Vec<Edit>) {
+    let prefix = common_prefix(&old[old_range.clone()], &new[new_range.clone()]);
+    old_range.start += prefix;
+    new_range.start += prefix;
+    let suffix = common_suffix(&old[old_range.clone()], &new[new_range.clone()]);
+    old_range.end -= suffix;
+    new_range.end -= suffix;
+
+    if old_range.is_empty() && new_range.is_empty() {
+        return;
+    }
+    if old_range.is_empty() || new_range.is_empty() {
+        edits.push(Edit { old: old_range, new: new_range });
+        return;
+    }
+    match middle_snake(old, old_range.clone(), new, new_range.clone(), vf, vb) {
+        Some((x, y)) => {
+            conquer(old, old_range.start..x, new, new_range.start..y, vf, vb, edits);
+            conquer(old, x..old_range.end, new, y..new_range.end, vf, vb, edits);
+        }
+        None => edits.push(Edit { old: old_range, new: new_range }),
+    }
+}
+
+/// How much of the larger file, in percent, is lines the other one
+/// shares; 0 early when the sizes alone rule out reaching `threshold`.
+pub fn similarity(a: &[u8], b: &[u8], threshold: usize) -> usize {
+    let (small, large) = (a.len().min(b.len()), a.len().max(b.len()));
+    if large == 0 {
+        return 100;
+    }
+    if small * 100 < large * threshold {
+        return 0;
+    }
+    let (a_lines, b_lines) = (lines(a), lines(b));
+    let changed: usize = diff(&a_lines, &b_lines).iter()
+        .flat_map(|edit| a_lines[edit.old.clone()].iter())
+        .map(|line| line.len())
+        .sum();
+    (a.len() - changed) * 100 / large
+}
+
+/// One side of a changed file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffFile {
+    pub path: String,
+    pub mode: FileMode,
+    pub id: Id,
+}
+
+impl DiffFile {
+    fn is_file(&self) -> bool {
+        self.mode == FileMode::FILE || self.mode == FileMode::EXE
+    }
+
+    /// Regular files of either mode pair up with each other; symlinks and
+    /// submodules only with their own kind.
+    fn same_type(&self, other: &DiffFile) -> bool {
+        (self.is_file() && other.is_file()) || self.mode == other.mode
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TreeChange {
+    Added(DiffFile),
+    Deleted(DiffFile),
+    Modified(DiffFile, DiffFile),
+    /// A deleted file paired with an added one, and how similar they are
+    /// in percent.
+    Renamed(DiffFile, DiffFile, usize),
+}
+
+impl TreeChange {
+    /// The path after the change, before it for a deletion.
+    pub fn path(&self) -> &str {
+        match self {
+            TreeChange::Added(file) | TreeChange::Deleted(file) => &file.path,
+            TreeChange::Modified(_, file) | TreeChange::Renamed(_, file, _) => &file.path,
+        }
+    }
+}
+
+/// Pairs `deleted` files with `added` ones: first those with the same
+/// content, then the most similar pairs scoring at least `threshold`
+/// percent, unless either side has more than `limit` files left. Returns
+/// the indices of each pair and its score.
+pub(crate) fn pair_renames(deleted: &[DiffFile], added: &[DiffFile], threshold: usize, limit: usize,
+                           content: &mut dyn FnMut(&Id) -> Result<Vec<u8>>) -> Result<Vec<(usize, usize, usize)>> {
+    let mut pairs = vec![];
+    let mut used_source = vec![false; deleted.len()];
+    let mut used_target = vec![false; added.len()];
+    for (i, source) in deleted.iter().enumerate() {
+        let exact = (0..added.len()).find(|&j| !used_target[j] && added[j].mode == source.mode && added[j].id == source.id);
+        if let Some(j) = exact {
+            used_source[i] = true;
+            used_target[j] = true;
+            pairs.push((i, j, 100));
+        }
+    }
+    let sources: Vec<usize> = (0..deleted.len()).filter(|&i| !used_source[i]).collect();
+    let targets: Vec<usize> = (0..added.len()).filter(|&j| !used_target[j]).collect();
+    if sources.is_empty() || targets.is_empty() || sources.len().max(targets.len()) > limit {
+        return Ok(pairs);
+    }
+
+    let mut contents = HashMap::new();
+    for file in sources.iter().map(|&i| &deleted[i]).chain(targets.iter().map(|&j| &added[j])) {
+        if !contents.contains_key(&file.id) {
+            contents.insert(file.id.clone(), content(&file.id)?);
+        }
+    }
+    let mut scores = vec![];
+    for &i in &sources {
+        for &j in &targets {
+            let (from, to) = (&deleted[i], &added[j]);
+            if from.same_type(to) {
+                let score = similarity(&contents[&from.id], &contents[&to.id], threshold);
+                if score >= threshold {
+                    scores.push((score, i, j));
+                }
+            }
+        }
+    }
+    // best pairs first; ties go to the earliest paths
+    scores.sort_by(|a, b| b.0.cmp(&a.0).then((a.1, a.2).cmp(&(b.1, b.2))));
+    for (score, i, j) in scores {
+        if !used_source[i] && !used_target[j] {
+            used_source[i] = true;
+            used_target[j] = true;
+            pairs.push((i, j, score));
+        }
+    }
+    Ok(pairs)
+}
+
+impl<FS: FileSystem> FileRepository<FS> {
+    fn tree_changes(&self, prefix: &str, old: Option<&Id>, new: Option<&Id>, changes: &mut Vec<TreeChange>) -> Result<()> {
+        let mut names: BTreeMap<String, (Option<TreeEntry>, Option<TreeEntry>)> = BTreeMap::new();
+        if let Some(id) = old {
+            for entry in self.find_tree(id)?.entries() {
+                names.entry(entry.name().to_owned()).or_default().0 = Some(entry.clone());
+            }
+        }
+        if let Some(id) = new {
+            for entry in self.find_tree(id)?.entries() {
+                names.entry(entry.name().to_owned()).or_default().1 = Some(entry.clone());
+            }
+        }
+        let file = |path: &str, entry: &TreeEntry| DiffFile { path: path.to_owned(), mode: entry.mode(), id: entry.id().clone() };
+        for (name, (old, new)) in names {
+            if old == new {
+                continue;
+            }
+            let path = format!("{}{}", prefix, name);
+            let is_dir = |entry: &Option<TreeEntry>| entry.as_ref().is_some_and(|e| e.mode() == FileMode::DIR);
+            if is_dir(&old) || is_dir(&new) {
+                let subtree = |entry: &Option<TreeEntry>| entry.as_ref().filter(|e| e.mode() == FileMode::DIR).map(|e| e.id().clone());
+                self.tree_changes(&format!("{}/", path), subtree(&old).as_ref(), subtree(&new).as_ref(), changes)?;
+            }
+            let (old, new) = (old.filter(|e| e.mode() != FileMode::DIR), new.filter(|e| e.mode() != FileMode::DIR));
+            match (old, new) {
+                (Some(old), Some(new)) => changes.push(TreeChange::Modified(file(&path, &old), file(&path, &new))),
+                (Some(old), None) => changes.push(TreeChange::Deleted(file(&path, &old))),
+                (None, Some(new)) => changes.push(TreeChange::Added(file(&path, &new))),
+                (None, None) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// The files that differ between two trees, `None` being the empty
+    /// tree, in path order. With a `rename_threshold`, deleted and added
+    /// files at least that similar in percent are paired up as renames.
+    pub fn diff_trees(&self, old: Option<&Id>, new: Option<&Id>, rename_threshold: Option<usize>) -> Result<Vec<TreeChange>> {
+        let mut changes = vec![];
+        self.tree_changes("", old, new, &mut changes)?;
+        if let Some(threshold) = rename_threshold {
+            let (mut deleted, mut added) = (vec![], vec![]);
+            let mut rest = vec![];
+            for change in changes {
+                match change {
+                    TreeChange::Deleted(file) if file.mode != FileMode::SUBMODULE => deleted.push(file),
+                    TreeChange::Added(file) if file.mode != FileMode::SUBMODULE => added.push(file),
+                    change => rest.push(change),
+                }
+            }
+            let pairs = pair_renames(&deleted, &added, threshold, RENAME_LIMIT,
+                                     &mut |id| Repository::read_content(self, &self.find_object(id)?))?;
+            let mut renamed_from = vec![None; deleted.len()];
+            let mut renamed_to = vec![false; added.len()];
+            for (i, j, score) in pairs {
+                renamed_from[i] = Some((j, score));
+                renamed_to[j] = true;
+            }
+            for (file, pair) in deleted.iter().zip(renamed_from) {
+                match pair {
+                    Some((j, score)) => rest.push(TreeChange::Renamed(file.clone(), added[j].clone(), score)),
+                    None => rest.push(TreeChange::Deleted(file.clone())),
+                }
+            }
+            rest.extend(added.into_iter().zip(renamed_to).filter(|(_, renamed)| !renamed).map(|(file, _)| TreeChange::Added(file)));
+            changes = rest;
+        }
+        changes.sort_by(|a, b| a.path().cmp(b.path()));
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply<'a>(old: &[&'a str], new: &[&'a str], edits: &[Edit]) -> Vec<&'a str> {
+        let mut out = vec![];
+        let mut at = 0;
+        for edit in edits {
+            out.extend_from_slice(&old[at..edit.old.start]);
+            out.extend_from_slice(&new[edit.new.clone()]);
+            at = edit.old.end;
+        }
+        out.extend_from_slice(&old[at..]);
+        out
+    }
+
+    fn lcs(a: &[&str], b: &[&str]) -> usize {
+        let mut table = vec![vec![0; b.len() + 1]; a.len() + 1];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                table[i][j] = if a[i] == b[j] { table[i + 1][j + 1] + 1 } else { table[i + 1][j].max(table[i][j + 1]) };
+            }
+        }
+        table[0][0]
+    }
+
+    #[test]
+    fn test_lines() {
+        assert_eq!(lines(b"a\nb\n"), [&b"a\n"[..], b"b\n"]);
+        assert_eq!(lines(b"a\nb"), [&b"a\n"[..], b"b"]);
+        assert!(lines(b"").is_empty());
+    }
+
+    #[test]
+    fn test_diff_is_minimal() {
+        let mut seed = 7u32;
+        let mut next = || { seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345); (seed >> 16) % 4 };
+        let words = ["a", "b", "c", "d"];
+        for round in 0..200 {
+            let old: Vec<&str> = (0..round % 13).map(|_| words[next() as usize]).collect();
+            let new: Vec<&str> = (0..round % 11).map(|_| words[next() as usize]).collect();
+            let edits = diff(&old, &new);
+            assert_eq!(apply(&old, &new, &edits), new);
+            let changed: usize = edits.iter().map(|e| e.old.len() + e.new.len()).sum();
+            assert_eq!(changed, old.len() + new.len() - 2 * lcs(&old, &new), "{:?} -> {:?}", old, new);
+            assert!(edits.windows(2).all(|w| w[0].old.end < w[1].old.start || w[0].new.end < w[1].new.start));
+        }
+    }
+}
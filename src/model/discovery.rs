@@ -0,0 +1,277 @@
+use std::path::{Path, PathBuf, Component};
+use std::io::Read;
+use std::ffi::OsString;
+use crate::errors::*;
+use crate::fs::FileSystem;
+use crate::config::Config;
+
+/// Where the pieces of a repository live on disk.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RepositoryLayout {
+    pub git_dir: PathBuf,
+    pub common_dir: PathBuf,
+    pub object_dir: PathBuf,
+    pub work_tree: Option<PathBuf>,
+    pub is_bare: bool,
+}
+
+/// The environment variables that influence discovery.
+#[derive(Debug, Clone, Default)]
+pub struct DiscoveryEnv {
+    pub git_dir: Option<PathBuf>,
+    pub work_tree: Option<PathBuf>,
+    pub common_dir: Option<PathBuf>,
+    pub object_dir: Option<PathBuf>,
+    pub ceiling_dirs: Vec<PathBuf>,
+    pub across_filesystem: bool,
+}
+
+impl DiscoveryEnv {
+    pub fn from_env() -> Self {
+        let var = |name: &str| std::env::var_os(name)
+            .filter(|v| !v.is_empty())
+            .map(PathBuf::from);
+        let ceiling_dirs = std::env::var_os("GIT_CEILING_DIRECTORIES")
+            .map(|dirs| std::env::split_paths(&dirs)
+                .filter(|p| p.is_absolute())
+                .map(|p| normalize(&p))
+                .collect())
+            .unwrap_or_default();
+        let across_filesystem = std::env::var("GIT_DISCOVERY_ACROSS_FILESYSTEM")
+            .map(|v| crate::config::value::parse_bool("GIT_DISCOVERY_ACROSS_FILESYSTEM", Some(&v))
+                .unwrap_or(false))
+            .unwrap_or(false);
+        DiscoveryEnv {
+            git_dir: var("GIT_DIR"),
+            work_tree: var("GIT_WORK_TREE"),
+            common_dir: var("GIT_COMMON_DIR"),
+            object_dir: var("GIT_OBJECT_DIRECTORY"),
+            ceiling_dirs,
+            across_filesystem,
+        }
+    }
+}
+
+/// Finds the repository containing `start`, walking up the directory tree
+/// like `git rev-parse --git-dir` does.
+pub fn discover<FS: FileSystem>(fs: &FS, start: &Path, env: &DiscoveryEnv) -> Result<RepositoryLayout> {
+    let start = absolute(start)?;
+    if let Some(git_dir) = &env.git_dir {
+        let git_dir = resolve_gitfile(fs, &absolute(git_dir)?)?;
+        if !is_git_dir(fs, &git_dir, env) {
+            return Err(ErrorKind::InvalidRepository(git_dir).into());
+        }
+        // with GIT_DIR set, the current directory is the work tree unless
+        // told otherwise
+        return layout(fs, git_dir, Some(start), env);
+    }
+
+    let device = fs.device(&start);
+    let mut dir = start.as_path();
+    loop {
+        let dot_git = dir.join(".git");
+        if fs.is_file(&dot_git) {
+            let git_dir = read_gitfile(fs, &dot_git)?;
+            if is_git_dir(fs, &git_dir, env) {
+                return layout(fs, git_dir, Some(dir.to_path_buf()), env);
+            }
+            return Err(ErrorKind::InvalidRepository(git_dir).into());
+        }
+        if is_git_dir(fs, &dot_git, env) {
+            return layout(fs, dot_git, Some(dir.to_path_buf()), env);
+        }
+        if is_git_dir(fs, dir, env) {
+            return layout(fs, dir.to_path_buf(), None, env);
+        }
+        let parent = match dir.parent() {
+            Some(parent) => parent,
+            None => break,
+        };
+        if env.ceiling_dirs.iter().any(|c| c == parent) {
+            break;
+        }
+        if !env.across_filesystem && device.is_some() && fs.device(parent) != device {
+            return Err(format!("not a git repository (or any parent up to mount point {})",
+                               dir.display()).into());
+        }
+        dir = parent;
+    }
+    Err(ErrorKind::InvalidRepository(start).into())
+}
+
+fn layout<FS: FileSystem>(fs: &FS, git_dir: PathBuf, work_tree: Option<PathBuf>,
+                          env: &DiscoveryEnv) -> Result<RepositoryLayout> {
+    let common_dir = common_dir(fs, &git_dir, env)?;
+    let object_dir = match &env.object_dir {
+        Some(dir) => absolute(dir)?,
+        None => common_dir.join("objects"),
+    };
+    let config = Config::open_repository(fs, &git_dir, &common_dir)?;
+    let configured_tree = match &env.work_tree {
+        Some(tree) => Some(absolute(tree)?),
+        None => config.get_path("core.worktree")?
+            .map(|tree| normalize(&git_dir.join(tree))),
+    };
+    let explicit_tree = configured_tree.is_some();
+    let is_bare = !explicit_tree && config.get_bool("core.bare")?.unwrap_or(work_tree.is_none());
+    let work_tree = if is_bare {
+        None
+    } else {
+        configured_tree.or(work_tree)
+    };
+    Ok(RepositoryLayout { git_dir, common_dir, object_dir, work_tree, is_bare })
+}
+
+fn common_dir<FS: FileSystem>(fs: &FS, git_dir: &Path, env: &DiscoveryEnv) -> Result<PathBuf> {
+    if let Some(dir) = &env.common_dir {
+        return absolute(dir);
+    }
+    match read_to_string(fs, &git_dir.join("commondir")) {
+        Some(dir) => Ok(normalize(&git_dir.join(dir.trim()))),
+        None => Ok(git_dir.to_path_buf())
+    }
+}
+
+/// A directory is a repository if it has `HEAD`, an object directory and
+/// `refs/`, the latter two possibly through `commondir`.
+fn is_git_dir<FS: FileSystem>(fs: &FS, dir: &Path, env: &DiscoveryEnv) -> bool {
+    if !fs.is_file(dir.join("HEAD")) {
+        return false;
+    }
+    let common = match common_dir(fs, dir, env) {
+        Ok(common) => common,
+        Err(_) => return false,
+    };
+    let has_objects = env.object_dir.is_some() || fs.is_dir(common.join("objects"));
+    has_objects && fs.is_dir(common.join("refs"))
+}
+
+fn resolve_gitfile<FS: FileSystem>(fs: &FS, path: &Path) -> Result<PathBuf> {
+    if fs.is_file(path) {
+        read_gitfile(fs, path)
+    } else {
+        Ok(path.to_path_buf())
+    }
+}
+
+/// Follows a `.git` file containing `gitdir: <path>`, as used by linked
+/// worktrees and submodules.
+fn read_gitfile<FS: FileSystem>(fs: &FS, path: &Path) -> Result<PathBuf> {
+    let content = read_to_string(fs, path)
+        .ok_or_else(|| Error::from(ErrorKind::InvalidRepository(path.to_path_buf())))?;
+    let target = content.trim_end_matches(['\n', '\r'])
+        .strip_prefix("gitdir: ")
+        .ok_or_else(|| Error::from(format!("invalid gitfile format: {}", path.display())))?;
+    let base = path.parent().unwrap_or_else(|| Path::new("/"));
+    Ok(normalize(&base.join(target)))
+}
+
+fn read_to_string<FS: FileSystem>(fs: &FS, path: &Path) -> Option<String> {
+    let mut reader = fs.read_file(path).ok()?;
+    let mut buf = vec![];
+    reader.read_to_end(&mut buf).ok()?;
+    String::from_utf8(buf).ok()
+}
+
+fn absolute(path: &Path) -> Result<PathBuf> {
+    if path.is_absolute() {
+        Ok(normalize(path))
+    } else {
+        Ok(normalize(&std::env::current_dir()?.join(path)))
+    }
+}
+
+/// Removes `.` and `..` components without touching the file system.
+pub(crate) fn normalize(path: &Path) -> PathBuf {
+    let mut parts: Vec<OsString> = vec![];
+    let mut root = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => root.push(component.as_os_str()),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if parts.pop().is_none() && root.as_os_str().is_empty() {
+                    parts.push(OsString::from(".."));
+                }
+            }
+            Component::Normal(part) => parts.push(part.to_os_string()),
+        }
+    }
+    let mut result = root;
+    for part in parts {
+        result.push(part);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::MemFs;
+
+    fn add_git_dir(fs: &mut MemFs, dir: &str, config: &str) {
+        fs.add_file(format!("{}/HEAD", dir), b"ref: refs/heads/master\n".to_vec());
+        fs.add_file(format!("{}/objects/info/packs", dir), vec![]);
+        fs.add_file(format!("{}/refs/heads/master", dir), vec![]);
+        fs.add_file(format!("{}/config", dir), config.as_bytes().to_vec());
+    }
+
+    #[test]
+    fn test_discover_from_subdirectory() {
+        let mut fs = MemFs::default();
+        add_git_dir(&mut fs, "/work/repo/.git", "[core]\n\tbare = false\n");
+        fs.add_file("/work/repo/src/deep/main.rs", vec![]);
+        let layout = discover(&fs, Path::new("/work/repo/src/deep"), &DiscoveryEnv::default()).unwrap();
+        assert_eq!(layout.git_dir, PathBuf::from("/work/repo/.git"));
+        assert_eq!(layout.object_dir, PathBuf::from("/work/repo/.git/objects"));
+        assert_eq!(layout.work_tree, Some(PathBuf::from("/work/repo")));
+        assert!(!layout.is_bare);
+    }
+
+    #[test]
+    fn test_discover_stops_at_ceiling() {
+        let mut fs = MemFs::default();
+        add_git_dir(&mut fs, "/work/repo/.git", "");
+        fs.add_file("/work/repo/src/main.rs", vec![]);
+        let env = DiscoveryEnv {
+            ceiling_dirs: vec![PathBuf::from("/work/repo")],
+            ..DiscoveryEnv::default()
+        };
+        assert!(discover(&fs, Path::new("/work/repo/src"), &env).is_err());
+        assert!(discover(&fs, Path::new("/work/repo"), &env).is_ok());
+    }
+
+    #[test]
+    fn test_discover_worktree_gitfile() {
+        let mut fs = MemFs::default();
+        add_git_dir(&mut fs, "/work/repo/.git", "[core]\n\tbare = false\n");
+        fs.add_file("/work/repo/.git/worktrees/feature/HEAD", b"ref: refs/heads/feature\n".to_vec());
+        fs.add_file("/work/repo/.git/worktrees/feature/commondir", b"../..\n".to_vec());
+        fs.add_file("/work/feature/.git", b"gitdir: ../repo/.git/worktrees/feature\n".to_vec());
+        let layout = discover(&fs, Path::new("/work/feature"), &DiscoveryEnv::default()).unwrap();
+        assert_eq!(layout.git_dir, PathBuf::from("/work/repo/.git/worktrees/feature"));
+        assert_eq!(layout.common_dir, PathBuf::from("/work/repo/.git"));
+        assert_eq!(layout.work_tree, Some(PathBuf::from("/work/feature")));
+    }
+
+    #[test]
+    fn test_bare_from_config() {
+        let mut fs = MemFs::default();
+        add_git_dir(&mut fs, "/srv/repo.git", "[core]\n\tbare = true\n");
+        let layout = discover(&fs, Path::new("/srv/repo.git/refs"), &DiscoveryEnv::default()).unwrap();
+        assert_eq!(layout.git_dir, PathBuf::from("/srv/repo.git"));
+        assert!(layout.is_bare);
+        assert_eq!(layout.work_tree, None);
+
+        let env = DiscoveryEnv {
+            git_dir: Some(PathBuf::from("/srv/repo.git")),
+            work_tree: Some(PathBuf::from("/tmp/checkout")),
+            object_dir: Some(PathBuf::from("/srv/objects")),
+            ..DiscoveryEnv::default()
+        };
+        let layout = discover(&fs, Path::new("/"), &env).unwrap();
+        assert!(!layout.is_bare);
+        assert_eq!(layout.work_tree, Some(PathBuf::from("/tmp/checkout")));
+        assert_eq!(layout.object_dir, PathBuf::from("/srv/objects"));
+    }
+}
@@ -0,0 +1,575 @@
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+use std::str::FromStr;
+use crate::errors::*;
+use crate::fs::FileSystem;
+use crate::fs::checksum::hash_object;
+use crate::model::id::{Id, ObjectFormat};
+use crate::model::object::{GitObject, ObjectHeader, ObjectType};
+use crate::model::refs::{check_ref_format, shorten_ref_name};
+use crate::model::repository::{FileRepository, Repository};
+
+/// Something `fsck` found, displayed the way `git fsck` reports it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FsckProblem {
+    /// A loose object whose content does not hash to its name.
+    HashMismatch { path: PathBuf, id: Id },
+    /// A loose object that cannot be read.
+    Corrupt { path: PathBuf, id: Id },
+    /// A packed object that cannot be read or does not hash to its name.
+    PackedCorrupt { path: PathBuf, id: Id },
+    BadPack { path: PathBuf, message: &'static str },
+    CrcMismatch { path: PathBuf, id: Id, offset: usize },
+    /// A failed format check; warnings do not make the repository broken.
+    BadObject { object_type: ObjectType, id: Id, check: &'static str, message: String, warning: bool },
+    BrokenRef(String),
+    BadRef { name: String, id: Id },
+    BadReflogEntry { name: String, id: Id },
+    UnbornHead(String),
+    BrokenLink { from: (ObjectType, Id), to: (ObjectType, Id) },
+    Missing(ObjectType, Id),
+    Dangling(ObjectType, Id),
+}
+
+impl FsckProblem {
+    /// Whether this makes `fsck` fail, as opposed to warnings, notices and
+    /// dangling objects.
+    pub fn is_error(&self) -> bool {
+        match self {
+            FsckProblem::BadObject { warning, .. } => !warning,
+            FsckProblem::UnbornHead(_) | FsckProblem::Dangling(..) => false,
+            _ => true,
+        }
+    }
+
+    /// `git fsck` reports connectivity on stdout and everything else on stderr.
+    pub fn is_connectivity(&self) -> bool {
+        matches!(self, FsckProblem::BrokenLink { .. } | FsckProblem::Missing(..) | FsckProblem::Dangling(..))
+    }
+}
+
+impl Display for FsckProblem {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            FsckProblem::HashMismatch { path, id } =>
+                write!(f, "error: hash mismatch for {} (expected {})", path.display(), id),
+            FsckProblem::Corrupt { path, id } =>
+                write!(f, "error: {}: object corrupt or missing: {}", id, path.display()),
+            FsckProblem::PackedCorrupt { path, id } =>
+                write!(f, "error: packed {} from {} is corrupt", id, path.display()),
+            FsckProblem::BadPack { path, message } =>
+                write!(f, "error: {} {}", path.display(), message),
+            FsckProblem::CrcMismatch { path, id, offset } =>
+                write!(f, "error: index CRC mismatch for object {} from {} at offset {}", id, path.display(), offset),
+            FsckProblem::BadObject { object_type, id, check, message, warning } =>
+                write!(f, "{} in {} {}: {}: {}", if *warning { "warning" } else { "error" }, object_type, id, check, message),
+            FsckProblem::BrokenRef(name) =>
+                write!(f, "error: {}: invalid ref content", name),
+            FsckProblem::BadRef { name, id } =>
+                write!(f, "error: {}: invalid sha1 pointer {}", name, id),
+            FsckProblem::BadReflogEntry { name, id } =>
+                write!(f, "error: {}: invalid reflog entry {}", name, id),
+            FsckProblem::UnbornHead(branch) =>
+                write!(f, "notice: HEAD points to an unborn branch ({})", branch),
+            FsckProblem::BrokenLink { from, to } =>
+                write!(f, "broken link from {:>7} {}\n              to {:>7} {}",
+                       from.0.as_str(), from.1, to.0.as_str(), to.1),
+            FsckProblem::Missing(object_type, id) => write!(f, "missing {} {}", object_type, id),
+            FsckProblem::Dangling(object_type, id) => write!(f, "dangling {} {}", object_type, id),
+        }
+    }
+}
+
+/// An object that was read successfully and the objects it points at.
+struct Checked {
+    object_type: ObjectType,
+    links: Vec<(ObjectType, Id)>,
+}
+
+impl<FS: FileSystem> FileRepository<FS> {
+    /// Checks the whole object database like `git fsck`: every loose and
+    /// packed object is re-hashed and parsed strictly, pack checksums and
+    /// index CRCs are verified, and objects are walked from refs, `HEAD` and
+    /// reflogs to find missing and dangling ones.
+    pub fn fsck(&self) -> Result<Vec<FsckProblem>> {
+        let mut problems = vec![];
+        let mut objects = HashMap::new();
+        self.fsck_loose(&mut objects, &mut problems);
+        self.fsck_packs(&mut objects, &mut problems);
+        let roots = self.fsck_refs(&objects, &mut problems)?;
+
+        let mut reachable = HashSet::new();
+        let mut missing = vec![];
+        let mut queue = roots;
+        while let Some(id) = queue.pop() {
+            if !reachable.insert(id.clone()) {
+                continue;
+            }
+            let checked: &Checked = match objects.get(&id) {
+                Some(checked) => checked,
+                None => continue,
+            };
+            for (object_type, target) in &checked.links {
+                if objects.contains_key(target) {
+                    queue.push(target.clone());
+                } else {
+                    problems.push(FsckProblem::BrokenLink {
+                        from: (checked.object_type.clone(), id.clone()),
+                        to: (object_type.clone(), target.clone()),
+                    });
+                    if !missing.iter().any(|(_, m)| m == target) {
+                        missing.push((object_type.clone(), target.clone()));
+                    }
+                }
+            }
+        }
+        missing.sort_by_key(|(_, id)| id.to_string());
+        problems.extend(missing.into_iter().map(|(t, id)| FsckProblem::Missing(t, id)));
+
+        let referenced: HashSet<&Id> = objects.values()
+            .flat_map(|checked| checked.links.iter().map(|link| &link.1))
+            .collect();
+        let mut dangling: Vec<(&Id, &Checked)> = objects.iter()
+            .filter(|(id, _)| !reachable.contains(*id) && !referenced.contains(id))
+            .collect();
+        dangling.sort_by_key(|(id, _)| id.to_string());
+        problems.extend(dangling.into_iter()
+            .map(|(id, checked)| FsckProblem::Dangling(checked.object_type.clone(), id.clone())));
+        Ok(problems)
+    }
+
+    fn fsck_loose(&self, objects: &mut HashMap<Id, Checked>, problems: &mut Vec<FsckProblem>) {
+        let format = self.object_format();
+        let mut dirs: Vec<PathBuf> = self.fs.read_dir(self.object_dir()).collect();
+        dirs.sort();
+        for dir in dirs {
+            let prefix = match dir.file_name().and_then(|name| name.to_str()) {
+                Some(prefix) if prefix.len() == 2 && is_hex(prefix) => prefix.to_owned(),
+                _ => continue,
+            };
+            let mut files: Vec<PathBuf> = self.fs.read_dir(&dir).collect();
+            files.sort();
+            for path in files {
+                let hex = match path.file_name().and_then(|name| name.to_str()) {
+                    Some(rest) => format!("{}{}", prefix, rest),
+                    None => continue,
+                };
+                let id = match Id::from_str(&hex) {
+                    Ok(id) if hex.len() == format.hex_len() && is_hex(&hex) => id,
+                    _ => continue,
+                };
+                let read = self.read_loose_object(&id)
+                    .and_then(|obj| Ok((obj.object_type(), Repository::read_content(self, &obj)?)));
+                match read {
+                    Ok((object_type, content)) => {
+                        if hash_object(format, object_type.as_str(), &content) != id {
+                            problems.push(FsckProblem::HashMismatch { path, id });
+                            continue;
+                        }
+                        let checked = check_object(object_type, &id, &content, format, problems);
+                        objects.insert(id, checked);
+                    }
+                    Err(_) => problems.push(FsckProblem::Corrupt { path, id }),
+                }
+            }
+        }
+    }
+
+    fn fsck_packs(&self, objects: &mut HashMap<Id, Checked>, problems: &mut Vec<FsckProblem>) {
+        let format = self.object_format();
+        let mut packs: Vec<(&Id, _)> = self.packfiles.iter().collect();
+        packs.sort_by_key(|(id, _)| id.to_string());
+        for (pack_id, pack) in packs {
+            let path = self.object_dir().join("pack").join(format!("pack-{}.pack", pack_id));
+            if pack.verify_checksum().is_err() {
+                problems.push(FsckProblem::BadPack { path: path.clone(), message: "pack checksum mismatch" });
+            }
+            let idx = match pack.idx() {
+                Some(idx) => idx,
+                None => {
+                    problems.push(FsckProblem::BadPack { path, message: "has no index" });
+                    continue;
+                }
+            };
+            if idx.pack_id() != pack_id {
+                problems.push(FsckProblem::BadPack { path: path.clone(), message: "pack checksum does not match its index" });
+            }
+
+            let mut entries: Vec<(&Id, usize, Option<u32>)> = idx.iter().collect();
+            entries.sort_by_key(|entry| entry.1);
+            for (i, (id, offset, crc)) in entries.iter().enumerate() {
+                let end = entries.get(i + 1).map(|entry| entry.1).unwrap_or_else(|| pack.data_end());
+                if let Some(crc) = crc {
+                    if pack.crc32(*offset, end) != Some(*crc) {
+                        problems.push(FsckProblem::CrcMismatch { path: path.clone(), id: (*id).clone(), offset: *offset });
+                    }
+                }
+                let read = pack.read_object(*offset).and_then(|(locator, object_type, length)| {
+                    let obj = GitObject::new(id, ObjectHeader { object_type: object_type.clone(), length }, locator);
+                    Ok((object_type, Repository::read_content(self, &obj)?))
+                });
+                match read {
+                    Ok((object_type, content)) if hash_object(format, object_type.as_str(), &content) == **id => {
+                        let checked = check_object(object_type, id, &content, format, problems);
+                        objects.insert((*id).clone(), checked);
+                    }
+                    _ => problems.push(FsckProblem::PackedCorrupt { path: path.clone(), id: (*id).clone() }),
+                }
+            }
+        }
+    }
+
+    /// Checks that `HEAD`, every ref and every reflog entry points at an
+    /// existing object, returning them as the roots of the walk.
+    fn fsck_refs(&self, objects: &HashMap<Id, Checked>, problems: &mut Vec<FsckProblem>) -> Result<Vec<Id>> {
+        let mut roots = vec![];
+        match self.resolve_symbolic("HEAD") {
+            Ok((name, None)) if name != "HEAD" =>
+                problems.push(FsckProblem::UnbornHead(shorten_ref_name(&name).to_owned())),
+            Ok((_, None)) => {}
+            Ok((_, Some(id))) if objects.contains_key(&id) => roots.push(id),
+            Ok((_, Some(id))) => problems.push(FsckProblem::BadRef { name: "HEAD".to_owned(), id }),
+            Err(_) => problems.push(FsckProblem::BrokenRef("HEAD".to_owned())),
+        }
+        for name in self.ref_names()? {
+            match self.resolve_ref(&name) {
+                Ok(Some(id)) if objects.contains_key(&id) => roots.push(id),
+                Ok(Some(id)) => problems.push(FsckProblem::BadRef { name, id }),
+                Ok(None) => {}
+                Err(_) => problems.push(FsckProblem::BrokenRef(name)),
+            }
+        }
+        for name in self.reflog_names() {
+            for entry in self.reflog(&name)?.iter() {
+                for id in &[&entry.old, &entry.new] {
+                    if id.is_zero() {
+                        continue;
+                    }
+                    if objects.contains_key(id) {
+                        roots.push((*id).clone());
+                    } else {
+                        problems.push(FsckProblem::BadReflogEntry { name: name.clone(), id: (*id).clone() });
+                    }
+                }
+            }
+        }
+        Ok(roots)
+    }
+}
+
+fn is_hex(s: &str) -> bool {
+    s.chars().all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase())
+}
+
+/// Parses `object_type` content strictly, reporting format problems and
+/// collecting the ids it refers to.
+fn check_object(object_type: ObjectType, id: &Id, content: &[u8], format: ObjectFormat,
+                problems: &mut Vec<FsckProblem>) -> Checked {
+    let mut links = vec![];
+    let mut report = |check: &'static str, message: String, warning: bool| {
+        problems.push(FsckProblem::BadObject {
+            object_type: object_type.clone(), id: id.clone(), check, message, warning,
+        });
+    };
+    match object_type {
+        ObjectType::BLOB => {}
+        ObjectType::COMMIT => check_commit(content, format, &mut links, &mut report),
+        ObjectType::TAG => check_tag(content, format, &mut links, &mut report),
+        ObjectType::TREE => check_tree(content, format, &mut links, &mut report),
+    }
+    Checked { object_type, links }
+}
+
+fn check_commit(content: &[u8], format: ObjectFormat, links: &mut Vec<(ObjectType, Id)>,
+                report: &mut dyn FnMut(&'static str, String, bool)) {
+    let mut rest = content;
+    match header(&mut rest, "tree") {
+        None => return report("missingTree", "invalid format - expected 'tree' line".to_owned(), false),
+        Some(value) => match parse_hex_id(value, format) {
+            Some(tree) => links.push((ObjectType::TREE, tree)),
+            None => return report("badTreeSha1", "invalid 'tree' line format - bad sha1".to_owned(), false),
+        },
+    }
+    while let Some(value) = header(&mut rest, "parent") {
+        match parse_hex_id(value, format) {
+            Some(parent) => links.push((ObjectType::COMMIT, parent)),
+            None => return report("badParentSha1", "invalid 'parent' line format - bad sha1".to_owned(), false),
+        }
+    }
+    for (name, check) in &[("author", "missingAuthor"), ("committer", "missingCommitter")] {
+        match header(&mut rest, name) {
+            None => return report(check, format!("invalid format - expected '{}' line", name), false),
+            Some(ident) => if let Err((check, message)) = check_ident(ident) {
+                return report(check, message.to_owned(), false);
+            },
+        }
+    }
+}
+
+fn check_tag(content: &[u8], format: ObjectFormat, links: &mut Vec<(ObjectType, Id)>,
+             report: &mut dyn FnMut(&'static str, String, bool)) {
+    let mut rest = content;
+    let object = match header(&mut rest, "object") {
+        None => return report("missingObject", "invalid format - expected 'object' line".to_owned(), false),
+        Some(value) => match parse_hex_id(value, format) {
+            Some(object) => object,
+            None => return report("badObjectSha1", "invalid 'object' line format - bad sha1".to_owned(), false),
+        },
+    };
+    match header(&mut rest, "type") {
+        None => return report("missingTypeEntry", "invalid format - expected 'type' line".to_owned(), false),
+        Some(value) => match parse_type(value) {
+            Some(object_type) => links.push((object_type, object)),
+            None => return report("badType", "invalid 'type' value".to_owned(), false),
+        },
+    }
+    match header(&mut rest, "tag") {
+        None => return report("missingTagEntry", "invalid format - expected 'tag' line".to_owned(), false),
+        Some(name) => {
+            let name = String::from_utf8_lossy(name);
+            if !check_ref_format(&format!("refs/tags/{}", name)) {
+                report("badTagName", format!("invalid 'tag' name: {}", name), true);
+            }
+        }
+    }
+    match header(&mut rest, "tagger") {
+        None => report("missingTaggerEntry", "invalid format - expected 'tagger' line".to_owned(), true),
+        Some(ident) => if let Err((check, message)) = check_ident(ident) {
+            report(check, message.to_owned(), false);
+        },
+    }
+}
+
+fn check_tree(content: &[u8], format: ObjectFormat, links: &mut Vec<(ObjectType, Id)>,
+              report: &mut dyn FnMut(&'static str, String, bool)) {
+    let (mut has_dups, mut full_path, mut empty_name, mut has_dot, mut has_dotdot, mut has_dotgit,
+        mut zero_padded, mut bad_modes, mut unsorted) = Default::default();
+    let mut previous: Option<(&[u8], bool)> = None;
+    let mut rest = content;
+    while !rest.is_empty() {
+        let entry = parse_tree_entry(rest, format);
+        let (mode_bytes, name, id, next) = match entry {
+            Some(entry) => entry,
+            None => return report("badTree", "cannot be parsed as a tree".to_owned(), false),
+        };
+        rest = next;
+        let mode = match u32::from_str_radix(std::str::from_utf8(mode_bytes).unwrap_or(""), 8) {
+            Ok(mode) => mode,
+            Err(_) => return report("badTree", "cannot be parsed as a tree".to_owned(), false),
+        };
+        zero_padded |= mode_bytes[0] == b'0';
+        match mode {
+            0o100644 | 0o100755 | 0o100664 | 0o120000 | 0o160000 => {}
+            0o40000 => {}
+            _ => bad_modes = true,
+        }
+        let is_dir = mode == 0o40000;
+        match mode {
+            0o40000 => links.push((ObjectType::TREE, id)),
+            0o160000 => {}
+            _ => links.push((ObjectType::BLOB, id)),
+        }
+
+        empty_name |= name.is_empty();
+        full_path |= name.contains(&b'/');
+        has_dot |= name == b".";
+        has_dotdot |= name == b"..";
+        has_dotgit |= name.eq_ignore_ascii_case(b".git");
+        if let Some((prev_name, prev_dir)) = previous {
+            if prev_name == name {
+                has_dups = true;
+            } else if compare_entries(prev_name, prev_dir, name, is_dir) == Ordering::Greater {
+                unsorted = true;
+            }
+        }
+        previous = Some((name, is_dir));
+    }
+
+    let checks: [(bool, &'static str, &str, bool); 9] = [
+        (has_dups, "duplicateEntries", "contains duplicate file entries", false),
+        (full_path, "fullPathname", "contains full pathnames", true),
+        (empty_name, "emptyName", "contains empty pathname", true),
+        (has_dot, "hasDot", "contains '.'", true),
+        (has_dotdot, "hasDotdot", "contains '..'", true),
+        (has_dotgit, "hasDotgit", "contains '.git'", true),
+        (zero_padded, "zeroPaddedFilemode", "contains zero-padded file modes", true),
+        (bad_modes, "badFilemode", "contains bad file modes", true),
+        (unsorted, "treeNotSorted", "not properly sorted", false),
+    ];
+    for (found, check, message, warning) in checks.iter() {
+        if *found {
+            report(check, (*message).to_owned(), *warning);
+        }
+    }
+}
+
+/// The mode, name and id of a raw tree entry, and the input after it.
+type RawTreeEntry<'a> = (&'a [u8], &'a [u8], Id, &'a [u8]);
+
+/// `<mode> <name>\0<raw id>`, returning the mode, name, id and the rest.
+fn parse_tree_entry(input: &[u8], format: ObjectFormat) -> Option<RawTreeEntry<'_>> {
+    let space = input.iter().position(|b| *b == b' ')?;
+    let nul = space + input[space..].iter().position(|b| *b == 0)?;
+    let end = nul + 1 + format.hash_len();
+    if space == 0 || end > input.len() {
+        return None;
+    }
+    Some((&input[..space], &input[space + 1..nul], Id::new(&input[nul + 1..end]), &input[end..]))
+}
+
+/// Git's tree order: names compare bytewise, with directories sorting as if
+/// they ended in `/`.
+fn compare_entries(a: &[u8], a_dir: bool, b: &[u8], b_dir: bool) -> Ordering {
+    let len = a.len().min(b.len());
+    match a[..len].cmp(&b[..len]) {
+        Ordering::Equal => {}
+        other => return other,
+    }
+    let end = |name: &[u8], dir: bool| name.get(len).cloned().unwrap_or(if dir { b'/' } else { 0 });
+    end(a, a_dir).cmp(&end(b, b_dir))
+}
+
+/// Takes a `<name> <value>\n` header line off the front of `rest`.
+fn header<'a>(rest: &mut &'a [u8], name: &str) -> Option<&'a [u8]> {
+    let line = rest.strip_prefix(name.as_bytes())?.strip_prefix(b" ")?;
+    let newline = line.iter().position(|b| *b == b'\n')?;
+    *rest = &line[newline + 1..];
+    Some(&line[..newline])
+}
+
+fn parse_hex_id(value: &[u8], format: ObjectFormat) -> Option<Id> {
+    let hex = std::str::from_utf8(value).ok()?;
+    if hex.len() != format.hex_len() || !is_hex(hex) {
+        return None;
+    }
+    Id::from_str(hex).ok()
+}
+
+fn parse_type(value: &[u8]) -> Option<ObjectType> {
+    match value {
+        b"blob" => Some(ObjectType::BLOB),
+        b"tree" => Some(ObjectType::TREE),
+        b"commit" => Some(ObjectType::COMMIT),
+        b"tag" => Some(ObjectType::TAG),
+        _ => None,
+    }
+}
+
+/// The checks git runs on `Name <email> <seconds> <+zone>` lines.
+fn check_ident(ident: &[u8]) -> std::result::Result<(), (&'static str, &'static str)> {
+    if ident.first() == Some(&b'<') {
+        return Err(("missingNameBeforeEmail", "invalid author/committer line - missing space before email"));
+    }
+    let lt = ident.iter().position(|b| *b == b'<')
+        .ok_or(("missingEmail", "invalid author/committer line - missing email"))?;
+    if ident[lt - 1] != b' ' {
+        return Err(("missingSpaceBeforeEmail", "invalid author/committer line - missing space before email"));
+    }
+    let rest = &ident[lt + 1..];
+    let gt = rest.iter().position(|b| *b == b'<' || *b == b'>')
+        .filter(|gt| rest[*gt] == b'>')
+        .ok_or(("badEmail", "invalid author/committer line - bad email"))?;
+    let rest = rest[gt + 1..].strip_prefix(b" ")
+        .ok_or(("missingSpaceBeforeDate", "invalid author/committer line - missing space before date"))?;
+    let digits = rest.iter().take_while(|b| b.is_ascii_digit()).count();
+    if digits > 1 && rest[0] == b'0' {
+        return Err(("zeroPaddedDate", "invalid author/committer line - zero-padded date"));
+    }
+    if digits == 0 {
+        return Err(("badDate", "invalid author/committer line - bad date"));
+    }
+    if std::str::from_utf8(&rest[..digits]).ok().and_then(|d| d.parse::<u64>().ok()).is_none() {
+        return Err(("badDateOverflow", "invalid author/committer line - date causes integer overflow"));
+    }
+    let zone = &rest[digits..];
+    let valid_zone = zone.len() == 6 && zone[0] == b' ' && (zone[1] == b'+' || zone[1] == b'-')
+        && zone[2..].iter().all(|b| b.is_ascii_digit());
+    if !valid_zone {
+        return Err(("badTimezone", "invalid author/committer line - bad time zone"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::MemFs;
+
+    fn tree_entry(mode: &str, name: &str, id: &Id) -> Vec<u8> {
+        let mut entry = format!("{} {}\0", mode, name).into_bytes();
+        entry.extend_from_slice(id.bytes());
+        entry
+    }
+
+    fn checks(object_type: ObjectType, content: &[u8]) -> Vec<String> {
+        let mut problems = vec![];
+        let id = hash_object(ObjectFormat::Sha1, object_type.as_str(), content);
+        check_object(object_type, &id, content, ObjectFormat::Sha1, &mut problems);
+        problems.iter().map(|p| match p {
+            FsckProblem::BadObject { check, .. } => check.to_string(),
+            _ => unreachable!(),
+        }).collect()
+    }
+
+    #[test]
+    fn test_check_tree() {
+        let blob = ObjectFormat::Sha1.null_id();
+        let sorted = [tree_entry("100644", "a", &blob), tree_entry("40000", "a-b", &blob),
+            tree_entry("100644", "a.c", &blob), tree_entry("40000", "a.c.d", &blob)].concat();
+        assert_eq!(checks(ObjectType::TREE, &sorted), Vec::<String>::new());
+        // a directory `a` sorts as `a/`, after `a.c`
+        let unsorted = [tree_entry("40000", "a", &blob), tree_entry("100644", "a.c", &blob)].concat();
+        assert_eq!(checks(ObjectType::TREE, &unsorted), ["treeNotSorted"]);
+        let odd = [tree_entry("040000", "dir", &blob), tree_entry("100600", "file", &blob),
+            tree_entry("100644", "file", &blob)].concat();
+        assert_eq!(checks(ObjectType::TREE, &odd), ["duplicateEntries", "zeroPaddedFilemode", "badFilemode"]);
+        assert_eq!(checks(ObjectType::TREE, b"100644 truncated\0abc"), ["badTree"]);
+    }
+
+    #[test]
+    fn test_check_commit_and_tag() {
+        let tree = "4b825dc642cb6eb9a060e54bf8d69288fbee4904";
+        let commit = |author: &str| format!("tree {}\nauthor {}\ncommitter A <a@x> 1 +0000\n\nmsg\n", tree, author);
+        assert!(checks(ObjectType::COMMIT, commit("A <a@x> 1500000000 +0100").as_bytes()).is_empty());
+        assert_eq!(checks(ObjectType::COMMIT, commit("A a@x> 1 +0000").as_bytes()), ["missingEmail"]);
+        assert_eq!(checks(ObjectType::COMMIT, commit("A<a@x> 1 +0000").as_bytes()), ["missingSpaceBeforeEmail"]);
+        assert_eq!(checks(ObjectType::COMMIT, commit("A <a@x> 01 +0000").as_bytes()), ["zeroPaddedDate"]);
+        assert_eq!(checks(ObjectType::COMMIT, commit("A <a@x> 1 0000").as_bytes()), ["badTimezone"]);
+        assert_eq!(checks(ObjectType::COMMIT, format!("tree {}\ncommitter A <a@x> 1 +0000\n", tree).as_bytes()),
+                   ["missingAuthor"]);
+        assert_eq!(checks(ObjectType::COMMIT, b"tree 1234\n"), ["badTreeSha1"]);
+
+        let tag = format!("object {}\ntype commit\ntag v1\n\nmsg\n", tree);
+        assert_eq!(checks(ObjectType::TAG, tag.as_bytes()), ["missingTaggerEntry"]);
+        let tag = format!("object {}\ntype thing\ntag v1\n", tree);
+        assert_eq!(checks(ObjectType::TAG, tag.as_bytes()), ["badType"]);
+    }
+
+    #[test]
+    fn test_fsck() {
+        let mut repo = FileRepository::<MemFs>::default();
+        let blob = repo.write_object(ObjectType::BLOB, b"hello\n").unwrap();
+        let dangling = repo.write_object(ObjectType::BLOB, b"dangling\n").unwrap();
+        let missing = hash_object(ObjectFormat::Sha1, "blob", b"missing\n");
+        let tree = repo.write_object(ObjectType::TREE,
+                                     &[tree_entry("100644", "a", &blob), tree_entry("100644", "b", &missing)].concat())
+            .unwrap();
+        let commit = format!("tree {}\nauthor A <a@x> 1 +0000\ncommitter A <a@x> 1 +0000\n\nmsg\n", tree);
+        let commit = repo.write_object(ObjectType::COMMIT, commit.as_bytes()).unwrap();
+        repo.add_file("HEAD", b"ref: refs/heads/master\n".to_vec());
+        repo.add_file("refs/heads/master", format!("{}\n", commit).into_bytes());
+        // a blob stored under the wrong name
+        let hex = hash_object(ObjectFormat::Sha1, "blob", b"other\n").to_string();
+        repo.add_loose(&hex, ObjectType::BLOB, b"hello\n");
+
+        let problems: Vec<String> = repo.fsck().unwrap().iter().map(|p| p.to_string()).collect();
+        assert_eq!(problems, vec![
+            format!("error: hash mismatch for objects/{}/{} (expected {})", &hex[..2], &hex[2..], hex),
+            format!("broken link from    tree {}\n              to    blob {}", tree, missing),
+            format!("missing blob {}", missing),
+            format!("dangling blob {}", dangling),
+        ]);
+    }
+}
@@ -0,0 +1,548 @@
+use std::fmt;
+use std::io::{Read, Write};
+use regex::bytes::{Regex, RegexBuilder};
+use crate::errors::*;
+use crate::fs::FileSystem;
+use crate::model::archive::pathspec_matches;
+use crate::model::id::Id;
+use crate::model::object::ObjectType;
+use crate::model::patch::is_binary;
+use crate::model::repository::{FileRepository, Repository};
+use crate::model::tree::FileMode;
+use crate::model::workers::run_workers;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PatternSyntax {
+    /// POSIX basic regular expressions, where `\(`, `\|`, `\+` and the
+    /// like are the special ones.
+    #[default]
+    Basic,
+    Extended,
+    Fixed,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PatternOptions {
+    pub syntax: PatternSyntax,
+    pub ignore_case: bool,
+    /// Only match whole words.
+    pub word: bool,
+}
+
+/// Copies a bracket expression starting at `chars[start]`, escaping what
+/// the regex crate would read differently from POSIX. Returns the index
+/// after it.
+fn copy_bracket(chars: &[char], start: usize, out: &mut String) -> usize {
+    let mut n = start + 1;
+    out.push('[');
+    if chars.get(n) == Some(&'^') {
+        out.push('^');
+        n += 1;
+    }
+    // a leading ] is a member, not the end
+    if chars.get(n) == Some(&']') {
+        out.push_str("\\]");
+        n += 1;
+    }
+    while n < chars.len() && chars[n] != ']' {
+        match chars[n] {
+            '[' if matches!(chars.get(n + 1), Some(':') | Some('.') | Some('=')) => {
+                let close = chars[n + 1];
+                let end = (n + 2..chars.len().saturating_sub(1))
+                    .find(|&m| chars[m] == close && chars[m + 1] == ']')
+                    .map_or(chars.len(), |m| m + 2);
+                out.extend(&chars[n..end]);
+                n = end;
+                continue;
+            }
+            c @ ('\\' | '[' | '&' | '~') => {
+                out.push('\\');
+                out.push(c);
+            }
+            c => out.push(c),
+        }
+        n += 1;
+    }
+    out.push(']');
+    n + 1
+}
+
+/// Rewrites a POSIX regular expression in the syntax of the regex crate.
+fn translate(pattern: &str, syntax: PatternSyntax) -> String {
+    if syntax == PatternSyntax::Fixed {
+        return regex::escape(pattern);
+    }
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut out = String::new();
+    let mut n = 0;
+    while n < chars.len() {
+        match (chars[n], chars.get(n + 1)) {
+            ('\\', Some('<')) | ('\\', Some('>')) => {
+                out.push_str("\\b");
+                n += 2;
+            }
+            ('\\', Some(&c)) if syntax == PatternSyntax::Basic && "+?|(){}".contains(c) => {
+                out.push(c);
+                n += 2;
+            }
+            ('\\', Some(&c)) => {
+                out.push('\\');
+                out.push(c);
+                n += 2;
+            }
+            ('[', _) => n = copy_bracket(&chars, n, &mut out),
+            (c, _) if syntax == PatternSyntax::Basic && "+?|(){}".contains(c) => {
+                out.push('\\');
+                out.push(c);
+                n += 1;
+            }
+            (c, _) => {
+                out.push(c);
+                n += 1;
+            }
+        }
+    }
+    out
+}
+
+fn is_word_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'_'
+}
+
+/// One compiled pattern.
+#[derive(Debug, Clone)]
+pub struct Pattern {
+    regex: Regex,
+    word: bool,
+}
+
+impl Pattern {
+    pub fn new(pattern: &str, opts: &PatternOptions) -> Result<Pattern> {
+        let regex = RegexBuilder::new(&translate(pattern, opts.syntax))
+            .case_insensitive(opts.ignore_case)
+            .build()
+            .chain_err(|| format!("command line, '{}': invalid regular expression", pattern))?;
+        Ok(Pattern { regex, word: opts.word })
+    }
+
+    /// Whether `line` matches, for a whole-word pattern somewhere the
+    /// match is neither preceded nor followed by a word character.
+    pub fn is_match(&self, line: &[u8]) -> bool {
+        if !self.word {
+            return self.regex.is_match(line);
+        }
+        let mut start = 0;
+        while let Some(m) = self.regex.find_at(line, start) {
+            let before = m.start() == 0 || !is_word_char(line[m.start() - 1]);
+            let after = m.end() == line.len() || !is_word_char(line[m.end()]);
+            if before && after {
+                return true;
+            }
+            // git retries one character after the start of the failed match
+            start = m.start() + 1;
+            if start > line.len() {
+                break;
+            }
+        }
+        false
+    }
+}
+
+/// An element of a pattern expression as given on the command line.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    Pattern(String),
+    And,
+    Or,
+    Not,
+    Open,
+    Close,
+}
+
+impl fmt::Display for Term {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Term::Pattern(pattern) => write!(f, "{}", pattern),
+            Term::And => write!(f, "--and"),
+            Term::Or => write!(f, "--or"),
+            Term::Not => write!(f, "--not"),
+            Term::Open => write!(f, "("),
+            Term::Close => write!(f, ")"),
+        }
+    }
+}
+
+/// Patterns combined with `--and`, `--or` and `--not`.
+#[derive(Debug, Clone)]
+pub enum GrepExpr {
+    Pattern(Pattern),
+    Not(Box<GrepExpr>),
+    And(Box<GrepExpr>, Box<GrepExpr>),
+    Or(Box<GrepExpr>, Box<GrepExpr>),
+}
+
+/// Parses terms the way git does: `--not` binds tightest, then `--and`,
+/// then `--or`, which is also what patterns without an operator between
+/// them are joined by.
+struct ExprParser<'a> {
+    terms: &'a [Term],
+    pos: usize,
+    opts: &'a PatternOptions,
+}
+
+impl<'a> ExprParser<'a> {
+    fn peek(&self) -> Option<&'a Term> {
+        self.terms.get(self.pos)
+    }
+
+    fn at_expr(&self) -> bool {
+        matches!(self.peek(), Some(Term::Pattern(_)) | Some(Term::Open) | Some(Term::Not))
+    }
+
+    fn atom(&mut self) -> Result<GrepExpr> {
+        match self.peek() {
+            Some(Term::Pattern(pattern)) => {
+                self.pos += 1;
+                Ok(GrepExpr::Pattern(Pattern::new(pattern, self.opts)?))
+            }
+            Some(Term::Open) => {
+                self.pos += 1;
+                let expr = self.or()?;
+                if self.peek() != Some(&Term::Close) {
+                    bail!("unmatched parenthesis");
+                }
+                self.pos += 1;
+                Ok(expr)
+            }
+            _ => bail!("incomplete pattern expression"),
+        }
+    }
+
+    fn not(&mut self) -> Result<GrepExpr> {
+        if self.peek() == Some(&Term::Not) {
+            self.pos += 1;
+            if !self.at_expr() {
+                bail!("--not not followed by pattern expression");
+            }
+            return Ok(GrepExpr::Not(Box::new(self.not()?)));
+        }
+        self.atom()
+    }
+
+    fn and(&mut self) -> Result<GrepExpr> {
+        let left = self.not()?;
+        if self.peek() == Some(&Term::And) {
+            self.pos += 1;
+            if !self.at_expr() {
+                bail!("--and not followed by pattern expression");
+            }
+            return Ok(GrepExpr::And(Box::new(left), Box::new(self.and()?)));
+        }
+        Ok(left)
+    }
+
+    fn or(&mut self) -> Result<GrepExpr> {
+        let left = self.and()?;
+        if self.peek() == Some(&Term::Or) {
+            self.pos += 1;
+            if !self.at_expr() {
+                bail!("--or not followed by pattern expression");
+            }
+        } else if !self.at_expr() {
+            return Ok(left);
+        }
+        Ok(GrepExpr::Or(Box::new(left), Box::new(self.or()?)))
+    }
+}
+
+impl GrepExpr {
+    pub fn parse(terms: &[Term], opts: &PatternOptions) -> Result<GrepExpr> {
+        if terms.is_empty() {
+            bail!("no pattern given");
+        }
+        let mut parser = ExprParser { terms, pos: 0, opts };
+        let expr = parser.or()?;
+        if let Some(term) = parser.peek() {
+            bail!("incomplete pattern expression: {}", term);
+        }
+        Ok(expr)
+    }
+
+    pub fn is_match(&self, line: &[u8]) -> bool {
+        match self {
+            GrepExpr::Pattern(pattern) => pattern.is_match(line),
+            GrepExpr::Not(expr) => !expr.is_match(line),
+            GrepExpr::And(left, right) => left.is_match(line) && right.is_match(line),
+            GrepExpr::Or(left, right) => left.is_match(line) || right.is_match(line),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum GrepOutput {
+    #[default]
+    Lines,
+    FilesWithMatches,
+    FilesWithoutMatch,
+    Count,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct GrepOptions {
+    /// Select the lines that do not match.
+    pub invert: bool,
+    pub line_number: bool,
+    pub output: GrepOutput,
+    /// Lines of context before and after each match.
+    pub before: usize,
+    pub after: usize,
+    /// Only files matching one of these, if any.
+    pub paths: Vec<String>,
+    /// Threads matching files; 0 reads `grep.threads`, and uses every CPU
+    /// when that is unset or below 1.
+    pub workers: usize,
+}
+
+/// What to search: a tree, with the name to show before each path, or
+/// the tracked files of the working tree.
+#[derive(Debug, Clone)]
+pub enum GrepSource {
+    Tree(String, Id),
+    WorkTree,
+}
+
+/// Greps one file, writing what `opts` asks for to `out`. Returns whether
+/// any line, or for `FilesWithoutMatch` the file, was selected.
+fn grep_buffer(expr: &GrepExpr, name: &str, content: &[u8], opts: &GrepOptions, out: &mut Vec<u8>) -> bool {
+    let content = content.strip_suffix(b"\n").unwrap_or(content);
+    let lines: Vec<&[u8]> = if content.is_empty() { vec![] } else { content.split(|&b| b == b'\n').collect() };
+    let context = opts.before > 0 || opts.after > 0;
+    let mut count = 0;
+    // 1-based numbers of the last line shown and the last context line due
+    let mut last_shown = 0;
+    let mut context_until = 0;
+    let show = |out: &mut Vec<u8>, last_shown: &mut usize, number: usize, separator: char| {
+        if context && *last_shown > 0 && number > *last_shown + 1 {
+            out.extend_from_slice(b"--\n");
+        }
+        write!(out, "{}{}", name, separator).unwrap();
+        if opts.line_number {
+            write!(out, "{}{}", number, separator).unwrap();
+        }
+        out.extend_from_slice(lines[number - 1]);
+        out.push(b'\n');
+        *last_shown = number;
+    };
+    for (n, line) in lines.iter().enumerate() {
+        let number = n + 1;
+        if expr.is_match(line) == opts.invert {
+            if opts.output == GrepOutput::Lines && number <= context_until {
+                show(out, &mut last_shown, number, '-');
+            }
+            continue;
+        }
+        count += 1;
+        match opts.output {
+            GrepOutput::Lines => {}
+            GrepOutput::Count => continue,
+            _ => break,
+        }
+        for before in number.saturating_sub(opts.before).max(last_shown + 1)..number {
+            show(out, &mut last_shown, before, '-');
+        }
+        show(out, &mut last_shown, number, ':');
+        context_until = number + opts.after;
+    }
+    match opts.output {
+        GrepOutput::FilesWithMatches if count > 0 => writeln!(out, "{}", name).unwrap(),
+        GrepOutput::FilesWithoutMatch if count == 0 => writeln!(out, "{}", name).unwrap(),
+        GrepOutput::Count if count > 0 => writeln!(out, "{}:{}", name, count).unwrap(),
+        _ => {}
+    }
+    // what -L selects is the files without a match
+    (count > 0) != (opts.output == GrepOutput::FilesWithoutMatch)
+}
+
+impl<FS: FileSystem> FileRepository<FS> {
+    /// The files to search, with the names to show them by and their
+    /// blobs, or no blob to read them from the working tree.
+    fn grep_files(&self, source: &GrepSource, opts: &GrepOptions) -> Result<Vec<(String, Option<Id>)>> {
+        let selected = |path: &str| opts.paths.is_empty() || opts.paths.iter().any(|spec| pathspec_matches(spec, path));
+        Ok(match source {
+            GrepSource::Tree(name, id) => {
+                let tree = self.peel_to_kind(id, ObjectType::TREE).chain_err(|| format!("unable to read tree ({})", id))?;
+                // a tree named with a path in it shows paths below it
+                let prefix = if name.contains(':') && !name.ends_with(':') && !name.ends_with('/') {
+                    format!("{}/", name)
+                } else if name.contains(':') {
+                    name.clone()
+                } else {
+                    format!("{}:", name)
+                };
+                self.tree_files(Some(tree.id()))?.into_iter()
+                    .filter(|(path, (mode, _))| (*mode == FileMode::FILE || *mode == FileMode::EXE) && selected(path))
+                    .map(|(path, (_, id))| (format!("{}{}", prefix, path), Some(id)))
+                    .collect()
+            }
+            GrepSource::WorkTree => {
+                if self.work_tree().is_none() {
+                    bail!("this operation must be run in a work tree");
+                }
+                let mut files: Vec<(String, Option<Id>)> = vec![];
+                for entry in self.read_index()?.entries() {
+                    let regular = matches!(FileMode::from_bits(entry.mode), Some(FileMode::FILE) | Some(FileMode::EXE));
+                    let duplicate = files.last().map(|(path, _)| path) == Some(&entry.path);
+                    if regular && !duplicate && selected(&entry.path) {
+                        // a file left out of the working tree is searched in the index
+                        let blob = if entry.skip_worktree { Some(entry.id.clone()) } else { None };
+                        files.push((entry.path.clone(), blob));
+                    }
+                }
+                files
+            }
+        })
+    }
+
+    fn grep_content(&self, name: &str, blob: &Option<Id>) -> Result<Option<Vec<u8>>> {
+        if let Some(id) = blob {
+            return Ok(Some(Repository::read_content(self, &self.find_object(id)?)?));
+        }
+        let path = self.work_tree().map(|dir| dir.join(name)).unwrap_or_default();
+        let mut file = match self.fs.read_file(&path) {
+            Ok(file) => file,
+            Err(e) => return match e.kind() {
+                // a tracked file deleted from the working tree is not searched
+                ErrorKind::Io(io) if io.kind() == std::io::ErrorKind::NotFound => Ok(None),
+                _ => Err(e),
+            },
+        };
+        let mut content = vec![];
+        file.read_to_end(&mut content)?;
+        Ok(Some(content))
+    }
+
+    /// Searches the files of `source` for lines `expr` selects, skipping
+    /// binary files, and writes the results to `out` in path order. Blobs
+    /// are read on this thread and matched by worker threads. Returns
+    /// whether anything was selected.
+    pub fn grep(&self, source: &GrepSource, expr: &GrepExpr, opts: &GrepOptions, out: &mut dyn Write) -> Result<bool> {
+        let files = self.grep_files(source, opts)?;
+        let workers = self.workers(opts.workers, "grep.threads")?.min(files.len());
+        let grep = |n: usize, content: &[u8]| -> Result<(bool, Vec<u8>)> {
+            let mut buffer = vec![];
+            if is_binary(content) {
+                return Ok((false, buffer));
+            }
+            let hit = grep_buffer(expr, &files[n].0, content, opts, &mut buffer);
+            Ok((hit, buffer))
+        };
+        let read = |n: usize| {
+            let (name, blob) = &files[n];
+            self.grep_content(name, blob)
+        };
+        let (results, result) = run_workers(files.len(), workers, read, grep);
+        result?;
+
+        let context = opts.output == GrepOutput::Lines && (opts.before > 0 || opts.after > 0);
+        let mut shown = false;
+        let mut found = false;
+        for (hit, buffer) in results.into_iter().flatten() {
+            found |= hit;
+            if buffer.is_empty() {
+                continue;
+            }
+            // hunks of different files are set apart like those of one
+            if context && shown {
+                out.write_all(b"--\n")?;
+            }
+            out.write_all(&buffer)?;
+            shown = true;
+        }
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use super::*;
+    use crate::fs::MemFs;
+    use crate::model::checkout::CheckoutOptions;
+    use crate::model::init::InitOptions;
+    use crate::model::tree::TreeEntry;
+
+    fn expr(terms: &[Term], opts: &PatternOptions) -> GrepExpr {
+        GrepExpr::parse(terms, opts).unwrap()
+    }
+
+    fn pattern(text: &str) -> Term {
+        Term::Pattern(text.to_owned())
+    }
+
+    #[test]
+    fn test_patterns_and_expressions() {
+        let basic = PatternOptions::default();
+        assert!(expr(&[pattern("a\\|b")], &basic).is_match(b"xbx"));
+        assert!(expr(&[pattern("a+")], &basic).is_match(b"a+"));
+        assert!(!expr(&[pattern("a+")], &basic).is_match(b"aa"));
+        let extended = PatternOptions { syntax: PatternSyntax::Extended, ..basic };
+        assert!(expr(&[pattern("fo+|[]x]")], &extended).is_match(b"]"));
+        let fixed = PatternOptions { syntax: PatternSyntax::Fixed, ignore_case: true, ..basic };
+        assert!(expr(&[pattern("A.B")], &fixed).is_match(b"xa.b"));
+        assert!(!expr(&[pattern("A.B")], &fixed).is_match(b"axb"));
+        let word = PatternOptions { word: true, ..basic };
+        assert!(expr(&[pattern("foo")], &word).is_match(b"foobar foo"));
+        assert!(!expr(&[pattern("foo")], &word).is_match(b"foobar"));
+
+        // --not binds tighter than --and, which binds tighter than --or
+        let terms = [pattern("a"), Term::And, Term::Not, pattern("b"), Term::Or, pattern("c")];
+        let e = expr(&terms, &basic);
+        assert!(e.is_match(b"a"));
+        assert!(!e.is_match(b"ab"));
+        assert!(e.is_match(b"abc"));
+        let terms = [pattern("a"), Term::And, Term::Open, pattern("b"), pattern("c"), Term::Close];
+        let e = expr(&terms, &basic);
+        assert!(e.is_match(b"ac"));
+        assert!(!e.is_match(b"a"));
+        assert!(GrepExpr::parse(&[pattern("a"), Term::And], &basic).is_err());
+        assert_eq!(GrepExpr::parse(&[pattern("a"), Term::Or], &basic).err().unwrap().to_string(),
+                   "--or not followed by pattern expression");
+        assert!(GrepExpr::parse(&[Term::Open, pattern("a")], &basic).is_err());
+    }
+
+    #[test]
+    fn test_grep_buffer_context() {
+        let e = expr(&[pattern("x")], &PatternOptions::default());
+        let content = b"x1\n2\n3\n4\n5\nx6\n7\n";
+        let opts = GrepOptions { line_number: true, after: 1, before: 1, ..Default::default() };
+        let mut out = vec![];
+        assert!(grep_buffer(&e, "f", content, &opts, &mut out));
+        assert_eq!(String::from_utf8(out).unwrap(), "f:1:x1\nf-2-2\n--\nf-5-5\nf:6:x6\nf-7-7\n");
+        let opts = GrepOptions { output: GrepOutput::Count, invert: true, ..Default::default() };
+        let mut out = vec![];
+        grep_buffer(&e, "f", content, &opts, &mut out);
+        assert_eq!(out, b"f:5\n");
+    }
+
+    #[test]
+    fn test_grep_work_tree_on_mem_fs() {
+        let (repo, _) = FileRepository::init_with(MemFs::default(), Path::new("/work"), &InitOptions::default()).unwrap();
+        let blob = |content: &[u8]| repo.write_object(ObjectType::BLOB, content).unwrap();
+        let tree = repo.write_tree(&[
+            TreeEntry::new(FileMode::FILE, blob(b"x\n"), "a"),
+            TreeEntry::new(FileMode::FILE, blob(b"x\n"), "b"),
+            TreeEntry::new(FileMode::FILE, blob(b"y\n"), "c"),
+        ]).unwrap();
+        repo.checkout_tree(&tree, &CheckoutOptions::default()).unwrap();
+        repo.fs.write_file("/work/c", b"x from the work tree\n").unwrap();
+        repo.fs.remove_file("/work/b").unwrap();
+
+        let e = expr(&[pattern("x")], &PatternOptions::default());
+        for workers in &[1, 4] {
+            let opts = GrepOptions { workers: *workers, ..Default::default() };
+            let mut out = vec![];
+            assert!(repo.grep(&GrepSource::WorkTree, &e, &opts, &mut out).unwrap());
+            assert_eq!(String::from_utf8(out).unwrap(), "a:x\nc:x from the work tree\n");
+        }
+    }
+}
@@ -5,10 +5,13 @@ use std::cmp::{Ordering};
 use nom::AsBytes;
 
 type IDBytes = [u8; 20];
+type IDBytes256 = [u8; 32];
 
 #[derive(Debug, PartialEq, Eq, PartialOrd, Clone, Hash)]
 pub enum Id {
     Full(IDBytes),
+    /// A full id in a SHA-256 repository.
+    Full256(IDBytes256),
     Partial(Vec<u8>)
 }
 
@@ -18,6 +21,10 @@ impl Id {
             let mut bytes = [0u8; 20];
             bytes.clone_from_slice(buf);
             Id::Full(bytes)
+        } else if buf.len() == 32 {
+            let mut bytes = [0u8; 32];
+            bytes.clone_from_slice(buf);
+            Id::Full256(bytes)
         } else {
             let mut bytes = Vec::new();
             bytes.extend_from_slice(&buf);
@@ -27,24 +34,57 @@ impl Id {
     pub fn bytes(&self) -> &[u8] {
         match self {
             Id::Full(bytes) => bytes,
+            Id::Full256(bytes) => bytes,
             Id::Partial(bytes) => bytes
         }
     }
 
-    pub fn partial_cmp(&self, other: &Id) -> Option<Ordering> {
+    pub fn is_full(&self) -> bool {
+        !matches!(self, Id::Partial(_))
+    }
+
+    /// The hash algorithm a full id was made with.
+    pub fn format(&self) -> Option<ObjectFormat> {
         match self {
-            Id::Full(bytes) => {
-                match other {
-                    Id::Full(other_bytes) => bytes.partial_cmp(other_bytes),
-                    Id::Partial(other_bytes) =>
-                        bytes.as_bytes().partial_cmp(other_bytes.as_slice())
-                }
-            },
-            Id::Partial(bytes) => {
-                let other_bytes = &other.bytes()[0..bytes.len()];
+            Id::Full(_) => Some(ObjectFormat::Sha1),
+            Id::Full256(_) => Some(ObjectFormat::Sha256),
+            Id::Partial(_) => None,
+        }
+    }
+
+    /// The all-zero id git uses for "no object", e.g. in reflogs and
+    /// `update-ref` old values.
+    pub fn is_zero(&self) -> bool {
+        self.bytes().iter().all(|b| *b == 0)
+    }
+
+    pub fn partial_cmp(&self, other: &Id) -> Option<Ordering> {
+        match (self, other) {
+            (Id::Partial(bytes), other) => {
+                let other_bytes = other.bytes().get(0..bytes.len())?;
                 bytes.as_slice().partial_cmp(other_bytes)
             }
+            (this, Id::Partial(other_bytes)) => {
+                let bytes = this.bytes().get(0..other_bytes.len())?;
+                bytes.partial_cmp(other_bytes.as_bytes())
+            }
+            (this, other) => this.bytes().partial_cmp(other.bytes()),
+        }
+    }
+
+    /// Parses a hex id the way a repository using `format` reads it: only
+    /// the full length of its hash is a complete id, anything shorter is
+    /// an abbreviation.
+    pub fn parse(s: &str, format: ObjectFormat) -> Result<Id> {
+        let trimmed = s.trim();
+        if trimmed.len() > format.hex_len() {
+            return Err(ErrorKind::BadId(trimmed.to_owned()).into());
         }
+        if trimmed.len() == format.hex_len() {
+            return trimmed.parse();
+        }
+        let bytes = Vec::from_hex(trimmed).chain_err(|| ErrorKind::BadId(trimmed.to_owned()))?;
+        Ok(Id::Partial(bytes))
     }
 }
 
@@ -53,19 +93,20 @@ impl std::str::FromStr for Id {
 
     fn from_str(target: &str) -> Result<Self> {
         let trimmed = target.trim();
-        if trimmed.len() > 40 {
-            return Err(ErrorKind::BadId.into())
-        }
         match trimmed.len() {
             40 => {
-              let bytes =  IDBytes::from_hex(trimmed).chain_err(|| ErrorKind::BadId)?;
+              let bytes =  IDBytes::from_hex(trimmed).chain_err(|| ErrorKind::BadId(trimmed.to_owned()))?;
               Ok(Id::Full(bytes))
             },
-            len if len < 40 =>  {
-                let bytes = Vec::from_hex(trimmed).chain_err(|| ErrorKind::BadId)?;
+            64 => {
+              let bytes = IDBytes256::from_hex(trimmed).chain_err(|| ErrorKind::BadId(trimmed.to_owned()))?;
+              Ok(Id::Full256(bytes))
+            },
+            len if len < 64 =>  {
+                let bytes = Vec::from_hex(trimmed).chain_err(|| ErrorKind::BadId(trimmed.to_owned()))?;
                 Ok(Id::Partial(bytes))
             },
-            _ => Err(ErrorKind::BadId.into())
+            _ => Err(ErrorKind::BadId(trimmed.to_owned()).into())
         }
     }
 }
@@ -81,3 +122,49 @@ impl Default for Id {
     fn default() -> Self { Id::Full([0u8;20]) }
 }
 
+
+/// The hash algorithm a repository names its objects with, as configured by
+/// `extensions.objectFormat`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ObjectFormat {
+    #[default]
+    Sha1,
+    Sha256,
+}
+
+impl ObjectFormat {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ObjectFormat::Sha1 => "sha1",
+            ObjectFormat::Sha256 => "sha256",
+        }
+    }
+
+    /// The length of a raw id in bytes.
+    pub fn hash_len(self) -> usize {
+        match self {
+            ObjectFormat::Sha1 => 20,
+            ObjectFormat::Sha256 => 32,
+        }
+    }
+
+    pub fn hex_len(self) -> usize {
+        self.hash_len() * 2
+    }
+
+    pub fn null_id(self) -> Id {
+        Id::new(&vec![0u8; self.hash_len()])
+    }
+}
+
+impl std::str::FromStr for ObjectFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "sha1" => Ok(ObjectFormat::Sha1),
+            "sha256" => Ok(ObjectFormat::Sha256),
+            _ => Err(ErrorKind::UnsupportedObjectFormat(s.to_owned()).into())
+        }
+    }
+}
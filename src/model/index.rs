@@ -0,0 +1,386 @@
+use std::convert::TryInto;
+use std::io::Read;
+use std::path::PathBuf;
+use crate::errors::*;
+use crate::fs::{FileSystem, LockFile};
+use crate::fs::checksum::Hasher;
+use crate::model::id::{Id, ObjectFormat};
+use crate::model::repository::FileRepository;
+
+const SIGNATURE: &[u8] = b"DIRC";
+
+const ASSUME_VALID: u16 = 0x8000;
+const EXTENDED: u16 = 0x4000;
+const STAGE_SHIFT: u16 = 12;
+const NAME_MASK: u16 = 0x0fff;
+
+const SKIP_WORKTREE: u16 = 0x4000;
+const INTENT_TO_ADD: u16 = 0x2000;
+
+/// One staged file with the `stat` data git uses to tell whether the
+/// working tree copy changed. Times are seconds and nanoseconds.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct IndexEntry {
+    pub ctime: (u32, u32),
+    pub mtime: (u32, u32),
+    pub dev: u32,
+    pub ino: u32,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+    pub id: Id,
+    /// 0 for a merged path, 1 to 3 for the base, ours and theirs of a
+    /// conflict.
+    pub stage: u8,
+    pub assume_valid: bool,
+    /// Set for paths outside the sparse-checkout, which are not in the
+    /// working tree.
+    pub skip_worktree: bool,
+    pub intent_to_add: bool,
+    pub path: String,
+}
+
+impl IndexEntry {
+    pub fn new(path: &str, mode: u32, id: Id) -> Self {
+        IndexEntry { path: path.to_owned(), mode, id, ..IndexEntry::default() }
+    }
+
+    fn is_extended(&self) -> bool {
+        self.skip_worktree || self.intent_to_add
+    }
+}
+
+/// The staging area, `.git/index`. Optional extensions such as the
+/// cache tree are dropped on reading; git rebuilds them as needed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Index {
+    version: u32,
+    entries: Vec<IndexEntry>,
+}
+
+impl Default for Index {
+    fn default() -> Self {
+        Index { version: 2, entries: vec![] }
+    }
+}
+
+fn invalid(reason: impl Into<String>) -> Error {
+    ErrorKind::InvalidIndex(reason.into()).into()
+}
+
+fn be_u32(data: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(data[at..at + 4].try_into().unwrap())
+}
+
+fn be_u16(data: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes(data[at..at + 2].try_into().unwrap())
+}
+
+/// The variable length integer of version 4 path compression, the same
+/// one pack files use for delta offsets.
+fn decode_varint(data: &[u8], at: &mut usize) -> Option<usize> {
+    let mut byte = *data.get(*at)?;
+    *at += 1;
+    let mut value = (byte & 0x7f) as usize;
+    while byte & 0x80 != 0 {
+        byte = *data.get(*at)?;
+        *at += 1;
+        value = value.checked_add(1)?.checked_mul(128)? + (byte & 0x7f) as usize;
+    }
+    Some(value)
+}
+
+fn encode_varint(mut value: usize, out: &mut Vec<u8>) {
+    let mut bytes = vec![(value & 0x7f) as u8];
+    value >>= 7;
+    while value != 0 {
+        value -= 1;
+        bytes.push(0x80 | (value & 0x7f) as u8);
+        value >>= 7;
+    }
+    out.extend(bytes.iter().rev());
+}
+
+impl Index {
+    pub fn parse(data: &[u8], format: ObjectFormat) -> Result<Index> {
+        let hash_len = format.hash_len();
+        if data.len() < 12 + hash_len || &data[..4] != SIGNATURE {
+            return Err(invalid("bad signature"));
+        }
+        let body_end = data.len() - hash_len;
+        let mut hasher = Hasher::new(format);
+        hasher.update(&data[..body_end]);
+        if hasher.digest().bytes() != &data[body_end..] {
+            return Err(invalid("checksum mismatch"));
+        }
+        let version = be_u32(data, 4);
+        if !(2..=4).contains(&version) {
+            return Err(invalid(format!("unsupported version {}", version)));
+        }
+        let count = be_u32(data, 8) as usize;
+        let fixed = 40 + hash_len + 2;
+        let mut entries = Vec::with_capacity(count.min(body_end / fixed));
+        let mut at = 12;
+        let mut previous = String::new();
+        for _ in 0..count {
+            let start = at;
+            if at + fixed > body_end {
+                return Err(invalid("truncated entry"));
+            }
+            let word = |n: usize| be_u32(data, start + n * 4);
+            let flags = be_u16(data, start + 40 + hash_len);
+            at += fixed;
+            let extended = if flags & EXTENDED != 0 {
+                if version < 3 || at + 2 > body_end {
+                    return Err(invalid("bad extended flags"));
+                }
+                at += 2;
+                be_u16(data, at - 2)
+            } else {
+                0
+            };
+            let strip = if version == 4 {
+                decode_varint(&data[..body_end], &mut at).ok_or_else(|| invalid("bad path prefix"))?
+            } else {
+                0
+            };
+            let name_end = data[at..body_end].iter().position(|b| *b == 0)
+                .ok_or_else(|| invalid("unterminated path"))? + at;
+            // a v4 prefix may end inside a character the suffix completes
+            let mut path = vec![];
+            if version == 4 {
+                let keep = previous.len().checked_sub(strip).ok_or_else(|| invalid("bad path prefix"))?;
+                path.extend_from_slice(&previous.as_bytes()[..keep]);
+            }
+            path.extend_from_slice(&data[at..name_end]);
+            let path = String::from_utf8(path).map_err(|_| invalid("path is not UTF-8"))?;
+            at = if version == 4 {
+                name_end + 1
+            } else {
+                // entries are NUL padded to a multiple of eight bytes
+                start + (name_end - start + 8) / 8 * 8
+            };
+            if at > body_end {
+                return Err(invalid("truncated entry"));
+            }
+            entries.push(IndexEntry {
+                ctime: (word(0), word(1)),
+                mtime: (word(2), word(3)),
+                dev: word(4),
+                ino: word(5),
+                mode: word(6),
+                uid: word(7),
+                gid: word(8),
+                size: word(9),
+                id: Id::new(&data[start + 40..start + 40 + hash_len]),
+                stage: ((flags >> STAGE_SHIFT) & 3) as u8,
+                assume_valid: flags & ASSUME_VALID != 0,
+                skip_worktree: extended & SKIP_WORKTREE != 0,
+                intent_to_add: extended & INTENT_TO_ADD != 0,
+                path: path.clone(),
+            });
+            previous = path;
+        }
+        while at < body_end {
+            if at + 8 > body_end {
+                return Err(invalid("truncated extension"));
+            }
+            let signature = &data[at..at + 4];
+            if !signature[0].is_ascii_uppercase() {
+                return Err(invalid(format!("unsupported extension '{}'", String::from_utf8_lossy(signature))));
+            }
+            at += 8 + be_u32(data, at + 4) as usize;
+        }
+        if at != body_end {
+            return Err(invalid("truncated extension"));
+        }
+        Ok(Index { version, entries })
+    }
+
+    /// The index file for these entries: version 4 if read as such, else
+    /// 3 when an entry needs extended flags and 2 otherwise.
+    pub fn encode(&self, format: ObjectFormat) -> Vec<u8> {
+        let version: u32 = match self.version {
+            4 => 4,
+            _ if self.entries.iter().any(IndexEntry::is_extended) => 3,
+            _ => 2,
+        };
+        let mut out = SIGNATURE.to_vec();
+        out.extend_from_slice(&version.to_be_bytes());
+        out.extend_from_slice(&(self.entries.len() as u32).to_be_bytes());
+        let mut previous = "";
+        for entry in &self.entries {
+            let start = out.len();
+            for value in [entry.ctime.0, entry.ctime.1, entry.mtime.0, entry.mtime.1, entry.dev, entry.ino,
+                          entry.mode, entry.uid, entry.gid, entry.size].iter() {
+                out.extend_from_slice(&value.to_be_bytes());
+            }
+            out.extend_from_slice(entry.id.bytes());
+            let mut flags = (entry.path.len().min(NAME_MASK as usize) as u16) | (entry.stage as u16 & 3) << STAGE_SHIFT;
+            if entry.assume_valid {
+                flags |= ASSUME_VALID;
+            }
+            if entry.is_extended() {
+                flags |= EXTENDED;
+            }
+            out.extend_from_slice(&flags.to_be_bytes());
+            if entry.is_extended() {
+                let mut extended = 0;
+                if entry.skip_worktree {
+                    extended |= SKIP_WORKTREE;
+                }
+                if entry.intent_to_add {
+                    extended |= INTENT_TO_ADD;
+                }
+                out.extend_from_slice(&u16::to_be_bytes(extended));
+            }
+            if version == 4 {
+                let common = previous.bytes().zip(entry.path.bytes()).take_while(|(a, b)| a == b).count();
+                encode_varint(previous.len() - common, &mut out);
+                out.extend_from_slice(&entry.path.as_bytes()[common..]);
+                out.push(0);
+                previous = &entry.path;
+            } else {
+                out.extend_from_slice(entry.path.as_bytes());
+                let padded = start + (out.len() - start + 8) / 8 * 8;
+                out.resize(padded, 0);
+            }
+        }
+        let mut hasher = Hasher::new(format);
+        hasher.update(&out);
+        out.extend_from_slice(hasher.digest().bytes());
+        out
+    }
+
+    pub fn entries(&self) -> &[IndexEntry] {
+        &self.entries
+    }
+
+    fn position(&self, path: &str, stage: u8) -> std::result::Result<usize, usize> {
+        self.entries.binary_search_by(|e| (e.path.as_str(), e.stage).cmp(&(path, stage)))
+    }
+
+    pub fn entry(&self, path: &str) -> Option<&IndexEntry> {
+        self.position(path, 0).ok().map(|n| &self.entries[n])
+    }
+
+    /// Whether `path` has conflict stages.
+    pub fn is_conflicted(&self, path: &str) -> bool {
+        let n = self.position(path, 0).unwrap_or_else(|n| n);
+        self.entries.get(n).is_some_and(|e| e.path == path && e.stage > 0)
+    }
+
+    /// Adds or replaces the entry at its path and stage.
+    pub fn add(&mut self, entry: IndexEntry) {
+        match self.position(&entry.path, entry.stage) {
+            Ok(n) => self.entries[n] = entry,
+            Err(n) => self.entries.insert(n, entry),
+        }
+    }
+
+    /// Removes every stage of `path`.
+    pub fn remove(&mut self, path: &str) {
+        self.entries.retain(|e| e.path != path);
+    }
+
+    /// Replaces all entries; they are sorted by path and stage.
+    pub fn set_entries(&mut self, mut entries: Vec<IndexEntry>) {
+        entries.sort_by(|a, b| (&a.path, a.stage).cmp(&(&b.path, b.stage)));
+        self.entries = entries;
+    }
+}
+
+impl<FS: FileSystem> FileRepository<FS> {
+    pub fn index_path(&self) -> PathBuf {
+        self.git_dir().join("index")
+    }
+
+    /// The index, empty when there is none yet.
+    pub fn read_index(&self) -> Result<Index> {
+        let path = self.index_path();
+        if !self.fs.is_file(&path) {
+            return Ok(Index::default());
+        }
+        let mut data = vec![];
+        self.fs.read_file(&path)?.read_to_end(&mut data)?;
+        Index::parse(&data, self.object_format())
+    }
+
+    /// Writes the index under `index.lock`.
+    pub fn write_index(&self, index: &Index) -> Result<()> {
+        let lock = LockFile::acquire(&self.fs, self.index_path())?;
+        lock.write(&index.encode(self.object_format()))?;
+        lock.commit()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::MemFs;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_index_round_trip() {
+        let id = Id::from_str("916269d397a334666906f57d69b297decf25da41").unwrap();
+        let mut index = Index::default();
+        index.add(IndexEntry::new("src/main.rs", 0o100644, id.clone()));
+        index.add(IndexEntry { mtime: (5, 6), size: 12, ..IndexEntry::new("README", 0o100755, id.clone()) });
+        let data = index.encode(ObjectFormat::Sha1);
+        assert_eq!(be_u32(&data, 4), 2);
+        assert_eq!(Index::parse(&data, ObjectFormat::Sha1).unwrap().entries(), index.entries());
+        assert_eq!(index.entries()[0].path, "README");
+
+        index.add(IndexEntry { skip_worktree: true, ..IndexEntry::new("src/lib.rs", 0o100644, id.clone()) });
+        let data = index.encode(ObjectFormat::Sha1);
+        assert_eq!(be_u32(&data, 4), 3);
+        assert_eq!(Index::parse(&data, ObjectFormat::Sha1).unwrap().entries(), index.entries());
+
+        index.version = 4;
+        let data = index.encode(ObjectFormat::Sha1);
+        assert_eq!(Index::parse(&data, ObjectFormat::Sha1).unwrap().entries(), index.entries());
+
+        let mut corrupt = data.clone();
+        corrupt[20] ^= 1;
+        assert_eq!(Index::parse(&corrupt, ObjectFormat::Sha1).unwrap_err().to_string(),
+                   "invalid index: checksum mismatch");
+
+        let repo = FileRepository::<MemFs>::default();
+        assert!(repo.read_index().unwrap().entries().is_empty());
+        repo.write_index(&index).unwrap();
+        assert_eq!(repo.read_index().unwrap().entries(), index.entries());
+    }
+
+    #[test]
+    fn test_read_git_index() {
+        let data = std::fs::read("tests/fixture_index/index").unwrap();
+        let index = Index::parse(&data, ObjectFormat::Sha1).unwrap();
+        let paths: Vec<&str> = index.entries().iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["a.txt", "dir/b.txt", "dir/sub/c.sh"]);
+        assert_eq!(index.entries()[2].mode, 0o100755);
+        assert_eq!(index.entry("dir/b.txt").unwrap().size, 2);
+    }
+
+    #[test]
+    fn test_v4_prefix_inside_character() {
+        // git 2.39 writes `é` after `è` as a one byte suffix of the shared
+        // first byte
+        let data = std::fs::read("tests/fixture_index/index-v4").unwrap();
+        let index = Index::parse(&data, ObjectFormat::Sha1).unwrap();
+        let paths: Vec<&str> = index.entries().iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["è", "é"]);
+
+        let data = index.encode(ObjectFormat::Sha1);
+        assert_eq!(be_u32(&data, 4), 4);
+        assert_eq!(Index::parse(&data, ObjectFormat::Sha1).unwrap().entries(), index.entries());
+
+        let id = Id::from_str("916269d397a334666906f57d69b297decf25da41").unwrap();
+        let mut index = Index { version: 4, ..Index::default() };
+        for path in &["dir/è", "dir/é/x", "dir/éa", "ü"] {
+            index.add(IndexEntry::new(path, 0o100644, id.clone()));
+        }
+        let data = index.encode(ObjectFormat::Sha1);
+        assert_eq!(Index::parse(&data, ObjectFormat::Sha1).unwrap().entries(), index.entries());
+    }
+}
@@ -0,0 +1,210 @@
+use std::path::{Path, PathBuf};
+use std::io::Read;
+use crate::errors::*;
+use crate::fs::{FileSystem, OsFs};
+use crate::config::{Config, ConfigFile};
+use crate::model::id::ObjectFormat;
+use crate::model::discovery::{normalize, RepositoryLayout};
+use crate::model::refs::check_ref_format;
+use crate::model::repository::FileRepository;
+
+const DESCRIPTION: &str = "Unnamed repository; edit this file 'description' to name the repository.\n";
+
+const EXCLUDE: &str = "# git ls-files --others --exclude-from=.git/info/exclude
+# Lines that start with '#' are comments.
+# For a project mostly in C, the following would be a good set of
+# exclude patterns (uncomment them if you want to use them):
+# *.[oa]
+# *~
+";
+
+#[derive(Debug, Clone, Default)]
+pub struct InitOptions {
+    pub bare: bool,
+    /// Defaults to `init.defaultBranch`, then `master`.
+    pub initial_branch: Option<String>,
+    /// Defaults to `GIT_TEMPLATE_DIR`, then `init.templateDir`.
+    pub template: Option<PathBuf>,
+    pub object_format: ObjectFormat,
+}
+
+impl<FS: FileSystem> FileRepository<FS> {
+    /// Creates a repository at `path`, or reinitializes the one already
+    /// there without touching existing files. Returns the repository and
+    /// whether it already existed.
+    pub fn init_with(fs: FS, path: &Path, opts: &InitOptions) -> Result<(Self, bool)> {
+        let path = normalize(path);
+        let git_dir = if opts.bare { path.clone() } else { path.join(".git") };
+        let existed = fs.is_file(git_dir.join("HEAD"));
+        let global = Config::open(&fs, None)?;
+        let branch = match &opts.initial_branch {
+            Some(branch) => branch.clone(),
+            None => global.get("init.defaultBranch")?.unwrap_or("master").to_owned(),
+        };
+        if (!existed || opts.initial_branch.is_some()) && !check_ref_format(&format!("refs/heads/{}", branch)) {
+            return Err(ErrorKind::InvalidRefName(branch).into());
+        }
+
+        for dir in &["objects/info", "objects/pack", "refs/heads", "refs/tags", "info", "hooks"] {
+            fs.create_dir_all(git_dir.join(dir))?;
+        }
+        let template = match &opts.template {
+            Some(template) => Some(template.clone()),
+            None => std::env::var_os("GIT_TEMPLATE_DIR").map(PathBuf::from)
+                .or(global.get_path("init.templateDir")?),
+        };
+        if let Some(template) = template {
+            copy_template(&fs, &template, &git_dir)?;
+        }
+
+        // an existing HEAD is kept, and with it any --initial-branch ignored
+        if !fs.is_file(git_dir.join("HEAD")) {
+            fs.write_file(git_dir.join("HEAD"), format!("ref: refs/heads/{}\n", branch).as_bytes())?;
+        }
+        if !fs.is_file(git_dir.join("description")) {
+            fs.write_file(git_dir.join("description"), DESCRIPTION.as_bytes())?;
+        }
+        if !fs.is_file(git_dir.join("info").join("exclude")) {
+            fs.write_file(git_dir.join("info").join("exclude"), EXCLUDE.as_bytes())?;
+        }
+        write_config(&fs, &git_dir, opts, existed)?;
+
+        let layout = RepositoryLayout {
+            object_dir: git_dir.join("objects"),
+            common_dir: git_dir.clone(),
+            work_tree: if opts.bare { None } else { Some(path) },
+            is_bare: opts.bare,
+            git_dir,
+        };
+        Ok((FileRepository::from_layout(fs, layout)?, existed))
+    }
+}
+
+impl FileRepository<OsFs> {
+    pub fn init<P: AsRef<Path>>(path: P, opts: &InitOptions) -> Result<(Self, bool)> {
+        let path = path.as_ref();
+        let path = if path.is_absolute() {
+            path.to_path_buf()
+        } else {
+            std::env::current_dir()?.join(path)
+        };
+        let fs = OsFs::new(&path);
+        FileRepository::init_with(fs, &path, opts)
+    }
+}
+
+fn write_config<FS: FileSystem>(fs: &FS, git_dir: &Path, opts: &InitOptions, existed: bool) -> Result<()> {
+    if existed {
+        return Ok(());
+    }
+    let config_path = git_dir.join("config");
+    let mut text = String::new();
+    if let Ok(mut reader) = fs.read_file(&config_path) {
+        reader.read_to_string(&mut text)?;
+    }
+    let mut config = ConfigFile::parse(&text)
+        .chain_err(|| format!("bad config file {}", config_path.display()))?;
+    let version = if opts.object_format == ObjectFormat::Sha1 { "0" } else { "1" };
+    config.set("core.repositoryformatversion", version)?;
+    config.set("core.filemode", if cfg!(unix) { "true" } else { "false" })?;
+    config.set("core.bare", if opts.bare { "true" } else { "false" })?;
+    if !opts.bare {
+        config.set("core.logallrefupdates", "true")?;
+    }
+    if opts.object_format != ObjectFormat::Sha1 {
+        config.set("extensions.objectformat", opts.object_format.as_str())?;
+    }
+    fs.write_file(&config_path, config.to_string().as_bytes())
+}
+
+/// Copies every file of `template` that does not exist yet in `git_dir`.
+fn copy_template<FS: FileSystem>(fs: &FS, template: &Path, git_dir: &Path) -> Result<()> {
+    if !fs.is_dir(template) {
+        return Ok(());
+    }
+    let entries: Vec<PathBuf> = fs.read_dir(template).collect();
+    for entry in entries {
+        let name = match entry.file_name() {
+            Some(name) => name,
+            None => continue,
+        };
+        let target = git_dir.join(name);
+        if fs.is_dir(&entry) {
+            fs.create_dir_all(&target)?;
+            copy_template(fs, &entry, &target)?;
+        } else if !fs.is_file(&target) {
+            let mut content = vec![];
+            fs.read_file(&entry)?.read_to_end(&mut content)?;
+            fs.write_file(&target, &content)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::MemFs;
+    use crate::model::discovery::{discover, DiscoveryEnv};
+
+    #[test]
+    fn test_init_in_memory() {
+        let mut fs = MemFs::default();
+        fs.add_file("/templates/hooks/pre-commit.sample", b"#!/bin/sh\n".to_vec());
+        fs.add_file("/templates/description", b"from template\n".to_vec());
+        let opts = InitOptions {
+            initial_branch: Some("main".to_owned()),
+            template: Some(PathBuf::from("/templates")),
+            ..InitOptions::default()
+        };
+        let (repo, existed) = FileRepository::init_with(fs, Path::new("/work/repo"), &opts).unwrap();
+        assert!(!existed);
+        assert!(!repo.is_bare());
+        assert_eq!(repo.git_dir(), Path::new("/work/repo/.git"));
+
+        let read = |p: &str| {
+            let mut s = String::new();
+            repo.fs.read_file(Path::new("/work/repo/.git").join(p)).unwrap()
+                .read_to_string(&mut s).unwrap();
+            s
+        };
+        assert_eq!(read("HEAD"), "ref: refs/heads/main\n");
+        assert_eq!(read("description"), "from template\n");
+        assert_eq!(read("hooks/pre-commit.sample"), "#!/bin/sh\n");
+        assert!(read("info/exclude").starts_with("# git ls-files"));
+        assert!(repo.fs.is_dir("/work/repo/.git/refs/tags"));
+        assert!(repo.fs.is_dir("/work/repo/.git/objects/pack"));
+        let config = repo.config().unwrap();
+        assert_eq!(config.get_bool("core.bare").unwrap(), Some(false));
+        assert_eq!(config.get("core.repositoryformatversion").unwrap(), Some("0"));
+
+        let layout = discover(&repo.fs, Path::new("/work/repo"), &DiscoveryEnv::default()).unwrap();
+        assert_eq!(layout.git_dir, PathBuf::from("/work/repo/.git"));
+        assert!(!layout.is_bare);
+    }
+
+    #[test]
+    fn test_init_bare_sha256() {
+        let opts = InitOptions {
+            bare: true,
+            object_format: ObjectFormat::Sha256,
+            ..InitOptions::default()
+        };
+        let (repo, _) = FileRepository::init_with(MemFs::default(), Path::new("/srv/repo.git"), &opts).unwrap();
+        assert!(repo.is_bare());
+        let config = repo.config().unwrap();
+        assert_eq!(config.get_bool("core.bare").unwrap(), Some(true));
+        assert_eq!(config.get("core.repositoryformatversion").unwrap(), Some("1"));
+        assert_eq!(config.get("extensions.objectformat").unwrap(), Some("sha256"));
+        assert_eq!(config.get("core.logallrefupdates").unwrap(), None);
+    }
+
+    #[test]
+    fn test_init_rejects_bad_branch_names() {
+        for name in &["a..b", "x.lock", "a b", "main:x", ""] {
+            let opts = InitOptions { initial_branch: Some(name.to_string()), ..InitOptions::default() };
+            let err = FileRepository::init_with(MemFs::default(), Path::new("/r"), &opts).err().unwrap();
+            assert!(matches!(err.kind(), ErrorKind::InvalidRefName(_)), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,781 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ops::Range;
+use std::str::FromStr;
+use crate::errors::*;
+use crate::fs::FileSystem;
+use crate::model::diff::{diff, lines, pair_renames, DiffFile, Edit};
+use crate::model::id::Id;
+use crate::model::object::ObjectType;
+use crate::model::repository::{FileRepository, Repository};
+use crate::model::tree::{FileMode, TreeEntry};
+
+const MARKER_SIZE: usize = 7;
+
+/// Git only looks this far into a file for a NUL when deciding it is
+/// binary.
+const BINARY_PROBE: usize = 8000;
+
+/// How conflicting hunks are written out.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConflictStyle {
+    /// Both sides, without lines they have in common.
+    Merge,
+    /// Both sides and the base, as they are.
+    Diff3,
+    /// Like `Diff3`, with lines common to both sides moved out.
+    Zdiff3,
+}
+
+impl FromStr for ConflictStyle {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "merge" => Ok(ConflictStyle::Merge),
+            "diff3" => Ok(ConflictStyle::Diff3),
+            "zdiff3" => Ok(ConflictStyle::Zdiff3),
+            _ => bail!("unknown conflict style '{}'", s),
+        }
+    }
+}
+
+/// The names put after conflict markers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Labels {
+    pub ours: String,
+    pub base: String,
+    pub theirs: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct MergeOptions {
+    pub style: ConflictStyle,
+    pub labels: Labels,
+    /// How similar, in percent, a deleted and an added file must be to
+    /// count as a rename.
+    pub rename_threshold: usize,
+    /// Inexact rename detection is skipped when a side has more deleted
+    /// or added files than this.
+    pub rename_limit: usize,
+    /// Merges commits without a common ancestor against an empty tree
+    /// instead of failing.
+    pub allow_unrelated: bool,
+}
+
+impl Default for MergeOptions {
+    fn default() -> Self {
+        MergeOptions {
+            style: ConflictStyle::Merge,
+            labels: Labels { ours: "ours".to_owned(), base: "base".to_owned(), theirs: "theirs".to_owned() },
+            rename_threshold: 50,
+            rename_limit: 7000,
+            allow_unrelated: false,
+        }
+    }
+}
+
+/// The result of a three-way text merge; `content` carries conflict
+/// markers when `conflicts` is not zero.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FileMerge {
+    pub content: Vec<u8>,
+    pub conflicts: usize,
+}
+
+/// Merges the changes `ours` and `theirs` made to `base` line by line.
+/// Changes that overlap or touch are conflicts unless both sides made the
+/// same one.
+pub fn merge_file(base: &[u8], ours: &[u8], theirs: &[u8], labels: &Labels, style: ConflictStyle) -> FileMerge {
+    let base = lines(base);
+    let sides = [lines(ours), lines(theirs)];
+    let mut changes: Vec<(Edit, usize)> = diff(&base, &sides[0]).into_iter().map(|e| (e, 0))
+        .chain(diff(&base, &sides[1]).into_iter().map(|e| (e, 1)))
+        .collect();
+    changes.sort_by_key(|(edit, _)| edit.old.start);
+
+    let mut out = vec![];
+    let mut conflicts = 0;
+    let mut cursor = 0;
+    // how far each side has drifted from base line numbers
+    let mut delta = [0isize; 2];
+    let mut i = 0;
+    while i < changes.len() {
+        let start = changes[i].0.old.start;
+        let mut end = changes[i].0.old.end;
+        let mut j = i + 1;
+        while j < changes.len() && changes[j].0.old.start <= end {
+            end = end.max(changes[j].0.old.end);
+            j += 1;
+        }
+        let mut changed = [false; 2];
+        let mut ranges: [Range<usize>; 2] = [0..0, 0..0];
+        for side in 0..2 {
+            let from = (start as isize + delta[side]) as usize;
+            for (edit, _) in changes[i..j].iter().filter(|(_, s)| *s == side) {
+                changed[side] = true;
+                delta[side] += edit.new.len() as isize - edit.old.len() as isize;
+            }
+            ranges[side] = from..(end as isize + delta[side]) as usize;
+        }
+        let ours = &sides[0][ranges[0].clone()];
+        let theirs = &sides[1][ranges[1].clone()];
+        out.extend(base[cursor..start].iter().flat_map(|line| line.iter()));
+        if !changed[1] || ours == theirs {
+            out.extend(ours.iter().flat_map(|line| line.iter()));
+        } else if !changed[0] {
+            out.extend(theirs.iter().flat_map(|line| line.iter()));
+        } else {
+            conflicts += 1;
+            write_conflict(&mut out, &base[start..end], ours, theirs, labels, style);
+        }
+        cursor = end;
+        i = j;
+    }
+    out.extend(base[cursor..].iter().flat_map(|line| line.iter()));
+    FileMerge { content: out, conflicts }
+}
+
+fn write_conflict(out: &mut Vec<u8>, base: &[&[u8]], ours: &[&[u8]], theirs: &[&[u8]],
+                  labels: &Labels, style: ConflictStyle) {
+    let (mut prefix, mut suffix) = (0, 0);
+    if style != ConflictStyle::Diff3 {
+        prefix = ours.iter().zip(theirs).take_while(|(a, b)| a == b).count();
+        suffix = ours[prefix..].iter().rev().zip(theirs[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    }
+    let push = |out: &mut Vec<u8>, lines: &[&[u8]]| out.extend(lines.iter().flat_map(|line| line.iter()));
+    let marker = |out: &mut Vec<u8>, c: char, label: &str| {
+        if out.last().is_some_and(|b| *b != b'\n') {
+            out.push(b'\n');
+        }
+        out.extend(c.to_string().repeat(MARKER_SIZE).bytes());
+        if !label.is_empty() {
+            out.push(b' ');
+            out.extend(label.bytes());
+        }
+        out.push(b'\n');
+    };
+    push(out, &ours[..prefix]);
+    marker(out, '<', &labels.ours);
+    push(out, &ours[prefix..ours.len() - suffix]);
+    if style != ConflictStyle::Merge {
+        marker(out, '|', &labels.base);
+        push(out, base);
+    }
+    marker(out, '=', "");
+    push(out, &theirs[prefix..theirs.len() - suffix]);
+    marker(out, '>', &labels.theirs);
+    push(out, &ours[ours.len() - suffix..]);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConflictKind {
+    /// Both sides changed the same lines, or a file that cannot be merged.
+    Content,
+    /// Both sides added different files at the same path.
+    AddAdd,
+    ModifyDelete,
+    RenameDelete,
+    /// Both sides renamed a file, to different paths.
+    RenameRename,
+    /// A file where the other side has a directory; the file was moved
+    /// aside.
+    FileDirectory,
+    /// A file on one side and a symlink or submodule on the other; both
+    /// were moved aside.
+    DistinctTypes,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Conflict {
+    pub kind: ConflictKind,
+    pub path: String,
+    pub message: String,
+}
+
+/// One version of a conflicted path, as the index would stage it: 1 for
+/// the base, 2 for ours and 3 for theirs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConflictEntry {
+    pub path: String,
+    pub stage: u8,
+    pub mode: FileMode,
+    pub id: Id,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TreeMerge {
+    /// The merged tree, with conflicted files written with markers.
+    pub tree: Id,
+    pub conflicts: Vec<Conflict>,
+    /// The versions of every conflicted path, by path and stage.
+    pub entries: Vec<ConflictEntry>,
+    /// What `git merge` would print, conflicts included, by path.
+    pub messages: Vec<String>,
+}
+
+impl TreeMerge {
+    pub fn is_clean(&self) -> bool {
+        self.conflicts.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Entry {
+    mode: FileMode,
+    id: Id,
+}
+
+impl Entry {
+    fn is_file(&self) -> bool {
+        self.mode == FileMode::FILE || self.mode == FileMode::EXE
+    }
+
+    /// Regular files of either mode merge with each other; symlinks and
+    /// submodules only with their own kind.
+    fn same_type(&self, other: &Entry) -> bool {
+        (self.is_file() && other.is_file()) || self.mode == other.mode
+    }
+}
+
+type Files = BTreeMap<String, Entry>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Origin {
+    Ours,
+    Theirs,
+    Both,
+}
+
+struct TreeMerger<'a, FS: FileSystem> {
+    repo: &'a FileRepository<FS>,
+    opts: &'a MergeOptions,
+    result: BTreeMap<String, (Entry, Origin)>,
+    conflicts: Vec<Conflict>,
+    entries: Vec<ConflictEntry>,
+    messages: Vec<(String, String)>,
+}
+
+impl<'a, FS: FileSystem> TreeMerger<'a, FS> {
+    /// Lists the files of the three trees by path. A subtree that is the
+    /// same in all of them is listed as one entry rather than walked.
+    fn collect(&self, prefix: &str, trees: [Option<&Id>; 3], files: &mut [Files; 3]) -> Result<()> {
+        let mut names: BTreeMap<String, [Option<TreeEntry>; 3]> = BTreeMap::new();
+        for (side, id) in trees.iter().enumerate() {
+            if let Some(id) = id {
+                for entry in self.repo.find_tree(id)?.entries() {
+                    names.entry(entry.name().to_owned()).or_default()[side] = Some(entry.clone());
+                }
+            }
+        }
+        for (name, found) in names {
+            let path = format!("{}{}", prefix, name);
+            let unchanged = found[0].is_some() && found[0] == found[1] && found[1] == found[2];
+            let mut subtrees: [Option<&Id>; 3] = [None, None, None];
+            for (side, entry) in found.iter().enumerate() {
+                match entry {
+                    Some(entry) if entry.mode() == FileMode::DIR && !unchanged => subtrees[side] = Some(entry.id()),
+                    Some(entry) => {
+                        files[side].insert(path.clone(), Entry { mode: entry.mode(), id: entry.id().clone() });
+                    }
+                    None => {}
+                }
+            }
+            if subtrees.iter().any(Option::is_some) {
+                self.collect(&format!("{}/", path), subtrees, files)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn content(&self, id: &Id) -> Result<Vec<u8>> {
+        Repository::read_content(self.repo, &self.repo.find_object(id)?)
+    }
+
+    /// Pairs files `side` deleted with files it added: first those with
+    /// the same content, then those similar enough.
+    fn renames(&self, base: &Files, side: &Files) -> Result<HashMap<String, String>> {
+        let renameable = |entry: &Entry| entry.mode != FileMode::DIR && entry.mode != FileMode::SUBMODULE;
+        let file = |(path, entry): (&String, &Entry)| DiffFile { path: path.clone(), mode: entry.mode.clone(), id: entry.id.clone() };
+        let deleted: Vec<DiffFile> = base.iter()
+            .filter(|(path, entry)| renameable(entry) && !side.contains_key(*path))
+            .map(file)
+            .collect();
+        let added: Vec<DiffFile> = side.iter()
+            .filter(|(path, entry)| renameable(entry) && !base.contains_key(*path))
+            .map(file)
+            .collect();
+        let pairs = pair_renames(&deleted, &added, self.opts.rename_threshold, self.opts.rename_limit,
+                                 &mut |id| self.content(id))?;
+        Ok(pairs.into_iter().map(|(i, j, _)| (deleted[i].path.clone(), added[j].path.clone())).collect())
+    }
+
+    fn message(&mut self, path: &str, message: String) {
+        self.messages.push((path.to_owned(), message));
+    }
+
+    fn conflict(&mut self, kind: ConflictKind, path: &str, message: String) {
+        self.message(path, message.clone());
+        self.conflicts.push(Conflict { kind, path: path.to_owned(), message });
+    }
+
+    fn stage(&mut self, path: &str, stage: u8, entry: &Entry) {
+        self.entries.push(ConflictEntry { path: path.to_owned(), stage, mode: entry.mode.clone(), id: entry.id.clone() });
+    }
+
+    /// Conflict labels, naming each side's path when they differ.
+    fn labels(&self, paths: [&str; 3]) -> Labels {
+        let labels = &self.opts.labels;
+        if paths[0] == paths[1] && paths[1] == paths[2] {
+            return labels.clone();
+        }
+        Labels {
+            base: format!("{}:{}", labels.base, paths[0]),
+            ours: format!("{}:{}", labels.ours, paths[1]),
+            theirs: format!("{}:{}", labels.theirs, paths[2]),
+        }
+    }
+
+    /// Merges two versions of the same file of the same type, returning
+    /// the merged entry and whether it merged cleanly.
+    fn merge_entries(&mut self, path: &str, paths: [&str; 3], base: Option<&Entry>, ours: &Entry,
+                     theirs: &Entry) -> Result<(Entry, bool)> {
+        if ours == theirs {
+            return Ok((ours.clone(), true));
+        }
+        if let Some(base) = base {
+            if ours == base {
+                return Ok((theirs.clone(), true));
+            }
+            if theirs == base {
+                return Ok((ours.clone(), true));
+            }
+        }
+        let mut clean = true;
+        let mode = match base {
+            Some(base) if ours.mode == base.mode => theirs.mode.clone(),
+            Some(base) if theirs.mode == base.mode => ours.mode.clone(),
+            _ => {
+                clean = ours.mode == theirs.mode;
+                ours.mode.clone()
+            }
+        };
+        let id = match base {
+            _ if ours.id == theirs.id => ours.id.clone(),
+            Some(base) if ours.id == base.id => theirs.id.clone(),
+            Some(base) if theirs.id == base.id => ours.id.clone(),
+            _ if !ours.is_file() => return Ok((ours.clone(), false)),
+            _ => {
+                let base = match base {
+                    Some(base) => self.content(&base.id)?,
+                    None => vec![],
+                };
+                let (ours_content, theirs_content) = (self.content(&ours.id)?, self.content(&theirs.id)?);
+                let labels = self.labels(paths);
+                self.message(path, format!("Auto-merging {}", path));
+                if [&base, &ours_content, &theirs_content].iter().any(|c| is_binary(c)) {
+                    self.message(path, format!("warning: Cannot merge binary files: {} ({} vs. {})",
+                                               path, labels.ours, labels.theirs));
+                    return Ok((ours.clone(), false));
+                }
+                let merged = merge_file(&base, &ours_content, &theirs_content, &labels, self.opts.style);
+                clean &= merged.conflicts == 0;
+                self.repo.write_object(ObjectType::BLOB, &merged.content)?
+            }
+        };
+        Ok((Entry { mode, id }, clean))
+    }
+
+    /// Puts a file into the result. A path already taken, say by a rename
+    /// on the other side, is merged with it as if both sides added it.
+    fn place(&mut self, path: &str, entry: Entry, origin: Origin) -> Result<()> {
+        let (existing, existing_origin) = match self.result.remove(path) {
+            None => {
+                self.result.insert(path.to_owned(), (entry, origin));
+                return Ok(());
+            }
+            Some(existing) => existing,
+        };
+        let (ours, theirs) = if existing_origin == Origin::Theirs { (entry, existing) } else { (existing, entry) };
+        self.add_add(path, &ours, &theirs)
+    }
+
+    fn add_add(&mut self, path: &str, ours: &Entry, theirs: &Entry) -> Result<()> {
+        if !ours.same_type(theirs) {
+            return self.distinct_types(path, None, ours, theirs);
+        }
+        let (merged, clean) = self.merge_entries(path, [path; 3], None, ours, theirs)?;
+        if !clean {
+            self.conflict(ConflictKind::AddAdd, path, format!("CONFLICT (add/add): Merge conflict in {}", path));
+            self.stage(path, 2, ours);
+            self.stage(path, 3, theirs);
+        }
+        self.result.insert(path.to_owned(), (merged, Origin::Both));
+        Ok(())
+    }
+
+    fn distinct_types(&mut self, path: &str, base: Option<&Entry>, ours: &Entry, theirs: &Entry) -> Result<()> {
+        self.conflict(ConflictKind::DistinctTypes, path, format!(
+            "CONFLICT (distinct types): {} had different types on each side; renamed both of them so each can be recorded somewhere.",
+            path));
+        let ours_path = format!("{}~{}", path, self.opts.labels.ours);
+        let theirs_path = format!("{}~{}", path, self.opts.labels.theirs);
+        if let Some(base) = base {
+            self.stage(&ours_path, 1, base);
+        }
+        self.stage(&ours_path, 2, ours);
+        self.stage(&theirs_path, 3, theirs);
+        self.result.insert(ours_path, (ours.clone(), Origin::Ours));
+        self.result.insert(theirs_path, (theirs.clone(), Origin::Theirs));
+        Ok(())
+    }
+
+    /// One side kept the file, possibly renamed and changed, and the other
+    /// deleted it.
+    fn kept_on_one_side(&mut self, path: &str, base: &Entry, kept_path: &str, kept: &Entry,
+                        origin: Origin) -> Result<()> {
+        if kept_path == path && kept == base {
+            return Ok(());
+        }
+        let (stage, keeper, deleter) = match origin {
+            Origin::Theirs => (3, &self.opts.labels.theirs, &self.opts.labels.ours),
+            _ => (2, &self.opts.labels.ours, &self.opts.labels.theirs),
+        };
+        let (keeper, deleter) = (keeper.clone(), deleter.clone());
+        if kept_path != path {
+            self.conflict(ConflictKind::RenameDelete, kept_path, format!(
+                "CONFLICT (rename/delete): {} renamed to {} in {}, but deleted in {}.", path, kept_path, keeper, deleter));
+        }
+        if kept != base {
+            self.conflict(ConflictKind::ModifyDelete, kept_path, format!(
+                "CONFLICT (modify/delete): {0} deleted in {1} and modified in {2}.  Version {2} of {0} left in tree.",
+                kept_path, deleter, keeper));
+        }
+        self.stage(kept_path, 1, base);
+        self.stage(kept_path, stage, kept);
+        self.place(kept_path, kept.clone(), origin)
+    }
+
+    /// A file of the base that both sides still have, maybe under other
+    /// names.
+    fn kept_on_both_sides(&mut self, path: &str, base: &Entry, ours_path: &str, ours: &Entry,
+                          theirs_path: &str, theirs: &Entry) -> Result<()> {
+        let target = if ours_path == path { theirs_path } else { ours_path };
+        if ours_path != theirs_path && ours_path != path && theirs_path != path {
+            self.conflict(ConflictKind::RenameRename, path, format!(
+                "CONFLICT (rename/rename): {} renamed to {} in {} and to {} in {}.",
+                path, ours_path, self.opts.labels.ours, theirs_path, self.opts.labels.theirs));
+            self.stage(path, 1, base);
+            self.stage(ours_path, 2, ours);
+            self.stage(theirs_path, 3, theirs);
+            self.place(ours_path, ours.clone(), Origin::Ours)?;
+            return self.place(theirs_path, theirs.clone(), Origin::Theirs);
+        }
+        if !ours.same_type(theirs) {
+            if ours == base {
+                return self.place(target, theirs.clone(), Origin::Both);
+            }
+            if theirs == base {
+                return self.place(target, ours.clone(), Origin::Both);
+            }
+            return self.distinct_types(target, Some(base), ours, theirs);
+        }
+        let (merged, clean) = self.merge_entries(target, [path, ours_path, theirs_path], Some(base), ours, theirs)?;
+        if !clean {
+            self.conflict(ConflictKind::Content, target, format!("CONFLICT (content): Merge conflict in {}", target));
+            self.stage(target, 1, base);
+            self.stage(target, 2, ours);
+            self.stage(target, 3, theirs);
+        }
+        self.place(target, merged, Origin::Both)
+    }
+
+    /// Moves files out of the way of directories, as `path~label`.
+    fn resolve_file_directory(&mut self) {
+        let mut moves = vec![];
+        for (path, (entry, origin)) in &self.result {
+            if entry.mode == FileMode::DIR {
+                continue;
+            }
+            let dir = format!("{}/", path);
+            if self.result.range(dir.clone()..).next().is_some_and(|(next, _)| next.starts_with(&dir)) {
+                moves.push((path.clone(), *origin));
+            }
+        }
+        for (path, origin) in moves {
+            let label = if origin == Origin::Theirs { &self.opts.labels.theirs } else { &self.opts.labels.ours };
+            let moved = format!("{}~{}", path, label);
+            self.conflict(ConflictKind::FileDirectory, &path, format!(
+                "CONFLICT (file/directory): directory in the way of {} from {}; moving it to {} instead.",
+                path, label, moved));
+            let (entry, origin) = self.result.remove(&path).unwrap();
+            let mut staged = false;
+            for staged_entry in self.entries.iter_mut().filter(|e| e.path == path) {
+                staged_entry.path = moved.clone();
+                staged = true;
+            }
+            if !staged {
+                self.stage(&moved, if origin == Origin::Theirs { 3 } else { 2 }, &entry);
+            }
+            self.result.insert(moved, (entry, origin));
+        }
+    }
+
+    /// Writes the result out as nested trees.
+    fn write(&self) -> Result<Id> {
+        #[derive(Default)]
+        struct Dir<'a> {
+            files: Vec<(&'a str, &'a Entry)>,
+            dirs: BTreeMap<&'a str, Dir<'a>>,
+        }
+        fn write<FS: FileSystem>(repo: &FileRepository<FS>, dir: &Dir) -> Result<Id> {
+            let mut entries = vec![];
+            for (name, entry) in &dir.files {
+                entries.push(TreeEntry::new(entry.mode.clone(), entry.id.clone(), *name));
+            }
+            for (name, sub) in &dir.dirs {
+                entries.push(TreeEntry::new(FileMode::DIR, write(repo, sub)?, *name));
+            }
+            repo.write_tree(&entries)
+        }
+        let mut root = Dir::default();
+        for (path, (entry, _)) in &self.result {
+            let mut dir = &mut root;
+            let mut parts: Vec<&str> = path.split('/').collect();
+            let name = parts.pop().unwrap();
+            for part in parts {
+                dir = dir.dirs.entry(part).or_default();
+            }
+            dir.files.push((name, entry));
+        }
+        write(self.repo, &root)
+    }
+
+    fn merge(mut self, base: Option<&Id>, ours: &Id, theirs: &Id) -> Result<TreeMerge> {
+        let mut files: [Files; 3] = Default::default();
+        self.collect("", [base, Some(ours), Some(theirs)], &mut files)?;
+        let [base, ours, theirs] = files;
+        let ours_renames = self.renames(&base, &ours)?;
+        let theirs_renames = self.renames(&base, &theirs)?;
+
+        let mut claimed: [HashSet<&String>; 2] = Default::default();
+        for (path, entry) in &base {
+            let find = |side: &'_ Files, renames: &'_ HashMap<String, String>| -> Option<String> {
+                if side.contains_key(path) { Some(path.clone()) } else { renames.get(path).cloned() }
+            };
+            let ours_path = find(&ours, &ours_renames);
+            let theirs_path = find(&theirs, &theirs_renames);
+            if let Some(p) = &ours_path {
+                claimed[0].insert(ours.get_key_value(p).unwrap().0);
+            }
+            if let Some(p) = &theirs_path {
+                claimed[1].insert(theirs.get_key_value(p).unwrap().0);
+            }
+            match (ours_path, theirs_path) {
+                (None, None) => {}
+                (Some(o), None) => self.kept_on_one_side(path, entry, &o, &ours[&o], Origin::Ours)?,
+                (None, Some(t)) => self.kept_on_one_side(path, entry, &t, &theirs[&t], Origin::Theirs)?,
+                (Some(o), Some(t)) => self.kept_on_both_sides(path, entry, &o, &ours[&o], &t, &theirs[&t])?,
+            }
+        }
+        for (path, entry) in &ours {
+            if claimed[0].contains(path) {
+                continue;
+            }
+            match theirs.get(path) {
+                Some(other) if !claimed[1].contains(path) => {
+                    claimed[1].insert(path);
+                    self.add_add(path, entry, other)?;
+                }
+                _ => self.place(path, entry.clone(), Origin::Ours)?,
+            }
+        }
+        for (path, entry) in &theirs {
+            if !claimed[1].contains(path) {
+                self.place(path, entry.clone(), Origin::Theirs)?;
+            }
+        }
+        self.resolve_file_directory();
+
+        let tree = self.write()?;
+        self.conflicts.sort_by(|a, b| a.path.cmp(&b.path));
+        self.entries.sort_by(|a, b| (&a.path, a.stage).cmp(&(&b.path, b.stage)));
+        self.entries.dedup();
+        // stable, so each path's messages keep their order
+        self.messages.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(TreeMerge {
+            tree,
+            conflicts: self.conflicts,
+            entries: self.entries,
+            messages: self.messages.into_iter().map(|(_, message)| message).collect(),
+        })
+    }
+}
+
+fn is_binary(content: &[u8]) -> bool {
+    content[..content.len().min(BINARY_PROBE)].contains(&0)
+}
+
+impl<FS: FileSystem> FileRepository<FS> {
+    /// Merges the trees `ours` and `theirs` against `base`, an empty tree
+    /// when `None`, and writes the result, conflicts and all.
+    pub fn merge_trees(&self, base: Option<&Id>, ours: &Id, theirs: &Id, opts: &MergeOptions) -> Result<TreeMerge> {
+        let merger = TreeMerger {
+            repo: self,
+            opts,
+            result: BTreeMap::new(),
+            conflicts: vec![],
+            entries: vec![],
+            messages: vec![],
+        };
+        merger.merge(base, ours, theirs)
+    }
+
+    /// Merges two commits the way `git merge` does. Several merge bases
+    /// are first merged into a virtual one, and the base label names the
+    /// merge base.
+    pub fn merge_commits(&self, ours: &Id, theirs: &Id, opts: &MergeOptions) -> Result<TreeMerge> {
+        let bases = self.merge_bases(ours, std::slice::from_ref(theirs))?;
+        if bases.is_empty() && !opts.allow_unrelated {
+            bail!("refusing to merge unrelated histories");
+        }
+        self.merge_commits_with_bases(&bases, ours, theirs, opts)
+    }
+
+    /// Like `merge_commits`, with the merge bases given.
+    pub fn merge_commits_with_bases(&self, bases: &[Id], ours: &Id, theirs: &Id,
+                                    opts: &MergeOptions) -> Result<TreeMerge> {
+        let mut opts = opts.clone();
+        opts.labels.base = match bases {
+            [base] => base.to_string()[..7].to_owned(),
+            _ => "merged common ancestors".to_owned(),
+        };
+        let base = self.virtual_base(bases, &opts)?;
+        let ours = self.peel_to_commit(ours)?;
+        let theirs = self.peel_to_commit(theirs)?;
+        self.merge_trees(base.as_ref(), ours.tree(), theirs.tree(), &opts)
+    }
+
+    /// The tree of the merge base, or of the merge of all of them, oldest
+    /// first, conflicts left in.
+    fn virtual_base(&self, bases: &[Id], opts: &MergeOptions) -> Result<Option<Id>> {
+        let mut bases: Vec<Id> = bases.to_vec();
+        bases.reverse();
+        let mut tree = match bases.first() {
+            Some(first) => self.peel_to_commit(first)?.tree().clone(),
+            None => return Ok(None),
+        };
+        let mut opts = opts.clone();
+        opts.labels = Labels {
+            ours: "Temporary merge branch 1".to_owned(),
+            base: "merged common ancestors".to_owned(),
+            theirs: "Temporary merge branch 2".to_owned(),
+        };
+        for i in 1..bases.len() {
+            let inner_bases = self.merge_bases(&bases[i], &bases[..i])?;
+            let inner = self.virtual_base(&inner_bases, &opts)?;
+            let next = self.peel_to_commit(&bases[i])?;
+            tree = self.merge_trees(inner.as_ref(), &tree, next.tree(), &opts)?.tree;
+        }
+        Ok(Some(tree))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::MemFs;
+
+    fn labels() -> Labels {
+        Labels { ours: "ours".to_owned(), base: "base".to_owned(), theirs: "theirs".to_owned() }
+    }
+
+    #[test]
+    fn test_merge_file() {
+        let base = b"a\nb\nc\nd\ne\n";
+        let clean = merge_file(base, b"a\nB\nc\nd\ne\n", b"a\nb\nc\nd\nE\n", &labels(), ConflictStyle::Merge);
+        assert_eq!((clean.content.as_slice(), clean.conflicts), (&b"a\nB\nc\nd\nE\n"[..], 0));
+
+        let ours = b"a\nx\nB1\ny\nc\nd\ne\n";
+        let theirs = b"a\nx\nB2\ny\nc\nd\ne";
+        let merged = merge_file(base, ours, theirs, &labels(), ConflictStyle::Merge);
+        assert_eq!(merged.conflicts, 1);
+        assert_eq!(String::from_utf8(merged.content).unwrap(),
+                   "a\nx\n<<<<<<< ours\nB1\n=======\nB2\n>>>>>>> theirs\ny\nc\nd\ne");
+
+        let merged = merge_file(base, ours, b"a\nx\nB2\ny\nc\nd\ne\n", &labels(), ConflictStyle::Diff3);
+        assert_eq!(String::from_utf8(merged.content).unwrap(),
+                   "a\n<<<<<<< ours\nx\nB1\ny\n||||||| base\nb\n=======\nx\nB2\ny\n>>>>>>> theirs\nc\nd\ne\n");
+        let merged = merge_file(base, ours, b"a\nx\nB2\ny\nc\nd\ne\n", &labels(), ConflictStyle::Zdiff3);
+        assert_eq!(String::from_utf8(merged.content).unwrap(),
+                   "a\nx\n<<<<<<< ours\nB1\n||||||| base\nb\n=======\nB2\n>>>>>>> theirs\ny\nc\nd\ne\n");
+    }
+
+    fn blob(repo: &FileRepository<MemFs>, content: &str) -> Entry {
+        Entry { mode: FileMode::FILE, id: repo.write_object(ObjectType::BLOB, content.as_bytes()).unwrap() }
+    }
+
+    fn tree(repo: &FileRepository<MemFs>, files: &[(&str, &Entry)]) -> Id {
+        let merger = TreeMerger {
+            repo,
+            opts: &MergeOptions::default(),
+            result: files.iter().map(|(path, entry)| (path.to_string(), ((*entry).clone(), Origin::Both))).collect(),
+            conflicts: vec![],
+            entries: vec![],
+            messages: vec![],
+        };
+        merger.write().unwrap()
+    }
+
+    #[test]
+    fn test_merge_trees() {
+        let repo = FileRepository::<MemFs>::default();
+        let long = "one\ntwo\nthree\nfour\nfive\nsix\nseven\neight\n";
+        let base = tree(&repo, &[
+            ("bin", &blob(&repo, "\0base\n")),
+            ("f", &blob(&repo, "a\nb\nc\n")),
+            ("dir/r", &blob(&repo, long)),
+            ("gone", &blob(&repo, "x\n")),
+            ("same/s", &blob(&repo, "s\n")),
+        ]);
+        let ours = tree(&repo, &[
+            ("bin", &blob(&repo, "\0ours\n")),
+            ("f", &blob(&repo, "A\nb\nc\n")),
+            ("dir/r", &blob(&repo, &long.replace("eight", "EIGHT"))),
+            ("added", &blob(&repo, "ours\n")),
+            ("same/s", &blob(&repo, "s\n")),
+        ]);
+        let theirs = tree(&repo, &[
+            ("bin", &blob(&repo, "\0theirs\n")),
+            ("f", &blob(&repo, "a\nb\nC\n")),
+            ("moved/r2", &blob(&repo, &long.replace("one", "ONE"))),
+            ("gone", &blob(&repo, "y\n")),
+            ("added", &blob(&repo, "theirs\n")),
+            ("same/s", &blob(&repo, "s\n")),
+        ]);
+        let merge = repo.merge_trees(Some(&base), &ours, &theirs, &MergeOptions::default()).unwrap();
+        let kinds: Vec<(ConflictKind, &str)> = merge.conflicts.iter().map(|c| (c.kind, c.path.as_str())).collect();
+        assert_eq!(kinds, [(ConflictKind::AddAdd, "added"), (ConflictKind::Content, "bin"), (ConflictKind::ModifyDelete, "gone")]);
+        assert_eq!(merge.messages, [
+            "Auto-merging added",
+            "CONFLICT (add/add): Merge conflict in added",
+            "Auto-merging bin",
+            "warning: Cannot merge binary files: bin (ours vs. theirs)",
+            "CONFLICT (content): Merge conflict in bin",
+            "Auto-merging f",
+            "CONFLICT (modify/delete): gone deleted in ours and modified in theirs.  Version theirs of gone left in tree.",
+            "Auto-merging moved/r2",
+        ]);
+        let stages: Vec<(&str, u8)> = merge.entries.iter().map(|e| (e.path.as_str(), e.stage)).collect();
+        assert_eq!(stages, [("added", 2), ("added", 3), ("bin", 1), ("bin", 2), ("bin", 3), ("gone", 1), ("gone", 3)]);
+
+        let expected = tree(&repo, &[
+            ("bin", &blob(&repo, "\0ours\n")),
+            ("f", &blob(&repo, "A\nb\nC\n")),
+            ("moved/r2", &blob(&repo, &long.replace("one", "ONE").replace("eight", "EIGHT"))),
+            ("gone", &blob(&repo, "y\n")),
+            ("added", &blob(&repo, "<<<<<<< ours\nours\n=======\ntheirs\n>>>>>>> theirs\n")),
+            ("same/s", &blob(&repo, "s\n")),
+        ]);
+        assert_eq!(merge.tree, expected);
+    }
+}
@@ -0,0 +1,271 @@
+use std::collections::{BinaryHeap, HashMap};
+use std::cmp::Reverse;
+use crate::errors::*;
+use crate::fs::FileSystem;
+use crate::fs::commit_graph::{CommitGraph, GENERATION_INFINITY};
+use crate::model::id::Id;
+use crate::model::repository::{FileRepository, Repository};
+
+const PARENT1: u8 = 1;
+const PARENT2: u8 = 2;
+const STALE: u8 = 4;
+const RESULT: u8 = 8;
+
+/// What the walk needs of a commit.
+struct Node {
+    parents: Vec<Id>,
+    generation: u32,
+    date: i64,
+}
+
+/// Walks commits for merge-base queries, reading parents, dates and
+/// generation numbers from the commit-graph when there is one and from
+/// the commits otherwise.
+struct Walker<'a, FS: FileSystem> {
+    repo: &'a FileRepository<FS>,
+    graph: Option<CommitGraph>,
+    nodes: HashMap<Id, Node>,
+}
+
+impl<'a, FS: FileSystem> Walker<'a, FS> {
+    fn new(repo: &'a FileRepository<FS>) -> Self {
+        // a broken graph only costs speed
+        let graph = CommitGraph::open(&repo.fs, repo.object_dir(), repo.object_format()).ok().flatten();
+        Walker { repo, graph, nodes: HashMap::new() }
+    }
+
+    fn node(&mut self, id: &Id) -> Result<&Node> {
+        if !self.nodes.contains_key(id) {
+            let node = match self.graph.as_ref().and_then(|graph| Some((graph, graph.lookup(id)?))) {
+                Some((graph, commit)) => Node {
+                    parents: commit.parents.iter().map(|p| graph.id(*p).clone()).collect(),
+                    generation: commit.generation,
+                    date: commit.commit_time as i64,
+                },
+                None => {
+                    let commit = self.repo.find_commit(id)?;
+                    Node {
+                        parents: commit.parent().to_vec(),
+                        generation: GENERATION_INFINITY,
+                        date: commit.committer().as_ref().map_or(0, |c| c.date.timestamp()),
+                    }
+                }
+            };
+            self.nodes.insert(id.clone(), node);
+        }
+        Ok(&self.nodes[id])
+    }
+
+    fn generation(&mut self, id: &Id) -> Result<u32> {
+        Ok(self.node(id)?.generation)
+    }
+
+    /// Paints everything reachable from `one` with PARENT1 and from `twos`
+    /// with PARENT2, newest first, and returns the commits painted with
+    /// both that are not reachable from another such commit found earlier.
+    /// Commits below `min_generation` are not visited.
+    fn paint_down_to_common(&mut self, one: &Id, twos: &[Id], min_generation: u32,
+                            flags: &mut HashMap<Id, u8>) -> Result<Vec<Id>> {
+        // newest first; ties go to the commit queued first
+        let mut queue = BinaryHeap::new();
+        let mut queued: Vec<Id> = vec![];
+        let push = |walker: &mut Self, queue: &mut BinaryHeap<_>, queued: &mut Vec<Id>, id: &Id| -> Result<()> {
+            let node = walker.node(id)?;
+            queue.push((node.generation, node.date, Reverse(queued.len())));
+            queued.push(id.clone());
+            Ok(())
+        };
+        *flags.entry(one.clone()).or_default() |= PARENT1;
+        push(self, &mut queue, &mut queued, one)?;
+        for two in twos {
+            *flags.entry(two.clone()).or_default() |= PARENT2;
+            push(self, &mut queue, &mut queued, two)?;
+        }
+        let mut result = vec![];
+        while queue.iter().any(|(_, _, Reverse(n))| flags[&queued[*n]] & STALE == 0) {
+            let (generation, _, Reverse(n)) = queue.pop().unwrap();
+            let id = queued[n].clone();
+            if generation < min_generation {
+                break;
+            }
+            let mut painted = flags[&id] & (PARENT1 | PARENT2 | STALE);
+            if painted == PARENT1 | PARENT2 {
+                if flags[&id] & RESULT == 0 {
+                    *flags.get_mut(&id).unwrap() |= RESULT;
+                    result.push(id.clone());
+                }
+                // everything below is reachable from this one
+                painted |= STALE;
+            }
+            let parents = self.node(&id)?.parents.clone();
+            for parent in parents {
+                let entry = flags.entry(parent.clone()).or_default();
+                if *entry & painted == painted {
+                    continue;
+                }
+                *entry |= painted;
+                push(self, &mut queue, &mut queued, &parent)?;
+            }
+        }
+        Ok(result)
+    }
+
+    fn merge_bases_many(&mut self, one: &Id, twos: &[Id]) -> Result<Vec<Id>> {
+        if twos.contains(one) {
+            return Ok(vec![one.clone()]);
+        }
+        let mut flags = HashMap::new();
+        let found = self.paint_down_to_common(one, twos, 0, &mut flags)?;
+        let bases: Vec<Id> = found.into_iter().filter(|id| flags[id] & STALE == 0).collect();
+        let mut bases = self.remove_redundant(&bases)?;
+        self.sort_by_date(&mut bases)?;
+        Ok(bases)
+    }
+
+    /// Drops the commits reachable from another of `commits`, keeping the
+    /// order of the rest.
+    fn remove_redundant(&mut self, commits: &[Id]) -> Result<Vec<Id>> {
+        let mut redundant = vec![false; commits.len()];
+        for i in 0..commits.len() {
+            if redundant[i] {
+                continue;
+            }
+            let others: Vec<usize> = (0..commits.len())
+                .filter(|j| *j != i && !redundant[*j])
+                .collect();
+            let work: Vec<Id> = others.iter().map(|j| commits[*j].clone()).collect();
+            let mut min_generation = self.generation(&commits[i])?;
+            for id in &work {
+                min_generation = min_generation.min(self.generation(id)?);
+            }
+            let mut flags = HashMap::new();
+            self.paint_down_to_common(&commits[i], &work, cutoff(min_generation), &mut flags)?;
+            if flags[&commits[i]] & PARENT2 != 0 {
+                redundant[i] = true;
+            }
+            for (j, id) in others.iter().zip(&work) {
+                if flags[id] & PARENT1 != 0 {
+                    redundant[*j] = true;
+                }
+            }
+        }
+        Ok(commits.iter().zip(redundant).filter(|(_, r)| !r).map(|(id, _)| id.clone()).collect())
+    }
+
+    fn sort_by_date(&mut self, commits: &mut Vec<Id>) -> Result<()> {
+        let mut dated = Vec::with_capacity(commits.len());
+        for id in commits.drain(..) {
+            dated.push((self.node(&id)?.date, id));
+        }
+        // stable, so equal dates keep the walk's order
+        dated.sort_by_key(|(date, _)| Reverse(*date));
+        commits.extend(dated.into_iter().map(|(_, id)| id));
+        Ok(())
+    }
+
+    fn is_ancestor(&mut self, ancestor: &Id, descendant: &Id) -> Result<bool> {
+        if ancestor == descendant {
+            return Ok(true);
+        }
+        let min_generation = self.generation(ancestor)?;
+        if min_generation != GENERATION_INFINITY && self.generation(descendant)? < min_generation {
+            return Ok(false);
+        }
+        let mut flags = HashMap::new();
+        self.paint_down_to_common(ancestor, std::slice::from_ref(descendant), cutoff(min_generation), &mut flags)?;
+        Ok(flags[ancestor] & PARENT2 != 0)
+    }
+}
+
+/// Walking can stop below the lowest generation of interest, unless that
+/// commit is missing from the graph: its parents could be anywhere.
+fn cutoff(min_generation: u32) -> u32 {
+    if min_generation == GENERATION_INFINITY { 0 } else { min_generation }
+}
+
+impl<FS: FileSystem> FileRepository<FS> {
+    /// The best common ancestor of `a` and `b`, `None` when their
+    /// histories are unrelated.
+    pub fn merge_base(&self, a: &Id, b: &Id) -> Result<Option<Id>> {
+        Ok(self.merge_bases(a, std::slice::from_ref(b))?.into_iter().next())
+    }
+
+    /// All best common ancestors of `one` and a hypothetical merge of
+    /// `twos`, newest first, as `git merge-base --all`.
+    pub fn merge_bases(&self, one: &Id, twos: &[Id]) -> Result<Vec<Id>> {
+        Walker::new(self).merge_bases_many(one, twos)
+    }
+
+    /// The best common ancestors of all of `commits`, for an octopus merge.
+    pub fn octopus_merge_bases(&self, commits: &[Id]) -> Result<Vec<Id>> {
+        let mut walker = Walker::new(self);
+        let mut bases: Vec<Id> = commits.iter().take(1).cloned().collect();
+        for commit in commits.iter().skip(1) {
+            let mut next = vec![];
+            for base in &bases {
+                for found in walker.merge_bases_many(base, std::slice::from_ref(commit))? {
+                    if !next.contains(&found) {
+                        next.push(found);
+                    }
+                }
+            }
+            bases = next;
+        }
+        Ok(bases)
+    }
+
+    /// The commits of `commits` that no other one can reach, in their
+    /// original order.
+    pub fn independent(&self, commits: &[Id]) -> Result<Vec<Id>> {
+        let mut unique: Vec<Id> = vec![];
+        for commit in commits {
+            if !unique.contains(commit) {
+                unique.push(commit.clone());
+            }
+        }
+        Walker::new(self).remove_redundant(&unique)
+    }
+
+    /// Whether `ancestor` can be reached from `descendant`, so that moving
+    /// a branch from one to the other is a fast-forward.
+    pub fn is_ancestor(&self, ancestor: &Id, descendant: &Id) -> Result<bool> {
+        Walker::new(self).is_ancestor(ancestor, descendant)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::MemFs;
+
+    /// A commit named `name` in all its digits, made at that time.
+    fn add_commit(repo: &mut FileRepository<MemFs>, name: u8, parents: &[&Id]) -> Id {
+        repo.add_commit(Some(&format!("{:02x}", name).repeat(20)), None, parents, name as i64, "msg")
+    }
+
+    #[test]
+    fn test_merge_bases_without_graph() {
+        let mut repo = FileRepository::<MemFs>::default();
+        // 1 - 2 - 4 - 6
+        //   \   X
+        //    3 - 5
+        let c1 = add_commit(&mut repo, 1, &[]);
+        let c2 = add_commit(&mut repo, 2, &[&c1]);
+        let c3 = add_commit(&mut repo, 3, &[&c1]);
+        let c4 = add_commit(&mut repo, 4, &[&c2, &c3]);
+        let c5 = add_commit(&mut repo, 5, &[&c3, &c2]);
+        let c6 = add_commit(&mut repo, 6, &[&c4]);
+        let c7 = add_commit(&mut repo, 7, &[]);
+
+        assert_eq!(repo.merge_bases(&c6, std::slice::from_ref(&c5)).unwrap(), [c3.clone(), c2.clone()]);
+        assert_eq!(repo.merge_base(&c2, &c3).unwrap(), Some(c1.clone()));
+        assert_eq!(repo.merge_base(&c6, &c2).unwrap(), Some(c2.clone()));
+        assert_eq!(repo.merge_base(&c6, &c7).unwrap(), None);
+        assert_eq!(repo.independent(&[c2.clone(), c6.clone(), c5.clone(), c1.clone(), c6.clone()]).unwrap(),
+                   [c6.clone(), c5.clone()]);
+        assert!(repo.is_ancestor(&c1, &c6).unwrap());
+        assert!(repo.is_ancestor(&c3, &c4).unwrap());
+        assert!(!repo.is_ancestor(&c5, &c6).unwrap());
+        assert_eq!(repo.octopus_merge_bases(&[c4, c5, c2.clone()]).unwrap(), [c1, c2]);
+    }
+}
@@ -5,6 +5,30 @@ pub mod commit;
 pub mod blob;
 pub mod tree;
 pub mod tag;
+pub mod discovery;
+pub mod init;
+pub mod refs;
+pub mod revision;
+pub mod date;
+pub mod fsck;
+pub mod signature;
+pub mod merge_base;
+pub mod diff;
+pub mod merge;
+pub mod index;
+pub mod sparse;
+pub mod checkout;
+pub mod attributes;
+pub mod archive;
+pub mod rev_walk;
+pub mod blame;
+pub mod pretty;
+pub mod patch;
+pub mod ref_filter;
+pub mod describe;
+pub mod name_rev;
+pub mod grep;
+pub mod workers;
 
 #[cfg(test)]
 mod tests {
@@ -15,6 +39,7 @@ mod tests {
     use crate::model::object::*;
     use flate2::Compression;
     use std::io::Write;
+    use crate::errors::ErrorKind;
 
     #[test]
     fn test_lookup_prefix() {
@@ -41,4 +66,34 @@ mod tests {
         assert!(obj.is_some());
         assert_eq!(obj.unwrap().header(), &header);
     }
+
+    #[test]
+    fn test_object_errors() {
+        let mut repo = FileRepository::<MemFs>::default();
+        let commit = "1a2b000000000000000000000000000000000000";
+        let tree = "3c4d000000000000000000000000000000000000";
+        repo.add_loose(commit, ObjectType::COMMIT,
+                       b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\nauthor A <a@x> soon +0000\n\nmsg\n");
+        repo.add_loose(tree, ObjectType::TREE, b"100644 a\0short");
+
+        let id: Id = commit.parse().unwrap();
+        let err = repo.find_commit(&id).unwrap_err();
+        assert_eq!(err.to_string(), format!("commit {}: invalid author line at byte 46", commit));
+        match err.kind() {
+            ErrorKind::CorruptObject(ObjectType::COMMIT, found, 46, _) => assert_eq!(found, &id),
+            kind => panic!("unexpected {:?}", kind),
+        }
+
+        let id: Id = tree.parse().unwrap();
+        assert_eq!(repo.find_tree(&id).unwrap_err().to_string(),
+                   format!("tree {}: invalid tree entry at byte 0", tree));
+        assert_eq!(repo.find_commit(&id).unwrap_err().to_string(),
+                   format!("object {} is a tree, not a commit", tree));
+
+        let missing: Id = "5e6f000000000000000000000000000000000000".parse().unwrap();
+        match repo.read_object(&missing).unwrap_err().kind() {
+            ErrorKind::NotFound(found) => assert_eq!(found, &missing),
+            kind => panic!("unexpected {:?}", kind),
+        }
+    }
 }
@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use crate::errors::*;
+use crate::fs::FileSystem;
+use crate::model::id::Id;
+use crate::model::object::Object;
+use crate::model::refs::shorten_ref_name;
+use crate::model::repository::{FileRepository, Repository};
+use crate::model::rev_walk::commit_time;
+use crate::wildmatch::wildmatch;
+
+/// How much farther a name through a second or later parent counts, so
+/// that first-parent chains win.
+const MERGE_TRAVERSAL_WEIGHT: usize = 65535;
+
+/// Commits older than those being named by more than this are not
+/// walked, allowing for some clock skew.
+const CUTOFF_DATE_SLOP: i64 = 86400;
+
+#[derive(Debug, Clone, Default)]
+pub struct NameRevOptions {
+    /// Only name commits by tags.
+    pub tags: bool,
+    /// Shorten ref names as far as `refs/heads/`, `refs/tags/` and
+    /// `refs/remotes/`, rather than only `refs/heads/` and `refs/`.
+    pub abbreviate: bool,
+    /// Only refs matching one of these, if any.
+    pub refs: Vec<String>,
+    pub excludes: Vec<String>,
+}
+
+/// The name of a commit relative to a ref: the ref's tip, `generation`
+/// first parents back.
+#[derive(Debug, Clone)]
+struct RevName {
+    tip: String,
+    /// The tagger date of a tag, the commit date of anything else.
+    date: i64,
+    generation: usize,
+    distance: usize,
+    from_tag: bool,
+}
+
+impl RevName {
+    /// Whether a name with these properties should replace this one:
+    /// older tags win over newer ones, tags over branches, and then the
+    /// fewest hops.
+    fn is_worse_than(&self, date: i64, distance: usize, from_tag: bool) -> bool {
+        if from_tag && self.from_tag {
+            return self.date > date || (self.date == date && self.distance > distance);
+        }
+        if self.from_tag != from_tag {
+            return from_tag;
+        }
+        if self.distance != distance {
+            return self.distance > distance;
+        }
+        self.date > date
+    }
+
+    fn tip_without_deref(&self) -> &str {
+        self.tip.strip_suffix("^0").unwrap_or(&self.tip)
+    }
+
+    pub fn name(&self) -> String {
+        if self.generation == 0 {
+            self.tip.clone()
+        } else {
+            format!("{}~{}", self.tip_without_deref(), self.generation)
+        }
+    }
+}
+
+/// A ref to name commits after.
+struct Tip {
+    name: String,
+    commit: Id,
+    date: i64,
+    from_tag: bool,
+    /// An annotated tag, named `<tag>^0` at its commit.
+    deref: bool,
+}
+
+/// Names commits after the refs they can be reached from, like
+/// `tags/v1.2~3` or `master~2^2`.
+pub struct NameRev {
+    names: HashMap<Id, RevName>,
+}
+
+impl NameRev {
+    /// The name of `id`, if any ref reaches it.
+    pub fn name(&self, id: &Id) -> Option<String> {
+        self.names.get(id).map(RevName::name)
+    }
+}
+
+/// Where `pattern` matches `path` or a part of it after a `/`: `Some(false)`
+/// for all of it, `Some(true)` for a part only.
+fn subpath_matches(path: &str, pattern: &str) -> Option<bool> {
+    let starts = std::iter::once(0).chain(path.match_indices('/').map(|(n, _)| n + 1));
+    starts.into_iter().find(|&n| wildmatch(pattern, &path[n..], false, false)).map(|n| n > 0)
+}
+
+impl<FS: FileSystem> FileRepository<FS> {
+    fn name_rev_tips(&self, opts: &NameRevOptions) -> Result<Vec<Tip>> {
+        let mut tips = vec![];
+        for path in self.ref_names()? {
+            let from_tag = path.starts_with("refs/tags/");
+            if (opts.tags && !from_tag) || opts.excludes.iter().any(|p| subpath_matches(&path, p).is_some()) {
+                continue;
+            }
+            let mut abbreviate = opts.abbreviate;
+            if !opts.refs.is_empty() {
+                let matched: Vec<bool> = opts.refs.iter().filter_map(|p| subpath_matches(&path, p)).collect();
+                if matched.is_empty() {
+                    continue;
+                }
+                // a pattern for part of the name accepts names shortened
+                abbreviate |= matched.contains(&true);
+            }
+            let id = match self.resolve_ref(&path)? {
+                Some(id) => id,
+                None => continue,
+            };
+            let commit = match self.peel_to_commit(&id) {
+                Ok(commit) => commit,
+                Err(_) => continue,
+            };
+            let (date, deref) = match self.read_object(&id)? {
+                Object::Tag(tag) => (tag.tagger().as_ref().map_or(0, |tagger| tagger.date.timestamp()), true),
+                _ => (commit_time(&commit), false),
+            };
+            let name = if abbreviate {
+                shorten_ref_name(&path)
+            } else {
+                path.strip_prefix("refs/heads/").or_else(|| path.strip_prefix("refs/")).unwrap_or(&path)
+            };
+            tips.push(Tip { name: name.to_owned(), commit: commit.id().clone(), date, from_tag, deref });
+        }
+        // tags first, and older ones before newer
+        tips.sort_by(|a, b| b.from_tag.cmp(&a.from_tag).then(a.date.cmp(&b.date)));
+        Ok(tips)
+    }
+
+    /// Names every commit reachable from the refs `opts` selects, down to
+    /// a day before the oldest of `commits`, or every commit when none is
+    /// given.
+    pub fn name_rev(&self, commits: &[Id], opts: &NameRevOptions) -> Result<NameRev> {
+        let mut cutoff = i64::MIN;
+        for id in commits {
+            let time = commit_time(&self.find_commit(id)?);
+            cutoff = if cutoff == i64::MIN { time } else { cutoff.min(time) };
+        }
+        let cutoff = cutoff.saturating_sub(CUTOFF_DATE_SLOP);
+        let mut names: HashMap<Id, RevName> = HashMap::new();
+        for tip in self.name_rev_tips(opts)? {
+            if commit_time(&self.find_commit(&tip.commit)?) < cutoff {
+                continue;
+            }
+            let better = |names: &HashMap<Id, RevName>, id: &Id, distance: usize| {
+                names.get(id).is_none_or(|name| name.is_worse_than(tip.date, distance, tip.from_tag))
+            };
+            if !better(&names, &tip.commit, 0) {
+                continue;
+            }
+            let name = if tip.deref { format!("{}^0", tip.name) } else { tip.name.clone() };
+            names.insert(tip.commit.clone(), RevName { tip: name, date: tip.date, generation: 0, distance: 0, from_tag: tip.from_tag });
+            // depth first, with the first parent taken first
+            let mut stack = vec![tip.commit.clone()];
+            while let Some(id) = stack.pop() {
+                let name = names[&id].clone();
+                let mut queued = vec![];
+                for (n, parent) in self.find_commit(&id)?.parent().iter().enumerate() {
+                    if commit_time(&self.find_commit(parent)?) < cutoff {
+                        continue;
+                    }
+                    let (generation, distance, tip_name) = if n > 0 {
+                        let tip_name = match name.generation {
+                            0 => format!("{}^{}", name.tip_without_deref(), n + 1),
+                            generation => format!("{}~{}^{}", name.tip_without_deref(), generation, n + 1),
+                        };
+                        (0, name.distance + MERGE_TRAVERSAL_WEIGHT, tip_name)
+                    } else {
+                        (name.generation + 1, name.distance + 1, name.tip.clone())
+                    };
+                    if better(&names, parent, distance) {
+                        names.insert(parent.clone(), RevName { tip: tip_name, date: tip.date, generation, distance, from_tag: tip.from_tag });
+                        queued.push(parent.clone());
+                    }
+                }
+                stack.extend(queued.into_iter().rev());
+            }
+        }
+        Ok(NameRev { names })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_names_and_preference() {
+        let name = RevName { tip: "tags/v1^0".to_owned(), date: 100, generation: 3, distance: 3, from_tag: true };
+        assert_eq!(name.name(), "tags/v1~3");
+        assert_eq!(RevName { generation: 0, ..name.clone() }.name(), "tags/v1^0");
+        // an older tag wins even when farther away
+        assert!(name.is_worse_than(50, 10, true));
+        assert!(!name.is_worse_than(150, 1, true));
+        // tags win over branches
+        assert!(!name.is_worse_than(50, 1, false));
+        let branch = RevName { from_tag: false, ..name };
+        assert!(branch.is_worse_than(200, 10, true));
+        assert!(branch.is_worse_than(200, 2, false));
+    }
+}
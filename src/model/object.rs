@@ -10,6 +10,11 @@ use crate::errors::*;
 
 use std::fmt::{Display, Formatter};
 use crate::model::id::Id;
+use crate::model::blob::Blob;
+use crate::model::tree::Tree;
+use crate::model::commit::Commit;
+use crate::model::tag::Tag;
+use crate::model::repository::Repository;
 
 
 
@@ -25,6 +30,23 @@ pub enum ObjectType {
     TAG
 }
 
+impl ObjectType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ObjectType::BLOB => "blob",
+            ObjectType::COMMIT => "commit",
+            ObjectType::TREE => "tree",
+            ObjectType::TAG => "tag",
+        }
+    }
+}
+
+impl Display for ObjectType {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 fn usize_from_str_bytes(input: &[u8]) -> Result<usize> {
     let str = str::from_utf8(input)?;
     usize::from_str(str).chain_err(|| "bad length str")
@@ -65,12 +87,7 @@ pub struct ObjectHeader {
 impl From<ObjectHeader> for Vec<u8> {
     fn from(o: ObjectHeader) -> Self {
         let mut ret: Vec<u8> = vec![];
-        match o.object_type {
-            ObjectType::TAG => ret.extend_from_slice(b"tag"),
-            ObjectType::BLOB => ret.extend_from_slice(b"blob"),
-            ObjectType::COMMIT => ret.extend_from_slice(b"commit"),
-            ObjectType::TREE => ret.extend_from_slice(b"tree")
-        }
+        ret.extend_from_slice(o.object_type.as_str().as_bytes());
         ret.push(b' ');
         ret.extend(o.length.to_string().as_bytes());
         ret.push(0u8);
@@ -107,6 +124,86 @@ impl GitObject {
     pub fn id(&self) -> &Id{
         &self.id
     }
+
+    /// The error for content of this object that stops parsing at byte
+    /// `offset`.
+    pub fn corrupt(&self, offset: usize, reason: impl Into<String>) -> Error {
+        ErrorKind::CorruptObject(self.object_type(), self.id.clone(), offset, reason.into()).into()
+    }
+
+    /// The error for asking this object to be of another type.
+    pub fn not_a(&self, expected: ObjectType) -> Error {
+        ErrorKind::InvalidObjectType(self.id.clone(), expected, self.object_type()).into()
+    }
+}
+
+/// Where in `input` a nom parser gave up.
+pub(crate) fn error_offset(input: &[u8], err: &nom::Err<(&[u8], nom::error::ErrorKind)>) -> usize {
+    match err {
+        nom::Err::Error((rest, _)) | nom::Err::Failure((rest, _)) => input.len() - rest.len(),
+        nom::Err::Incomplete(_) => input.len(),
+    }
+}
+
+/// How commit and tag headers are checked.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParseMode {
+    /// Rejects misordered, duplicated or malformed headers, identities
+    /// without a date and non-UTF-8 messages without an `encoding` header.
+    Strict,
+    /// Accepts what mirrored third-party repositories contain, keeping
+    /// whatever it does not understand as extra headers.
+    Lenient,
+}
+
+/// A commit or tag header. Continuation lines, which start with a space,
+/// are joined to the value with newlines, as in `gpgsig` and `mergetag`.
+pub(crate) struct Header<'a> {
+    pub key: &'a [u8],
+    pub value: Vec<u8>,
+    /// Where the header starts in the object.
+    pub offset: usize,
+    /// Whether a space separated the key from the value.
+    pub spaced: bool,
+}
+
+impl<'a> Header<'a> {
+    pub(crate) fn name(&self) -> String {
+        String::from_utf8_lossy(self.key).into_owned()
+    }
+}
+
+/// Splits commit or tag content into its headers and the message after the
+/// blank line, if there is one. A continuation line with nothing to
+/// continue becomes a header with an empty key.
+pub(crate) fn split_headers(content: &[u8]) -> (Vec<Header<'_>>, Option<&[u8]>) {
+    let mut headers: Vec<Header> = vec![];
+    let mut pos = 0;
+    while pos < content.len() {
+        let end = content[pos..].iter().position(|b| *b == b'\n').map_or(content.len(), |n| pos + n);
+        let line = &content[pos..end];
+        let next = (end + 1).min(content.len());
+        if line.is_empty() {
+            return (headers, Some(&content[next..]));
+        }
+        match headers.last_mut() {
+            Some(last) if line[0] == b' ' => {
+                last.value.push(b'\n');
+                last.value.extend_from_slice(&line[1..]);
+            }
+            _ => {
+                let split = line.iter().position(|b| *b == b' ');
+                headers.push(Header {
+                    key: &line[..split.unwrap_or(line.len())],
+                    value: split.map(|n| line[n + 1..].to_vec()).unwrap_or_default(),
+                    offset: pos,
+                    spaced: split.is_some(),
+                });
+            }
+        }
+        pos = next;
+    }
+    (headers, None)
 }
 
 impl Display for GitObject {
@@ -115,6 +212,43 @@ impl Display for GitObject {
     }
 }
 
+/// A parsed object of any type.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Object {
+    Blob(Blob),
+    Tree(Tree),
+    Commit(Commit),
+    Tag(Tag),
+}
+
+impl Object {
+    pub fn from(repo: &dyn Repository, obj: &GitObject) -> Result<Self> {
+        Ok(match obj.object_type() {
+            ObjectType::BLOB => Object::Blob(Blob::from(repo, obj)?),
+            ObjectType::TREE => Object::Tree(Tree::from(repo, obj)?),
+            ObjectType::COMMIT => Object::Commit(Commit::from(repo, obj)?),
+            ObjectType::TAG => Object::Tag(Tag::from(repo, obj)?),
+        })
+    }
+
+    pub fn id(&self) -> &Id {
+        match self {
+            Object::Blob(blob) => blob.id(),
+            Object::Tree(tree) => tree.id(),
+            Object::Commit(commit) => commit.id(),
+            Object::Tag(tag) => tag.id(),
+        }
+    }
+
+    pub fn kind(&self) -> ObjectType {
+        match self {
+            Object::Blob(_) => ObjectType::BLOB,
+            Object::Tree(_) => ObjectType::TREE,
+            Object::Commit(_) => ObjectType::COMMIT,
+            Object::Tag(_) => ObjectType::TAG,
+        }
+    }
+}
 
 #[cfg(test)]
 mod tests {
@@ -130,4 +264,18 @@ mod tests {
         let expected_bytes: Vec<u8> = expected.into();
         assert_eq!(expected_bytes, header.as_bytes());
     }
+
+    #[test]
+    fn test_split_headers() {
+        let content = b"tree 1\ngpgsig -----BEGIN\n \n sig\nbare\n\nmessage\n";
+        let (headers, message) = split_headers(content);
+        let keys: Vec<_> = headers.iter().map(|h| (h.name(), h.value.clone(), h.offset, h.spaced)).collect();
+        assert_eq!(keys, vec![
+            ("tree".to_owned(), b"1".to_vec(), 0, true),
+            ("gpgsig".to_owned(), b"-----BEGIN\n\nsig".to_vec(), 7, true),
+            ("bare".to_owned(), vec![], 32, false),
+        ]);
+        assert_eq!(message, Some(&b"message\n"[..]));
+        assert_eq!(split_headers(b"tree 1\n").1, None);
+    }
 }
\ No newline at end of file
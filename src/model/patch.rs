@@ -0,0 +1,703 @@
+use std::io::Write;
+use crate::errors::*;
+use crate::fs::FileSystem;
+use crate::model::diff::{diff, lines, DiffFile, Edit, TreeChange};
+use crate::model::id::Id;
+use crate::model::repository::{FileRepository, Repository};
+use crate::model::tree::FileMode;
+
+/// Lines of context around each change in a patch.
+pub const CONTEXT: usize = 3;
+
+/// Git looks this far into a file for a NUL to call it binary.
+const BINARY_PROBE: usize = 8000;
+
+/// Function names in hunk headers are cut to this many bytes.
+const FUNCNAME_LEN: usize = 80;
+
+pub fn is_binary(content: &[u8]) -> bool {
+    content[..content.len().min(BINARY_PROBE)].contains(&0)
+}
+
+/// A changed file with both versions loaded, as patches and stats need it.
+#[derive(Debug, Clone)]
+pub struct FilePatch {
+    pub change: TreeChange,
+    /// The content before, empty for an added file.
+    pub old: Vec<u8>,
+    /// The content after, empty for a deleted file.
+    pub new: Vec<u8>,
+    pub binary: bool,
+    /// The line edits, empty for a binary file.
+    pub edits: Vec<Edit>,
+}
+
+impl FilePatch {
+    fn sides(&self) -> (Option<&DiffFile>, Option<&DiffFile>) {
+        match &self.change {
+            TreeChange::Added(new) => (None, Some(new)),
+            TreeChange::Deleted(old) => (Some(old), None),
+            TreeChange::Modified(old, new) | TreeChange::Renamed(old, new, _) => (Some(old), Some(new)),
+        }
+    }
+
+    /// Lines added and deleted; for a binary file, the sizes after and
+    /// before, which is what `--stat` shows for one.
+    pub fn counts(&self) -> (usize, usize) {
+        if self.binary {
+            return (self.new.len(), self.old.len());
+        }
+        self.edits.iter().fold((0, 0), |(added, deleted), edit| (added + edit.new.len(), deleted + edit.old.len()))
+    }
+
+    /// The path as `--stat` and `--numstat` name it, `old => new` for a
+    /// rename with the common leading directories and trailing part
+    /// factored out as in `dir/{a => b}/file`.
+    pub fn display_name(&self) -> String {
+        match &self.change {
+            TreeChange::Renamed(old, new, _) => rename_name(&old.path, &new.path),
+            change => change.path().to_owned(),
+        }
+    }
+}
+
+fn rename_name(old: &str, new: &str) -> String {
+    let (a, b) = (old.as_bytes(), new.as_bytes());
+    let mut prefix = 0;
+    for (i, (x, y)) in a.iter().zip(b).enumerate() {
+        if x != y {
+            break;
+        }
+        if *x == b'/' {
+            prefix = i + 1;
+        }
+    }
+    // walk back from the ends, which compare equal as the terminators,
+    // stopping short of the prefix but for its slash
+    let mut suffix = 0;
+    let stop = prefix as isize - if prefix > 0 { 1 } else { 0 };
+    let (mut i, mut j) = (a.len() as isize, b.len() as isize);
+    let at = |s: &[u8], n: isize| s.get(n as usize).copied().unwrap_or(0);
+    while stop <= i && stop <= j && at(a, i) == at(b, j) {
+        if at(a, i) == b'/' {
+            suffix = a.len() - i as usize;
+        }
+        i -= 1;
+        j -= 1;
+    }
+    let a_mid = a.len().saturating_sub(prefix + suffix);
+    let b_mid = b.len().saturating_sub(prefix + suffix);
+    if prefix + suffix == 0 {
+        return format!("{} => {}", old, new);
+    }
+    format!("{}{{{} => {}}}{}", &old[..prefix], &old[prefix..prefix + a_mid], &new[prefix..prefix + b_mid], &old[old.len() - suffix..])
+}
+
+fn hunk_range(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, count),
+    }
+}
+
+/// The last line before `before` that starts with a letter, `_` or `$`,
+/// the way git's default funcname pattern picks it.
+fn funcname(lines: &[&[u8]], before: usize) -> Option<String> {
+    lines[..before].iter().rev()
+        .find(|line| line.first().is_some_and(|&c| c.is_ascii_alphabetic() || c == b'_' || c == b'$'))
+        .map(|line| {
+            let line = &line[..line.len().min(FUNCNAME_LEN)];
+            let end = line.iter().rposition(|c| !c.is_ascii_whitespace()).map_or(0, |n| n + 1);
+            String::from_utf8_lossy(&line[..end]).into_owned()
+        })
+}
+
+/// Groups edits no more than twice the context apart into hunks.
+fn hunks(edits: &[Edit], context: usize) -> Vec<&[Edit]> {
+    let mut hunks = vec![];
+    let mut start = 0;
+    for n in 1..=edits.len() {
+        if n == edits.len() || edits[n].old.start - edits[n - 1].old.end > 2 * context {
+            hunks.push(&edits[start..n]);
+            start = n;
+        }
+    }
+    hunks
+}
+
+fn write_line(out: &mut dyn Write, prefix: u8, line: &[u8]) -> Result<()> {
+    out.write_all(&[prefix])?;
+    out.write_all(line)?;
+    if !line.ends_with(b"\n") {
+        out.write_all(b"\n\\ No newline at end of file\n")?;
+    }
+    Ok(())
+}
+
+/// Writes the unified diff of `old` and `new`, whose `edits` are given.
+fn write_hunks(out: &mut dyn Write, old: &[&[u8]], new: &[&[u8]], edits: &[Edit], context: usize) -> Result<()> {
+    for hunk in hunks(edits, context) {
+        let (first, last) = (&hunk[0], &hunk[hunk.len() - 1]);
+        let old_start = first.old.start.saturating_sub(context);
+        let new_start = first.new.start.saturating_sub(first.old.start - old_start);
+        let old_end = (last.old.end + context).min(old.len());
+        let new_end = last.new.end + (old_end - last.old.end);
+        write!(out, "@@ -{} +{} @@", hunk_range(old_start, old_end - old_start), hunk_range(new_start, new_end - new_start))?;
+        if let Some(name) = funcname(old, old_start) {
+            write!(out, " {}", name)?;
+        }
+        writeln!(out)?;
+        let mut at = old_start;
+        for edit in hunk {
+            for line in &old[at..edit.old.start] {
+                write_line(out, b' ', line)?;
+            }
+            for line in &old[edit.old.clone()] {
+                write_line(out, b'-', line)?;
+            }
+            for line in &new[edit.new.clone()] {
+                write_line(out, b'+', line)?;
+            }
+            at = edit.old.end;
+        }
+        for line in &old[at..old_end] {
+            write_line(out, b' ', line)?;
+        }
+    }
+    Ok(())
+}
+
+fn abbrev(id: &Id) -> String {
+    id.to_string()[..7].to_owned()
+}
+
+/// Writes `patches` as `git diff` does, with `context` lines around each
+/// change.
+pub fn write_patch(out: &mut dyn Write, patches: &[FilePatch], context: usize) -> Result<()> {
+    let null = "0".repeat(7);
+    for patch in patches {
+        let (old, new) = patch.sides();
+        let (old_path, new_path) = (old.or(new).map_or("", |f| f.path.as_str()), new.or(old).map_or("", |f| f.path.as_str()));
+        writeln!(out, "diff --git a/{} b/{}", old_path, new_path)?;
+        match (old, new) {
+            (None, Some(new)) => writeln!(out, "new file mode {:06o}", new.mode.bits())?,
+            (Some(old), None) => writeln!(out, "deleted file mode {:06o}", old.mode.bits())?,
+            (Some(old), Some(new)) if old.mode != new.mode => {
+                writeln!(out, "old mode {:06o}\nnew mode {:06o}", old.mode.bits(), new.mode.bits())?;
+            }
+            _ => {}
+        }
+        if let TreeChange::Renamed(old, new, score) = &patch.change {
+            writeln!(out, "similarity index {}%\nrename from {}\nrename to {}", score, old.path, new.path)?;
+        }
+        let ids = (old.map(|f| &f.id), new.map(|f| &f.id));
+        if ids.0 != ids.1 {
+            let name = |id: Option<&Id>| id.map_or_else(|| null.clone(), abbrev);
+            write!(out, "index {}..{}", name(ids.0), name(ids.1))?;
+            match (old, new) {
+                (Some(old), Some(new)) if old.mode == new.mode => writeln!(out, " {:06o}", old.mode.bits())?,
+                _ => writeln!(out)?,
+            }
+        }
+        if ids.0 == ids.1 {
+            continue;
+        }
+        let (a, b) = (old.map_or("/dev/null".to_owned(), |f| format!("a/{}", f.path)),
+                      new.map_or("/dev/null".to_owned(), |f| format!("b/{}", f.path)));
+        if patch.binary {
+            writeln!(out, "Binary files {} and {} differ", a, b)?;
+        } else if !patch.edits.is_empty() {
+            writeln!(out, "--- {}\n+++ {}", a, b)?;
+            write_hunks(out, &lines(&patch.old), &lines(&patch.new), &patch.edits, context)?;
+        }
+    }
+    Ok(())
+}
+
+fn decimal_width(n: usize) -> usize {
+    n.to_string().len()
+}
+
+/// `count` of `+` or `-` scaled so `max` fills `width`, at least one for
+/// any change.
+fn scale(count: usize, width: usize, max: usize) -> usize {
+    if count == 0 { 0 } else { 1 + count * (width - 1) / max }
+}
+
+/// ` 3 files changed, 1 insertion(+), 1 deletion(-)`, which leaves out
+/// the side that is zero unless both are.
+fn summary(patches: &[FilePatch]) -> String {
+    let (mut insertions, mut deletions) = (0, 0);
+    for patch in patches.iter().filter(|patch| !patch.binary) {
+        let (added, deleted) = patch.counts();
+        insertions += added;
+        deletions += deleted;
+    }
+    let plural = |n: usize| if n == 1 { "" } else { "s" };
+    let mut line = format!(" {} file{} changed", patches.len(), plural(patches.len()));
+    if insertions > 0 || deletions == 0 {
+        line.push_str(&format!(", {} insertion{}(+)", insertions, plural(insertions)));
+    }
+    if deletions > 0 || insertions == 0 {
+        line.push_str(&format!(", {} deletion{}(-)", deletions, plural(deletions)));
+    }
+    line
+}
+
+/// Writes the `--stat` graph for a terminal `width` columns wide.
+pub fn write_stat(out: &mut dyn Write, patches: &[FilePatch], width: usize) -> Result<()> {
+    if patches.is_empty() {
+        return Ok(());
+    }
+    let names: Vec<String> = patches.iter().map(FilePatch::display_name).collect();
+    let max_len = names.iter().map(|name| name.chars().count()).max().unwrap_or(0);
+    let (mut max_change, mut number_width, mut bin_width) = (0, 0, 0);
+    for patch in patches {
+        let (added, deleted) = patch.counts();
+        if patch.binary {
+            // "Bin XXX -> YYY bytes"
+            bin_width = bin_width.max(14 + decimal_width(added) + decimal_width(deleted));
+            number_width = 3;
+        } else {
+            max_change = max_change.max(added + deleted);
+        }
+    }
+    number_width = number_width.max(decimal_width(max_change));
+    let width = width.max(16 + 6 + number_width);
+    let mut graph_width = if max_change + 4 > bin_width { max_change } else { bin_width - 4 };
+    let mut name_width = max_len;
+    if name_width + number_width + 6 + graph_width > width {
+        if graph_width > (width * 3 / 8).saturating_sub(number_width + 6) {
+            graph_width = (width * 3 / 8).saturating_sub(number_width + 6).max(6);
+        }
+        if name_width > width - number_width - 6 - graph_width {
+            name_width = width - number_width - 6 - graph_width;
+        } else {
+            graph_width = width - number_width - 6 - name_width;
+        }
+    }
+
+    for (patch, name) in patches.iter().zip(&names) {
+        let (mut prefix, mut name) = ("", name.as_str());
+        let mut len = name_width;
+        if name.chars().count() > name_width {
+            // keep the end of the name, from a slash if there is one
+            prefix = "...";
+            len = len.saturating_sub(3);
+            let skip = name.chars().count() - len;
+            name = &name[name.char_indices().nth(skip).map_or(name.len(), |(n, _)| n)..];
+            if let Some(n) = name.find('/') {
+                name = &name[n..];
+            }
+        }
+        let (added, deleted) = patch.counts();
+        if patch.binary {
+            write!(out, " {}{:<len$} | {:>number_width$}", prefix, name, "Bin", len = len, number_width = number_width)?;
+            if added == 0 && deleted == 0 {
+                writeln!(out)?;
+            } else {
+                writeln!(out, " {} -> {} bytes", deleted, added)?;
+            }
+            continue;
+        }
+        let (mut plus, mut minus) = (added, deleted);
+        if graph_width <= max_change {
+            let mut total = scale(added + deleted, graph_width, max_change);
+            if total < 2 && added > 0 && deleted > 0 {
+                total = 2;
+            }
+            if added < deleted {
+                plus = scale(added, graph_width, max_change);
+                minus = total - plus;
+            } else {
+                minus = scale(deleted, graph_width, max_change);
+                plus = total - minus;
+            }
+        }
+        writeln!(out, " {}{:<len$} | {:>number_width$}{}{}{}", prefix, name, added + deleted,
+                 if added + deleted > 0 { " " } else { "" }, "+".repeat(plus), "-".repeat(minus),
+                 len = len, number_width = number_width)?;
+    }
+    writeln!(out, "{}", summary(patches))?;
+    Ok(())
+}
+
+pub fn write_numstat(out: &mut dyn Write, patches: &[FilePatch]) -> Result<()> {
+    for patch in patches {
+        if patch.binary {
+            writeln!(out, "-\t-\t{}", patch.display_name())?;
+        } else {
+            let (added, deleted) = patch.counts();
+            writeln!(out, "{}\t{}\t{}", added, deleted, patch.display_name())?;
+        }
+    }
+    Ok(())
+}
+
+pub fn write_shortstat(out: &mut dyn Write, patches: &[FilePatch]) -> Result<()> {
+    if !patches.is_empty() {
+        writeln!(out, "{}", summary(patches))?;
+    }
+    Ok(())
+}
+
+/// A file a merge changed from every parent, for `--cc` and `-c`.
+#[derive(Debug, Clone)]
+pub struct CombinedFile {
+    pub path: String,
+    /// The file in each parent, `None` where it is missing.
+    pub parents: Vec<Option<(FileMode, Id)>>,
+    pub result: Option<(FileMode, Id)>,
+}
+
+/// One line of the result with the parents it is new to, and the lines
+/// before it that are gone, each with the parents that had it.
+#[derive(Debug, Clone, Default)]
+struct CombinedLine {
+    new_to: u64,
+    lost: Vec<(Vec<u8>, u64)>,
+    /// The line number of the next line of each parent, from 1.
+    parent_line: Vec<usize>,
+    shown: bool,
+    /// Shown as leading context, without the lost lines before it.
+    no_lost: bool,
+}
+
+impl CombinedLine {
+    fn interesting(&self) -> bool {
+        self.new_to != 0 || !self.lost.is_empty()
+    }
+}
+
+/// Merges the lines lost from `parent` into those lost from earlier
+/// parents, sharing the ones they have in common.
+fn coalesce(lost: &mut Vec<(Vec<u8>, u64)>, from_parent: Vec<Vec<u8>>, parent: usize) {
+    let existing: Vec<&[u8]> = lost.iter().map(|(line, _)| line.as_slice()).collect();
+    let new: Vec<&[u8]> = from_parent.iter().map(Vec::as_slice).collect();
+    let edits = diff(&existing, &new);
+    let end = Edit { old: existing.len()..existing.len(), new: new.len()..new.len() };
+    let mut merged = vec![];
+    let mut i = 0;
+    for edit in edits.iter().chain(std::iter::once(&end)) {
+        merged.extend(lost[i..edit.old.start].iter().map(|(line, set)| (line.clone(), set | 1 << parent)));
+        merged.extend(lost[edit.old.clone()].iter().cloned());
+        merged.extend(from_parent[edit.new.clone()].iter().map(|line| (line.clone(), 1 << parent)));
+        i = edit.old.end;
+    }
+    *lost = merged;
+}
+
+impl<FS: FileSystem> FileRepository<FS> {
+    fn blob_or_empty(&self, file: Option<&(FileMode, Id)>) -> Result<Vec<u8>> {
+        match file {
+            Some((FileMode::SUBMODULE, id)) => Ok(format!("Subproject commit {}\n", id).into_bytes()),
+            Some((_, id)) => Repository::read_content(self, &self.find_object(id)?),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Loads both sides of each change and diffs their lines.
+    pub fn file_patches(&self, changes: Vec<TreeChange>) -> Result<Vec<FilePatch>> {
+        let mut patches = vec![];
+        let side = |file: Option<&DiffFile>| file.map(|f| (f.mode.clone(), f.id.clone()));
+        for change in changes {
+            let mut patch = FilePatch { change, old: vec![], new: vec![], binary: false, edits: vec![] };
+            let (old, new) = patch.sides();
+            let (old, new) = (side(old), side(new));
+            patch.old = self.blob_or_empty(old.as_ref())?;
+            patch.new = self.blob_or_empty(new.as_ref())?;
+            patch.binary = is_binary(&patch.old) || is_binary(&patch.new);
+            if !patch.binary {
+                patch.edits = diff(&lines(&patch.old), &lines(&patch.new));
+            }
+            patches.push(patch);
+        }
+        Ok(patches)
+    }
+
+    /// The files `tree` has different from every one of the `parents`
+    /// trees, in path order.
+    pub fn combined_changes(&self, parents: &[Id], tree: &Id) -> Result<Vec<CombinedFile>> {
+        let mut files: Option<Vec<CombinedFile>> = None;
+        for (n, parent) in parents.iter().enumerate() {
+            let changes = self.diff_trees(Some(parent), Some(tree), None)?;
+            let side = |file: &DiffFile| Some((file.mode.clone(), file.id.clone()));
+            let changed = changes.iter().map(|change| {
+                let (old, new) = match change {
+                    TreeChange::Added(new) => (None, side(new)),
+                    TreeChange::Deleted(old) => (side(old), None),
+                    TreeChange::Modified(old, new) | TreeChange::Renamed(old, new, _) => (side(old), side(new)),
+                };
+                (change.path().to_owned(), old, new)
+            });
+            files = Some(match files {
+                None => changed.map(|(path, old, new)| {
+                    let mut parents = vec![None; parents.len()];
+                    parents[0] = old;
+                    CombinedFile { path, parents, result: new }
+                }).collect(),
+                Some(files) => {
+                    let mut changed: Vec<_> = changed.collect();
+                    files.into_iter().filter_map(|mut file| {
+                        let at = changed.iter().position(|(path, _, _)| *path == file.path)?;
+                        file.parents[n] = changed.swap_remove(at).1;
+                        Some(file)
+                    }).collect()
+                }
+            });
+        }
+        Ok(files.unwrap_or_default())
+    }
+}
+
+/// Finds the hunks of a combined diff, marking the lines to show. With
+/// `dense`, as for `--cc`, hunks where the result took one parent's
+/// version outright are dropped.
+fn mark_hunks(lines: &mut [CombinedLine], parents: usize, dense: bool) {
+    let all = (1u64 << parents) - 1;
+    let count = lines.len() - 1;
+    for line in lines.iter_mut() {
+        line.shown = line.interesting();
+    }
+    // a hunk's last line counts less when only lines lost before it make
+    // it interesting, since it shows as context after them anyway
+    let tail = |lines: &[CombinedLine], begin: usize, end: usize| {
+        if begin < end && lines[end - 1].new_to & all == 0 { end - 1 } else { end }
+    };
+    if dense {
+        let mut i = 0;
+        while i <= count {
+            while i <= count && !lines[i].shown {
+                i += 1;
+            }
+            if i > count {
+                break;
+            }
+            let begin = i;
+            let mut j = i + 1;
+            while j <= count {
+                if !lines[j].shown {
+                    // carry on if another shown line is within context
+                    let ahead = (tail(lines, begin, j) + CONTEXT).min(count + 1);
+                    match (j..ahead).rev().find(|&k| lines[k].shown) {
+                        Some(k) => j = k,
+                        None => break,
+                    }
+                }
+                j += 1;
+            }
+            let end = j;
+            let mut same = 0;
+            let mut several = false;
+            'lines: for line in &lines[begin..end] {
+                for set in std::iter::once(line.new_to).filter(|&set| set != 0).chain(line.lost.iter().map(|(_, set)| *set)) {
+                    if same == 0 {
+                        same = set;
+                    } else if same != set {
+                        several = true;
+                        break 'lines;
+                    }
+                }
+            }
+            if !several && same != all {
+                for line in &mut lines[begin..end] {
+                    line.shown = false;
+                }
+            }
+            i = end;
+        }
+    }
+
+    // paint context around the shown lines, joining hunks close together
+    let next = |lines: &[CombinedLine], from: usize, shown: bool| (from..=count).find(|&k| lines[k].shown == shown).unwrap_or(count + 1);
+    let mut i = next(lines, 0, true);
+    while i <= count {
+        for line in &mut lines[i.saturating_sub(CONTEXT)..i] {
+            if !line.shown {
+                line.no_lost = true;
+            }
+            line.shown = true;
+        }
+        loop {
+            let j = next(lines, i, false);
+            if j > count {
+                return;
+            }
+            let k = next(lines, j, true);
+            let j = tail(lines, i, j);
+            if k < j + CONTEXT {
+                for line in &mut lines[j..k] {
+                    line.shown = true;
+                }
+                i = k;
+                continue;
+            }
+            for line in &mut lines[j..(j + CONTEXT).min(count + 1)] {
+                line.shown = true;
+            }
+            i = k;
+            break;
+        }
+    }
+}
+
+/// Writes a combined diff of each file, the way `git show` shows a merge:
+/// a column per parent saying whether a line is new to it (`+`) or was
+/// removed from it (`-`).
+pub fn write_combined<FS: FileSystem>(out: &mut dyn Write, repo: &FileRepository<FS>, files: &[CombinedFile], dense: bool) -> Result<()> {
+    for file in files {
+        let parents = file.parents.len();
+        let result = repo.blob_or_empty(file.result.as_ref())?;
+        let contents = file.parents.iter().map(|parent| repo.blob_or_empty(parent.as_ref())).collect::<Result<Vec<_>>>()?;
+        let binary = is_binary(&result) || contents.iter().any(|content| is_binary(content));
+        let result_lines = lines(&result);
+        let mut combined = vec![CombinedLine { parent_line: vec![1; parents], ..CombinedLine::default() }; result_lines.len() + 1];
+        if !binary {
+            for (n, content) in contents.iter().enumerate() {
+                let parent_lines = lines(content);
+                let mut lost_at: Vec<Vec<Vec<u8>>> = vec![vec![]; result_lines.len() + 1];
+                for edit in diff(&parent_lines, &result_lines) {
+                    for line in &mut combined[edit.new.clone()] {
+                        line.new_to |= 1 << n;
+                    }
+                    lost_at[edit.new.start].extend(parent_lines[edit.old.clone()].iter().map(|line| line.to_vec()));
+                }
+                let mut number = 1;
+                for (at, lost) in lost_at.into_iter().enumerate() {
+                    combined[at].parent_line[n] = number;
+                    if !lost.is_empty() {
+                        coalesce(&mut combined[at].lost, lost, n);
+                    }
+                    number += combined[at].lost.iter().filter(|(_, set)| set & 1 << n != 0).count();
+                    if at < result_lines.len() && combined[at].new_to & 1 << n == 0 {
+                        number += 1;
+                    }
+                }
+            }
+            mark_hunks(&mut combined, parents, dense);
+        }
+        let modes_differ = file.parents.iter().any(|parent| parent.as_ref().map(|p| &p.0) != file.result.as_ref().map(|r| &r.0));
+        if !binary && !modes_differ && !combined.iter().any(|line| line.shown) {
+            continue;
+        }
+
+        writeln!(out, "diff --{} {}", if dense { "cc" } else { "combined" }, file.path)?;
+        let abbrev_of = |side: &Option<(FileMode, Id)>| side.as_ref().map_or_else(|| "0".repeat(7), |(_, id)| abbrev(id));
+        let parent_ids: Vec<String> = file.parents.iter().map(abbrev_of).collect();
+        writeln!(out, "index {}..{}", parent_ids.join(","), abbrev_of(&file.result))?;
+        let mode_of = |side: &Option<(FileMode, Id)>| side.as_ref().map_or(0, |(mode, _)| mode.bits());
+        if file.parents.iter().all(Option::is_none) {
+            writeln!(out, "new file mode {:06o}", mode_of(&file.result))?;
+        } else if modes_differ {
+            let modes: Vec<String> = file.parents.iter().map(|parent| format!("{:06o}", mode_of(parent))).collect();
+            writeln!(out, "{}mode {}..{:06o}", if file.result.is_none() { "deleted file " } else { "" }, modes.join(","), mode_of(&file.result))?;
+        }
+        let a = if file.parents.iter().all(Option::is_none) { "/dev/null".to_owned() } else { format!("a/{}", file.path) };
+        let b = if file.result.is_none() { "/dev/null".to_owned() } else { format!("b/{}", file.path) };
+        if binary {
+            writeln!(out, "Binary files differ")?;
+            continue;
+        }
+        writeln!(out, "--- {}\n+++ {}", a, b)?;
+        write_combined_hunks(out, &combined, &result_lines, parents)?;
+    }
+    Ok(())
+}
+
+fn write_combined_hunks(out: &mut dyn Write, combined: &[CombinedLine], result: &[&[u8]], parents: usize) -> Result<()> {
+    let count = result.len();
+    let marker = "@".repeat(parents + 1);
+    let mut n = 0;
+    loop {
+        let mut comment = None;
+        while n <= count && !combined[n].shown {
+            if result.get(n).and_then(|line| line.first()).is_some_and(|&c| c.is_ascii_alphabetic() || c == b'_' || c == b'$') {
+                comment = Some(result[n]);
+            }
+            n += 1;
+        }
+        if n > count {
+            return Ok(());
+        }
+        let end = (n + 1..=count).find(|&k| !combined[k].shown).unwrap_or(count + 1);
+        let result_lines = end.min(count) - n;
+        write!(out, "{}", marker)?;
+        for parent in 0..parents {
+            let first = combined[n].parent_line[parent];
+            let last = combined.get(end).map_or_else(|| combined[count].parent_line[parent] + lost_from(&combined[count], parent), |line| line.parent_line[parent]);
+            write!(out, " -{},{}", first, last - first)?;
+        }
+        write!(out, " +{},{} {}", n + 1, result_lines, marker)?;
+        if let Some(comment) = comment {
+            // git's cut-off drops the last character of the comment
+            let comment = &comment[..comment.len().min(40)];
+            let comment = &comment[..comment.iter().position(|&c| c == b'\n').unwrap_or(comment.len())];
+            if let Some(last) = comment.iter().rposition(|c| !c.is_ascii_whitespace()).filter(|&last| last > 0) {
+                out.write_all(b" ")?;
+                out.write_all(&comment[..last])?;
+            }
+        }
+        writeln!(out)?;
+        for at in n..end.min(count + 1) {
+            let line = &combined[at];
+            if !line.no_lost {
+                for (lost, set) in &line.lost {
+                    let columns: String = (0..parents).map(|p| if set & 1 << p != 0 { '-' } else { ' ' }).collect();
+                    out.write_all(columns.as_bytes())?;
+                    write_tail(out, lost)?;
+                }
+            }
+            if at == count {
+                break;
+            }
+            let columns: String = (0..parents).map(|p| if line.new_to & 1 << p != 0 { '+' } else { ' ' }).collect();
+            out.write_all(columns.as_bytes())?;
+            write_tail(out, result[at])?;
+        }
+        n = end;
+    }
+}
+
+fn lost_from(line: &CombinedLine, parent: usize) -> usize {
+    line.lost.iter().filter(|(_, set)| set & 1 << parent != 0).count()
+}
+
+fn write_tail(out: &mut dyn Write, line: &[u8]) -> Result<()> {
+    out.write_all(line)?;
+    if !line.ends_with(b"\n") {
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rename_name() {
+        assert_eq!(rename_name("nums", "numbers"), "nums => numbers");
+        assert_eq!(rename_name("a/b/c.txt", "a/d/c.txt"), "a/{b => d}/c.txt");
+        assert_eq!(rename_name("src/x.rs", "src/y.rs"), "src/{x.rs => y.rs}");
+        assert_eq!(rename_name("a/c", "a/b/c"), "a/{ => b}/c");
+    }
+
+    #[test]
+    fn test_patch_hunks_and_funcname() {
+        let old: Vec<u8> = (1..=20).map(|n| format!("{}\n", if n == 2 { "fn two".to_owned() } else { n.to_string() })).collect::<String>().into_bytes();
+        let new = String::from_utf8(old.clone()).unwrap().replace("10\n", "ten\n").replace("20\n", "twenty").into_bytes();
+        let file = |id: &str| DiffFile { path: "f".to_owned(), mode: FileMode::FILE, id: id.parse().unwrap() };
+        let patch = FilePatch {
+            change: TreeChange::Modified(file("1111111111111111111111111111111111111111"), file("2222222222222222222222222222222222222222")),
+            edits: diff(&lines(&old), &lines(&new)),
+            old, new, binary: false,
+        };
+        let mut out = vec![];
+        write_patch(&mut out, std::slice::from_ref(&patch), CONTEXT).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "diff --git a/f b/f\nindex 1111111..2222222 100644\n--- a/f\n+++ b/f\n\
+            @@ -7,7 +7,7 @@ fn two\n 7\n 8\n 9\n-10\n+ten\n 11\n 12\n 13\n\
+            @@ -17,4 +17,4 @@ fn two\n 17\n 18\n 19\n-20\n+twenty\n\\ No newline at end of file\n");
+        assert_eq!(patch.counts(), (2, 2));
+    }
+}
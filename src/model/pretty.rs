@@ -0,0 +1,999 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use chrono::{DateTime, Datelike, FixedOffset, Local};
+use crate::config::value::parse_color;
+use crate::errors::*;
+use crate::fs::FileSystem;
+use crate::model::commit::{Commit, Identity};
+use crate::model::id::Id;
+use crate::model::object::Object;
+use crate::model::repository::{FileRepository, Repository};
+use crate::model::signature::{split_commit, verify_ssh, AllowedSigners, SignatureFormat, SshSignature, Verification};
+
+/// How dates are shown, as `--date=<mode>` names them.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum DateMode {
+    /// `Thu Jan 2 03:04:05 2020 +0100`
+    #[default]
+    Default,
+    /// Like `Default` in the local timezone, without the offset.
+    Local,
+    /// `2020-01-02 03:04:05 +0100`
+    Iso,
+    /// `2020-01-02T03:04:05+01:00`
+    IsoStrict,
+    /// `Thu, 2 Jan 2020 03:04:05 +0100`
+    Rfc,
+    /// `2020-01-02`
+    Short,
+    /// `1577930645 +0100`
+    Raw,
+    /// `1577930645`
+    Unix,
+    /// `3 years ago`
+    Relative,
+    /// As much of the date as is far enough from now to matter:
+    /// `Jan 2 2020`, `Thu Jan 2 03:04`, `3 hours ago`.
+    Human,
+    /// A strftime format, in the date's own timezone.
+    Format(String),
+}
+
+impl FromStr for DateMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(format) = s.strip_prefix("format:") {
+            return Ok(DateMode::Format(format.to_owned()));
+        }
+        Ok(match s {
+            "default" => DateMode::Default,
+            "local" | "default-local" => DateMode::Local,
+            "iso" | "iso8601" => DateMode::Iso,
+            "iso-strict" | "iso8601-strict" => DateMode::IsoStrict,
+            "rfc" | "rfc2822" => DateMode::Rfc,
+            "short" => DateMode::Short,
+            "raw" => DateMode::Raw,
+            "unix" => DateMode::Unix,
+            "relative" => DateMode::Relative,
+            "human" => DateMode::Human,
+            _ => bail!("unknown date format {}", s),
+        })
+    }
+}
+
+pub fn format_date(date: &DateTime<FixedOffset>, mode: &DateMode, now: &DateTime<FixedOffset>) -> String {
+    let format = match mode {
+        DateMode::Default => "%a %b %-d %H:%M:%S %Y %z",
+        DateMode::Local => return date.with_timezone(&Local).format("%a %b %-d %H:%M:%S %Y").to_string(),
+        DateMode::Iso => "%Y-%m-%d %H:%M:%S %z",
+        DateMode::IsoStrict => "%Y-%m-%dT%H:%M:%S%:z",
+        DateMode::Rfc => "%a, %-d %b %Y %H:%M:%S %z",
+        DateMode::Short => "%Y-%m-%d",
+        DateMode::Raw => "%s %z",
+        DateMode::Unix => "%s",
+        DateMode::Relative => return relative_date(date.timestamp(), now.timestamp()),
+        DateMode::Human => return human_date(date, now),
+        DateMode::Format(format) => format,
+    };
+    date.format(format).to_string()
+}
+
+/// git's `human` dates: relative today, then without the seconds and
+/// zone, the weekday and time dropped once the year differs.
+fn human_date(date: &DateTime<FixedOffset>, now: &DateTime<FixedOffset>) -> String {
+    let same_year = date.year() == now.year();
+    let (mut hide_date, mut today) = (false, false);
+    if same_year && date.month() == now.month() && date.day() <= now.day() {
+        today = date.day() == now.day();
+        // just the weekday for a few days ago
+        hide_date = date.day() + 5 > now.day();
+    }
+    if today {
+        return relative_date(date.timestamp(), now.timestamp());
+    }
+    let mut out = String::new();
+    if same_year {
+        out.push_str(&date.format("%a ").to_string());
+    }
+    if !hide_date {
+        out.push_str(&date.format("%b %-d ").to_string());
+    }
+    if same_year {
+        out.push_str(&date.format("%H:%M").to_string());
+    } else {
+        out.truncate(out.trim_end().len());
+        out.push_str(&date.format(" %Y").to_string());
+    }
+    if hide_date && date.offset() != now.offset() {
+        out.push_str(&date.format(" %z").to_string());
+    }
+    out
+}
+
+/// `date` as seen from `now`, rounded the way git rounds it.
+fn relative_date(date: i64, now: i64) -> String {
+    if date > now {
+        return "in the future".to_owned();
+    }
+    let plural = |n: i64, unit: &str| format!("{} {}{}", n, unit, if n == 1 { "" } else { "s" });
+    let mut diff = now - date;
+    if diff < 90 {
+        return format!("{} ago", plural(diff, "second"));
+    }
+    diff = (diff + 30) / 60;
+    if diff < 90 {
+        return format!("{} ago", plural(diff, "minute"));
+    }
+    diff = (diff + 30) / 60;
+    if diff < 36 {
+        return format!("{} ago", plural(diff, "hour"));
+    }
+    // days from here on
+    diff = (diff + 12) / 24;
+    if diff < 14 {
+        return format!("{} ago", plural(diff, "day"));
+    }
+    if diff < 70 {
+        return format!("{} ago", plural((diff + 3) / 7, "week"));
+    }
+    if diff < 365 {
+        return format!("{} ago", plural((diff + 15) / 30, "month"));
+    }
+    if diff < 1825 {
+        let months = (diff * 12 * 2 + 365) / (365 * 2);
+        let (years, months) = (months / 12, months % 12);
+        if months > 0 {
+            return format!("{}, {} ago", plural(years, "year"), plural(months, "month"));
+        }
+        return format!("{} ago", plural(years, "year"));
+    }
+    format!("{} ago", plural((diff + 183) / 365, "year"))
+}
+
+
+const RESET: &str = "\x1b[m";
+/// The color of commit ids and of the parentheses around decorations.
+const COMMIT_COLOR: &str = "\x1b[33m";
+
+/// A ref pointing at a commit, as `--decorate` and `%d` show it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Decoration {
+    /// `HEAD`, with the branch it is on unless detached.
+    Head(Option<String>),
+    Branch(String),
+    RemoteBranch(String),
+    Tag(String),
+    Stash,
+    Other(String),
+}
+
+impl Decoration {
+    fn color(&self) -> &'static str {
+        match self {
+            Decoration::Head(_) => "\x1b[1;36m",
+            Decoration::Branch(_) => "\x1b[1;32m",
+            Decoration::RemoteBranch(_) => "\x1b[1;31m",
+            Decoration::Tag(_) => "\x1b[1;33m",
+            Decoration::Stash => "\x1b[1;35m",
+            Decoration::Other(_) => "",
+        }
+    }
+
+    /// `HEAD -> master`, `tag: v1`, or the short ref name, colored when
+    /// `color`.
+    fn write(&self, out: &mut String, color: bool) {
+        let paint = |code: &str| if color { code.to_owned() } else { String::new() };
+        out.push_str(&paint(self.color()));
+        match self {
+            Decoration::Head(branch) => {
+                out.push_str("HEAD");
+                if let Some(branch) = branch {
+                    out.push_str(" -> ");
+                    out.push_str(&paint(RESET));
+                    out.push_str(&paint(Decoration::Branch(String::new()).color()));
+                    out.push_str(branch);
+                }
+            }
+            Decoration::Tag(name) => out.push_str(&format!("tag: {}", name)),
+            Decoration::Stash => out.push_str("refs/stash"),
+            Decoration::Branch(name) | Decoration::RemoteBranch(name) | Decoration::Other(name) => out.push_str(name),
+        }
+        out.push_str(&paint(RESET));
+    }
+}
+
+/// The refs pointing at each commit.
+#[derive(Debug, Clone, Default)]
+pub struct Decorations {
+    names: HashMap<Id, Vec<Decoration>>,
+}
+
+impl Decorations {
+    pub fn get(&self, id: &Id) -> &[Decoration] {
+        self.names.get(id).map_or(&[], Vec::as_slice)
+    }
+
+    /// The decorations of `id` between `prefix` and `suffix`, or nothing
+    /// when it has none.
+    fn write(&self, out: &mut String, id: &Id, color: bool, prefix: &str, suffix: &str) {
+        let decorations = self.get(id);
+        if decorations.is_empty() {
+            return;
+        }
+        let paint = |code: &'static str| if color { code } else { "" };
+        for (n, decoration) in decorations.iter().enumerate() {
+            out.push_str(paint(COMMIT_COLOR));
+            out.push_str(if n == 0 { prefix } else { ", " });
+            out.push_str(paint(RESET));
+            decoration.write(out, color);
+        }
+        out.push_str(paint(COMMIT_COLOR));
+        out.push_str(suffix);
+        out.push_str(paint(RESET));
+    }
+}
+
+impl<FS: FileSystem> FileRepository<FS> {
+    /// Every ref by kind and short name, with the checked out branch
+    /// folded into `HEAD`. Like git, `HEAD` comes first and the other refs
+    /// in reverse order.
+    pub fn decorations(&self) -> Result<Decorations> {
+        let mut names: HashMap<Id, Vec<Decoration>> = HashMap::new();
+        let current = self.current_branch()?;
+        for name in self.ref_names()? {
+            let id = match self.resolve_ref(&name)? {
+                Some(id) => id,
+                None => continue,
+            };
+            if Some(&name) == current.as_ref() {
+                continue;
+            }
+            let decoration = if let Some(tag) = name.strip_prefix("refs/tags/") {
+                Decoration::Tag(tag.to_owned())
+            } else if let Some(branch) = name.strip_prefix("refs/heads/") {
+                Decoration::Branch(branch.to_owned())
+            } else if let Some(branch) = name.strip_prefix("refs/remotes/") {
+                Decoration::RemoteBranch(branch.to_owned())
+            } else if name == "refs/stash" {
+                Decoration::Stash
+            } else {
+                Decoration::Other(name.clone())
+            };
+            // an annotated tag decorates whatever it points at too
+            let mut target = id;
+            loop {
+                names.entry(target.clone()).or_default().insert(0, decoration.clone());
+                match self.read_object(&target) {
+                    Ok(Object::Tag(tag)) => target = tag.object().clone(),
+                    _ => break,
+                }
+            }
+        }
+        if let Some(id) = self.resolve_ref("HEAD")? {
+            let branch = current.map(|branch| branch.strip_prefix("refs/heads/").map_or(branch.clone(), str::to_owned));
+            names.entry(id).or_default().insert(0, Decoration::Head(branch));
+        }
+        Ok(Decorations { names })
+    }
+
+    /// The signers `gpg.ssh.allowedSignersFile` trusts, if it is set and
+    /// readable.
+    pub fn allowed_signers(&self) -> Result<Option<AllowedSigners>> {
+        let text = match self.config()?.get_path("gpg.ssh.allowedSignersFile")? {
+            Some(path) => std::fs::read_to_string(path).ok(),
+            None => None,
+        };
+        text.map(|text| AllowedSigners::parse(&text)).transpose()
+    }
+
+    /// Checks the signature on commit `id` against `signers`, the way
+    /// `%G?` reports it.
+    pub fn signature_check(&self, id: &Id, signers: Option<&AllowedSigners>) -> Result<SignatureCheck> {
+        let content = Repository::read_content(self, &self.find_object(id)?)?;
+        let signed = match split_commit(&content, self.object_format()) {
+            Some(signed) => signed,
+            None => return Ok(SignatureCheck::default()),
+        };
+        let unchecked = SignatureCheck { status: 'E', ..SignatureCheck::default() };
+        if signed.format != SignatureFormat::Ssh {
+            return Ok(unchecked);
+        }
+        let key = match SshSignature::parse(&signed.signature) {
+            Ok(signature) => signature.key().fingerprint(),
+            Err(_) => return Ok(unchecked),
+        };
+        // without signers a valid signature is still checked, by nobody trusted
+        let nobody = AllowedSigners::parse("")?;
+        let (status, signer) = match verify_ssh(&signed, signers.unwrap_or(&nobody)) {
+            Ok(Verification::Good { principal, .. }) => ('G', principal),
+            Ok(Verification::Untrusted { .. }) => ('U', String::new()),
+            Ok(Verification::Bad { .. }) => ('B', String::new()),
+            Err(_) => ('E', String::new()),
+        };
+        Ok(SignatureCheck { status, signer, key: key.clone(), fingerprint: key })
+    }
+}
+
+/// What `%G?`, `%GS`, `%GK` and `%GF` show about a commit's signature.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SignatureCheck {
+    /// `G` for good, `B` bad, `U` good but by an unknown key, `E` when it
+    /// cannot be checked and `N` for no signature.
+    pub status: char,
+    pub signer: String,
+    pub key: String,
+    pub fingerprint: String,
+}
+
+impl Default for SignatureCheck {
+    fn default() -> Self {
+        SignatureCheck { status: 'N', signer: String::new(), key: String::new(), fingerprint: String::new() }
+    }
+}
+
+/// What placeholders draw on besides the commit itself.
+#[derive(Debug, Clone)]
+pub struct FormatContext {
+    pub date_mode: DateMode,
+    pub decorations: Decorations,
+    /// Whether the named formats show decorations after the id.
+    pub decorate: bool,
+    /// Whether the named formats abbreviate the commit id.
+    pub abbrev_commit: bool,
+    /// Whether colors are on, as `--color` turns them on; `%C(always,...)`
+    /// colors regardless.
+    pub color: bool,
+    /// Signature checks by commit, for `%G` placeholders; a commit
+    /// missing here shows as unsigned.
+    pub signatures: HashMap<Id, SignatureCheck>,
+    pub now: DateTime<FixedOffset>,
+}
+
+impl Default for FormatContext {
+    fn default() -> Self {
+        let now = Local::now();
+        FormatContext {
+            date_mode: DateMode::Default,
+            decorations: Decorations::default(),
+            decorate: false,
+            abbrev_commit: false,
+            color: false,
+            signatures: HashMap::new(),
+            now: now.with_timezone(now.offset()),
+        }
+    }
+}
+
+impl FormatContext {
+    /// ` (HEAD -> master, tag: v1)`, or nothing.
+    fn decoration(&self, id: &Id, color: bool) -> String {
+        let mut out = String::new();
+        self.decorations.write(&mut out, id, color, " (", ")");
+        out
+    }
+}
+
+/// The subject, its lines joined with spaces, and the body after it.
+fn split_message(message: &str) -> (String, &str) {
+    let (subject, body) = match message.find("\n\n") {
+        Some(n) => (&message[..n], message[n + 2..].trim_start_matches('\n')),
+        None => (message, ""),
+    };
+    (subject.lines().map(str::trim).collect::<Vec<_>>().join(" "), body)
+}
+
+fn abbrev(id: &Id) -> String {
+    id.to_string()[..7].to_owned()
+}
+
+/// The subject made fit for a file name: runs of other characters
+/// between letters, digits, `.` and `_` become one `-`.
+fn sanitize(subject: &str) -> String {
+    let mut out = String::new();
+    let mut gap = false;
+    let mut last = None;
+    for c in subject.chars() {
+        if c.is_ascii_alphanumeric() || c == '.' || c == '_' {
+            if gap && !out.is_empty() {
+                out.push('-');
+            }
+            gap = false;
+            // dots do not repeat
+            if !(c == '.' && last == Some('.')) {
+                out.push(c);
+            }
+        } else {
+            gap = true;
+        }
+        last = Some(c);
+    }
+    out.trim_end_matches(['.', '-']).to_owned()
+}
+
+/// Expands `%a?` or `%c?` for `identity`: its name, email or date in one
+/// of the styles `code` picks.
+pub fn format_identity(identity: &Identity, code: char, context: &FormatContext) -> Option<String> {
+    let mode = match code {
+        'n' | 'N' => return Some(identity.name.clone()),
+        'e' | 'E' => return Some(identity.email.clone()),
+        'l' | 'L' => return Some(identity.email.split('@').next().unwrap_or_default().to_owned()),
+        'd' => context.date_mode.clone(),
+        'D' => DateMode::Rfc,
+        'i' => DateMode::Iso,
+        'I' => DateMode::IsoStrict,
+        'r' => DateMode::Relative,
+        't' => DateMode::Unix,
+        's' => DateMode::Short,
+        'h' => DateMode::Human,
+        _ => return None,
+    };
+    Some(format_date(&identity.date, &mode, &context.now))
+}
+
+/// Display width, leaving out color escapes.
+fn width(text: &str) -> usize {
+    let mut width = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            chars.by_ref().find(|&c| c == 'm');
+        } else {
+            width += 1;
+        }
+    }
+    width
+}
+
+/// Where `%<(N)` and friends put the next placeholder in its columns.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Align {
+    /// `%<`: text first, then the padding.
+    Left,
+    /// `%>`: padding first.
+    Right,
+    /// `%><`: padding split around the text.
+    Center,
+    /// `%>>`: like `%>`, taking spaces from the left when the text is
+    /// too wide.
+    RightSteal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Truncate {
+    None,
+    Left,
+    Middle,
+    Right,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Padding {
+    align: Align,
+    columns: usize,
+    /// `%<|(N)`: pad up to column N rather than by N columns.
+    until_column: bool,
+    truncate: Truncate,
+}
+
+/// Parses the `<(N[,trunc])` after a `%`, giving the padding and the
+/// length of its spec.
+fn parse_padding(spec: &str) -> Option<(Padding, usize)> {
+    let (align, mut len) = if spec.starts_with(">>") {
+        (Align::RightSteal, 2)
+    } else if spec.starts_with("><") {
+        (Align::Center, 2)
+    } else if spec.starts_with('>') {
+        (Align::Right, 1)
+    } else if spec.starts_with('<') {
+        (Align::Left, 1)
+    } else {
+        return None;
+    };
+    let until_column = spec[len..].starts_with('|');
+    if until_column {
+        len += 1;
+    }
+    let args = spec[len..].strip_prefix('(')?;
+    let close = args.find(')')?;
+    let mut args = args[..close].splitn(2, ',');
+    let columns = args.next()?.trim().parse().ok()?;
+    let truncate = match args.next().map(str::trim) {
+        None => Truncate::None,
+        Some("trunc") => Truncate::Right,
+        Some("ltrunc") => Truncate::Left,
+        Some("mtrunc") => Truncate::Middle,
+        Some(_) => return None,
+    };
+    Some((Padding { align, columns, until_column, truncate }, len + close + 2))
+}
+
+/// Keeps `keep` columns of `text` around the `..` that replaces the
+/// rest: from its start, its end, or both.
+fn truncate(text: &str, keep: usize, how: Truncate) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    let keep = keep.min(chars.len());
+    let tail = |n: usize| chars[chars.len() - n..].iter().collect::<String>();
+    match how {
+        Truncate::Right => format!("{}..", chars[..keep].iter().collect::<String>()),
+        Truncate::Left => format!("..{}", tail(keep)),
+        Truncate::Middle => {
+            let head = keep.min((keep + 2) / 2 - 1);
+            format!("{}..{}", chars[..head].iter().collect::<String>(), tail(keep - head))
+        }
+        Truncate::None => text.to_owned(),
+    }
+}
+
+impl Padding {
+    /// Lays `text` out in the padding's columns, appending it to `out`.
+    fn apply(&self, out: &mut String, text: &str) {
+        let column = width(&out[out.rfind('\n').map_or(0, |n| n + 1)..]);
+        let mut columns = if self.until_column { self.columns as isize - column as isize } else { self.columns as isize };
+        let len = width(text) as isize;
+        if self.align == Align::RightSteal {
+            while len > columns && out.ends_with(' ') {
+                out.pop();
+                columns += 1;
+            }
+        }
+        if len > columns {
+            if self.truncate == Truncate::None || columns < 2 {
+                out.push_str(text);
+            } else {
+                out.push_str(&truncate(text, columns as usize - 2, self.truncate));
+            }
+            return;
+        }
+        let padding = (columns - len) as usize;
+        let before = match self.align {
+            Align::Left => 0,
+            Align::Center => padding / 2,
+            Align::Right | Align::RightSteal => padding,
+        };
+        out.extend(std::iter::repeat_n(' ', before));
+        out.push_str(text);
+        out.extend(std::iter::repeat_n(' ', padding - before));
+    }
+}
+
+/// Expands placeholders one at a time for a commit, keeping the state
+/// `%C(auto)` turns on.
+struct Expander<'a> {
+    commit: &'a Commit,
+    context: &'a FormatContext,
+    subject: String,
+    body: String,
+    auto_color: bool,
+}
+
+impl<'a> Expander<'a> {
+    fn new(commit: &'a Commit, context: &'a FormatContext) -> Self {
+        let (subject, body) = split_message(commit.message());
+        // the message comes trimmed; %b ends with a newline like git's
+        let body = if body.is_empty() { String::new() } else { format!("{}\n", body) };
+        Expander { commit, context, subject, body, auto_color: false }
+    }
+
+    fn paint(&self, text: String) -> String {
+        if self.auto_color { format!("{}{}{}", COMMIT_COLOR, text, RESET) } else { text }
+    }
+
+    /// `%C...`: the color's escape and the length of its spec, `None` if
+    /// it is no color.
+    fn color(&mut self, spec: &str, out: &str) -> Option<(String, usize)> {
+        for (name, code) in &[("red", "\x1b[31m"), ("green", "\x1b[32m"), ("blue", "\x1b[34m"), ("reset", RESET)] {
+            if spec.starts_with(name) {
+                let code = if self.context.color { (*code).to_owned() } else { String::new() };
+                return Some((code, name.len()));
+            }
+        }
+        let close = spec.strip_prefix('(')?.find(')')? + 1;
+        let value = &spec[1..close];
+        if value == "auto" {
+            self.auto_color = self.context.color;
+            let reset = if self.auto_color && !out.is_empty() { RESET } else { "" };
+            return Some((reset.to_owned(), close + 1));
+        }
+        let (always, value) = match value.strip_prefix("always,") {
+            Some(value) => (true, value),
+            None => (false, value.strip_prefix("auto,").unwrap_or(value)),
+        };
+        let code = parse_color("--format", value).ok()?;
+        Some((if always || self.context.color { code } else { String::new() }, close + 1))
+    }
+
+    /// Expands the placeholder at the start of `spec`, which follows a
+    /// `%`, into its text, how much of `spec` it took, and whether it was
+    /// a color.
+    fn expand(&mut self, spec: &str, out: &str) -> Option<(String, usize, bool)> {
+        let commit = self.commit;
+        let context = self.context;
+        let ids = |ids: &[Id], long: bool| {
+            ids.iter().map(|id| if long { id.to_string() } else { abbrev(id) }).collect::<Vec<_>>().join(" ")
+        };
+        let person = |identity: &Option<Identity>, code: Option<char>| -> Option<String> {
+            let code = code?;
+            match identity {
+                Some(identity) => format_identity(identity, code, context),
+                None => format_identity(&Identity { name: String::new(), email: String::new(), date: context.now }, code, context)
+                    .map(|_| String::new()),
+            }
+        };
+        let signature = || context.signatures.get(commit.id()).cloned().unwrap_or_default();
+        let mut chars = spec.chars();
+        let (first, second) = (chars.next()?, chars.next());
+        let text = match first {
+            'C' => return self.color(&spec[1..], out).map(|(code, len)| (code, len + 1, true)),
+            'a' => return person(commit.author(), second).map(|text| (text, 2, false)),
+            'c' => return person(commit.committer(), second).map(|text| (text, 2, false)),
+            'G' => {
+                let check = signature();
+                let text = match second? {
+                    '?' => check.status.to_string(),
+                    'S' => check.signer,
+                    'K' => check.key,
+                    'F' => check.fingerprint,
+                    _ => return None,
+                };
+                return Some((text, 2, false));
+            }
+            'x' => {
+                let hex = spec.get(1..3)?;
+                let byte = u8::from_str_radix(hex, 16).ok()?;
+                return Some(((byte as char).to_string(), 3, false));
+            }
+            'H' => self.paint(commit.id().to_string()),
+            'h' => self.paint(abbrev(commit.id())),
+            'T' => commit.tree().to_string(),
+            't' => abbrev(commit.tree()),
+            'P' => ids(commit.parent(), true),
+            'p' => ids(commit.parent(), false),
+            's' => self.subject.clone(),
+            'f' => sanitize(commit.message().lines().next().unwrap_or_default()),
+            'b' => self.body.clone(),
+            'B' => String::from_utf8_lossy(commit.raw_message()).into_owned(),
+            'e' => commit.encoding().unwrap_or_default().to_owned(),
+            'd' => context.decoration(commit.id(), self.auto_color),
+            'D' => {
+                let mut text = String::new();
+                context.decorations.write(&mut text, commit.id(), self.auto_color, "", "");
+                text
+            }
+            'n' => "\n".to_owned(),
+            '%' => "%".to_owned(),
+            _ => return None,
+        };
+        Some((text, 1, false))
+    }
+}
+
+/// Expands the `%` placeholders of a `--format` string for `commit`; an
+/// unknown one is kept as written.
+pub fn format_commit(commit: &Commit, format: &str, context: &FormatContext) -> String {
+    let mut expander = Expander::new(commit, context);
+    let mut out = String::new();
+    let mut padding = None;
+    let mut rest = format;
+    while let Some(n) = rest.find('%') {
+        out.push_str(&rest[..n]);
+        rest = &rest[n + 1..];
+        if let Some((pad, len)) = parse_padding(rest) {
+            padding = Some(pad);
+            rest = &rest[len..];
+            continue;
+        }
+        // %+x, %-x and % x add a newline or space only when x is not empty
+        let magic = rest.chars().next().filter(|c| ['+', '-', ' '].contains(c));
+        let spec = if magic.is_some() { &rest[1..] } else { rest };
+        let (mut text, len, color) = match expander.expand(spec, &out) {
+            Some(expansion) => expansion,
+            None => {
+                out.push('%');
+                continue;
+            }
+        };
+        rest = &spec[len..];
+        if let Some(pad) = padding.take() {
+            // colors go with the placeholder after them
+            let mut color = color;
+            while color && rest.starts_with('%') {
+                match expander.expand(&rest[1..], &out) {
+                    Some((more, len, is_color)) => {
+                        text.push_str(&more);
+                        rest = &rest[1 + len..];
+                        color = is_color;
+                    }
+                    None => break,
+                }
+            }
+            pad.apply(&mut out, &text);
+            continue;
+        }
+        match magic {
+            Some('+') if !text.is_empty() => out.push('\n'),
+            Some(' ') if !text.is_empty() => out.push(' '),
+            Some('-') if text.is_empty() => out.truncate(out.trim_end_matches('\n').len()),
+            _ => {}
+        }
+        out.push_str(&text);
+    }
+    out.push_str(rest);
+    out
+}
+
+/// How `log` and `show` present each commit.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pretty {
+    Oneline,
+    Short,
+    Medium,
+    Full,
+    Fuller,
+    Raw,
+    Email,
+    /// `format:`, whose output separates commits rather than ending
+    /// each one.
+    Format(String),
+    /// `tformat:`, or a bare format string: each commit's output ends with
+    /// a newline.
+    TFormat(String),
+}
+
+impl FromStr for Pretty {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if let Some(format) = s.strip_prefix("format:") {
+            return Ok(Pretty::Format(format.to_owned()));
+        } else if let Some(format) = s.strip_prefix("tformat:") {
+            return Ok(Pretty::TFormat(format.to_owned()));
+        }
+        Ok(match s {
+            "oneline" => Pretty::Oneline,
+            "short" => Pretty::Short,
+            "medium" => Pretty::Medium,
+            "full" => Pretty::Full,
+            "fuller" => Pretty::Fuller,
+            "raw" => Pretty::Raw,
+            "email" => Pretty::Email,
+            _ if s.contains('%') => Pretty::TFormat(s.to_owned()),
+            _ => bail!("invalid --pretty format: {}", s),
+        })
+    }
+}
+
+impl Pretty {
+    /// Whether commits are ended by a newline rather than separated by one.
+    pub fn terminates(&self) -> bool {
+        matches!(self, Pretty::Oneline | Pretty::TFormat(_))
+    }
+}
+
+/// Expands tabs to 8 columns.
+fn expand_tabs(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    for c in line.chars() {
+        if c == '\t' {
+            let pad = 8 - out.chars().count() % 8;
+            out.extend(std::iter::repeat_n(' ', pad));
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+/// Whether a header needs RFC 2047 encoding to carry `text`.
+fn needs_encoding(text: &str) -> bool {
+    !text.is_ascii() || text.contains('\n') || text.contains("=?")
+}
+
+/// `text` as RFC 2047 `Q` encoded words, starting at `column` and folded
+/// to keep lines within 76 columns. In an address, fewer characters may
+/// stand for themselves.
+fn rfc2047(text: &str, column: usize, address: bool) -> String {
+    const MAX: usize = 76;
+    let open = "=?UTF-8?q?";
+    let mut out = open.to_owned();
+    let mut line = column + open.len();
+    for c in text.chars() {
+        let special = !c.is_ascii() || c.is_ascii_control() || c.is_ascii_whitespace() || "=?_".contains(c)
+            || (address && !(c.is_ascii_alphanumeric() || "!*+-/".contains(c)));
+        let mut encoded = String::new();
+        if special {
+            let mut buf = [0; 4];
+            for byte in c.encode_utf8(&mut buf).bytes() {
+                encoded.push_str(&format!("={:02X}", byte));
+            }
+        } else {
+            encoded.push(c);
+        }
+        if line + encoded.len() + 2 > MAX {
+            out.push_str("?=\n ");
+            out.push_str(open);
+            line = open.len() + 1;
+        }
+        line += encoded.len();
+        out.push_str(&encoded);
+    }
+    out.push_str("?=");
+    out
+}
+
+/// Wraps `text` at spaces to `width` columns, the first line starting at
+/// `column` and the others indented by one space.
+fn wrap(text: &str, column: usize, width: usize) -> String {
+    let mut out = String::new();
+    let mut line = column;
+    for (n, word) in text.split(' ').enumerate() {
+        let len = word.chars().count();
+        if n > 0 {
+            if line + 1 + len > width {
+                out.push_str("\n ");
+                line = 1;
+            } else {
+                out.push(' ');
+                line += 1;
+            }
+        }
+        out.push_str(word);
+        line += len;
+    }
+    out
+}
+
+/// A name for a `From:` header: encoded, or quoted when it holds
+/// characters special in addresses.
+fn email_name(name: &str) -> String {
+    if needs_encoding(name) {
+        rfc2047(name, "From: ".len(), true)
+    } else if name.contains(|c| "()<>@,;:\\\".[]".contains(c)) {
+        format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        name.to_owned()
+    }
+}
+
+fn show_email(commit: &Commit, context: &FormatContext) -> String {
+    let (subject, body) = split_message(commit.message());
+    let mut out = format!("From {} Mon Sep 17 00:00:00 2001\n", commit.id());
+    if let Some(author) = commit.author() {
+        out.push_str(&show_identity("Author", author, &Pretty::Email, context));
+    }
+    let prefix = "Subject: [PATCH] ";
+    let subject = if needs_encoding(&subject) { rfc2047(&subject, prefix.len(), false) } else { wrap(&subject, prefix.len(), 78) };
+    out.push_str(&format!("{}{}\n", prefix, subject));
+    if !commit.message().is_ascii() {
+        out.push_str("MIME-Version: 1.0\nContent-Type: text/plain; charset=UTF-8\nContent-Transfer-Encoding: 8bit\n");
+    }
+    out.push('\n');
+    for line in body.lines() {
+        out.push_str(line);
+        out.push('\n');
+    }
+    out
+}
+
+/// The headers of the commit object as stored, for `--pretty=raw`.
+fn raw_headers(commit: &Commit) -> String {
+    let mut out = format!("tree {}\n", commit.tree());
+    for parent in commit.parent() {
+        out.push_str(&format!("parent {}\n", parent));
+    }
+    for (key, identity) in &[("author", commit.author()), ("committer", commit.committer())] {
+        if let Some(identity) = identity {
+            out.push_str(&format!("{} {} <{}> {}\n", key, identity.name, identity.email, identity.date.format("%s %z")));
+        }
+    }
+    for (key, value) in commit.extra_headers() {
+        out.push_str(&format!("{} {}\n", key, value.replace('\n', "\n ")));
+    }
+    out
+}
+
+/// The `Author:`, `Commit:` or `Tagger:` lines for `identity` in the
+/// header of `pretty`.
+pub fn show_identity(label: &str, identity: &Identity, pretty: &Pretty, context: &FormatContext) -> String {
+    let date = |mode: &DateMode| format_date(&identity.date, mode, &context.now);
+    match pretty {
+        Pretty::Oneline => String::new(),
+        Pretty::Email => format!("From: {} <{}>\nDate: {}\n", email_name(&identity.name), identity.email, date(&DateMode::Rfc)),
+        Pretty::Medium => format!("{}: {} <{}>\nDate:   {}\n", label, identity.name, identity.email, date(&context.date_mode)),
+        Pretty::Fuller => format!("{0}:     {1} <{2}>\n{0}Date: {3}\n", label, identity.name, identity.email, date(&context.date_mode)),
+        _ => format!("{}: {} <{}>\n", label, identity.name, identity.email),
+    }
+}
+
+/// `commit` as `pretty` shows it, without the separator `log` puts
+/// between commits.
+pub fn show_commit(commit: &Commit, pretty: &Pretty, context: &FormatContext) -> String {
+    let id = if context.abbrev_commit { abbrev(commit.id()) } else { commit.id().to_string() };
+    let paint = |text: String| if context.color { format!("{}{}{}", COMMIT_COLOR, text, RESET) } else { text };
+    let decoration = if context.decorate { context.decoration(commit.id(), context.color) } else { String::new() };
+    let mut out = match pretty {
+        Pretty::Format(format) => return format_commit(commit, format, context),
+        Pretty::TFormat(format) => return format!("{}\n", format_commit(commit, format, context)),
+        Pretty::Oneline => return format!("{}{} {}\n", paint(id), decoration, split_message(commit.message()).0),
+        Pretty::Email => return show_email(commit, context),
+        _ => format!("{}{}\n", paint(format!("commit {}", id)), decoration),
+    };
+    if *pretty == Pretty::Raw {
+        out.push_str(&raw_headers(commit));
+    } else if commit.parent().len() > 1 {
+        let parents: Vec<String> = commit.parent().iter().map(abbrev).collect();
+        out.push_str(&format!("Merge: {}\n", parents.join(" ")));
+    }
+    let identities = match pretty {
+        Pretty::Full | Pretty::Fuller => vec![("Author", commit.author()), ("Commit", commit.committer())],
+        Pretty::Raw => vec![],
+        _ => vec![("Author", commit.author())],
+    };
+    for (label, identity) in identities {
+        if let Some(identity) = identity {
+            out.push_str(&show_identity(label, identity, pretty, context));
+        }
+    }
+    out.push('\n');
+    let message = commit.message();
+    // short shows the title paragraph alone
+    let message = match pretty {
+        Pretty::Short => &message[..message.find("\n\n").unwrap_or(message.len())],
+        _ => message,
+    };
+    for line in message.lines() {
+        let line = if *pretty == Pretty::Raw { line.to_owned() } else { expand_tabs(line) };
+        out.push_str(&format!("    {}\n", line));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::commit::parse_commit;
+    use crate::model::object::ParseMode;
+
+    #[test]
+    fn test_format_and_dates() {
+        let id: Id = "26e30e73560e29029a578514aff4380ac4606b04".parse().unwrap();
+        let content = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+                        author A U Thor <a@x> 1577930645 +0100\n\
+                        committer C <c@x> 1577930645 +0100\n\nfirst\ncommit\n\nbody\n";
+        let commit = parse_commit(content, &id, ParseMode::Strict).unwrap();
+        let now = DateTime::parse_from_rfc3339("2020-01-02T03:04:05+01:00").unwrap();
+        let mut context = FormatContext { date_mode: DateMode::Iso, now, ..FormatContext::default() };
+        context.decorations.names.insert(id.clone(), vec![Decoration::Head(Some("master".to_owned())), Decoration::Tag("v1".to_owned())]);
+        assert_eq!(format_commit(&commit, "%h %an <%ae> %ad%d%n%s|%b|%x", &context),
+                   "26e30e7 A U Thor <a@x> 2020-01-02 03:04:05 +0100 (HEAD -> master, tag: v1)\nfirst commit|body\n|%x");
+        assert_eq!(format_date(&now, &"format:%Y/%m %H".parse().unwrap(), &now), "2020/01 03");
+        assert_eq!(relative_date(0, 89), "89 seconds ago");
+        assert_eq!(relative_date(0, 3600), "60 minutes ago");
+        assert_eq!(relative_date(0, 86400 * 400), "1 year, 1 month ago");
+        assert_eq!(relative_date(0, 86400 * 3650), "10 years ago");
+    }
+
+    #[test]
+    fn test_padding_colors_and_named_formats() {
+        let id: Id = "26e30e73560e29029a578514aff4380ac4606b04".parse().unwrap();
+        let content = "tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+                       author Ánn Äuthor <ann@x> 1577930645 +0100\n\
+                       committer C <c@x> 1577930645 +0100\n\nfirst: line\tof it\n";
+        let commit = parse_commit(content.as_bytes(), &id, ParseMode::Strict).unwrap();
+        let mut context = FormatContext::default();
+        context.decorations.names.insert(id.clone(), vec![Decoration::Head(Some("master".to_owned()))]);
+        assert_eq!(format_commit(&commit, "%<(8,trunc)%s|%>(9)%h|%<(8,ltrunc)%s|%<(9,mtrunc)%s|x%-b|%al|%f", &context),
+                   "first:..|  26e30e7|..\tof it|fir..f it|x|ann|first-line-of-it");
+        assert_eq!(format_commit(&commit, "ab  %>>(4)%h|%C(red)%C(always,red)%D", &context),
+                   "ab26e30e7|\x1b[31mHEAD -> master");
+        context.color = true;
+        assert_eq!(format_commit(&commit, "%C(auto)%h%d", &context),
+                   "\x1b[33m26e30e7\x1b[m\x1b[33m (\x1b[m\x1b[1;36mHEAD -> \x1b[m\x1b[1;32mmaster\x1b[m\x1b[33m)\x1b[m");
+        context.color = false;
+        assert_eq!(show_commit(&commit, &Pretty::Oneline, &context), format!("{} first: line\tof it\n", id));
+        let email = show_commit(&commit, &Pretty::Email, &context);
+        assert!(email.starts_with("From 26e30e73560e29029a578514aff4380ac4606b04 Mon Sep 17 00:00:00 2001\n\
+                                   From: =?UTF-8?q?=C3=81nn=20=C3=84uthor?= <ann@x>\n"));
+        assert!(email.ends_with("Subject: [PATCH] first: line\tof it\n\n"));
+    }
+}
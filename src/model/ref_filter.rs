@@ -0,0 +1,521 @@
+use std::cmp::Ordering;
+use std::str::FromStr;
+use chrono::{DateTime, FixedOffset, Local};
+use crate::errors::*;
+use crate::fs::FileSystem;
+use crate::model::commit::Identity;
+use crate::model::id::Id;
+use crate::model::object::Object;
+use crate::model::pretty::{format_date, DateMode};
+use crate::model::refs::{shorten_ref_name, RefTarget};
+use crate::model::repository::{FileRepository, Repository};
+use crate::wildmatch::wildmatch;
+
+/// The format `for-each-ref` uses when none is given.
+pub const DEFAULT_FORMAT: &str = "%(objectname) %(objecttype)\t%(refname)";
+
+/// A ref with the object it points at, and for a tag the object the tag
+/// points at, which `*` atoms read.
+#[derive(Debug, Clone)]
+pub struct RefItem {
+    pub name: String,
+    pub id: Id,
+    /// The ref a symbolic ref points at.
+    pub symref: Option<String>,
+    /// The branch it merges from, resolved through the remote's fetch
+    /// refspecs.
+    pub upstream: Option<String>,
+    pub head: bool,
+    object: Object,
+    size: usize,
+    tagged: Option<(Object, usize)>,
+}
+
+impl RefItem {
+    /// The commit this ref ends at once tags are peeled, if any.
+    fn commit<FS: FileSystem>(&self, repo: &FileRepository<FS>) -> Option<Id> {
+        repo.peel_to_commit(&self.id).ok().map(|commit| commit.id().clone())
+    }
+
+    fn object(&self, deref: bool) -> Option<(&Object, usize)> {
+        if deref {
+            self.tagged.as_ref().map(|(object, size)| (object, *size))
+        } else {
+            Some((&self.object, self.size))
+        }
+    }
+}
+
+/// One `%(name:modifier)` of a format, `*name` reading the tagged object.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Atom {
+    deref: bool,
+    name: String,
+    modifier: Option<String>,
+}
+
+const ATOMS: &[&str] = &[
+    "refname", "objectname", "objecttype", "objectsize", "upstream", "symref", "HEAD", "tag", "type", "object",
+    "tagger", "taggername", "taggeremail", "taggerdate", "author", "authorname", "authoremail", "authordate",
+    "committer", "committername", "committeremail", "committerdate", "creator", "creatordate", "subject", "body",
+    "contents",
+];
+
+impl FromStr for Atom {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (deref, s) = match s.strip_prefix('*') {
+            Some(s) => (true, s),
+            None => (false, s),
+        };
+        let (name, modifier) = match s.find(':') {
+            Some(n) => (&s[..n], Some(s[n + 1..].to_owned())),
+            None => (s, None),
+        };
+        if !ATOMS.contains(&name) {
+            bail!("unknown field name: {}", name);
+        }
+        let atom = Atom { deref, name: name.to_owned(), modifier };
+        atom.check()?;
+        Ok(atom)
+    }
+}
+
+/// Drops `n` components of `name` from the left, or keeps `-n` of them
+/// from the right, as `lstrip` does; from the other end for `rstrip`.
+fn strip(name: &str, n: isize, left: bool) -> String {
+    let parts: Vec<&str> = name.split('/').collect();
+    let len = parts.len() as isize;
+    let drop = if n < 0 { (len + n).max(0) } else { n.min(len) } as usize;
+    if left { parts[drop..].join("/") } else { parts[..parts.len() - drop].join("/") }
+}
+
+impl Atom {
+    fn is_date(&self) -> bool {
+        self.name.ends_with("date")
+    }
+
+    /// Rejects modifiers the atom does not take, the way git words it.
+    fn check(&self) -> Result<()> {
+        let modifier = match &self.modifier {
+            Some(modifier) => modifier.as_str(),
+            None => return Ok(()),
+        };
+        let strip = |arg: &str| arg.parse::<isize>().is_ok();
+        let valid = match self.name.as_str() {
+            "refname" | "upstream" | "symref" => {
+                modifier == "short"
+                    || modifier.strip_prefix("lstrip=").or_else(|| modifier.strip_prefix("strip=")).is_some_and(strip)
+                    || modifier.strip_prefix("rstrip=").is_some_and(strip)
+            }
+            "objectname" => modifier == "short" || modifier.strip_prefix("short=").is_some_and(|n| n.parse::<usize>().is_ok()),
+            "contents" => ["subject", "body"].contains(&modifier),
+            name if name.ends_with("email") => ["trim", "localpart"].contains(&modifier),
+            _ if self.is_date() => DateMode::from_str(modifier).is_ok(),
+            _ => false,
+        };
+        if !valid {
+            bail!("unrecognized %({}:{}) argument: {}", self.name, modifier, modifier);
+        }
+        Ok(())
+    }
+
+    /// The person the atom reads: the tagger of a tag, the author or
+    /// committer of a commit, and for `creator` whichever made the object.
+    fn identity<'a>(&self, object: &'a Object) -> Option<&'a Identity> {
+        let person = ["tagger", "author", "committer", "creator"].iter().find(|p| self.name.starts_with(*p))?;
+        match (object, *person) {
+            (Object::Tag(tag), "tagger") | (Object::Tag(tag), "creator") => tag.tagger().as_ref(),
+            (Object::Commit(commit), "author") => commit.author().as_ref(),
+            (Object::Commit(commit), "committer") | (Object::Commit(commit), "creator") => commit.committer().as_ref(),
+            _ => None,
+        }
+    }
+
+    /// The atom's text for `item`.
+    pub fn value(&self, item: &RefItem, now: &DateTime<FixedOffset>) -> String {
+        let name = |name: &str| match self.modifier.as_deref() {
+            Some("short") => shorten_ref_name(name).to_owned(),
+            Some(modifier) => match modifier.split_once('=') {
+                Some(("rstrip", n)) => strip(name, n.parse().unwrap_or(0), false),
+                Some((_, n)) => strip(name, n.parse().unwrap_or(0), true),
+                None => name.to_owned(),
+            },
+            None => name.to_owned(),
+        };
+        match self.name.as_str() {
+            "refname" if !self.deref => return name(&item.name),
+            "upstream" if !self.deref => return item.upstream.as_deref().map(name).unwrap_or_default(),
+            "symref" if !self.deref => return item.symref.as_deref().map(name).unwrap_or_default(),
+            "HEAD" if !self.deref => return if item.head { "*" } else { " " }.to_owned(),
+            _ => {}
+        }
+        let (object, size) = match item.object(self.deref) {
+            Some(object) => object,
+            None => return String::new(),
+        };
+        let message = match object {
+            Object::Commit(commit) => commit.message(),
+            Object::Tag(tag) => tag.message(),
+            _ => "",
+        };
+        let (subject, body) = match message.find("\n\n") {
+            Some(n) => (&message[..n], message[n + 2..].trim_start_matches('\n')),
+            None => (message, ""),
+        };
+        let line = |text: &str| if text.is_empty() { String::new() } else { format!("{}\n", text) };
+        match (self.name.as_str(), self.modifier.as_deref()) {
+            ("objectname", None) => object.id().to_string(),
+            ("objectname", Some(modifier)) => {
+                let len = modifier.strip_prefix("short=").and_then(|n| n.parse().ok()).unwrap_or(7);
+                object.id().to_string().chars().take(len.max(4)).collect()
+            }
+            ("objecttype", _) => object.kind().to_string(),
+            ("objectsize", _) => size.to_string(),
+            ("tag", _) | ("type", _) | ("object", _) => match object {
+                Object::Tag(tag) => match self.name.as_str() {
+                    "tag" => tag.tag().to_owned(),
+                    "type" => tag.object_type().to_string(),
+                    _ => tag.object().to_string(),
+                },
+                _ => String::new(),
+            },
+            ("subject", _) | ("contents", Some("subject")) => subject.lines().map(str::trim).collect::<Vec<_>>().join(" "),
+            ("body", _) | ("contents", Some("body")) => line(body),
+            ("contents", _) => line(message),
+            (name, modifier) => {
+                let identity = match self.identity(object) {
+                    Some(identity) => identity,
+                    None => return String::new(),
+                };
+                if self.is_date() {
+                    let mode = modifier.and_then(|m| m.parse().ok()).unwrap_or(DateMode::Default);
+                    format_date(&identity.date, &mode, now)
+                } else if name.ends_with("name") {
+                    identity.name.clone()
+                } else if name.ends_with("email") {
+                    match modifier {
+                        Some("trim") => identity.email.clone(),
+                        Some(_) => identity.email.split('@').next().unwrap_or_default().to_owned(),
+                        None => format!("<{}>", identity.email),
+                    }
+                } else {
+                    format!("{} <{}> {}", identity.name, identity.email, identity.date.format("%s %z"))
+                }
+            }
+        }
+    }
+
+    /// The atom's value for sorting: dates by their time, sizes by
+    /// number and everything else as text.
+    fn key(&self, item: &RefItem, now: &DateTime<FixedOffset>) -> SortValue {
+        if self.is_date() {
+            let date = item.object(self.deref).and_then(|(object, _)| self.identity(object)).map(|i| i.date.timestamp());
+            return SortValue::Number(date.unwrap_or(0));
+        }
+        if self.name == "objectsize" {
+            return SortValue::Number(item.object(self.deref).map_or(0, |(_, size)| size as i64));
+        }
+        SortValue::Text(self.value(item, now))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum SortValue {
+    Number(i64),
+    Text(String),
+}
+
+/// Compares with runs of digits taken as numbers, so `v1.9` sorts before
+/// `v1.10`.
+fn version_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let digits = |s: &str| s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        match (a.chars().next(), b.chars().next()) {
+            (None, None) => return Ordering::Equal,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let (na, nb) = (digits(a), digits(b));
+                let (x, y) = (a[..na].trim_start_matches('0'), b[..nb].trim_start_matches('0'));
+                let order = x.len().cmp(&y.len()).then_with(|| x.cmp(y));
+                if order != Ordering::Equal {
+                    return order;
+                }
+                a = &a[na..];
+                b = &b[nb..];
+            }
+            (Some(x), Some(y)) if x == y => {
+                a = &a[x.len_utf8()..];
+                b = &b[y.len_utf8()..];
+            }
+            (x, y) => return x.cmp(&y),
+        }
+    }
+}
+
+/// A `--sort` key: an atom, `-` to reverse it and `version:` to compare
+/// numbers in it as numbers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortKey {
+    atom: Atom,
+    reverse: bool,
+    version: bool,
+}
+
+impl FromStr for SortKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (reverse, s) = match s.strip_prefix('-') {
+            Some(s) => (true, s),
+            None => (false, s),
+        };
+        let (version, s) = match s.strip_prefix("version:").or_else(|| s.strip_prefix("v:")) {
+            Some(s) => (true, s),
+            None => (false, s),
+        };
+        Ok(SortKey { atom: s.parse()?, reverse, version })
+    }
+}
+
+/// Sorts `items` by `keys`, the last key first as in git, then by name.
+pub fn sort_refs(items: &mut [RefItem], keys: &[SortKey]) {
+    let now = now();
+    items.sort_by(|a, b| {
+        for key in keys.iter().rev() {
+            let order = match (key.atom.key(a, &now), key.atom.key(b, &now)) {
+                (SortValue::Text(x), SortValue::Text(y)) if key.version => version_cmp(&x, &y),
+                (x, y) => x.cmp(&y),
+            };
+            let order = if key.reverse { order.reverse() } else { order };
+            if order != Ordering::Equal {
+                return order;
+            }
+        }
+        a.name.cmp(&b.name)
+    });
+}
+
+fn now() -> DateTime<FixedOffset> {
+    let now = Local::now();
+    now.with_timezone(now.offset())
+}
+
+/// A parsed `--format`: literal text and atoms in turn.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RefFormat {
+    pieces: Vec<Piece>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Piece {
+    Text(Vec<u8>),
+    Atom(Atom),
+}
+
+impl FromStr for RefFormat {
+    type Err = Error;
+
+    /// Reads `%(atom)`, `%%` and `%xx` hex bytes; any other `%` stands
+    /// for itself.
+    fn from_str(s: &str) -> Result<Self> {
+        let mut pieces = vec![];
+        let mut text = vec![];
+        let mut rest = s;
+        while let Some(n) = rest.find('%') {
+            text.extend_from_slice(&rest.as_bytes()[..n]);
+            rest = &rest[n + 1..];
+            if let Some(atom) = rest.strip_prefix('(') {
+                let close = atom.find(')').ok_or_else(|| format!("malformed format string {}", s))?;
+                pieces.push(Piece::Text(std::mem::take(&mut text)));
+                pieces.push(Piece::Atom(atom[..close].parse()?));
+                rest = &atom[close + 1..];
+            } else if let Some(rest2) = rest.strip_prefix('%') {
+                text.push(b'%');
+                rest = rest2;
+            } else if let Some(byte) = rest.get(..2).and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                text.push(byte);
+                rest = &rest[2..];
+            } else {
+                text.push(b'%');
+            }
+        }
+        text.extend_from_slice(rest.as_bytes());
+        pieces.push(Piece::Text(text));
+        Ok(RefFormat { pieces })
+    }
+}
+
+impl RefFormat {
+    /// The line for `item`, without its newline.
+    pub fn format(&self, item: &RefItem) -> Vec<u8> {
+        let now = now();
+        let mut out = vec![];
+        for piece in &self.pieces {
+            match piece {
+                Piece::Text(text) => out.extend_from_slice(text),
+                Piece::Atom(atom) => out.extend_from_slice(atom.value(item, &now).as_bytes()),
+            }
+        }
+        out
+    }
+
+    fn wants_upstream(&self) -> bool {
+        self.pieces.iter().any(|piece| matches!(piece, Piece::Atom(atom) if atom.name == "upstream"))
+    }
+}
+
+/// Which refs `for-each-ref` lists.
+#[derive(Debug, Clone, Default)]
+pub struct RefFilter {
+    /// Full names or prefixes ending at a `/`, or wildcard patterns; all
+    /// refs when empty.
+    pub patterns: Vec<String>,
+    /// Refs pointing at one of these, directly or through a tag.
+    pub points_at: Vec<Id>,
+    /// Refs whose commit is reachable from one of these.
+    pub merged: Vec<Id>,
+    pub no_merged: Vec<Id>,
+    /// Refs whose commit has one of these in its history.
+    pub contains: Vec<Id>,
+    pub no_contains: Vec<Id>,
+}
+
+impl RefFilter {
+    fn matches_name(&self, name: &str) -> bool {
+        self.patterns.is_empty() || self.patterns.iter().any(|pattern| {
+            let pattern = pattern.trim_end_matches('/');
+            name == pattern
+                || name.strip_prefix(pattern).is_some_and(|rest| rest.starts_with('/'))
+                || wildmatch(pattern, name, true, false)
+        })
+    }
+}
+
+impl<FS: FileSystem> FileRepository<FS> {
+    /// The upstream of branch `name`: `branch.<name>.merge` as the local
+    /// ref the remote's fetch refspecs map it to.
+    pub fn upstream(&self, name: &str) -> Result<Option<String>> {
+        let branch = match name.strip_prefix("refs/heads/") {
+            Some(branch) => branch,
+            None => return Ok(None),
+        };
+        let config = self.config()?;
+        let (remote, merge) = match (config.get(&format!("branch.{}.remote", branch))?, config.get(&format!("branch.{}.merge", branch))?) {
+            (Some(remote), Some(merge)) => (remote, merge),
+            _ => return Ok(None),
+        };
+        if remote == "." {
+            return Ok(Some(merge.to_owned()));
+        }
+        for entry in config.get_all(&format!("remote.{}.fetch", remote))? {
+            let refspec = entry.value.as_deref().unwrap_or_default().trim_start_matches('+');
+            let (src, dst) = match refspec.split_once(':') {
+                Some(sides) => sides,
+                None => continue,
+            };
+            let mapped = match (src.split_once('*'), dst.split_once('*')) {
+                (Some((prefix, suffix)), Some((to_prefix, to_suffix))) => merge.strip_prefix(prefix)
+                    .and_then(|rest| rest.strip_suffix(suffix))
+                    .map(|matched| format!("{}{}{}", to_prefix, matched, to_suffix)),
+                (None, None) if src == merge => Some(dst.to_owned()),
+                _ => None,
+            };
+            if mapped.is_some() {
+                return Ok(mapped);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reads the refs `filter` selects, in name order, with what `format`
+    /// needs of them.
+    pub fn filter_refs(&self, filter: &RefFilter, format: &RefFormat) -> Result<Vec<RefItem>> {
+        let head = self.current_branch()?;
+        let merged = self.reachable(&filter.merged)?;
+        let no_merged = self.reachable(&filter.no_merged)?;
+        let mut items = vec![];
+        for name in self.ref_names()? {
+            if !filter.matches_name(&name) {
+                continue;
+            }
+            let (target, id) = match self.resolve_symbolic(&name) {
+                Ok((target, Some(id))) => (target, id),
+                _ => continue,
+            };
+            let object = self.find_object(&id)?;
+            let size = object.header().length;
+            let object = Object::from(self, &object)?;
+            let tagged = match &object {
+                Object::Tag(tag) => {
+                    let target = self.find_object(tag.object())?;
+                    Some((Object::from(self, &target)?, target.header().length))
+                }
+                _ => None,
+            };
+            let symref = match self.read_ref(&name)? {
+                Some(RefTarget::Symbolic(_)) => Some(target),
+                _ => None,
+            };
+            let upstream = if format.wants_upstream() { self.upstream(&name)? } else { None };
+            let item = RefItem { head: head.as_deref() == Some(name.as_str()), name, id, symref, upstream, object, size, tagged };
+
+            if !filter.points_at.is_empty() {
+                let peeled = item.tagged.as_ref().map(|(object, _)| object.id());
+                if !filter.points_at.iter().any(|id| *id == item.id || Some(id) == peeled) {
+                    continue;
+                }
+            }
+            let commits = !(filter.merged.is_empty() && filter.no_merged.is_empty()
+                && filter.contains.is_empty() && filter.no_contains.is_empty());
+            if commits {
+                let commit = match item.commit(self) {
+                    Some(commit) => commit,
+                    None => continue,
+                };
+                let contains = |bases: &[Id]| -> Result<bool> {
+                    for base in bases {
+                        if self.is_ancestor(base, &commit)? {
+                            return Ok(true);
+                        }
+                    }
+                    Ok(false)
+                };
+                if (!filter.merged.is_empty() && !merged.contains(&commit))
+                    || (!filter.no_merged.is_empty() && no_merged.contains(&commit))
+                    || (!filter.contains.is_empty() && !contains(&filter.contains)?)
+                    || (!filter.no_contains.is_empty() && contains(&filter.no_contains)?) {
+                    continue;
+                }
+            }
+            items.push(item);
+        }
+        Ok(items)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_atoms_and_sort_keys() {
+        assert_eq!(strip("refs/heads/feature/x", 2, true), "feature/x");
+        assert_eq!(strip("refs/heads/feature/x", -1, true), "x");
+        assert_eq!(strip("refs/heads/feature/x", 1, false), "refs/heads/feature");
+        assert_eq!(version_cmp("v1.9", "v1.10"), Ordering::Less);
+        assert_eq!(version_cmp("v1.010", "v1.9"), Ordering::Greater);
+        assert_eq!(version_cmp("a", "b"), Ordering::Less);
+        assert!("refname:short".parse::<Atom>().is_ok());
+        assert!("*objectname".parse::<Atom>().is_ok());
+        assert_eq!("bogus".parse::<Atom>().unwrap_err().to_string(), "unknown field name: bogus");
+        assert_eq!("refname:bogus".parse::<Atom>().unwrap_err().to_string(),
+                   "unrecognized %(refname:bogus) argument: bogus");
+        let key: SortKey = "-version:refname".parse().unwrap();
+        assert!(key.reverse && key.version);
+        let format: RefFormat = "%(refname)%%%00x%q".parse().unwrap();
+        assert_eq!(format.pieces.len(), 3);
+        assert_eq!(format.pieces[2], Piece::Text(b"%\0x%q".to_vec()));
+    }
+}
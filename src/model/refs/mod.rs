@@ -0,0 +1,250 @@
+use std::path::{Path, PathBuf};
+use std::fmt::{Display, Formatter};
+use std::io::Read;
+use std::str::FromStr;
+use crate::errors::*;
+use crate::fs::FileSystem;
+use crate::model::id::Id;
+use crate::model::repository::FileRepository;
+
+pub mod packed;
+pub mod reflog;
+pub mod transaction;
+
+pub use self::packed::{PackedRef, PackedRefs};
+pub use self::reflog::{Reflog, ReflogEntry};
+pub use self::transaction::{PreparedTransaction, RefChange, RefTransaction, RefUpdate};
+
+/// How many symbolic refs are followed before giving up, as in git.
+const MAX_SYMREF_DEPTH: usize = 5;
+
+/// What a ref file contains: an object id, or `ref: <name>` for a symbolic ref.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RefTarget {
+    Direct(Id),
+    Symbolic(String),
+}
+
+impl RefTarget {
+    pub fn parse(name: &str, content: &str) -> Result<RefTarget> {
+        let content = content.trim_end();
+        if let Some(target) = content.strip_prefix("ref:") {
+            Ok(RefTarget::Symbolic(target.trim().to_owned()))
+        } else {
+            match Id::from_str(content) {
+                Ok(id) if id.is_full() => Ok(RefTarget::Direct(id)),
+                _ => Err(ErrorKind::BrokenRef(name.to_owned()).into()),
+            }
+        }
+    }
+}
+
+impl Display for RefTarget {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        match self {
+            RefTarget::Direct(id) => write!(f, "{}", id),
+            RefTarget::Symbolic(name) => write!(f, "ref: {}", name),
+        }
+    }
+}
+
+/// Pseudorefs such as `HEAD`, `ORIG_HEAD` or `FETCH_HEAD` live directly in
+/// the git directory and are all caps.
+pub fn is_pseudoref(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_uppercase() || c == '_')
+}
+
+/// The rules of `git check-ref-format`, for a name with at least two
+/// components.
+pub fn check_ref_format(name: &str) -> bool {
+    if name.is_empty() || name == "@" || name.ends_with('/') || name.ends_with('.')
+        || name.contains("@{") || !name.contains('/') {
+        return false;
+    }
+    name.split('/').all(|part| {
+        !part.is_empty()
+            && !part.starts_with('.')
+            && !part.ends_with(".lock")
+            && !part.contains("..")
+            && !part.chars().any(|c| c.is_ascii_control() || " ~^:?*[\\".contains(c))
+    })
+}
+
+/// Whether `name` may be written as a ref: a pseudoref or a well-formed
+/// name below `refs/`.
+pub fn is_valid_ref_name(name: &str) -> bool {
+    is_pseudoref(name) || (name.starts_with("refs/") && check_ref_format(name))
+}
+
+/// Strips `refs/heads/`, `refs/tags/`, `refs/remotes/` or `refs/`.
+pub fn shorten_ref_name(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/", "refs/"].iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+/// Refs that belong to a single worktree rather than the whole repository.
+fn is_per_worktree(name: &str) -> bool {
+    is_pseudoref(name) || name.starts_with("refs/worktree/")
+        || name.starts_with("refs/bisect/") || name.starts_with("refs/rewritten/")
+}
+
+impl<FS: FileSystem> FileRepository<FS> {
+    pub(crate) fn ref_path(&self, name: &str) -> PathBuf {
+        if is_per_worktree(name) {
+            self.git_dir().join(name)
+        } else {
+            self.common_dir().join(name)
+        }
+    }
+
+    pub(crate) fn reflog_path(&self, name: &str) -> PathBuf {
+        if is_per_worktree(name) {
+            self.git_dir().join("logs").join(name)
+        } else {
+            self.common_dir().join("logs").join(name)
+        }
+    }
+
+    pub(crate) fn packed_refs_path(&self) -> PathBuf {
+        self.common_dir().join("packed-refs")
+    }
+
+    pub fn packed_refs(&self) -> Result<PackedRefs> {
+        match read_to_string(&self.fs, &self.packed_refs_path())? {
+            Some(text) => PackedRefs::parse(&text),
+            None => Ok(PackedRefs::default()),
+        }
+    }
+
+    /// Reads a ref without following symbolic refs, looking at the loose
+    /// file first and `packed-refs` second.
+    pub fn read_ref(&self, name: &str) -> Result<Option<RefTarget>> {
+        let path = self.ref_path(name);
+        if self.fs.is_file(&path) {
+            if let Some(content) = read_to_string(&self.fs, &path)? {
+                return RefTarget::parse(name, &content).map(Some);
+            }
+        }
+        if is_per_worktree(name) {
+            return Ok(None);
+        }
+        Ok(self.packed_refs()?.find(name).map(|r| RefTarget::Direct(r.id.clone())))
+    }
+
+    /// Follows symbolic refs from `name`, returning the name of the last ref
+    /// in the chain and the id it points at, if it exists.
+    pub fn resolve_symbolic(&self, name: &str) -> Result<(String, Option<Id>)> {
+        let mut name = name.to_owned();
+        for _ in 0..=MAX_SYMREF_DEPTH {
+            match self.read_ref(&name)? {
+                Some(RefTarget::Symbolic(target)) => name = target,
+                Some(RefTarget::Direct(id)) => return Ok((name, Some(id))),
+                None => return Ok((name, None)),
+            }
+        }
+        Err(ErrorKind::BrokenRef(name).into())
+    }
+
+    pub fn resolve_ref(&self, name: &str) -> Result<Option<Id>> {
+        Ok(self.resolve_symbolic(name)?.1)
+    }
+
+    /// The names of all refs below `refs/`, loose and packed, sorted.
+    pub fn ref_names(&self) -> Result<Vec<String>> {
+        let mut names: Vec<String> = self.packed_refs()?.iter().map(|r| r.name.clone()).collect();
+        let root = self.common_dir();
+        let mut dirs = vec![root.join("refs")];
+        while let Some(dir) = dirs.pop() {
+            for path in self.fs.read_dir(&dir) {
+                if self.fs.is_dir(&path) {
+                    dirs.push(path);
+                } else if let Ok(name) = path.strip_prefix(root) {
+                    let name = name.to_string_lossy().replace('\\', "/");
+                    if !name.ends_with(".lock") {
+                        names.push(name);
+                    }
+                }
+            }
+        }
+        names.sort();
+        names.dedup();
+        Ok(names)
+    }
+
+    /// Expands a short name the way `git rev-parse` does, trying `<name>`,
+    /// `refs/<name>`, `refs/tags/<name>`, `refs/heads/<name>`,
+    /// `refs/remotes/<name>` and `refs/remotes/<name>/HEAD` in that order.
+    pub fn dwim_ref(&self, name: &str) -> Result<Option<(String, Id)>> {
+        let candidates = [
+            name.to_owned(),
+            format!("refs/{}", name),
+            format!("refs/tags/{}", name),
+            format!("refs/heads/{}", name),
+            format!("refs/remotes/{}", name),
+            format!("refs/remotes/{}/HEAD", name),
+        ];
+        for candidate in candidates.iter() {
+            if candidate.as_str() == name && !is_pseudoref(name) && !name.starts_with("refs/") {
+                continue;
+            }
+            if let Some(id) = self.resolve_ref(candidate)? {
+                return Ok(Some((candidate.to_owned(), id)));
+            }
+        }
+        Ok(None)
+    }
+}
+
+pub(crate) fn read_to_string<FS: FileSystem>(fs: &FS, path: &Path) -> Result<Option<String>> {
+    if !fs.is_file(path) {
+        return Ok(None);
+    }
+    let mut text = String::new();
+    fs.read_file(path)?.read_to_string(&mut text)?;
+    Ok(Some(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::MemFs;
+
+    const MASTER: &str = "a6952adde41289267215c9cdd0487df025214952";
+
+    #[test]
+    fn test_check_ref_format() {
+        assert!(is_valid_ref_name("refs/heads/feature/x"));
+        assert!(is_valid_ref_name("HEAD"));
+        assert!(is_valid_ref_name("ORIG_HEAD"));
+        assert!(!is_valid_ref_name("refs/heads/a..b"));
+        assert!(!is_valid_ref_name("refs/heads/.hidden"));
+        assert!(!is_valid_ref_name("refs/heads/x.lock"));
+        assert!(!is_valid_ref_name("refs/heads/x@{1}"));
+        assert!(!is_valid_ref_name("refs/heads/a b"));
+        assert!(!is_valid_ref_name("refs/heads//x"));
+        assert!(!is_valid_ref_name("heads/master"));
+    }
+
+    #[test]
+    fn test_read_loose_and_packed_refs() {
+        let mut repo = FileRepository::<MemFs>::default();
+        repo.add_file("HEAD", b"ref: refs/heads/master\n".to_vec());
+        repo.add_file("packed-refs", format!("# pack-refs with: peeled fully-peeled sorted \n\
+                                              {} refs/heads/master\n", MASTER).into_bytes());
+        assert_eq!(repo.read_ref("HEAD").unwrap(),
+                   Some(RefTarget::Symbolic("refs/heads/master".to_owned())));
+        assert_eq!(repo.resolve_symbolic("HEAD").unwrap(),
+                   ("refs/heads/master".to_owned(), MASTER.parse().ok()));
+        assert_eq!(repo.dwim_ref("master").unwrap().map(|r| r.0),
+                   Some("refs/heads/master".to_owned()));
+
+        repo.add_file("refs/heads/master", b"00b05df6a04840cd719b750ed53db08c8a1a4624\n".to_vec());
+        assert_eq!(repo.resolve_ref("HEAD").unwrap(),
+                   "00b05df6a04840cd719b750ed53db08c8a1a4624".parse().ok());
+        repo.add_file("refs/heads/broken", b"not an id\n".to_vec());
+        assert!(repo.read_ref("refs/heads/broken").is_err());
+        assert_eq!(repo.read_ref("refs/heads/missing").unwrap(), None);
+        assert_eq!(repo.ref_names().unwrap(), ["refs/heads/broken", "refs/heads/master"]);
+    }
+}
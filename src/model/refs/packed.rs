@@ -0,0 +1,96 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use crate::errors::*;
+use crate::model::id::Id;
+
+const DEFAULT_HEADER: &str = "# pack-refs with: peeled fully-peeled sorted ";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PackedRef {
+    pub name: String,
+    pub id: Id,
+    /// For annotated tags, the object the tag ultimately points at.
+    pub peeled: Option<Id>,
+}
+
+/// The content of `packed-refs`: one `<id> <name>` line per ref, each
+/// optionally followed by a `^<peeled id>` line.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PackedRefs {
+    header: Option<String>,
+    refs: Vec<PackedRef>,
+}
+
+impl PackedRefs {
+    pub fn parse(text: &str) -> Result<PackedRefs> {
+        let mut packed = PackedRefs::default();
+        for (number, line) in text.lines().enumerate() {
+            let bad_line = || Error::from(format!("unexpected line in packed-refs: {}", number + 1));
+            if line.starts_with('#') {
+                if number == 0 {
+                    packed.header = Some(line.to_owned());
+                }
+            } else if let Some(peeled) = line.strip_prefix('^') {
+                let last = packed.refs.last_mut().ok_or_else(bad_line)?;
+                last.peeled = Some(Id::from_str(peeled).chain_err(bad_line)?);
+            } else if !line.is_empty() {
+                let mut parts = line.splitn(2, ' ');
+                let id = parts.next().and_then(|id| Id::from_str(id).ok()).ok_or_else(bad_line)?;
+                let name = parts.next().ok_or_else(bad_line)?;
+                packed.refs.push(PackedRef { name: name.to_owned(), id, peeled: None });
+            }
+        }
+        Ok(packed)
+    }
+
+    pub fn find(&self, name: &str) -> Option<&PackedRef> {
+        self.refs.iter().find(|r| r.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=&PackedRef> {
+        self.refs.iter()
+    }
+
+    /// Removes `name`, returning whether it was present.
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.refs.len();
+        self.refs.retain(|r| r.name != name);
+        self.refs.len() != len
+    }
+}
+
+impl Display for PackedRefs {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        writeln!(f, "{}", self.header.as_deref().unwrap_or(DEFAULT_HEADER))?;
+        let mut refs: Vec<&PackedRef> = self.refs.iter().collect();
+        refs.sort_by(|a, b| a.name.cmp(&b.name));
+        for r in refs {
+            writeln!(f, "{} {}", r.id, r.name)?;
+            if let Some(peeled) = &r.peeled {
+                writeln!(f, "^{}", peeled)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_packed_refs_round_trip() {
+        let text = "# pack-refs with: peeled fully-peeled sorted \n\
+                    00b05df6a04840cd719b750ed53db08c8a1a4624 refs/heads/master\n\
+                    a6952adde41289267215c9cdd0487df025214952 refs/tags/v1\n\
+                    ^00b05df6a04840cd719b750ed53db08c8a1a4624\n";
+        let mut packed = PackedRefs::parse(text).unwrap();
+        assert_eq!(packed.find("refs/tags/v1").unwrap().peeled,
+                   "00b05df6a04840cd719b750ed53db08c8a1a4624".parse().ok());
+        assert_eq!(packed.to_string(), text);
+        assert!(packed.remove("refs/tags/v1"));
+        assert!(!packed.remove("refs/tags/v1"));
+        assert_eq!(packed.iter().count(), 1);
+        assert!(PackedRefs::parse("^00b05df6a04840cd719b750ed53db08c8a1a4624\n").is_err());
+    }
+}
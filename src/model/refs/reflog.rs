@@ -0,0 +1,213 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+use std::io::Read;
+use crate::errors::*;
+use crate::config::Config;
+use crate::fs::{FileSystem, LockFile};
+use crate::model::commit::{parse_identity, Identity};
+use crate::model::id::Id;
+use crate::model::repository::FileRepository;
+
+/// One line of `logs/<ref>`: `<old> <new> <identity>\t<message>`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReflogEntry {
+    pub old: Id,
+    pub new: Id,
+    pub identity: Identity,
+    pub message: String,
+}
+
+impl Display for ReflogEntry {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.old, self.new, self.identity)?;
+        if !self.message.is_empty() {
+            write!(f, "\t{}", self.message)?;
+        }
+        Ok(())
+    }
+}
+
+impl ReflogEntry {
+    pub fn parse(line: &[u8]) -> Result<ReflogEntry> {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let invalid = || Error::from(ErrorKind::InvalidReflogEntry(String::from_utf8_lossy(line).into_owned()));
+        let (head, message) = match line.iter().position(|b| *b == b'\t') {
+            Some(tab) => (&line[..tab], &line[tab + 1..]),
+            None => (line, &b""[..]),
+        };
+        let mut parts = head.splitn(3, |b| *b == b' ');
+        let mut next_id = || -> Result<Id> {
+            let id = std::str::from_utf8(parts.next().unwrap_or_default()).ok()
+                .and_then(|id| Id::from_str(id).ok())
+                .filter(Id::is_full);
+            id.ok_or_else(invalid)
+        };
+        let old = next_id()?;
+        let new = next_id()?;
+        let identity = parts.next().ok_or_else(invalid)?;
+        let (_, identity) = parse_identity(identity).map_err(|_| invalid())?;
+        Ok(ReflogEntry {
+            old,
+            new,
+            identity,
+            message: String::from_utf8_lossy(message).into_owned(),
+        })
+    }
+}
+
+/// The entries of one reflog, oldest first as they are stored.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Reflog {
+    entries: Vec<ReflogEntry>,
+}
+
+impl Reflog {
+    pub fn parse(content: &[u8]) -> Result<Reflog> {
+        let entries = content.split(|b| *b == b'\n')
+            .enumerate()
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(number, line)| ReflogEntry::parse(line)
+                .chain_err(|| format!("bad reflog entry at line {}", number + 1)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Reflog { entries })
+    }
+
+    /// Newest first, so that the n-th item is `<ref>@{n}`.
+    pub fn iter(&self) -> impl Iterator<Item=&ReflogEntry> {
+        self.entries.iter().rev()
+    }
+
+    /// `<ref>@{n}`: the n-th newest entry.
+    pub fn get(&self, n: usize) -> Option<&ReflogEntry> {
+        self.entries.iter().rev().nth(n)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Keeps the entries for which `keep` returns true.
+    pub fn retain<F: FnMut(&ReflogEntry) -> bool>(&mut self, keep: F) {
+        self.entries.retain(keep)
+    }
+}
+
+impl Display for Reflog {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        for entry in &self.entries {
+            writeln!(f, "{}", entry)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reflog messages are a single line; git folds newlines into spaces.
+pub(crate) fn normalize_message(message: &str) -> String {
+    message.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+impl<FS: FileSystem> FileRepository<FS> {
+    /// Whether an update of `name` should be logged, following
+    /// `core.logAllRefUpdates`: existing reflogs are always appended to.
+    pub(crate) fn should_log(&self, config: &Config, name: &str, create: bool) -> Result<bool> {
+        if create || self.fs.is_file(self.reflog_path(name)) {
+            return Ok(true);
+        }
+        let setting = match config.get("core.logAllRefUpdates")? {
+            Some(value) if value.eq_ignore_ascii_case("always") => return Ok(true),
+            Some(_) => config.get_bool("core.logAllRefUpdates")?.unwrap_or(false),
+            None => !self.is_bare(),
+        };
+        Ok(setting && (name == "HEAD" || name.starts_with("refs/heads/")
+            || name.starts_with("refs/remotes/") || name.starts_with("refs/notes/")))
+    }
+
+    pub fn has_reflog(&self, name: &str) -> bool {
+        self.fs.is_file(self.reflog_path(name))
+    }
+
+    /// The names of every ref with a reflog, `HEAD` first.
+    pub fn reflog_names(&self) -> Vec<String> {
+        let mut names = vec![];
+        let logs = self.common_dir().join("logs");
+        let mut dirs = vec![logs.join("refs")];
+        while let Some(dir) = dirs.pop() {
+            for path in self.fs.read_dir(&dir) {
+                if self.fs.is_dir(&path) {
+                    dirs.push(path);
+                } else if let Ok(name) = path.strip_prefix(&logs) {
+                    names.push(name.to_string_lossy().into_owned());
+                }
+            }
+        }
+        names.sort();
+        if self.has_reflog("HEAD") {
+            names.insert(0, "HEAD".to_owned());
+        }
+        names
+    }
+
+    /// Reads `logs/<name>`; a missing log is empty.
+    pub fn reflog(&self, name: &str) -> Result<Reflog> {
+        let path = self.reflog_path(name);
+        if !self.fs.is_file(&path) {
+            return Ok(Reflog::default());
+        }
+        let mut content = vec![];
+        self.fs.read_file(&path)?.read_to_end(&mut content)?;
+        Reflog::parse(&content).chain_err(|| format!("unable to read {}", path.display()))
+    }
+
+    /// Replaces `logs/<name>`, holding the ref's lock so no update can
+    /// append meanwhile.
+    pub fn write_reflog(&self, name: &str, reflog: &Reflog) -> Result<()> {
+        let _ref_lock = LockFile::acquire(&self.fs, self.ref_path(name))?;
+        let lock = LockFile::acquire(&self.fs, self.reflog_path(name))?;
+        lock.write(reflog.to_string().as_bytes())?;
+        lock.commit()
+    }
+
+    pub(crate) fn append_reflog(&self, name: &str, entry: &ReflogEntry) -> Result<()> {
+        let path = self.reflog_path(name);
+        if let Some(parent) = path.parent() {
+            self.fs.create_dir_all(parent)?;
+        }
+        self.fs.append_file(&path, format!("{}\n", entry).as_bytes())
+            .chain_err(|| format!("unable to append to {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_reflog() {
+        let content = b"0000000000000000000000000000000000000000 a6952adde41289267215c9cdd0487df025214952 \
+space dragon <allendragon@gmail.com> 1500000000 +0800\tcommit (initial): init
+a6952adde41289267215c9cdd0487df025214952 00b05df6a04840cd719b750ed53db08c8a1a4624 \
+space dragon <allendragon@gmail.com> 1500000100 +0800\tcheckout: moving from master to topic
+";
+        let reflog = Reflog::parse(content).unwrap();
+        assert_eq!(reflog.len(), 2);
+        let newest = reflog.get(0).unwrap();
+        assert_eq!(newest.message, "checkout: moving from master to topic");
+        assert_eq!(newest.identity.date.timestamp(), 1_500_000_100);
+        assert!(reflog.get(1).unwrap().old.is_zero());
+        assert_eq!(reflog.iter().map(|e| e.message.as_str()).collect::<Vec<_>>(),
+                   vec!["checkout: moving from master to topic", "commit (initial): init"]);
+        assert_eq!(reflog.to_string().as_bytes(), &content[..]);
+        assert!(Reflog::parse(b"garbage\n").is_err());
+
+        let sha256 = format!("{} {} A <a@x> 1500000000 +0000\tbranch: Created from HEAD\n",
+                             "0".repeat(64), "f05ee31c7c233f5d847ffdc91f7d6777abc004a33b3c0cd4c7d25de5f1e80c17");
+        let entry = ReflogEntry::parse(sha256.as_bytes()).unwrap();
+        assert!(entry.old.is_zero());
+        assert_eq!(entry.new.to_string().len(), 64);
+        assert_eq!(format!("{}\n", entry), sha256);
+    }
+}
@@ -0,0 +1,534 @@
+use std::path::{Path, PathBuf};
+use crate::errors::*;
+use crate::config::Config;
+use crate::fs::{FileSystem, LockFile};
+use crate::model::commit::Identity;
+use crate::model::id::Id;
+use crate::model::repository::{FileRepository, Repository};
+use super::{is_valid_ref_name, read_to_string, RefTarget};
+use super::reflog::{normalize_message, ReflogEntry};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RefChange {
+    Update(Id),
+    Symbolic(String),
+    Delete,
+    /// Only checks the old value.
+    Verify,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RefUpdate {
+    pub name: String,
+    pub change: RefChange,
+    /// The value the ref must have for the update to go ahead. The zero id
+    /// means the ref must not exist; `None` skips the check.
+    pub old: Option<Id>,
+    /// Update a symbolic ref itself instead of the ref it points at.
+    pub no_deref: bool,
+    pub message: String,
+}
+
+impl RefUpdate {
+    pub fn new(name: &str, change: RefChange) -> Self {
+        RefUpdate {
+            name: name.to_owned(),
+            change,
+            old: None,
+            no_deref: false,
+            message: String::new(),
+        }
+    }
+
+    pub fn old(mut self, old: Id) -> Self {
+        self.old = Some(old);
+        self
+    }
+
+    pub fn no_deref(mut self) -> Self {
+        self.no_deref = true;
+        self
+    }
+
+    pub fn message(mut self, message: &str) -> Self {
+        self.message = message.to_owned();
+        self
+    }
+}
+
+/// A set of ref updates applied all-or-nothing: every ref is locked and
+/// checked against its expected old value before anything is written.
+pub struct RefTransaction<'r, FS: FileSystem> {
+    repo: &'r FileRepository<FS>,
+    updates: Vec<RefUpdate>,
+    create_reflog: bool,
+}
+
+/// An update whose ref is locked and whose old value has been checked.
+struct Prepared<'r, FS: FileSystem> {
+    update: RefUpdate,
+    ref_name: String,
+    lock: LockFile<'r, FS>,
+    old_id: Option<Id>,
+    /// The loose file as it was, to put back if the transaction fails.
+    previous: Option<String>,
+}
+
+/// A transaction holding the locks of all its refs.
+pub struct PreparedTransaction<'r, FS: FileSystem> {
+    transaction: RefTransaction<'r, FS>,
+    prepared: Vec<Prepared<'r, FS>>,
+    packed_update: Option<(LockFile<'r, FS>, Option<String>)>,
+    config: Config,
+    identity: Identity,
+}
+
+/// Puts files back the way they were before a failed transaction, latest
+/// change first.
+fn restore<FS: FileSystem>(fs: &FS, undo: Vec<(PathBuf, Option<String>)>) {
+    for (path, content) in undo.into_iter().rev() {
+        let _ = match content {
+            Some(content) => fs.write_atomic(&path, content.as_bytes()),
+            None if fs.is_file(&path) => fs.remove_file(&path),
+            None => Ok(()),
+        };
+    }
+}
+
+impl<'r, FS: FileSystem> RefTransaction<'r, FS> {
+    pub fn new(repo: &'r FileRepository<FS>) -> Self {
+        RefTransaction { repo, updates: vec![], create_reflog: false }
+    }
+
+    pub fn push(&mut self, update: RefUpdate) -> &mut Self {
+        self.updates.push(update);
+        self
+    }
+
+    /// Create reflogs for the updated refs even where
+    /// `core.logAllRefUpdates` would not.
+    pub fn create_reflog(&mut self, create: bool) -> &mut Self {
+        self.create_reflog = create;
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.updates.is_empty()
+    }
+
+    pub fn commit(self) -> Result<()> {
+        self.prepare()?.commit()
+    }
+
+    /// Locks every ref and checks its old value without changing any, so
+    /// that other work can be done before committing.
+    pub fn prepare(mut self) -> Result<PreparedTransaction<'r, FS>> {
+        let repo = self.repo;
+        let mut prepared: Vec<Prepared<'r, FS>> = vec![];
+        for update in std::mem::take(&mut self.updates) {
+            let p = self.prepare_update(update, &prepared)?;
+            prepared.push(p);
+        }
+
+        // packed-refs is read only once locked, so no other writer's
+        // change to it is lost
+        let deleted: Vec<&str> = prepared.iter()
+            .filter(|p| p.update.change == RefChange::Delete)
+            .map(|p| p.ref_name.as_str())
+            .collect();
+        let mut packed_update = None;
+        if !deleted.is_empty() {
+            let lock = LockFile::acquire(&repo.fs, repo.packed_refs_path())?;
+            let previous = read_to_string(&repo.fs, &repo.packed_refs_path())?;
+            let mut packed = repo.packed_refs()?;
+            let mut changed = false;
+            for name in &deleted {
+                changed |= packed.remove(name);
+            }
+            if changed {
+                lock.write(packed.to_string().as_bytes())?;
+                packed_update = Some((lock, previous));
+            }
+        }
+        for p in &prepared {
+            match &p.update.change {
+                RefChange::Update(id) => p.lock.write(format!("{}\n", id).as_bytes())?,
+                RefChange::Symbolic(target) => p.lock.write(format!("ref: {}\n", target).as_bytes())?,
+                RefChange::Delete | RefChange::Verify => {}
+            }
+        }
+        // what the reflog needs is known before any ref moves
+        let config = repo.config()?;
+        let identity = Identity::committer(&config)?;
+        Ok(PreparedTransaction { transaction: self, prepared, packed_update, config, identity })
+    }
+
+    fn should_log(&self, config: &Config, name: &str) -> Result<bool> {
+        self.repo.should_log(config, name, self.create_reflog)
+    }
+
+    fn prepare_update(&self, update: RefUpdate, prepared: &[Prepared<'r, FS>]) -> Result<Prepared<'r, FS>> {
+        let repo = self.repo;
+        if !is_valid_ref_name(&update.name) {
+            return Err(ErrorKind::InvalidRefName(update.name).into());
+        }
+        let deref = !update.no_deref && !matches!(update.change, RefChange::Symbolic(_));
+        let ref_name = if deref {
+            repo.resolve_symbolic(&update.name)?.0
+        } else {
+            update.name.clone()
+        };
+        let reject = |reason: String| -> Error {
+            ErrorKind::RefUpdateRejected(ref_name.clone(), reason).into()
+        };
+        if prepared.iter().any(|p| p.ref_name == ref_name) {
+            return Err(reject("multiple updates for this ref are not allowed".to_owned()));
+        }
+        // one ref of the batch can't be a directory of another it creates
+        let creates = |change: &RefChange| matches!(change, RefChange::Update(_) | RefChange::Symbolic(_));
+        if creates(&update.change) {
+            let nested = |outer: &str, inner: &str| inner.starts_with(&format!("{}/", outer));
+            if let Some(other) = prepared.iter()
+                .find(|p| creates(&p.update.change) && (nested(&p.ref_name, &ref_name) || nested(&ref_name, &p.ref_name))) {
+                return Err(reject(format!("cannot process '{}' and '{}' at the same time", other.ref_name, ref_name)));
+            }
+        }
+        if let RefChange::Symbolic(target) = &update.change {
+            if !is_valid_ref_name(target) {
+                return Err(ErrorKind::InvalidRefName(target.to_owned()).into());
+            }
+        }
+        if let RefChange::Update(id) = &update.change {
+            if repo.get_object(id).is_none() {
+                return Err(reject(format!("trying to write ref with nonexistent object {}", id)));
+            }
+        }
+        if matches!(update.change, RefChange::Update(_) | RefChange::Symbolic(_)) {
+            self.check_conflicts(&ref_name)?;
+        }
+
+        let path = repo.ref_path(&ref_name);
+        if let Some(parent) = path.parent() {
+            repo.fs.create_dir_all(parent).chain_err(|| reject("unable to create directory".to_owned()))?;
+        }
+        let lock = LockFile::acquire(&repo.fs, &path)?;
+
+        let previous = read_to_string(&repo.fs, &path)?;
+        let current = repo.read_ref(&ref_name)?;
+        let old_id = match &current {
+            Some(RefTarget::Direct(id)) => Some(id.clone()),
+            Some(RefTarget::Symbolic(target)) => repo.resolve_ref(target)?,
+            None => None,
+        };
+        match (&update.old, &old_id) {
+            (Some(expected), Some(_)) if expected.is_zero() =>
+                return Err(reject("reference already exists".to_owned())),
+            (Some(expected), None) if !expected.is_zero() =>
+                return Err(reject(format!("unable to resolve reference, expected {}", expected))),
+            (Some(expected), Some(actual)) if expected != actual =>
+                return Err(reject(format!("is at {} but expected {}", actual, expected))),
+            _ => {}
+        }
+        Ok(Prepared { update, ref_name, lock, old_id, previous })
+    }
+
+    /// A ref can't be created where another ref is one of its directories,
+    /// nor where it would be a directory of another ref.
+    fn check_conflicts(&self, name: &str) -> Result<()> {
+        let repo = self.repo;
+        let mut prefix = String::new();
+        let parts: Vec<&str> = name.split('/').collect();
+        for part in &parts[..parts.len() - 1] {
+            prefix.push_str(part);
+            if prefix != "refs" && repo.read_ref(&prefix)?.is_some() {
+                return Err(ErrorKind::RefUpdateRejected(
+                    name.to_owned(), format!("'{}' exists", prefix)).into());
+            }
+            prefix.push('/');
+        }
+        let dir = format!("{}/", name);
+        let nested = repo.fs.is_dir(repo.ref_path(name))
+            && repo.fs.ls_files(repo.ref_path(name)).next().is_some();
+        if nested || repo.packed_refs()?.iter().any(|r| r.name.starts_with(&dir)) {
+            return Err(ErrorKind::RefUpdateRejected(
+                name.to_owned(), format!("there are refs below '{}'", name)).into());
+        }
+        Ok(())
+    }
+}
+
+impl<'r, FS: FileSystem> PreparedTransaction<'r, FS> {
+    /// Applies every update and writes its reflog, or on failure puts back
+    /// everything already written. Dropping it instead releases the locks
+    /// and changes nothing.
+    pub fn commit(self) -> Result<()> {
+        let PreparedTransaction { transaction, prepared, packed_update, config, identity } = self;
+        let repo = transaction.repo;
+
+        // every ref is still locked: apply them all, or none
+        let mut undo: Vec<(PathBuf, Option<String>)> = vec![];
+        let applied = (|| -> Result<()> {
+            let mut logs = vec![];
+            if let Some((lock, previous)) = packed_update {
+                let path = lock.path().to_path_buf();
+                lock.commit()?;
+                undo.push((path, previous));
+            }
+            for p in prepared {
+                let Prepared { update, ref_name, lock, old_id, previous } = p;
+                let path = repo.ref_path(&ref_name);
+                match &update.change {
+                    RefChange::Update(_) | RefChange::Symbolic(_) => {
+                        lock.commit()?;
+                        undo.push((path, previous));
+                        logs.push((update, ref_name, old_id));
+                    }
+                    RefChange::Delete => {
+                        if repo.fs.is_file(&path) {
+                            repo.fs.remove_file(&path)?;
+                        }
+                        undo.push((path, previous));
+                        lock.rollback()?;
+                        logs.push((update, ref_name, old_id));
+                    }
+                    RefChange::Verify => lock.rollback()?,
+                }
+            }
+
+            let head = repo.read_ref("HEAD")?;
+            for (update, ref_name, old_id) in logs {
+                let new_id = match &update.change {
+                    RefChange::Update(id) => Some(id.clone()),
+                    RefChange::Symbolic(target) => match repo.resolve_ref(target)? {
+                        Some(id) => Some(id),
+                        // pointing at an unborn branch leaves nothing to log
+                        None => continue,
+                    },
+                    _ => {
+                        let log = repo.reflog_path(&ref_name);
+                        if repo.fs.is_file(&log) {
+                            undo.push((log.clone(), read_to_string(&repo.fs, &log)?));
+                            repo.fs.remove_file(&log)?;
+                        }
+                        continue;
+                    }
+                };
+
+                let entry = ReflogEntry {
+                    old: old_id.unwrap_or_else(|| repo.object_format().null_id()),
+                    new: new_id.unwrap_or_else(|| repo.object_format().null_id()),
+                    identity: identity.clone(),
+                    message: normalize_message(&update.message),
+                };
+                let mut logged = vec![ref_name.as_str()];
+                if update.name != ref_name {
+                    logged.push(update.name.as_str());
+                }
+                let head_points_here = head == Some(RefTarget::Symbolic(ref_name.clone()));
+                if head_points_here && !logged.contains(&"HEAD") {
+                    logged.push("HEAD");
+                }
+                for name in logged {
+                    if transaction.should_log(&config, name)? {
+                        let log = repo.reflog_path(name);
+                        undo.push((log.clone(), read_to_string(&repo.fs, &log)?));
+                        repo.append_reflog(name, &entry)?;
+                    }
+                }
+            }
+            Ok(())
+        })();
+        if let Err(e) = applied {
+            restore(&repo.fs, undo);
+            return Err(e);
+        }
+
+        for (path, _) in &undo {
+            if !repo.fs.is_file(path) {
+                let stop = if path.starts_with(repo.common_dir().join("logs")) { "logs" } else { "refs" };
+                prune_empty_dirs(&repo.fs, path, &repo.common_dir().join(stop));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<FS: FileSystem> FileRepository<FS> {
+    pub fn transaction(&self) -> RefTransaction<'_, FS> {
+        RefTransaction::new(self)
+    }
+}
+
+/// Removes the now empty directories between `path` and `stop`.
+fn prune_empty_dirs<FS: FileSystem>(fs: &FS, path: &Path, stop: &Path) {
+    let mut dir = path.parent();
+    while let Some(d) = dir {
+        if d == stop || !d.starts_with(stop) || fs.remove_dir(d).is_err() {
+            break;
+        }
+        dir = d.parent();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::MemFs;
+    use crate::model::object::ObjectType;
+    use std::io::Read;
+    use std::str::FromStr;
+
+    const A: &str = "1234567890123456789012345678901234567890";
+    const B: &str = "abcdefabcdefabcdefabcdefabcdefabcdefabcd";
+
+    fn repo() -> FileRepository<MemFs> {
+        let mut repo = FileRepository::<MemFs>::default();
+        repo.add_file("HEAD", b"ref: refs/heads/master\n".to_vec());
+        repo.add_file("config", b"[core]\n\tlogAllRefUpdates = true\n".to_vec());
+        repo.add_loose(A, ObjectType::BLOB, b"");
+        repo.add_loose(B, ObjectType::BLOB, b"");
+        repo
+    }
+
+    fn read(repo: &FileRepository<MemFs>, path: &str) -> String {
+        let mut s = String::new();
+        repo.fs.read_file(path).unwrap().read_to_string(&mut s).unwrap();
+        s
+    }
+
+    fn id(s: &str) -> Id {
+        Id::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_update_through_head() {
+        let repo = repo();
+        let mut tx = repo.transaction();
+        tx.push(RefUpdate::new("HEAD", RefChange::Update(id(A))).old(Id::default()).message("commit (initial): one"));
+        tx.commit().unwrap();
+        assert_eq!(read(&repo, "refs/heads/master"), format!("{}\n", A));
+        assert_eq!(read(&repo, "HEAD"), "ref: refs/heads/master\n");
+        assert!(!repo.fs.is_file("refs/heads/master.lock"));
+
+        let mut tx = repo.transaction();
+        tx.push(RefUpdate::new("refs/heads/master", RefChange::Update(id(B))).old(id(A)));
+        tx.commit().unwrap();
+        let log = read(&repo, "logs/refs/heads/master");
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with(&format!("{} {} ", Id::default(), A)));
+        assert!(lines[0].ends_with("\tcommit (initial): one"));
+        assert!(lines[1].starts_with(&format!("{} {} ", A, B)));
+        assert_eq!(read(&repo, "logs/HEAD").lines().count(), 2);
+    }
+
+    #[test]
+    fn test_stale_old_value_rolls_back() {
+        let repo = repo();
+        let mut tx = repo.transaction();
+        tx.push(RefUpdate::new("refs/heads/master", RefChange::Update(id(A))));
+        tx.commit().unwrap();
+
+        let mut tx = repo.transaction();
+        tx.push(RefUpdate::new("refs/heads/topic", RefChange::Update(id(B))));
+        tx.push(RefUpdate::new("refs/heads/master", RefChange::Update(id(B))).old(id(B)));
+        match tx.commit() {
+            Err(e) => match e.kind() {
+                ErrorKind::RefUpdateRejected(name, _) => assert_eq!(name, "refs/heads/master"),
+                _ => panic!("unexpected error {}", e),
+            },
+            Ok(_) => panic!("stale update accepted"),
+        }
+        assert!(!repo.fs.is_file("refs/heads/topic"));
+        assert!(!repo.fs.is_file("refs/heads/topic.lock"));
+        assert!(!repo.fs.is_file("refs/heads/master.lock"));
+        assert_eq!(read(&repo, "refs/heads/master"), format!("{}\n", A));
+
+        let mut tx = repo.transaction();
+        tx.push(RefUpdate::new("refs/heads/master/sub", RefChange::Update(id(B))));
+        assert!(tx.commit().is_err());
+    }
+
+    #[test]
+    fn test_delete_packed_ref() {
+        let mut repo = repo();
+        repo.add_file("packed-refs", format!("# pack-refs with: peeled fully-peeled sorted \n\
+                                              {} refs/heads/master\n{} refs/tags/v1\n", A, B).into_bytes());
+        repo.add_file("refs/heads/master", format!("{}\n", B).into_bytes());
+        let mut tx = repo.transaction();
+        tx.push(RefUpdate::new("refs/heads/master", RefChange::Delete).old(id(B)));
+        tx.commit().unwrap();
+        assert_eq!(repo.read_ref("refs/heads/master").unwrap(), None);
+        assert_eq!(read(&repo, "packed-refs"),
+                   format!("# pack-refs with: peeled fully-peeled sorted \n{} refs/tags/v1\n", B));
+
+        let mut tx = repo.transaction();
+        tx.push(RefUpdate::new("HEAD", RefChange::Symbolic("refs/tags/v1".to_owned())).message("switch"));
+        tx.commit().unwrap();
+        assert_eq!(read(&repo, "HEAD"), "ref: refs/tags/v1\n");
+        assert_eq!(repo.resolve_ref("HEAD").unwrap(), Some(id(B)));
+    }
+
+    #[test]
+    fn test_nested_refs_in_one_batch() {
+        let repo = repo();
+        let mut tx = repo.transaction();
+        tx.push(RefUpdate::new("refs/heads/a", RefChange::Update(id(A))));
+        tx.push(RefUpdate::new("refs/heads/a/b", RefChange::Update(id(B))));
+        assert_eq!(tx.commit().unwrap_err().to_string(),
+                   "cannot lock ref 'refs/heads/a/b': cannot process 'refs/heads/a' and 'refs/heads/a/b' at the same time");
+        assert!(!repo.fs.is_file("refs/heads/a"));
+        assert!(!repo.fs.is_file("refs/heads/a.lock"));
+
+        let mut tx = repo.transaction();
+        tx.push(RefUpdate::new("refs/heads/ab", RefChange::Update(id(A))));
+        tx.push(RefUpdate::new("refs/heads/a", RefChange::Update(id(B))));
+        tx.commit().unwrap();
+    }
+
+    #[test]
+    fn test_locked_packed_refs_changes_nothing() {
+        let mut repo = repo();
+        repo.add_file("packed-refs", format!("{} refs/heads/old\n", A).into_bytes());
+        repo.add_file("refs/heads/master", format!("{}\n", A).into_bytes());
+        repo.add_file("packed-refs.lock", vec![]);
+        let mut tx = repo.transaction();
+        tx.push(RefUpdate::new("refs/heads/master", RefChange::Update(id(B))));
+        tx.push(RefUpdate::new("refs/heads/old", RefChange::Delete));
+        assert!(tx.commit().is_err());
+        assert_eq!(read(&repo, "refs/heads/master"), format!("{}\n", A));
+        assert_eq!(read(&repo, "packed-refs"), format!("{} refs/heads/old\n", A));
+        assert!(!repo.fs.is_file("refs/heads/master.lock"));
+        assert!(!repo.fs.is_file("refs/heads/old.lock"));
+    }
+
+    #[test]
+    fn test_failed_reflog_puts_refs_back() {
+        let mut repo = repo();
+        repo.add_file("refs/heads/master", format!("{}\n", A).into_bytes());
+        repo.add_file("logs/HEAD", b"previous\n".to_vec());
+        // a file where the branch's log directory belongs
+        repo.add_file("logs/refs/heads", vec![]);
+        let mut tx = repo.transaction();
+        tx.push(RefUpdate::new("HEAD", RefChange::Update(id(B))).message("commit: two"));
+        assert!(tx.commit().is_err());
+        assert_eq!(read(&repo, "refs/heads/master"), format!("{}\n", A));
+        assert_eq!(read(&repo, "logs/HEAD"), "previous\n");
+        assert!(!repo.fs.is_file("refs/heads/master.lock"));
+    }
+
+    #[test]
+    fn test_restore_undoes_in_reverse() {
+        let mut repo = repo();
+        repo.add_file("refs/heads/master", format!("{}\n", B).into_bytes());
+        repo.add_file("refs/heads/topic", format!("{}\n", B).into_bytes());
+        let undo = vec![
+            (PathBuf::from("refs/heads/master"), Some(format!("{}\n", A))),
+            (PathBuf::from("refs/heads/topic"), None),
+            (PathBuf::from("refs/heads/master"), Some("stale\n".to_owned())),
+        ];
+        restore(&repo.fs, undo);
+        assert_eq!(read(&repo, "refs/heads/master"), format!("{}\n", A));
+        assert!(!repo.fs.is_file("refs/heads/topic"));
+    }
+}
@@ -1,32 +1,86 @@
 use std::path::{Path, PathBuf};
 use flate2::bufread::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
 use super::object::GitObject;
-use super::id::Id;
+use super::id::{Id, ObjectFormat};
 use crate::fs::{FileSystem, OsFs, MemFs};
 
 use crate::errors::*;
 use std::str::FromStr;
-use crate::model::object::{parse_header};
-use std::io::{BufReader, BufRead, Write};
+use crate::model::object::{parse_header, ObjectHeader};
+use crate::fs::checksum::hash_object;
+use std::io::{BufReader, BufRead, Cursor, Write};
+use crate::fs::blob_reader::{BlobReader, SPILL_THRESHOLD};
+use crate::model::object::{Object, ObjectType};
+use crate::model::commit::Commit;
+use crate::model::tree::{Tree, TreeEntry};
 use crate::fs::pack_file::PackFile;
 use crate::fs::locator::Locator;
-use crate::fs::pack_idx::PackIdx;
-use std::convert::TryInto;
 use std::fmt::{Display, Formatter};
 use std::collections::HashMap;
 use crate::fs::content_reader::ContentReader;
+use crate::config::Config;
+use crate::model::discovery::{discover, DiscoveryEnv, RepositoryLayout};
+
+/// Git never writes longer delta chains; anything deeper is a loop.
+const MAX_DELTA_DEPTH: usize = 4095;
+
+/// Objects claim their own size, so buffers sized from it are capped.
+const MAX_PREALLOCATION: usize = 64 << 20;
 
 pub trait Repository {
     fn lookup(&self, id: &str) -> Option<GitObject>;
     fn get_object(&self, id: &Id) -> Option<GitObject>;
+    /// Like `get_object`, but fails with `NotFound` for a missing object
+    /// and with the cause for one that cannot be read.
+    fn find_object(&self, id: &Id) -> Result<GitObject>;
     fn read_content(&self, git_object: &GitObject) -> Result<(Vec<u8>)>;
     fn write_content(&self, git_object: &GitObject, writer: &mut dyn Write) -> Result<u64>;
+    /// Opens a blob for streaming reads with random access, without
+    /// loading it into memory.
+    fn open_blob(&self, id: &Id) -> Result<BlobReader<'_>>;
+    fn read_object(&self, id: &Id) -> Result<Object>;
+
+    fn find_commit(&self, id: &Id) -> Result<Commit>;
+    fn find_tree(&self, id: &Id) -> Result<Tree>;
+
+    /// Follows tags until an object of `kind`; a commit peels to its tree.
+    fn peel_to_kind(&self, id: &Id, kind: ObjectType) -> Result<Object> {
+        let mut object = self.read_object(id)?;
+        loop {
+            object = match object {
+                object if object.kind() == kind => return Ok(object),
+                Object::Tag(tag) => self.read_object(tag.object())?,
+                Object::Commit(commit) if kind == ObjectType::TREE => self.read_object(commit.tree())?,
+                object => return Err(ErrorKind::InvalidObjectType(id.clone(), kind, object.kind()).into()),
+            };
+        }
+    }
+
+    fn peel_to_commit(&self, id: &Id) -> Result<Commit> {
+        match self.peel_to_kind(id, ObjectType::COMMIT)? {
+            Object::Commit(commit) => Ok(commit),
+            object => Err(ErrorKind::InvalidObjectType(id.clone(), ObjectType::COMMIT, object.kind()).into()),
+        }
+    }
+
+    fn peel_to_tree(&self, id: &Id) -> Result<Tree> {
+        match self.peel_to_kind(id, ObjectType::TREE)? {
+            Object::Tree(tree) => Ok(tree),
+            object => Err(ErrorKind::InvalidObjectType(id.clone(), ObjectType::TREE, object.kind()).into()),
+        }
+    }
 }
 
 pub struct FileRepository<FS: FileSystem> {
     path: PathBuf,
     git_dir: PathBuf,
+    common_dir: PathBuf,
+    object_dir: PathBuf,
+    work_tree: Option<PathBuf>,
     is_bare: bool,
+    object_format: ObjectFormat,
     pub(crate) fs: FS,
     pub(crate) packfiles: HashMap<Id, PackFile>,
 }
@@ -38,6 +92,68 @@ impl<FS: FileSystem> Display for FileRepository<FS> {
 }
 
 impl<FS: FileSystem> FileRepository<FS> {
+    /// Reads the layered configuration for this repository.
+    pub fn config(&self) -> Result<Config> {
+        Config::open_repository(&self.fs, &self.git_dir, &self.common_dir)
+    }
+
+    /// Builds a repository from an already discovered layout.
+    pub fn from_layout(fs: FS, layout: RepositoryLayout) -> Result<Self> {
+        let path = layout.work_tree.clone().unwrap_or_else(|| layout.git_dir.clone());
+        let mut repo = FileRepository {
+            path,
+            git_dir: layout.git_dir,
+            common_dir: layout.common_dir,
+            object_dir: layout.object_dir,
+            work_tree: layout.work_tree,
+            is_bare: layout.is_bare,
+            object_format: ObjectFormat::Sha1,
+            fs,
+            packfiles: HashMap::default(),
+        };
+        if let Some(format) = repo.config()?.get("extensions.objectFormat")? {
+            repo.object_format = format.parse()?;
+        }
+        repo.scan_packs()?;
+        Ok(repo)
+    }
+
+    pub fn is_bare(&self) -> bool {
+        self.is_bare
+    }
+    pub fn path(&self) -> &Path {
+        self.path.as_path()
+    }
+    pub fn git_dir(&self) -> &Path {
+        self.git_dir.as_path()
+    }
+    pub fn common_dir(&self) -> &Path {
+        self.common_dir.as_path()
+    }
+    pub fn object_dir(&self) -> &Path {
+        self.object_dir.as_path()
+    }
+    pub fn work_tree(&self) -> Option<&Path> {
+        self.work_tree.as_deref()
+    }
+    /// The hash algorithm objects are named with, from `extensions.objectFormat`.
+    pub fn object_format(&self) -> ObjectFormat {
+        self.object_format
+    }
+
+    pub fn scan_packs(&mut self) -> Result<()> {
+        self.packfiles.clear();
+        let dir = self.object_dir.join("pack");
+        let packs: Vec<PathBuf> = self.fs.read_dir(&dir)
+            .filter(|p| p.extension().map(|ext| ext == "pack").unwrap_or(false))
+            .collect();
+        for path in packs {
+            let pack = PackFile::open(&self.fs, &path, self.object_format)?;
+            self.packfiles.insert(pack.id(), pack);
+        }
+        Ok(())
+    }
+
     pub fn lookup_packfile_by_prefix(&self, idstr: &str) -> Option<Id> {
         if idstr.len() <= 2 {
             return None;
@@ -59,7 +175,7 @@ impl<FS: FileSystem> FileRepository<FS> {
             return None;
         }
         let (prefix, rest) = idstr.split_at(2);
-        let objects_dir = self.git_dir.join("objects").join(prefix);
+        let objects_dir = self.object_dir.join(prefix);
         let files: Vec<PathBuf> = self.fs.ls_files(objects_dir.join(rest))
             .take(2).collect();
         if files.len() == 1 {
@@ -82,76 +198,110 @@ impl<FS: FileSystem> FileRepository<FS> {
         self.packfiles.values().find_map(|p| p.find_object(&id))
     }
 
-    pub fn read_loose_object(&self, id: &Id) -> Result<GitObject> {
-        if let Id::Partial(_) = id {
-            return Err(ErrorKind::BadId.into());
-        }
+    fn loose_path(&self, id: &Id) -> PathBuf {
         let id_string = id.to_string();
         let (prefix, rest) = id_string.split_at(2);
-        let path = self.git_dir
-            .join("objects")
+        self.object_dir
             .join(prefix)
-            .join(rest);
+            .join(rest)
+    }
+
+    pub fn read_loose_object(&self, id: &Id) -> Result<GitObject> {
+        if !id.is_full() {
+            return Err(ErrorKind::BadId(id.to_string()).into());
+        }
+        let path = self.loose_path(id);
         let file_reader = self.fs.read_file(&path)?;
         let mut reader = BufReader::new(
             ZlibDecoder::new(BufReader::new(file_reader))
         );
 
+        let corrupt = |reason: String| Error::from(ErrorKind::CorruptLooseObject(id.clone(), path.clone(), reason));
         let mut vec = Vec::new();
-        let offset = reader.read_until(0, &mut vec).chain_err(|| "read file failed.")?;
+        let offset = reader.read_until(0, &mut vec)
+            .map_err(|e| corrupt(format!("unable to inflate header: {}", e)))?;
         if let Ok((_, header)) = parse_header(&vec) {
             Ok(GitObject::new(id, header, Locator::LooseObject(path, offset)))
         } else {
-            Err(ErrorKind::ParseError.into())
+            Err(corrupt("unable to parse header".to_owned()))
         }
     }
 
-    pub fn read_content_by_id(&self, id: &Id) -> Result<ContentReader> {
-        if let Some(obj) = self.get_object(id) {
-            self.read_content(&obj.locator, obj.size())
-        } else {
-            Err(ErrorKind::NotBelongThisRepo.into())
+    /// Stores `content` as a loose object unless the repository already has
+    /// it, and returns its id.
+    pub fn write_object(&self, object_type: ObjectType, content: &[u8]) -> Result<Id> {
+        let id = hash_object(self.object_format, object_type.as_str(), content);
+        if self.find_object(&id).is_ok() {
+            return Ok(id);
+        }
+        let header = ObjectHeader { object_type, length: content.len() };
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&Vec::<u8>::from(header))?;
+        encoder.write_all(content)?;
+        let path = self.loose_path(&id);
+        if let Some(dir) = path.parent() {
+            self.fs.create_dir_all(dir)?;
         }
+        self.fs.write_atomic(&path, &encoder.finish()?)?;
+        Ok(id)
+    }
+
+    pub fn write_tree(&self, entries: &[TreeEntry]) -> Result<Id> {
+        self.write_object(ObjectType::TREE, &Tree::encode(entries))
+    }
+
+    pub fn read_content_by_id(&self, id: &Id) -> Result<ContentReader> {
+        let obj = self.find_object(id)?;
+        self.read_content(&obj.locator, obj.size())
     }
 
+    /// Follows the delta chain down to a whole object without recursing,
+    /// then applies the deltas from the bottom up.
     fn read_content(&self, locator: &Locator, size: usize) -> Result<ContentReader> {
-        match locator {
-            Locator::PackOfs(pack_id, offset, base_offset) => {
-                if let Some(pack) = self.packfiles.get(pack_id) {
-                    let (base_locator, _, base_len) =
-                        pack.read_object(*base_offset)?;
-                    let base = self.read_content(&base_locator, base_len)?;
-                    let obj = pack.read_object_content(*offset, size)?;
-                    let obj = obj.attach_base(base, size)?;
-                    Ok(obj)
-                } else {
-                    Err(ErrorKind::NotBelongThisRepo.into())
-                }
+        let mut deltas: Vec<(&PackFile, usize, usize)> = vec![];
+        let mut locator = locator.clone();
+        let mut size = size;
+        loop {
+            let (pack_id, offset) = match &locator {
+                Locator::PackOfs(pack_id, offset, _) | Locator::PackRef(pack_id, offset, _) => (pack_id, *offset),
+                _ => break,
+            };
+            let pack = self.packfiles.get(pack_id).ok_or(ErrorKind::NotBelongThisRepo)?;
+            if deltas.len() >= MAX_DELTA_DEPTH {
+                return Err(ErrorKind::CorruptPackEntry(pack.path().to_path_buf(), offset,
+                                                       "delta chain too deep".to_owned()).into());
             }
-            Locator::PackRef(pack_id, offset, ref_id) => {
-                if let Some(pack) = self.packfiles.get(pack_id) {
-                    let base =
-                        self.read_content_by_id(ref_id)?;
-                    let obj = pack.read_object_content(*offset, size)?;
-                    let obj = obj.attach_base(base, size)?;
-                    Ok(obj)
-                } else {
-                    Err(ErrorKind::NotBelongThisRepo.into())
+            deltas.push((pack, offset, size));
+            let (base_locator, base_size) = match &locator {
+                Locator::PackOfs(_, _, base_offset) => {
+                    let (base_locator, _, base_size) = pack.read_object(*base_offset)?;
+                    (base_locator, base_size)
                 }
-            }
-            Locator::Packfile(pack_id, offset) => {
-                if let Some(pack) = self.packfiles.get(pack_id) {
-                    let obj = pack.read_object_content(*offset, size)?;
-                    Ok(obj)
-                } else {
-                    Err(ErrorKind::NotBelongThisRepo.into())
+                Locator::PackRef(_, _, base_id) => {
+                    let base = self.find_object(base_id)?;
+                    let base_size = base.size();
+                    (base.locator, base_size)
                 }
+                _ => break,
+            };
+            locator = base_locator;
+            size = base_size;
+        }
+        let mut content = match &locator {
+            Locator::Packfile(pack_id, offset) => {
+                let pack = self.packfiles.get(pack_id).ok_or(ErrorKind::NotBelongThisRepo)?;
+                pack.read_object_content(*offset, size)?
             }
             Locator::LooseObject(path, offset) => {
                 let file_reader = self.fs.read_file(&path)?;
-                Ok(ContentReader::from_loose_file(file_reader, *offset, size))
+                ContentReader::from_loose_file(file_reader, *offset, size)?
             }
+            _ => return Err(ErrorKind::NotBelongThisRepo.into()),
+        };
+        for (pack, offset, size) in deltas.into_iter().rev() {
+            content = pack.read_object_content(offset, size)?.attach_base(content, size)?;
         }
+        Ok(content)
     }
 }
 
@@ -170,8 +320,23 @@ impl<FS: FileSystem> Repository for FileRepository<FS> {
         self.read_loose_object(id).ok().or_else(|| self.read_from_packfile(&id))
     }
 
+    fn find_object(&self, id: &Id) -> Result<GitObject> {
+        if !id.is_full() {
+            return Err(ErrorKind::BadId(id.to_string()).into());
+        }
+        if self.fs.is_file(self.loose_path(id)) {
+            return self.read_loose_object(id);
+        }
+        for pack in self.packfiles.values() {
+            if let Some(obj) = pack.lookup_object(id)? {
+                return Ok(obj);
+            }
+        }
+        Err(ErrorKind::NotFound(id.clone()).into())
+    }
+
     fn read_content(&self, git_object: &GitObject) -> Result<Vec<u8>> {
-        let mut buf = Vec::with_capacity(git_object.size());
+        let mut buf = Vec::with_capacity(git_object.size().min(MAX_PREALLOCATION));
         self.write_content(git_object, &mut buf)?;
         Ok(buf)
     }
@@ -181,13 +346,53 @@ impl<FS: FileSystem> Repository for FileRepository<FS> {
         let size = std::io::copy(&mut reader, writer)?;
         Ok(size)
     }
+
+    fn read_object(&self, id: &Id) -> Result<Object> {
+        Object::from(self, &self.find_object(id)?)
+    }
+
+    fn find_commit(&self, id: &Id) -> Result<Commit> {
+        Commit::from(self, &self.find_object(id)?)
+    }
+
+    fn find_tree(&self, id: &Id) -> Result<Tree> {
+        Tree::from(self, &self.find_object(id)?)
+    }
+
+    fn open_blob(&self, id: &Id) -> Result<BlobReader<'_>> {
+        let obj = self.find_object(id)?;
+        if obj.object_type() != ObjectType::BLOB {
+            return Err(obj.not_a(ObjectType::BLOB));
+        }
+        let size = obj.size() as u64;
+        let reader = match &obj.locator {
+            Locator::LooseObject(path, offset) => {
+                BlobReader::inflate(id, self.fs.read_file(path)?, *offset as u64, size)?
+            }
+            Locator::Packfile(pack_id, offset) => {
+                let pack = self.packfiles.get(pack_id).ok_or(ErrorKind::NotBelongThisRepo)?;
+                let mut data = Cursor::new(pack.data());
+                data.set_position(*offset as u64);
+                BlobReader::inflate(id, Box::new(data), 0, size)?
+            }
+            locator => {
+                let content = self.read_content(locator, obj.size())?;
+                BlobReader::materialize(id, content, SPILL_THRESHOLD)?
+            }
+        };
+        Ok(reader)
+    }
 }
 
 impl FileRepository<MemFs> {
     pub fn default() -> Self {
         FileRepository::<MemFs> {
             git_dir: Path::new("").to_path_buf(),
+            common_dir: Path::new("").to_path_buf(),
+            object_dir: Path::new("objects").to_path_buf(),
+            work_tree: None,
             is_bare: true,
+            object_format: ObjectFormat::Sha1,
             path: Path::new("").to_path_buf(),
             fs: MemFs::default(),
             packfiles: HashMap::new(),
@@ -196,75 +401,54 @@ impl FileRepository<MemFs> {
     pub fn add_file<P: AsRef<Path>>(&mut self, file_name: P, content: Vec<u8>) {
         self.fs.add_file(file_name, content);
     }
-}
 
-
-impl FileRepository<OsFs> {
-    pub fn is_bare(&self) -> bool {
-        self.is_bare
-    }
-    pub fn path(&self) -> &Path {
-        self.path.as_path()
-    }
-    pub fn git_dir(&self) -> &Path {
-        self.git_dir.as_path()
+    /// Stores a loose object under `id`, which need not be its hash, so
+    /// tests can make up short readable ids or corrupt objects.
+    #[cfg(test)]
+    pub(crate) fn add_loose(&mut self, id: &str, object_type: ObjectType, content: &[u8]) {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&Vec::<u8>::from(ObjectHeader { object_type, length: content.len() })).unwrap();
+        encoder.write_all(content).unwrap();
+        self.add_file(format!("objects/{}/{}", &id[..2], &id[2..]), encoder.finish().unwrap());
     }
 
-    pub fn scan_packs(&mut self) -> Result<()> {
-        self.packfiles.clear();
-        let dir = self.git_dir.join("objects").join("pack");
-        let readir = dir.read_dir()?;
-        for entry in readir {
-            if let Ok(e) = entry {
-                if let Some(ext) = e.path().extension() {
-                    if ext == "pack" {
-                        let pack = self.load_packfile(&e.path())?;
-                        self.packfiles.insert(pack.id(), pack);
-                    }
-                }
+    /// Stores a commit of `tree`, or of the empty tree, by `A <a@x>` at
+    /// `time`. It goes under the made-up `id` like `add_loose` if one is
+    /// given, under its hash otherwise.
+    #[cfg(test)]
+    pub(crate) fn add_commit(&mut self, id: Option<&str>, tree: Option<&Id>, parents: &[&Id], time: i64, message: &str) -> Id {
+        let tree = match tree {
+            Some(tree) => tree.clone(),
+            None => self.write_tree(&[]).unwrap(),
+        };
+        let mut content = format!("tree {}\n", tree);
+        for parent in parents {
+            content.push_str(&format!("parent {}\n", parent));
+        }
+        content.push_str(&format!("author A <a@x> {0} +0000\ncommitter A <a@x> {0} +0000\n\n{1}\n", time, message));
+        match id {
+            Some(id) => {
+                self.add_loose(id, ObjectType::COMMIT, content.as_bytes());
+                Id::parse(id, self.object_format).unwrap()
             }
+            None => self.write_object(ObjectType::COMMIT, content.as_bytes()).unwrap(),
         }
-        Ok(())
     }
+}
 
-    fn load_packfile(&mut self, path: &Path) -> Result<PackFile> {
-        let map_file = self.fs.map_file(path)?;
-        let mut packfile = PackFile::try_from(map_file)?;
-        let idx_path = path.with_extension("idx");
-        if idx_path.is_file() {
-            let idx_file = self.fs.read_file(idx_path)?;
-            let packidx: PackIdx = idx_file.try_into()?;
-            packfile.load_idx(packidx);
-        }
-        Ok(packfile)
-    }
 
+impl FileRepository<OsFs> {
+    /// Opens the repository containing `path`, searching parent directories
+    /// and honouring `GIT_DIR`, `GIT_WORK_TREE`, `GIT_COMMON_DIR`,
+    /// `GIT_OBJECT_DIRECTORY` and `GIT_CEILING_DIRECTORIES`.
     pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let repo_path = PathBuf::new().join(path);
-        let git_dir = repo_path.join(".git");
-        let fs = OsFs {
-            prefix: git_dir.clone()
-        };
-        let mut repo = if fs.is_dir(&git_dir) {
-            FileRepository {
-                git_dir,
-                is_bare: false,
-                path: repo_path,
-                fs,
-                packfiles: HashMap::default(),
-            }
-        } else if fs.is_dir(&repo_path) {
-            FileRepository {
-                git_dir: repo_path.clone(),
-                is_bare: true,
-                path: repo_path,
-                fs,
-                packfiles: HashMap::default(),
-            }
-        } else {
-            return Err(ErrorKind::InvalidRepository(repo_path).into());
-        };
-        repo.scan_packs()?;
-        Ok(repo)
+        FileRepository::discover(path, &DiscoveryEnv::from_env())
     }
-}
\ No newline at end of file
+
+    pub fn discover<P: AsRef<Path>>(path: P, env: &DiscoveryEnv) -> Result<Self> {
+        let fs = OsFs::new("/");
+        let layout = discover(&fs, path.as_ref(), env)?;
+        let fs = OsFs::new(&layout.common_dir);
+        FileRepository::from_layout(fs, layout)
+    }
+}
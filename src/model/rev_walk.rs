@@ -0,0 +1,222 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use crate::errors::*;
+use crate::fs::FileSystem;
+use crate::model::commit::Commit;
+use crate::model::id::Id;
+use crate::model::repository::{FileRepository, Repository};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Sorting {
+    /// Newest committer date first, like `git log`.
+    Date,
+    /// No parent before all of its children, and a merge's last parent's
+    /// line before its first's, like `git log --topo-order`.
+    Topo,
+}
+
+struct Queued {
+    time: i64,
+    seq: usize,
+    commit: Commit,
+}
+
+impl Queued {
+    fn key(&self) -> (i64, std::cmp::Reverse<usize>) {
+        (self.time, std::cmp::Reverse(self.seq))
+    }
+}
+
+impl PartialEq for Queued {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl Eq for Queued {}
+
+impl PartialOrd for Queued {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Queued {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+pub(crate) fn commit_time(commit: &Commit) -> i64 {
+    commit.committer().as_ref().map_or(0, |identity| identity.date.timestamp())
+}
+
+/// Walks the history behind the pushed commits, leaving out whatever the
+/// hidden ones reach. Commits with equal dates come out in the order
+/// they were found.
+pub struct RevWalk<'a, FS: FileSystem> {
+    repo: &'a FileRepository<FS>,
+    queue: BinaryHeap<Queued>,
+    seen: HashSet<Id>,
+    hidden: HashSet<Id>,
+    first_parent: bool,
+    sorting: Sorting,
+    /// Topo order is worked out in full on the first call, newest last.
+    sorted: Option<Vec<Commit>>,
+    seq: usize,
+}
+
+impl<'a, FS: FileSystem> RevWalk<'a, FS> {
+    /// Starts the walk at the commit `id` peels to.
+    pub fn push(&mut self, id: &Id) -> Result<()> {
+        let commit = self.repo.peel_to_commit(id)?;
+        self.enqueue(commit);
+        Ok(())
+    }
+
+    /// Leaves out the commit `id` peels to and everything it reaches.
+    pub fn hide(&mut self, id: &Id) -> Result<()> {
+        let commit = self.repo.peel_to_commit(id)?;
+        self.hidden.extend(self.repo.reachable(&[commit.id().clone()])?);
+        Ok(())
+    }
+
+    /// Follows only the first parent of merges.
+    pub fn first_parent(&mut self, first_parent: bool) {
+        self.first_parent = first_parent;
+    }
+
+    pub fn sorting(&mut self, sorting: Sorting) {
+        self.sorting = sorting;
+    }
+
+    fn enqueue(&mut self, commit: Commit) {
+        if self.seen.insert(commit.id().clone()) {
+            self.seq += 1;
+            self.queue.push(Queued { time: commit_time(&commit), seq: self.seq, commit });
+        }
+    }
+
+    fn parents<'c>(&self, commit: &'c Commit) -> &'c [Id] {
+        let parents = commit.parent();
+        if self.first_parent && !parents.is_empty() { &parents[..1] } else { parents }
+    }
+
+    fn next_by_date(&mut self) -> Result<Option<Commit>> {
+        while let Some(Queued { commit, .. }) = self.queue.pop() {
+            if self.hidden.contains(commit.id()) {
+                continue;
+            }
+            for parent in self.parents(&commit).to_vec() {
+                if !self.seen.contains(&parent) && !self.hidden.contains(&parent) {
+                    let parent = self.repo.find_commit(&parent)?;
+                    self.enqueue(parent);
+                }
+            }
+            return Ok(Some(commit));
+        }
+        Ok(None)
+    }
+
+    /// Kahn's algorithm over the whole walk with the commits whose
+    /// children have all come out kept on a stack, as git does, so the
+    /// last parent's line of history comes out before the first's.
+    fn topo_sort(&mut self) -> Result<Vec<Commit>> {
+        let mut commits = vec![];
+        while let Some(commit) = self.next_by_date()? {
+            commits.push(commit);
+        }
+        let index: HashMap<Id, usize> = commits.iter().enumerate().map(|(n, c)| (c.id().clone(), n)).collect();
+        let mut children = vec![0usize; commits.len()];
+        for commit in &commits {
+            for parent in self.parents(commit) {
+                if let Some(&n) = index.get(parent) {
+                    children[n] += 1;
+                }
+            }
+        }
+        let mut ready: Vec<usize> = (0..commits.len()).rev().filter(|&n| children[n] == 0).collect();
+        let mut order = vec![];
+        while let Some(n) = ready.pop() {
+            order.push(n);
+            for parent in self.parents(&commits[n]) {
+                if let Some(&p) = index.get(parent) {
+                    children[p] -= 1;
+                    if children[p] == 0 {
+                        ready.push(p);
+                    }
+                }
+            }
+        }
+        let mut slots: Vec<Option<Commit>> = commits.into_iter().map(Some).collect();
+        Ok(order.into_iter().rev().filter_map(|n| slots[n].take()).collect())
+    }
+}
+
+impl<'a, FS: FileSystem> Iterator for RevWalk<'a, FS> {
+    type Item = Result<Commit>;
+
+    fn next(&mut self) -> Option<Result<Commit>> {
+        match self.sorting {
+            Sorting::Date => self.next_by_date().transpose(),
+            Sorting::Topo => {
+                if self.sorted.is_none() {
+                    match self.topo_sort() {
+                        Ok(sorted) => self.sorted = Some(sorted),
+                        Err(e) => return Some(Err(e)),
+                    }
+                }
+                self.sorted.as_mut().and_then(Vec::pop).map(Ok)
+            }
+        }
+    }
+}
+
+impl<FS: FileSystem> FileRepository<FS> {
+    /// A walk with nothing pushed yet, in date order.
+    pub fn rev_walk(&self) -> RevWalk<'_, FS> {
+        RevWalk {
+            repo: self,
+            queue: BinaryHeap::new(),
+            seen: HashSet::new(),
+            hidden: HashSet::new(),
+            first_parent: false,
+            sorting: Sorting::Date,
+            sorted: None,
+            seq: 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::MemFs;
+
+    fn walk(repo: &FileRepository<MemFs>, tips: &[&Id], hide: &[&Id], sorting: Sorting) -> Vec<Id> {
+        let mut walk = repo.rev_walk();
+        walk.sorting(sorting);
+        for tip in tips {
+            walk.push(tip).unwrap();
+        }
+        for id in hide {
+            walk.hide(id).unwrap();
+        }
+        walk.map(|commit| commit.unwrap().id().clone()).collect()
+    }
+
+    #[test]
+    fn test_date_and_topo_order() {
+        let mut repo = FileRepository::<MemFs>::default();
+        let root = repo.add_commit(None, None, &[], 10, "10");
+        let a = repo.add_commit(None, None, &[&root], 30, "30");
+        // b's clock is behind its parent a
+        let b = repo.add_commit(None, None, &[&a], 20, "20");
+        let c = repo.add_commit(None, None, &[&a], 25, "25");
+        let merge = repo.add_commit(None, None, &[&b, &c], 40, "40");
+        let ids = |list: &[&Id]| list.iter().map(|&id| id.clone()).collect::<Vec<Id>>();
+        assert_eq!(walk(&repo, &[&merge], &[], Sorting::Date), ids(&[&merge, &c, &a, &b, &root]));
+        assert_eq!(walk(&repo, &[&merge], &[], Sorting::Topo), ids(&[&merge, &c, &b, &a, &root]));
+        assert_eq!(walk(&repo, &[&merge], &[&c], Sorting::Date), ids(&[&merge, &b]));
+    }
+}
@@ -0,0 +1,259 @@
+use std::collections::HashSet;
+use chrono::{DateTime, FixedOffset, Local};
+use crate::errors::*;
+use crate::fs::FileSystem;
+use crate::model::date::approxidate;
+use crate::model::id::Id;
+use crate::model::refs::{RefTarget, Reflog};
+use crate::model::repository::{FileRepository, Repository};
+
+impl<FS: FileSystem> FileRepository<FS> {
+    /// Resolves a revision the way `git rev-parse` does. Supported are object
+    /// ids and unique prefixes, ref names, `@` for `HEAD`, reflog selectors
+    /// (`<ref>@{<n>}`, `<ref>@{<date>}`, `@{<n>}` for the current branch and
+    /// `@{-<n>}` for the n-th previously checked out branch), followed by any
+    /// number of `^<n>` and `~<n>`.
+    pub fn rev_parse(&self, spec: &str) -> Result<Id> {
+        let bad = || Error::from(ErrorKind::BadRevision(spec.to_owned()));
+        let (base, suffix) = split_suffix(spec);
+        let mut id = self.resolve_base(base)?.ok_or_else(bad)?;
+
+        let mut rest = suffix;
+        while let Some(c) = rest.chars().next() {
+            if c != '^' && c != '~' {
+                return Err(bad());
+            }
+            rest = &rest[c.len_utf8()..];
+            let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+            let n: usize = if digits == 0 { 1 } else { rest[..digits].parse().map_err(|_| bad())? };
+            rest = &rest[digits..];
+            match c {
+                '^' if n == 0 => {}
+                '^' => id = self.parents(&id)?.get(n - 1).cloned().ok_or_else(bad)?,
+                _ => for _ in 0..n {
+                    id = self.parents(&id)?.first().cloned().ok_or_else(bad)?;
+                },
+            }
+        }
+        Ok(id)
+    }
+
+    fn resolve_base(&self, base: &str) -> Result<Option<Id>> {
+        if let Some(open) = base.find("@{") {
+            if !base.ends_with('}') {
+                return Ok(None);
+            }
+            let selector = &base[open + 2..base.len() - 1];
+            let name = &base[..open];
+            if let Some(n) = selector.strip_prefix('-') {
+                if !name.is_empty() {
+                    return Ok(None);
+                }
+                let n = n.parse().map_err(|_| ErrorKind::BadRevision(base.to_owned()))?;
+                let branch = self.previous_branch(n)?;
+                return Ok(self.dwim_ref(&branch)?.map(|r| r.1)
+                    .or_else(|| Id::parse(&branch, self.object_format()).ok().filter(Id::is_full)));
+            }
+            let ref_name = match name {
+                "" => self.resolve_symbolic("HEAD")?.0,
+                "@" => "HEAD".to_owned(),
+                name => match self.dwim_ref(name)? {
+                    Some((ref_name, _)) => ref_name,
+                    None => return Ok(None),
+                },
+            };
+            return self.reflog_selector(&ref_name, selector);
+        }
+        if base == "@" {
+            return self.resolve_ref("HEAD");
+        }
+        if let Ok(id) = Id::parse(base, self.object_format()) {
+            if id.is_full() {
+                return Ok(Some(id));
+            }
+        }
+        if let Some((_, id)) = self.dwim_ref(base)? {
+            return Ok(Some(id));
+        }
+        if base.len() >= 4 && base.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Ok(self.lookup(base).map(|obj| obj.id().to_owned()));
+        }
+        Ok(None)
+    }
+
+    /// `<ref>@{<n>}` or `<ref>@{<date>}`.
+    fn reflog_selector(&self, ref_name: &str, selector: &str) -> Result<Option<Id>> {
+        let reflog = self.reflog(ref_name)?;
+        if reflog.is_empty() {
+            return Err(format!("log for '{}' is empty", ref_name).into());
+        }
+        if let Ok(n) = selector.parse::<usize>() {
+            return match reflog.get(n) {
+                Some(entry) => Ok(Some(entry.new.clone())),
+                None if n == reflog.len() => Ok(oldest(&reflog)),
+                None => Err(format!("log for '{}' only has {} entries", ref_name, reflog.len()).into()),
+            };
+        }
+        let now = Local::now();
+        let date = approxidate(selector, now.with_timezone(now.offset()))?;
+        // before the log starts, the oldest known value is the best answer
+        Ok(entry_at(&reflog, date).or_else(|| oldest(&reflog))
+            .or_else(|| reflog.iter().last().map(|entry| entry.new.clone())))
+    }
+
+    /// The branch checked out before the n-th most recent checkout, read
+    /// from the `checkout: moving from <old> to <new>` entries of HEAD's
+    /// reflog.
+    pub fn previous_branch(&self, n: usize) -> Result<String> {
+        self.reflog("HEAD")?.iter()
+            .filter_map(|entry| entry.message.strip_prefix("checkout: moving from "))
+            .filter_map(|moving| moving.split(" to ").next())
+            .nth(n.saturating_sub(1))
+            .map(str::to_owned)
+            .ok_or_else(|| format!("no previous branch: @{{-{}}}", n).into())
+    }
+
+    /// The branch `HEAD` points at, `None` when detached.
+    pub fn current_branch(&self) -> Result<Option<String>> {
+        match self.read_ref("HEAD")? {
+            Some(RefTarget::Symbolic(target)) => Ok(Some(target)),
+            _ => Ok(None),
+        }
+    }
+
+    pub fn parents(&self, id: &Id) -> Result<Vec<Id>> {
+        Ok(self.find_commit(id)?.parent().to_vec())
+    }
+
+    /// Every commit reachable from `tips`. A commit that cannot be read is
+    /// an error, since what lies behind it is unknown.
+    pub fn reachable(&self, tips: &[Id]) -> Result<HashSet<Id>> {
+        let mut seen = HashSet::new();
+        let mut queue: Vec<Id> = tips.to_vec();
+        while let Some(id) = queue.pop() {
+            if id.is_zero() || !seen.insert(id.clone()) {
+                continue;
+            }
+            queue.extend(self.parents(&id)?);
+        }
+        Ok(seen)
+    }
+}
+
+/// The value the ref had at `date`: the newest entry made at or before it.
+fn entry_at(reflog: &Reflog, date: DateTime<FixedOffset>) -> Option<Id> {
+    reflog.iter()
+        .find(|entry| entry.identity.date <= date)
+        .map(|entry| entry.new.clone())
+}
+
+fn oldest(reflog: &Reflog) -> Option<Id> {
+    reflog.iter().last()
+        .map(|entry| entry.old.clone())
+        .filter(|id| !id.is_zero())
+}
+
+/// Splits `spec` before the first `^` or `~` that is not inside `@{...}`.
+fn split_suffix(spec: &str) -> (&str, &str) {
+    let mut depth = 0;
+    for (i, c) in spec.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => depth -= 1,
+            '^' | '~' if depth == 0 => return spec.split_at(i),
+            _ => {}
+        }
+    }
+    (spec, "")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::MemFs;
+
+    const C1: &str = "1111111111111111111111111111111111111111";
+    const C2: &str = "2222222222222222222222222222222222222222";
+    const C3: &str = "3333333333333333333333333333333333333333";
+
+    fn log_line(old: &str, new: &str, time: i64, message: &str) -> String {
+        format!("{} {} A <a@x> {} +0000\t{}\n", old, new, time, message)
+    }
+
+    fn repo() -> FileRepository<MemFs> {
+        let zero = Id::default().to_string();
+        let mut repo = FileRepository::<MemFs>::default();
+        repo.add_commit(Some(C1), None, &[], 1_500_000_000, "msg");
+        repo.add_commit(Some(C2), None, &[&id(C1)], 1_500_000_000, "msg");
+        repo.add_commit(Some(C3), None, &[&id(C2)], 1_500_000_000, "msg");
+        repo.add_file("HEAD", b"ref: refs/heads/main\n".to_vec());
+        repo.add_file("refs/heads/main", format!("{}\n", C3).into_bytes());
+        repo.add_file("refs/heads/topic", format!("{}\n", C1).into_bytes());
+        let main_log = [
+            log_line(&zero, C1, 1_000_000_000, "commit (initial): one"),
+            log_line(C1, C2, 1_000_100_000, "commit: two"),
+            log_line(C2, C3, 1_000_200_000, "commit: three"),
+        ].concat();
+        repo.add_file("logs/refs/heads/main", main_log.into_bytes());
+        let head_log = [
+            log_line(&zero, C1, 1_000_000_000, "commit (initial): one"),
+            log_line(C1, C1, 1_000_000_100, "checkout: moving from main to topic"),
+            log_line(C1, C1, 1_000_000_200, "checkout: moving from topic to main"),
+        ].concat();
+        repo.add_file("logs/HEAD", head_log.into_bytes());
+        repo
+    }
+
+    fn id(s: &str) -> Id {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn test_rev_parse() {
+        let repo = repo();
+        assert_eq!(repo.rev_parse("HEAD").unwrap(), id(C3));
+        assert_eq!(repo.rev_parse("@").unwrap(), id(C3));
+        assert_eq!(repo.rev_parse("main~2").unwrap(), id(C1));
+        assert_eq!(repo.rev_parse("HEAD^^").unwrap(), id(C1));
+        assert_eq!(repo.rev_parse("3333").unwrap(), id(C3));
+        assert!(repo.rev_parse("main~3").is_err());
+        assert!(repo.rev_parse("nope").is_err());
+        assert_eq!(repo.rev_parse("HEAD~1^1").unwrap(), id(C1));
+        for spec in &["HEAD^é", "HEAD~é", "HEAD~1é", "HEAD^x"] {
+            match repo.rev_parse(spec).unwrap_err().kind() {
+                ErrorKind::BadRevision(bad) => assert_eq!(bad, spec),
+                kind => panic!("unexpected {:?}", kind),
+            }
+        }
+    }
+
+    #[test]
+    fn test_rev_parse_reflog() {
+        let repo = repo();
+        assert_eq!(repo.rev_parse("main@{0}").unwrap(), id(C3));
+        assert_eq!(repo.rev_parse("main@{2}").unwrap(), id(C1));
+        assert_eq!(repo.rev_parse("@{1}").unwrap(), id(C2));
+        assert_eq!(repo.rev_parse("main@{1}~1").unwrap(), id(C1));
+        assert!(repo.rev_parse("main@{3}").is_err());
+        assert!(repo.rev_parse("main@{4}").is_err());
+        assert_eq!(repo.rev_parse("main@{2001-09-10 12:00:00 +0000}").unwrap(), id(C2));
+        assert_eq!(repo.rev_parse("main@{1990-01-01}").unwrap(), id(C1));
+        assert_eq!(repo.rev_parse("main@{yesterday}").unwrap(), id(C3));
+        assert_eq!(repo.previous_branch(1).unwrap(), "topic");
+        assert_eq!(repo.rev_parse("@{-1}").unwrap(), id(C1));
+        assert_eq!(repo.rev_parse("@{-2}").unwrap(), id(C3));
+        assert!(repo.rev_parse("@{-3}").is_err());
+    }
+
+    #[test]
+    fn test_reachable() {
+        let mut repo = repo();
+        let reachable = repo.reachable(&[id(C2)]).unwrap();
+        assert!(reachable.contains(&id(C1)));
+        assert!(!reachable.contains(&id(C3)));
+        // behind a missing parent, reachability is unknown
+        let c4 = "4444444444444444444444444444444444444444";
+        repo.add_commit(Some(c4), None, &[&id("5555555555555555555555555555555555555555")], 1_500_000_000, "msg");
+        assert!(repo.reachable(&[id(c4)]).is_err());
+    }
+}
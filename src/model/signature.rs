@@ -0,0 +1,484 @@
+use std::fmt::{Display, Formatter};
+use ed25519_dalek::Verifier;
+use rsa::{BigUint, Pkcs1v15Sign, RsaPublicKey};
+use sha2::{Digest, Sha256, Sha512};
+use crate::errors::*;
+use crate::model::id::ObjectFormat;
+use crate::model::object::split_headers;
+
+const SSH_ARMOR: &str = "-----BEGIN SSH SIGNATURE-----";
+const PGP_ARMORS: &[&str] = &["-----BEGIN PGP SIGNATURE-----", "-----BEGIN PGP MESSAGE-----"];
+const X509_ARMOR: &str = "-----BEGIN SIGNED MESSAGE-----";
+
+/// The namespace git signs commits and tags in.
+pub const GIT_NAMESPACE: &str = "git";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SignatureFormat {
+    Pgp,
+    Ssh,
+    X509,
+}
+
+impl SignatureFormat {
+    fn detect(signature: &[u8]) -> Option<SignatureFormat> {
+        let first = signature.split(|b| *b == b'\n').next().unwrap_or_default();
+        let first = String::from_utf8_lossy(first);
+        let first = first.trim_end();
+        if first == SSH_ARMOR {
+            Some(SignatureFormat::Ssh)
+        } else if PGP_ARMORS.contains(&first) {
+            Some(SignatureFormat::Pgp)
+        } else if first == X509_ARMOR {
+            Some(SignatureFormat::X509)
+        } else {
+            None
+        }
+    }
+}
+
+/// A commit or tag split into the bytes that were signed and the armored
+/// signature over them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Signed {
+    pub payload: Vec<u8>,
+    pub signature: Vec<u8>,
+    pub format: SignatureFormat,
+}
+
+/// The header holding a commit's signature: `gpgsig` in SHA-1
+/// repositories, `gpgsig-sha256` in SHA-256 ones.
+pub fn signature_header(format: ObjectFormat) -> &'static str {
+    match format {
+        ObjectFormat::Sha1 => "gpgsig",
+        ObjectFormat::Sha256 => "gpgsig-sha256",
+    }
+}
+
+/// Splits commit content at its signature header. The payload is the
+/// commit with that header and its continuation lines removed.
+pub fn split_commit(content: &[u8], format: ObjectFormat) -> Option<Signed> {
+    let key = signature_header(format).as_bytes();
+    let (headers, _) = split_headers(content);
+    let header = headers.into_iter().find(|h| h.key == key && h.spaced)?;
+    let start = header.offset;
+    // the header's own line and every continuation line
+    let lines = header.value.iter().filter(|b| **b == b'\n').count() + 1;
+    let mut end = start;
+    for _ in 0..lines {
+        end += content[end..].iter().position(|b| *b == b'\n').map_or(content.len() - end, |n| n + 1);
+    }
+    let mut payload = content[..start].to_vec();
+    payload.extend_from_slice(&content[end..]);
+    let mut signature = header.value;
+    signature.push(b'\n');
+    let format = SignatureFormat::detect(&signature)?;
+    Some(Signed { payload, signature, format })
+}
+
+/// Splits tag content at the signature block that ends its message.
+pub fn split_tag(content: &[u8]) -> Option<Signed> {
+    let mut start = None;
+    let mut pos = 0;
+    while pos < content.len() {
+        if SignatureFormat::detect(&content[pos..]).is_some() {
+            start = Some(pos);
+        }
+        pos += content[pos..].iter().position(|b| *b == b'\n').map_or(content.len() - pos, |n| n + 1);
+    }
+    let start = start?;
+    let signature = content[start..].to_vec();
+    Some(Signed { payload: content[..start].to_vec(), format: SignatureFormat::detect(&signature)?, signature })
+}
+
+/// Reads the SSH wire format: big-endian lengths before each string.
+struct Wire<'a>(&'a [u8]);
+
+impl<'a> Wire<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(invalid("truncated"));
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn string(&mut self) -> Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.bytes(len)
+    }
+
+    fn text(&mut self) -> Result<&'a str> {
+        std::str::from_utf8(self.string()?).map_err(|_| invalid("bad string"))
+    }
+}
+
+fn invalid(reason: &str) -> Error {
+    ErrorKind::InvalidSignature(reason.to_owned()).into()
+}
+
+fn put_string(out: &mut Vec<u8>, value: &[u8]) {
+    out.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    out.extend_from_slice(value);
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum KeyData {
+    Ed25519([u8; 32]),
+    Rsa { e: Vec<u8>, n: Vec<u8> },
+}
+
+/// An SSH public key, as found in signatures and `allowed_signers`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PublicKey {
+    blob: Vec<u8>,
+    data: KeyData,
+}
+
+impl PublicKey {
+    /// Parses the wire encoding of a key, the base64 part of a `.pub` file.
+    pub fn from_blob(blob: &[u8]) -> Result<PublicKey> {
+        let mut wire = Wire(blob);
+        let data = match wire.text()? {
+            "ssh-ed25519" => {
+                let key = wire.string()?;
+                let mut bytes = [0u8; 32];
+                if key.len() != bytes.len() {
+                    return Err(invalid("bad ed25519 key"));
+                }
+                bytes.copy_from_slice(key);
+                KeyData::Ed25519(bytes)
+            }
+            "ssh-rsa" => {
+                let e = wire.string()?.to_vec();
+                let n = wire.string()?.to_vec();
+                KeyData::Rsa { e, n }
+            }
+            other => return Err(invalid(&format!("unsupported key type {}", other))),
+        };
+        Ok(PublicKey { blob: blob.to_vec(), data })
+    }
+
+    /// `ED25519` or `RSA`, as ssh-keygen names them.
+    pub fn kind(&self) -> &'static str {
+        match self.data {
+            KeyData::Ed25519(_) => "ED25519",
+            KeyData::Rsa { .. } => "RSA",
+        }
+    }
+
+    /// `SHA256:` and the unpadded base64 of the key's hash.
+    pub fn fingerprint(&self) -> String {
+        format!("SHA256:{}", base64::encode_config(Sha256::digest(&self.blob), base64::STANDARD_NO_PAD))
+    }
+
+    fn verify(&self, algorithm: &str, message: &[u8], signature: &[u8]) -> bool {
+        match (&self.data, algorithm) {
+            (KeyData::Ed25519(key), "ssh-ed25519") => {
+                let key = match ed25519_dalek::VerifyingKey::from_bytes(key) {
+                    Ok(key) => key,
+                    Err(_) => return false,
+                };
+                match ed25519_dalek::Signature::from_slice(signature) {
+                    Ok(signature) => key.verify(message, &signature).is_ok(),
+                    Err(_) => false,
+                }
+            }
+            (KeyData::Rsa { e, n }, "rsa-sha2-256") | (KeyData::Rsa { e, n }, "rsa-sha2-512") => {
+                let key = match RsaPublicKey::new(BigUint::from_bytes_be(n), BigUint::from_bytes_be(e)) {
+                    Ok(key) => key,
+                    Err(_) => return false,
+                };
+                if algorithm == "rsa-sha2-256" {
+                    key.verify(Pkcs1v15Sign::new::<Sha256>(), &Sha256::digest(message), signature).is_ok()
+                } else {
+                    key.verify(Pkcs1v15Sign::new::<Sha512>(), &Sha512::digest(message), signature).is_ok()
+                }
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Display for PublicKey {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        write!(f, "{} key {}", self.kind(), self.fingerprint())
+    }
+}
+
+/// A decoded `-----BEGIN SSH SIGNATURE-----` block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SshSignature {
+    key: PublicKey,
+    namespace: String,
+    reserved: Vec<u8>,
+    hash: String,
+    algorithm: String,
+    signature: Vec<u8>,
+}
+
+impl SshSignature {
+    pub fn parse(armored: &[u8]) -> Result<SshSignature> {
+        let text = std::str::from_utf8(armored).map_err(|_| invalid("not ASCII armored"))?;
+        let mut lines = text.lines().map(str::trim).filter(|line| !line.is_empty());
+        if lines.next() != Some(SSH_ARMOR) {
+            return Err(invalid("missing SSH signature header"));
+        }
+        let body: String = lines.take_while(|line| *line != "-----END SSH SIGNATURE-----").collect();
+        let blob = base64::decode(&body).map_err(|_| invalid("bad base64"))?;
+        let mut wire = Wire(&blob);
+        if wire.bytes(6)? != b"SSHSIG" {
+            return Err(invalid("missing SSHSIG magic"));
+        }
+        if wire.u32()? != 1 {
+            return Err(invalid("unsupported SSHSIG version"));
+        }
+        let key = PublicKey::from_blob(wire.string()?)?;
+        let namespace = wire.text()?.to_owned();
+        let reserved = wire.string()?.to_vec();
+        let hash = wire.text()?.to_owned();
+        let mut inner = Wire(wire.string()?);
+        let algorithm = inner.text()?.to_owned();
+        let signature = inner.string()?.to_vec();
+        Ok(SshSignature { key, namespace, reserved, hash, algorithm, signature })
+    }
+
+    pub fn key(&self) -> &PublicKey {
+        &self.key
+    }
+
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    /// Whether this signs `payload` in `namespace` with its own key.
+    pub fn verify(&self, payload: &[u8], namespace: &str) -> Result<bool> {
+        if self.namespace != namespace {
+            return Ok(false);
+        }
+        let digest = match self.hash.as_str() {
+            "sha256" => Sha256::digest(payload).to_vec(),
+            "sha512" => Sha512::digest(payload).to_vec(),
+            other => return Err(invalid(&format!("unsupported hash {}", other))),
+        };
+        let mut message = b"SSHSIG".to_vec();
+        put_string(&mut message, namespace.as_bytes());
+        put_string(&mut message, &self.reserved);
+        put_string(&mut message, self.hash.as_bytes());
+        put_string(&mut message, &digest);
+        Ok(self.key.verify(&self.algorithm, &message, &self.signature))
+    }
+}
+
+/// One line of an `allowed_signers` file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AllowedSigner {
+    pub principals: String,
+    pub namespaces: Option<Vec<String>>,
+    pub key: Vec<u8>,
+}
+
+/// The keys `gpg.ssh.allowedSignersFile` trusts, in the format of
+/// ssh-keygen(1): `principals [options] keytype base64-key [comment]`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AllowedSigners {
+    signers: Vec<AllowedSigner>,
+}
+
+impl AllowedSigners {
+    pub fn parse(input: &str) -> Result<AllowedSigners> {
+        let mut signers = vec![];
+        for line in input.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let bad = || invalid(&format!("bad allowed signers line '{}'", line));
+            let (principals, rest) = split_field(line).ok_or_else(bad)?;
+            let (field, mut rest) = split_field(rest).ok_or_else(bad)?;
+            let mut namespaces = None;
+            let mut certificate = false;
+            if !is_key_type(field) {
+                for option in split_options(field) {
+                    let lower = option.to_ascii_lowercase();
+                    if lower == "cert-authority" {
+                        certificate = true;
+                    } else if lower.starts_with("namespaces=") {
+                        let list = option["namespaces=".len()..].trim_matches('"');
+                        namespaces = Some(list.split(',').map(str::to_owned).collect());
+                    }
+                }
+                // then the key type
+                rest = split_field(rest).ok_or_else(bad)?.1;
+            }
+            let key = rest.split_whitespace().next().ok_or_else(bad)?;
+            let key = base64::decode(key).map_err(|_| bad())?;
+            // signatures by certificates are not supported
+            if !certificate {
+                signers.push(AllowedSigner { principals: principals.trim_matches('"').to_owned(), namespaces, key });
+            }
+        }
+        Ok(AllowedSigners { signers })
+    }
+
+    /// The principals allowed to sign in `namespace` with `key`.
+    pub fn find_principals(&self, key: &PublicKey, namespace: &str) -> Vec<&str> {
+        self.signers.iter()
+            .filter(|signer| signer.key == key.blob)
+            .filter(|signer| signer.namespaces.as_ref().is_none_or(|list| list.iter().any(|n| n == namespace)))
+            .map(|signer| signer.principals.as_str())
+            .collect()
+    }
+}
+
+fn is_key_type(field: &str) -> bool {
+    field.starts_with("ssh-") || field.starts_with("ecdsa-") || field.starts_with("sk-")
+}
+
+/// The first whitespace separated field of `line`, honouring quotes.
+fn split_field(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim_start();
+    if line.is_empty() {
+        return None;
+    }
+    let mut quoted = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => return Some((&line[..i], &line[i..])),
+            _ => {}
+        }
+    }
+    Some((line, ""))
+}
+
+fn split_options(field: &str) -> Vec<&str> {
+    let mut options = vec![];
+    let mut quoted = false;
+    let mut start = 0;
+    for (i, c) in field.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                options.push(&field[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    options.push(&field[start..]);
+    options
+}
+
+/// What checking an SSH signature against the allowed signers found.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verification {
+    /// A valid signature by a key trusted for `principal`.
+    Good { principal: String, key: PublicKey },
+    /// A valid signature by a key nobody is allowed to sign with.
+    Untrusted { key: PublicKey },
+    /// The signature does not match the payload.
+    Bad { key: PublicKey },
+}
+
+impl Verification {
+    pub fn is_good(&self) -> bool {
+        matches!(self, Verification::Good { .. })
+    }
+}
+
+/// Checks an SSH signed payload in the git namespace.
+pub fn verify_ssh(signed: &Signed, signers: &AllowedSigners) -> Result<Verification> {
+    if signed.format != SignatureFormat::Ssh {
+        return Err(invalid("not an SSH signature"));
+    }
+    let signature = SshSignature::parse(&signed.signature)?;
+    let key = signature.key().clone();
+    if !signature.verify(&signed.payload, GIT_NAMESPACE)? {
+        return Ok(Verification::Bad { key });
+    }
+    Ok(match signers.find_principals(&key, GIT_NAMESPACE).first() {
+        Some(principal) => Verification::Good { principal: principal.to_string(), key },
+        None => Verification::Untrusted { key },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ED25519_COMMIT: &[u8] = include_bytes!("../../tests/fixture_signed/ed25519.commit");
+    const RSA_COMMIT: &[u8] = include_bytes!("../../tests/fixture_signed/rsa.commit");
+    const SIGNED_TAG: &[u8] = include_bytes!("../../tests/fixture_signed/ed25519.tag");
+    const ALLOWED_SIGNERS: &str = include_str!("../../tests/fixture_signed/allowed_signers");
+
+    #[test]
+    fn test_split_commit() {
+        let signed = split_commit(ED25519_COMMIT, ObjectFormat::Sha1).unwrap();
+        assert_eq!(signed.format, SignatureFormat::Ssh);
+        assert!(signed.signature.starts_with(SSH_ARMOR.as_bytes()));
+        assert!(signed.signature.ends_with(b"-----END SSH SIGNATURE-----\n"));
+        assert_eq!(signed.payload, &b"tree aaff74984cccd156a469afa7d9ab10e4777beb24
+author T <t@x> 1500000000 +0000
+committer T <t@x> 1500000000 +0000
+
+one
+"[..]);
+        assert_eq!(split_commit(ED25519_COMMIT, ObjectFormat::Sha256), None);
+        assert_eq!(split_commit(&signed.payload, ObjectFormat::Sha1), None);
+
+        let pgp = b"tree 1\ngpgsig -----BEGIN PGP SIGNATURE-----\n \n abc\n -----END PGP SIGNATURE-----\n\nmsg\n";
+        let signed = split_commit(pgp, ObjectFormat::Sha1).unwrap();
+        assert_eq!(signed.format, SignatureFormat::Pgp);
+        assert_eq!(signed.payload, b"tree 1\n\nmsg\n");
+        assert_eq!(signed.signature, b"-----BEGIN PGP SIGNATURE-----\n\nabc\n-----END PGP SIGNATURE-----\n");
+    }
+
+    #[test]
+    fn test_split_tag() {
+        let signed = split_tag(SIGNED_TAG).unwrap();
+        assert_eq!(signed.format, SignatureFormat::Ssh);
+        assert!(signed.payload.ends_with(b"\n\nsigned tag\n"));
+        assert_eq!(signed.payload.len() + signed.signature.len(), SIGNED_TAG.len());
+        assert_eq!(split_tag(&signed.payload), None);
+    }
+
+    #[test]
+    fn test_verify_ssh() {
+        let signers = AllowedSigners::parse(ALLOWED_SIGNERS).unwrap();
+        for (content, principal, kind) in &[(ED25519_COMMIT, "t@x", "ED25519"), (RSA_COMMIT, "r@x", "RSA")] {
+            let signed = split_commit(content, ObjectFormat::Sha1).unwrap();
+            match verify_ssh(&signed, &signers).unwrap() {
+                Verification::Good { principal: found, key } => {
+                    assert_eq!(&found, principal);
+                    assert_eq!(key.kind(), *kind);
+                }
+                other => panic!("unexpected {:?}", other),
+            }
+            assert!(!verify_ssh(&signed, &AllowedSigners::default()).unwrap().is_good());
+
+            let mut tampered = signed.clone();
+            tampered.payload.extend_from_slice(b"more\n");
+            assert!(matches!(verify_ssh(&tampered, &signers).unwrap(), Verification::Bad { .. }));
+        }
+        let signed = split_tag(SIGNED_TAG).unwrap();
+        let key = SshSignature::parse(&signed.signature).unwrap().key().clone();
+        assert_eq!(key.fingerprint(), "SHA256:xFAGbjazFt/glyIsaeOWQU5SvsKhuvvSsjpajcoyP6g");
+        assert!(verify_ssh(&signed, &signers).unwrap().is_good());
+    }
+
+    #[test]
+    fn test_allowed_signers_namespaces() {
+        let signers = AllowedSigners::parse(ALLOWED_SIGNERS).unwrap();
+        let signed = split_commit(RSA_COMMIT, ObjectFormat::Sha1).unwrap();
+        let key = SshSignature::parse(&signed.signature).unwrap().key().clone();
+        assert_eq!(signers.find_principals(&key, "git"), ["r@x"]);
+        assert!(signers.find_principals(&key, "file").is_empty());
+        assert!(AllowedSigners::parse("only-a-principal").is_err());
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::HashSet;
+use std::io::Read;
+use crate::errors::*;
+use crate::fs::FileSystem;
+use crate::model::repository::FileRepository;
+use crate::wildmatch::wildmatch;
+
+/// The paths `info/sparse-checkout` keeps in the working tree.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SparseCheckout {
+    /// Cone mode: files at the top level, files directly in `parents`,
+    /// and everything below `recursive`.
+    Cone {
+        recursive: HashSet<String>,
+        parents: HashSet<String>,
+    },
+    /// Full gitignore-style patterns, the last match deciding.
+    Patterns(Vec<(bool, String)>),
+}
+
+impl SparseCheckout {
+    /// Reads cone patterns as `git sparse-checkout set` writes them:
+    /// `/dir/` includes a directory, `!/dir/*/` then limits it to its own
+    /// files. Returns `None` for anything else.
+    pub fn parse_cone(text: &str) -> Option<SparseCheckout> {
+        let mut included = vec![];
+        let mut limited = HashSet::new();
+        for line in text.lines().map(str::trim_end).filter(|l| !l.is_empty() && !l.starts_with('#')) {
+            if line == "/*" || line == "!/*/" {
+                continue;
+            }
+            if let Some(dir) = line.strip_prefix("!/").and_then(|l| l.strip_suffix("/*/")) {
+                limited.insert(dir.to_owned());
+            } else if let Some(dir) = line.strip_prefix('/').and_then(|l| l.strip_suffix('/')) {
+                if dir.is_empty() || dir.contains('*') {
+                    return None;
+                }
+                included.push(dir.to_owned());
+            } else {
+                return None;
+            }
+        }
+        let (parents, recursive) = included.into_iter().partition(|dir| limited.contains(dir));
+        Some(SparseCheckout::Cone { recursive, parents })
+    }
+
+    pub fn parse_patterns(text: &str) -> SparseCheckout {
+        let patterns = text.lines()
+            .map(str::trim_end)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(|l| match l.strip_prefix('!') {
+                Some(pattern) => (false, pattern.to_owned()),
+                None => (true, l.to_owned()),
+            })
+            .collect();
+        SparseCheckout::Patterns(patterns)
+    }
+
+    /// Whether the file at `path` belongs in the working tree.
+    pub fn includes(&self, path: &str) -> bool {
+        match self {
+            SparseCheckout::Cone { recursive, parents } => {
+                let dir = match path.rfind('/') {
+                    Some(n) => &path[..n],
+                    None => return true,
+                };
+                if parents.contains(dir) {
+                    return true;
+                }
+                let mut prefix = dir;
+                loop {
+                    if recursive.contains(prefix) {
+                        return true;
+                    }
+                    match prefix.rfind('/') {
+                        Some(n) => prefix = &prefix[..n],
+                        None => return false,
+                    }
+                }
+            }
+            SparseCheckout::Patterns(patterns) => patterns.iter().rev()
+                .find(|(_, pattern)| pattern_matches(pattern, path))
+                .is_some_and(|(include, _)| *include),
+        }
+    }
+}
+
+/// Matches a gitignore-style pattern against a file, or against one of
+/// its directories, which covers everything inside.
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    let dir_only = pattern.ends_with('/');
+    let pattern = pattern.trim_end_matches('/');
+    let anchored = pattern.contains('/');
+    let pattern = pattern.trim_start_matches('/');
+    let mut candidates: Vec<&str> = path.match_indices('/').map(|(n, _)| &path[..n]).collect();
+    if !dir_only {
+        candidates.push(path);
+    }
+    candidates.iter().any(|candidate| {
+        if anchored {
+            wildmatch(pattern, candidate, true, false)
+        } else {
+            let name = candidate.rsplit('/').next().unwrap_or(candidate);
+            wildmatch(pattern, name, true, false)
+        }
+    })
+}
+
+impl<FS: FileSystem> FileRepository<FS> {
+    /// The sparse-checkout in effect, `None` unless `core.sparseCheckout`
+    /// is set. Cone patterns are read as such only with
+    /// `core.sparseCheckoutCone`.
+    pub fn sparse_checkout(&self) -> Result<Option<SparseCheckout>> {
+        let config = self.config()?;
+        if config.get_bool("core.sparseCheckout")? != Some(true) {
+            return Ok(None);
+        }
+        let path = self.git_dir().join("info").join("sparse-checkout");
+        let mut text = String::new();
+        if self.fs.is_file(&path) {
+            self.fs.read_file(&path)?.read_to_string(&mut text)?;
+        }
+        let cone = if config.get_bool("core.sparseCheckoutCone")? == Some(true) {
+            SparseCheckout::parse_cone(&text)
+        } else {
+            None
+        };
+        Ok(Some(cone.unwrap_or_else(|| SparseCheckout::parse_patterns(&text))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cone_patterns() {
+        let sparse = SparseCheckout::parse_cone("/*\n!/*/\n/docs/\n/src/\n!/src/*/\n/src/core/\n").unwrap();
+        assert!(sparse.includes("README"));
+        assert!(sparse.includes("docs/a/b.md"));
+        assert!(sparse.includes("src/main.rs"));
+        assert!(!sparse.includes("src/cli/args.rs"));
+        assert!(sparse.includes("src/core/deep/mod.rs"));
+        assert!(!sparse.includes("tests/a.rs"));
+        assert_eq!(SparseCheckout::parse_cone("*.md\n"), None);
+    }
+
+    #[test]
+    fn test_full_patterns() {
+        let sparse = SparseCheckout::parse_patterns("/*\n!/*/\n*.md\n/lib/\n!/lib/big/\n");
+        assert!(sparse.includes("top"));
+        assert!(!sparse.includes("src/main.rs"));
+        assert!(sparse.includes("src/notes.md"));
+        assert!(sparse.includes("lib/a.c"));
+        assert!(!sparse.includes("lib/big/blob.bin"));
+    }
+}
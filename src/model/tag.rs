@@ -1,17 +1,8 @@
 use super::object::ObjectType;
 use super::id::Id;
 use super::commit::Identity;
-use nom::IResult;
-use nom::bytes::complete::{tag, take_while, take_until, take_till};
-use nom::combinator::{map_res, rest};
-use nom::character::complete::{not_line_ending, line_ending};
-use nom::character::is_space;
 use crate::model::commit::*;
-use crate::model::object::{parse_object_type, GitObject};
-use nom::branch::alt;
-use nom::multi::separated_list;
-use std::collections::HashMap;
-
+use crate::model::object::{parse_object_type, split_headers, GitObject, ParseMode};
 
 use crate::errors::*;
 use crate::model::repository::Repository;
@@ -24,7 +15,8 @@ pub struct Tag {
     tag: String,
     tagger: Option<Identity>,
     message: String,
-    other: HashMap<String, String>,
+    raw_message: Vec<u8>,
+    headers: Vec<(String, String)>,
 }
 
 impl Tag {
@@ -47,95 +39,102 @@ impl Tag {
     pub fn object_type(&self) -> ObjectType {
         self.object_type.clone()
     }
+    pub fn raw_message(&self) -> &[u8] {
+        &self.raw_message
+    }
+    /// Headers other than object, type, tag and tagger, in order.
+    pub fn extra_headers(&self) -> &[(String, String)] {
+        &self.headers
+    }
+    pub fn header(&self, key: &str) -> Option<&str> {
+        self.headers.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str())
+    }
 
+    /// Reads a tag leniently.
     pub fn from(repo: &dyn Repository,obj: &GitObject) -> Result<Self> {
+        Tag::with_mode(repo, obj, ParseMode::Lenient)
+    }
+
+    pub fn with_mode(repo: &dyn Repository, obj: &GitObject, mode: ParseMode) -> Result<Self> {
         if obj.object_type() == ObjectType::TAG {
             let buf = repo.read_content(&obj)?;
-            let tag: Tag = parse_tag_object(&buf, obj.id()).map(|res| res.1)
-                .map_err(|_|ErrorKind::ParseError)?;
-            Ok(tag)
+            parse_tag_object(&buf, obj.id(), mode)
         } else {
-            Err(ErrorKind::InvalidObjectType.into())
+            Err(obj.not_a(ObjectType::TAG))
         }
     }
 }
 
-pub enum Attr {
-    Object(Id),
-    Tagger(Identity),
-    Tag(String),
-    Type(ObjectType),
-    Unknown(String, String),
-}
-
-fn parse_object(input :&[u8]) -> IResult<&[u8] , Attr> {
-    let (input, _key) = tag("object")(input)?;
-    let (input, _) = take_while(is_space)(input)?;
-    let (input, id) = map_res(not_line_ending, id_from_str_bytes)(input)?;
-    Ok((input, Attr::Object(id)))
-}
-
-fn parse_tagger(input: &[u8]) -> IResult<&[u8], Attr> {
-    let (input, _key) = tag("tagger")(input)?;
-    let (input, _) = tag(" ")(input)?;
-    let (input, identity) = parse_identity(input)?;
-    Ok((input, Attr::Tagger(identity)))
-}
-
-fn parse_tag(input: &[u8]) -> IResult<&[u8], Attr> {
-    let (input, _key) = tag("tag")(input)?;
-    let (input, _) = tag(" ")(input)?;
-    let (input, tag) = map_res(not_line_ending, std::str::from_utf8)(input)?;
-    Ok((input, Attr::Tag(tag.to_string())))
-}
-fn parse_type(input: &[u8]) -> IResult<&[u8], Attr> {
-    let (input, _key) = tag("type")(input)?;
-    let (input, _) = tag(" ")(input)?;
-    let (input, object_type) = parse_object_type(input)?;
-    Ok((input, Attr::Type(object_type)))
-}
-
-fn parse_attr(input: &[u8]) -> IResult<&[u8], Attr> {
-    let (input, key) = map_res(take_till(is_space), std::str::from_utf8)(input)?;
-    let (input, _) = take_while(is_space)(input)?;
-    let (input, value) = map_res(not_line_ending, std::str::from_utf8)(input)?;
-    Ok((input, Attr::Unknown(key.to_owned(), value.to_owned())))
-}
-
-fn parse_attrs(input: &[u8]) -> IResult<&[u8], Attr> {
-    let (input, attr) = alt((
-        parse_object,
-        parse_type,
-        parse_tag,
-        parse_tagger,
-        parse_attr
-    ))(input)?;
-    Ok((input, attr))
-}
-
-fn parse_tag_object<'a>(input :&'a[u8], id: &Id) -> IResult<&'a[u8] , Tag> {
-    let (input, attrs_str) = take_until("\n\n")(input)?;
-    let (_, attrs) = separated_list(line_ending, parse_attrs)(attrs_str)?;
-    let (input, message) = map_res(rest, std::str::from_utf8)(input)?;
-    let mut tag = Tag {
-        object_type: ObjectType::BLOB,
-        id: id.to_owned(),
-        object: Id::default(),
-        tag: "".to_string(),
-        message: message.trim().to_owned(),
-        tagger: None,
-        other: HashMap::new()
+const OBJECT: usize = 0;
+const TYPE: usize = 1;
+const TAG: usize = 2;
+const TAGGER: usize = 3;
+const OTHER: usize = 4;
+
+/// Parses tag content. Strict mode wants `object`, `type`, `tag` and an
+/// optional `tagger` in that order before any other header.
+pub fn parse_tag_object(content: &[u8], id: &Id, mode: ParseMode) -> Result<Tag> {
+    let corrupt = |offset: usize, reason: String| -> Error {
+        ErrorKind::CorruptObject(ObjectType::TAG, id.to_owned(), offset, reason).into()
     };
-    for attr in attrs {
-        match attr {
-            Attr::Tag(t) => tag.tag = t,
-            Attr::Tagger(identity) => tag.tagger = Some(identity),
-            Attr::Object(id) => tag.object = id,
-            Attr::Type(t) => tag.object_type = t,
-            Attr::Unknown(k, v) => { tag.other.insert(k, v); }
+    let strict = mode == ParseMode::Strict;
+    let (headers, message) = split_headers(content);
+    let message = match message {
+        Some(message) => message,
+        None if strict => return Err(corrupt(content.len(), "missing blank line after header".to_owned())),
+        None => &[],
+    };
+    let (mut object, mut object_type, mut name, mut tagger) = (None, None, None, None);
+    let mut extra = vec![];
+    let mut last = None;
+    for header in headers {
+        let order = match header.key {
+            b"object" => OBJECT,
+            b"type" => TYPE,
+            b"tag" => TAG,
+            b"tagger" => TAGGER,
+            _ => OTHER,
+        };
+        if strict {
+            if let Some(problem) = check_header(&header, order, &mut last, &[OTHER]) {
+                return Err(corrupt(header.offset, format!("{} {} line", problem, header.name())));
+            }
+        }
+        let invalid = || corrupt(header.offset, format!("invalid {} line", header.name()));
+        match order {
+            OBJECT if object.is_none() => object = Some(id_value(&header.value).ok_or_else(invalid)?),
+            TYPE if object_type.is_none() => object_type = Some(match parse_object_type(&header.value) {
+                Ok(([], object_type)) => object_type,
+                _ => return Err(invalid()),
+            }),
+            TAG if name.is_none() => name = Some(match std::str::from_utf8(&header.value) {
+                Ok(name) => name.to_owned(),
+                Err(_) if strict => return Err(invalid()),
+                Err(_) => String::from_utf8_lossy(&header.value).into_owned(),
+            }),
+            TAGGER if tagger.is_none() => tagger = Some(identity_value(&header.value, mode).ok_or_else(invalid)?),
+            _ if header.key.is_empty() => {}
+            _ => extra.push((header.name(), String::from_utf8_lossy(&header.value).into_owned())),
         }
     }
-    Ok((input, tag))
+    let object = object.ok_or_else(|| corrupt(0, "missing object line".to_owned()))?;
+    let object_type = object_type.ok_or_else(|| corrupt(0, "missing type line".to_owned()))?;
+    if strict && name.is_none() {
+        return Err(corrupt(0, "missing tag line".to_owned()));
+    }
+    let encoded = extra.iter().any(|(key, _)| key == "encoding");
+    let text = message_text(message, mode, encoded)
+        .ok_or_else(|| corrupt(content.len() - message.len(), "message is not valid UTF-8".to_owned()))?;
+    Ok(Tag {
+        id: id.to_owned(),
+        object_type,
+        object,
+        tag: name.unwrap_or_default(),
+        tagger,
+        message: text,
+        raw_message: message.to_vec(),
+        headers: extra,
+    })
 }
 
 #[cfg(test)]
@@ -153,7 +152,7 @@ tagger spacedragon <allendragon@gmail.com> 1565707955 +0800
 
 a tag";
             let id = Id::default();
-            let (_, tag) = parse_tag_object(str, &id).expect("parse failed");
+            let tag = parse_tag_object(str, &id, ParseMode::Strict).expect("parse failed");
         assert_eq!(tag.object, Id::from_str("a541069eb298c4969982721adea07e526d899351").unwrap());
         assert_eq!(tag.object_type, ObjectType::COMMIT);
         assert_eq!(tag.tagger, Some(Identity {
@@ -164,5 +163,27 @@ a tag";
         assert_eq!(tag.message, "a tag".to_string());
 
     }
+
+    #[test]
+    fn test_parse_tag_modes() {
+        let id = Id::default();
+        let str = b"object a541069eb298c4969982721adea07e526d899351
+type commit
+tagger spacedragon <allendragon@gmail.com>
+tag v0.1
+gpgsig-sha256 -----BEGIN
+ sig
+
+a tag";
+        let err = parse_tag_object(str, &id, ParseMode::Strict).unwrap_err();
+        assert_eq!(err.to_string(), format!("tag {}: invalid tagger line at byte 60", id));
+        let tag = parse_tag_object(str, &id, ParseMode::Lenient).unwrap();
+        assert_eq!(tag.tag(), "v0.1");
+        assert_eq!(tag.tagger().as_ref().unwrap().date.timestamp(), 0);
+        assert_eq!(tag.header("gpgsig-sha256"), Some("-----BEGIN\nsig"));
+
+        let err = parse_tag_object(b"type commit\ntag v1\n\n", &id, ParseMode::Lenient).unwrap_err();
+        assert_eq!(err.to_string(), format!("tag {}: missing object line at byte 0", id));
+    }
 }
 
@@ -1,4 +1,4 @@
-use crate::model::id::Id;
+use crate::model::id::{Id, ObjectFormat};
 use nom::IResult;
 use nom::bytes::complete::{take_while, tag, take_until, take};
 use nom::character::{is_digit, is_space};
@@ -29,7 +29,7 @@ impl FromStr for EntryType {
             "commit" => Ok(EntryType::COMMIT),
             "blob" => Ok(EntryType::BLOB),
             "tree" => Ok(EntryType::TREE),
-            _ => Err(ErrorKind::ParseError.into()),
+            _ => Err(ErrorKind::UnknownObjectType(s.to_owned()).into()),
         }
     }
 }
@@ -48,16 +48,75 @@ impl Tree {
         self.entries.as_slice()
     }
 
+    /// The raw tree object for `entries`, sorted the way git sorts them:
+    /// by name, with a tree compared as if its name ended in `/`.
+    pub fn encode(entries: &[TreeEntry]) -> Vec<u8> {
+        let mut sorted: Vec<&TreeEntry> = entries.iter().collect();
+        sorted.sort_by_cached_key(|entry| {
+            let mut key = entry.name.as_bytes().to_vec();
+            if entry.mode == FileMode::DIR {
+                key.push(b'/');
+            }
+            key
+        });
+        let mut content = vec![];
+        for entry in sorted {
+            content.extend_from_slice(entry.mode.as_str().as_bytes());
+            content.push(b' ');
+            content.extend_from_slice(entry.name.as_bytes());
+            content.push(0);
+            content.extend_from_slice(entry.id.bytes());
+        }
+        content
+    }
+
     pub fn from(repo: &dyn Repository, obj: &GitObject) -> Result<Self> {
         if obj.object_type() == ObjectType::TREE {
             let buf = repo.read_content(&obj)?;
-            let tree: Tree = parse_tree(&buf, obj.id()).map(|res| res.1)
-                .map_err(|_|ErrorKind::ParseError)?;
-            Ok(tree)
+            match parse_tree(&buf, obj.id()) {
+                Ok(([], tree)) => Ok(tree),
+                Ok((rest, _)) => Err(obj.corrupt(buf.len() - rest.len(), "invalid tree entry")),
+                Err(e) => Err(obj.corrupt(error_offset(&buf, &e), "invalid tree entry")),
+            }
         } else {
-            Err(ErrorKind::InvalidObjectType.into())
+            Err(obj.not_a(ObjectType::TREE))
+        }
+    }
+
+    /// Visits every entry below this tree depth-first in tree order, with
+    /// its path. A directory is visited before its contents, which are
+    /// skipped when `visit` returns false for it.
+    pub fn walk(&self, repo: &dyn Repository, visit: &mut dyn FnMut(&str, &TreeEntry) -> Result<bool>) -> Result<()> {
+        self.walk_under("", repo, visit)
+    }
+
+    /// The entry at the slash separated `path` below this tree.
+    pub fn entry_at(&self, repo: &dyn Repository, path: &str) -> Result<Option<TreeEntry>> {
+        let (name, rest) = match path.find('/') {
+            Some(n) => (&path[..n], Some(&path[n + 1..])),
+            None => (path, None),
+        };
+        let entry = match self.entries.iter().find(|e| e.name == name) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        match rest {
+            None => Ok(Some(entry.clone())),
+            Some(rest) if entry.mode == FileMode::DIR => repo.find_tree(&entry.id)?.entry_at(repo, rest),
+            Some(_) => Ok(None),
         }
     }
+
+    fn walk_under(&self, prefix: &str, repo: &dyn Repository,
+                  visit: &mut dyn FnMut(&str, &TreeEntry) -> Result<bool>) -> Result<()> {
+        for entry in &self.entries {
+            let path = format!("{}{}", prefix, entry.name);
+            if visit(&path, entry)? && entry.mode == FileMode::DIR {
+                repo.find_tree(&entry.id)?.walk_under(&format!("{}/", path), repo, visit)?;
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -70,6 +129,28 @@ pub enum FileMode {
 }
 
 impl FileMode {
+    /// The mode as the index and `stat` spell it.
+    pub fn bits(&self) -> u32 {
+        match self {
+            FileMode::DIR => 0o40000,
+            FileMode::FILE => 0o100644,
+            FileMode::EXE => 0o100755,
+            FileMode::LINK => 0o120000,
+            FileMode::SUBMODULE => 0o160000,
+        }
+    }
+
+    pub fn from_bits(bits: u32) -> Option<FileMode> {
+        match bits {
+            0o40000 => Some(FileMode::DIR),
+            0o100644 => Some(FileMode::FILE),
+            0o100755 => Some(FileMode::EXE),
+            0o120000 => Some(FileMode::LINK),
+            0o160000 => Some(FileMode::SUBMODULE),
+            _ => None,
+        }
+    }
+
     pub fn as_str(&self) -> &'static str {
         match self {
             FileMode::DIR => "40000",
@@ -89,6 +170,9 @@ pub struct TreeEntry {
 }
 
 impl TreeEntry {
+    pub fn new(mode: FileMode, id: Id, name: impl Into<String>) -> Self {
+        TreeEntry { mode, id, name: name.into() }
+    }
     pub fn mode(&self) -> FileMode {
         self.mode.clone()
     }
@@ -117,13 +201,13 @@ impl FromStr for FileMode {
             "100755" => Ok(FileMode::EXE),
             "120000" => Ok(FileMode::LINK),
             "160000" => Ok(FileMode::SUBMODULE),
-            _ => Err(ErrorKind::BadMode.into()),
+            _ => Err(ErrorKind::BadMode(s.to_owned()).into()),
         }
     }
 }
 fn parse_from_str<T: FromStr>(input: &[u8]) -> Result<T> {
     let str = std::str::from_utf8(input)?;
-    T::from_str(str).map_err(|_| ErrorKind::ParseError.into())
+    T::from_str(str).map_err(|_| format!("cannot parse '{}'", str).into())
 }
 
 fn parse_mode(input: &[u8]) -> IResult<&[u8], FileMode> {
@@ -134,8 +218,13 @@ fn parse_mode(input: &[u8]) -> IResult<&[u8], FileMode> {
 }
 
 pub fn parse_id(input: &[u8]) -> IResult<&[u8], Id> {
-    map(
-        take(20u8),
+    parse_id_of(ObjectFormat::Sha1)(input)
+}
+
+/// Parses a raw id of the length `format` hashes to.
+pub fn parse_id_of(format: ObjectFormat) -> impl Fn(&[u8]) -> IResult<&[u8], Id> {
+    move |input| map(
+        take(format.hash_len()),
         Id::new
     )(input)
 }
@@ -147,21 +236,24 @@ fn parse_name(input: &[u8]) -> IResult<&[u8], String> {
     )(input)
 }
 
-fn parse_entry(input: &[u8]) -> IResult<&[u8], TreeEntry> {
-    let (input, mode) = parse_mode(input)?;
-    let (input, _) = take_while(is_space)(input)?;
-    let (input, name) = parse_name(input)?;
-    let (input, _) = tag("\0")(input)?;
-    let (input, id) = parse_id(input)?;
-    Ok((input, TreeEntry {
-        id,
-        name,
-        mode
-    }))
+fn parse_entry_of(format: ObjectFormat) -> impl Fn(&[u8]) -> IResult<&[u8], TreeEntry> {
+    move |input| {
+        let (input, mode) = parse_mode(input)?;
+        let (input, _) = take_while(is_space)(input)?;
+        let (input, name) = parse_name(input)?;
+        let (input, _) = tag("\0")(input)?;
+        let (input, id) = parse_id_of(format)(input)?;
+        Ok((input, TreeEntry {
+            id,
+            name,
+            mode
+        }))
+    }
 }
 
-fn parse_tree<'a>(input: &'a[u8], id: &Id) -> IResult<&'a[u8], Tree> {
-    let (input, entries) = many0(parse_entry)(input)?;
+pub(crate) fn parse_tree<'a>(input: &'a[u8], id: &Id) -> IResult<&'a[u8], Tree> {
+    let format = id.format().unwrap_or_default();
+    let (input, entries) = many0(parse_entry_of(format))(input)?;
     Ok((input, Tree {
         id: id.to_owned(),
         entries
@@ -172,8 +264,8 @@ fn parse_tree<'a>(input: &'a[u8], id: &Id) -> IResult<&'a[u8], Tree> {
 
 #[cfg(test)]
 mod tests {
-    use crate::model::tree::{TreeEntry, FileMode,  parse_entry};
-    use crate::model::id::Id;
+    use crate::model::tree::{Tree, TreeEntry, FileMode, parse_entry_of, parse_tree};
+    use crate::model::id::{Id, ObjectFormat};
     use std::str::FromStr;
 
     #[test]
@@ -181,11 +273,26 @@ mod tests {
         let id = Id::from_str("916269d397a334666906f57d69b297decf25da41").expect("");
         let mut str = b"100644 README.md\0".to_vec();
         str.extend(id.bytes());
-        let (_, entry) = parse_entry(&str).expect("parse failed.");
+        let (_, entry) = parse_entry_of(ObjectFormat::Sha1)(&str).expect("parse failed.");
         assert_eq!(entry, TreeEntry {
             mode: FileMode::FILE,
             id,
             name: "README.md".to_string(),
         })
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_encode_sorts_trees_with_slash() {
+        let id = Id::from_str("916269d397a334666906f57d69b297decf25da41").expect("");
+        let entries = vec![
+            TreeEntry::new(FileMode::FILE, id.clone(), "a.c"),
+            TreeEntry::new(FileMode::DIR, id.clone(), "a"),
+            TreeEntry::new(FileMode::FILE, id.clone(), "a-b"),
+        ];
+        let content = Tree::encode(&entries);
+        let (rest, tree) = parse_tree(&content, &id).expect("parse failed.");
+        assert!(rest.is_empty());
+        let names: Vec<&str> = tree.entries().iter().map(|e| e.name()).collect();
+        assert_eq!(names, ["a-b", "a.c", "a"]);
+    }
+}
@@ -0,0 +1,89 @@
+use std::sync::{mpsc, Arc, Mutex};
+use crate::errors::*;
+use crate::fs::FileSystem;
+use crate::model::repository::FileRepository;
+
+impl<FS: FileSystem> FileRepository<FS> {
+    /// How many threads to work with: `requested` unless it is 0, then the
+    /// config `key`, and every CPU when that is unset or below 1.
+    pub(crate) fn workers(&self, requested: usize, key: &str) -> Result<usize> {
+        if requested > 0 {
+            return Ok(requested);
+        }
+        Ok(match self.config()?.get_int(key)? {
+            Some(n) if n >= 1 => n as usize,
+            _ => std::thread::available_parallelism().map_or(1, |n| n.get()),
+        })
+    }
+}
+
+/// Runs `work` on what `read` gives for each of `count` items, reading in
+/// order on this thread and handing the content to `workers` threads when
+/// there is more than one. An item `read` gives nothing for is skipped.
+/// Returns what `work` made of each item, and the first error, after which
+/// the remaining items may not have been worked on.
+pub(crate) fn run_workers<T, R, W>(count: usize, workers: usize, mut read: R, work: W) -> (Vec<Option<T>>, Result<()>)
+where
+    T: Send,
+    R: FnMut(usize) -> Result<Option<Vec<u8>>>,
+    W: Fn(usize, &[u8]) -> Result<T> + Sync,
+{
+    let mut results: Vec<Option<T>> = (0..count).map(|_| None).collect();
+    if workers <= 1 {
+        let mut first_error = None;
+        for (n, result) in results.iter_mut().enumerate() {
+            match read(n).and_then(|content| content.map(|content| work(n, &content)).transpose()) {
+                Ok(done) => *result = done,
+                Err(e) => {
+                    first_error = Some(e);
+                    break;
+                }
+            }
+        }
+        return (results, first_error.map_or(Ok(()), Err));
+    }
+
+    // the workers own the receiving end, so sending fails instead of
+    // blocking once none are left
+    let (job_tx, job_rx) = mpsc::sync_channel::<(usize, Vec<u8>)>(workers * 4);
+    let job_rx = Arc::new(Mutex::new(job_rx));
+    let (done_tx, done_rx) = mpsc::channel();
+    let result = std::thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers).map(|_| {
+            let (jobs, done, work) = (Arc::clone(&job_rx), done_tx.clone(), &work);
+            scope.spawn(move || -> Result<()> {
+                loop {
+                    let job = jobs.lock().map_err(|_| Error::from("worker thread failed"))?.recv();
+                    match job {
+                        Ok((n, content)) => {
+                            let _ = done.send((n, work(n, &content)));
+                        }
+                        Err(_) => return Ok(()),
+                    }
+                }
+            })
+        }).collect();
+        drop(job_rx);
+        let sent = (0..count).try_for_each(|n| -> Result<()> {
+            match read(n)? {
+                Some(content) => job_tx.send((n, content)).map_err(|_| Error::from("worker thread stopped")),
+                None => Ok(()),
+            }
+        });
+        drop(job_tx);
+        handles.into_iter()
+            .map(|handle| handle.join().unwrap_or_else(|_| Err("worker thread panicked".into())))
+            .fold(sent, |result, joined| result.and(joined))
+    });
+    drop(done_tx);
+    let mut first_error = result.err();
+    for (n, outcome) in done_rx {
+        match outcome {
+            Ok(done) => results[n] = Some(done),
+            Err(e) => if first_error.is_none() {
+                first_error = Some(e);
+            },
+        }
+    }
+    (results, first_error.map_or(Ok(()), Err))
+}
@@ -0,0 +1,145 @@
+/// Minimal implementation of git's `wildmatch`, used for glob patterns in
+/// config conditions, ref patterns and pathspecs.
+///
+/// Supports `*`, `?`, `[...]` (with `!`/`^` negation and ranges) and `**`
+/// spanning directory separators when `pathname` is set.
+pub fn wildmatch(pattern: &str, text: &str, pathname: bool, ignore_case: bool) -> bool {
+    let (pattern, text) = if ignore_case {
+        (pattern.to_lowercase(), text.to_lowercase())
+    } else {
+        (pattern.to_owned(), text.to_owned())
+    };
+    do_match(pattern.as_bytes(), text.as_bytes(), pathname)
+}
+
+fn do_match(pattern: &[u8], text: &[u8], pathname: bool) -> bool {
+    let mut p = 0;
+    let mut t = 0;
+    while p < pattern.len() {
+        match pattern[p] {
+            b'*' => {
+                let double = p + 1 < pattern.len() && pattern[p + 1] == b'*';
+                if double {
+                    let mut next = p;
+                    while next < pattern.len() && pattern[next] == b'*' {
+                        next += 1;
+                    }
+                    // `**/` also matches zero directories.
+                    if next < pattern.len() && pattern[next] == b'/' &&
+                        (p == 0 || pattern[p - 1] == b'/') &&
+                        do_match(&pattern[next + 1..], &text[t..], pathname) {
+                        return true;
+                    }
+                    for start in t..=text.len() {
+                        if do_match(&pattern[next..], &text[start..], pathname) {
+                            return true;
+                        }
+                    }
+                    return false;
+                }
+                for start in t..=text.len() {
+                    if do_match(&pattern[p + 1..], &text[start..], pathname) {
+                        return true;
+                    }
+                    if start < text.len() && pathname && text[start] == b'/' {
+                        return false;
+                    }
+                }
+                return false;
+            }
+            b'?' => {
+                if t >= text.len() || (pathname && text[t] == b'/') {
+                    return false;
+                }
+                p += 1;
+                t += 1;
+            }
+            b'[' => {
+                if t >= text.len() {
+                    return false;
+                }
+                match match_class(&pattern[p..], text[t]) {
+                    Some((len, matched)) => {
+                        if !matched || (pathname && text[t] == b'/') {
+                            return false;
+                        }
+                        p += len;
+                        t += 1;
+                    }
+                    None => {
+                        if text[t] != b'[' {
+                            return false;
+                        }
+                        p += 1;
+                        t += 1;
+                    }
+                }
+            }
+            b'\\' if p + 1 < pattern.len() => {
+                if t >= text.len() || text[t] != pattern[p + 1] {
+                    return false;
+                }
+                p += 2;
+                t += 1;
+            }
+            c => {
+                if t >= text.len() || text[t] != c {
+                    return false;
+                }
+                p += 1;
+                t += 1;
+            }
+        }
+    }
+    t == text.len()
+}
+
+/// Returns the length of the bracket expression and whether `c` matched it,
+/// or `None` if the expression is not terminated.
+fn match_class(pattern: &[u8], c: u8) -> Option<(usize, bool)> {
+    let mut i = 1;
+    let negate = i < pattern.len() && (pattern[i] == b'!' || pattern[i] == b'^');
+    if negate {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    while i < pattern.len() {
+        let ch = pattern[i];
+        if ch == b']' && !first {
+            return Some((i + 1, matched != negate));
+        }
+        first = false;
+        if i + 2 < pattern.len() && pattern[i + 1] == b'-' && pattern[i + 2] != b']' {
+            if pattern[i] <= c && c <= pattern[i + 2] {
+                matched = true;
+            }
+            i += 3;
+        } else {
+            if ch == c {
+                matched = true;
+            }
+            i += 1;
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::wildmatch;
+
+    #[test]
+    fn test_wildmatch() {
+        assert!(wildmatch("v1.*", "v1.2.3", false, false));
+        assert!(!wildmatch("v1.*", "v2.0", false, false));
+        assert!(wildmatch("**/repo/**", "/home/me/repo/.git", true, false));
+        assert!(wildmatch("/home/*/repo/.git", "/home/me/repo/.git", true, false));
+        assert!(!wildmatch("/home/*/.git", "/home/me/repo/.git", true, false));
+        assert!(wildmatch("src/**/*.rs", "src/main.rs", true, false));
+        assert!(wildmatch("src/**/*.rs", "src/model/id.rs", true, false));
+        assert!(wildmatch("[a-c]?x", "bqx", true, false));
+        assert!(!wildmatch("[!a-c]?x", "bqx", true, false));
+        assert!(wildmatch("FOO*", "foobar", false, true));
+    }
+}
@@ -0,0 +1,164 @@
+use std::io::Read;
+use flate2::read::DeflateDecoder;
+use rust_git::fs::OsFs;
+use rust_git::model::archive::{ArchiveFormat, ArchiveOptions};
+use rust_git::model::id::Id;
+use rust_git::model::init::InitOptions;
+use rust_git::model::object::ObjectType;
+use rust_git::model::repository::FileRepository;
+use rust_git::model::tree::{FileMode, TreeEntry};
+
+/// A zip entry as its central directory header describes it.
+struct ZipEntry {
+    name: String,
+    text: bool,
+    content: Vec<u8>,
+}
+
+fn u16_at(buf: &[u8], at: usize) -> usize {
+    u16::from_le_bytes([buf[at], buf[at + 1]]) as usize
+}
+
+fn u32_at(buf: &[u8], at: usize) -> usize {
+    u32::from_le_bytes([buf[at], buf[at + 1], buf[at + 2], buf[at + 3]]) as usize
+}
+
+fn read_zip(zip: &[u8]) -> Vec<ZipEntry> {
+    let end = zip.windows(4).rposition(|w| w == b"PK\x05\x06").expect("no end of central directory");
+    let count = u16_at(zip, end + 10);
+    let mut at = u32_at(zip, end + 16);
+    let mut entries = vec![];
+    for _ in 0..count {
+        assert_eq!(&zip[at..at + 4], b"PK\x01\x02");
+        let method = u16_at(zip, at + 10);
+        let stored = u32_at(zip, at + 20);
+        let (name_len, extra_len, comment_len) = (u16_at(zip, at + 28), u16_at(zip, at + 30), u16_at(zip, at + 32));
+        let text = u16_at(zip, at + 36) & 1 == 1;
+        let local = u32_at(zip, at + 42);
+        let name = String::from_utf8(zip[at + 46..at + 46 + name_len].to_vec()).unwrap();
+        let data = local + 30 + u16_at(zip, local + 26) + u16_at(zip, local + 28);
+        let raw = &zip[data..data + stored];
+        let content = match method {
+            8 => {
+                let mut content = vec![];
+                DeflateDecoder::new(raw).read_to_end(&mut content).unwrap();
+                content
+            }
+            _ => raw.to_vec(),
+        };
+        entries.push(ZipEntry { name, text, content });
+        at += 46 + name_len + extra_len + comment_len;
+    }
+    entries
+}
+
+/// Writes the trees for `files`, given as path and content, and returns
+/// the top one.
+fn write_tree(repo: &FileRepository<OsFs>, files: &[(&str, &[u8])]) -> Id {
+    let mut entries = vec![];
+    let mut dirs: Vec<&str> = vec![];
+    for (path, content) in files {
+        match path.find('/') {
+            Some(slash) => if !dirs.contains(&&path[..slash]) {
+                dirs.push(&path[..slash]);
+            },
+            None => {
+                let id = repo.write_object(ObjectType::BLOB, content).unwrap();
+                entries.push(TreeEntry::new(FileMode::FILE, id, *path));
+            }
+        }
+    }
+    for dir in dirs {
+        let prefix = format!("{}/", dir);
+        let below: Vec<(&str, &[u8])> = files.iter()
+            .filter_map(|(path, content)| path.strip_prefix(&prefix).map(|rest| (rest, *content)))
+            .collect();
+        entries.push(TreeEntry::new(FileMode::DIR, write_tree(repo, &below), dir));
+    }
+    entries.sort_by_key(|e| if e.mode() == FileMode::DIR { format!("{}/", e.name()) } else { e.name().to_owned() });
+    repo.write_tree(&entries).unwrap()
+}
+
+fn commit(repo: &FileRepository<OsFs>, files: &[(&str, &[u8])]) -> (Id, Id) {
+    let tree = write_tree(repo, files);
+    let content = format!("tree {}\nauthor A U Thor <a@x> 1577930645 +0100\n\
+                           committer A U Thor <a@x> 1577930645 +0100\n\nfirst commit\n", tree);
+    (repo.write_object(ObjectType::COMMIT, content.as_bytes()).unwrap(), tree)
+}
+
+fn zip(repo: &FileRepository<OsFs>, treeish: &Id, paths: &[&str]) -> Vec<ZipEntry> {
+    let opts = ArchiveOptions {
+        format: ArchiveFormat::Zip,
+        prefix: "p/".to_owned(),
+        paths: paths.iter().map(|path| path.to_string()).collect(),
+    };
+    let mut out = vec![];
+    repo.archive(treeish, &opts, &mut out).unwrap();
+    read_zip(&out)
+}
+
+fn names(entries: &[ZipEntry]) -> Vec<&str> {
+    entries.iter().map(|entry| entry.name.as_str()).collect()
+}
+
+#[test]
+fn test_zip_archive() {
+    let dir = tempfile::tempdir().unwrap();
+    let (repo, _) = FileRepository::init(dir.path(), &InitOptions::default()).unwrap();
+    let text = "line\n".repeat(100);
+    let (id, _) = commit(&repo, &[
+        ("a.txt", text.as_bytes()),
+        ("bin", b"\0\x01\x02"),
+        ("marked.txt", b"text, but marked binary\n"),
+        ("sub/b.txt", b"b\n"),
+        (".gitattributes", b"marked.txt binary\n"),
+    ]);
+    let entries = zip(&repo, &id, &[]);
+    assert_eq!(names(&entries), ["p/", "p/.gitattributes", "p/a.txt", "p/bin", "p/marked.txt", "p/sub/", "p/sub/b.txt"]);
+    let text_bits: Vec<bool> = entries.iter().map(|entry| entry.text).collect();
+    assert_eq!(text_bits, [false, true, true, false, false, false, true]);
+    assert_eq!(entries[2].content, text.as_bytes());
+    assert_eq!(entries[3].content, b"\0\x01\x02");
+    assert_eq!(entries[6].content, b"b\n");
+}
+
+#[test]
+fn test_zip_archive_export_attributes() {
+    let dir = tempfile::tempdir().unwrap();
+    let (repo, _) = FileRepository::init(dir.path(), &InitOptions::default()).unwrap();
+    let (id, tree) = commit(&repo, &[
+        (".gitattributes", b"ignored.txt export-ignore\nhidden export-ignore\nversion export-subst\n"),
+        ("hidden/x", b"x\n"),
+        ("ignored.txt", b"gone\n"),
+        ("kept.txt", b"$Format:%h$\n"),
+        ("version", b"$Format:%an: %s$\n"),
+    ]);
+    let entries = zip(&repo, &id, &[]);
+    assert_eq!(names(&entries), ["p/", "p/.gitattributes", "p/kept.txt", "p/version"]);
+    assert_eq!(entries[2].content, b"$Format:%h$\n");
+    assert_eq!(entries[3].content, b"A U Thor: first commit\n");
+
+    // a bare tree has no commit to expand
+    let entries = zip(&repo, &tree, &[]);
+    assert_eq!(entries[3].content, b"$Format:%an: %s$\n");
+}
+
+#[test]
+fn test_zip_archive_pathspecs() {
+    let dir = tempfile::tempdir().unwrap();
+    let (repo, _) = FileRepository::init(dir.path(), &InitOptions::default()).unwrap();
+    let (id, _) = commit(&repo, &[
+        ("a.txt", b"a\n"),
+        ("sub/b.txt", b"b\n"),
+        ("sub/c.md", b"c\n"),
+        ("sub/deep/d.txt", b"d\n"),
+        ("other/e.txt", b"e\n"),
+    ]);
+    assert_eq!(names(&zip(&repo, &id, &["sub/deep"])), ["p/", "p/sub/", "p/sub/deep/", "p/sub/deep/d.txt"]);
+    // as in git, `*` in a pathspec matches across slashes
+    assert_eq!(names(&zip(&repo, &id, &["sub/*.txt"])), ["p/", "p/sub/", "p/sub/b.txt", "p/sub/deep/", "p/sub/deep/d.txt"]);
+    assert_eq!(names(&zip(&repo, &id, &["a.txt", "other"])), ["p/", "p/a.txt", "p/other/", "p/other/e.txt"]);
+
+    let opts = ArchiveOptions { format: ArchiveFormat::Zip, paths: vec!["missing".to_owned()], ..Default::default() };
+    assert!(repo.archive(&id, &opts, &mut vec![]).is_err());
+}
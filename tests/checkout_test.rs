@@ -0,0 +1,210 @@
+use std::fs;
+use std::path::Path;
+use rust_git::errors::ErrorKind;
+use rust_git::fs::OsFs;
+use rust_git::model::checkout::CheckoutOptions;
+use rust_git::model::id::Id;
+use rust_git::model::init::InitOptions;
+use rust_git::model::object::ObjectType;
+use rust_git::model::refs::{RefChange, RefUpdate};
+use rust_git::model::repository::FileRepository;
+use rust_git::model::tree::{FileMode, TreeEntry};
+
+fn commit(repo: &FileRepository<OsFs>, files: &[(&str, &str)]) -> (Id, Id) {
+    let entries: Vec<TreeEntry> = files.iter()
+        .map(|(name, content)| {
+            let id = repo.write_object(ObjectType::BLOB, content.as_bytes()).unwrap();
+            TreeEntry::new(FileMode::FILE, id, *name)
+        })
+        .collect();
+    let tree = repo.write_tree(&entries).unwrap();
+    (commit_tree(repo, &tree), tree)
+}
+
+fn commit_tree(repo: &FileRepository<OsFs>, tree: &Id) -> Id {
+    let content = format!("tree {}\nauthor a <a@b> 0 +0000\ncommitter a <a@b> 0 +0000\n\nc\n", tree);
+    repo.write_object(ObjectType::COMMIT, content.as_bytes()).unwrap()
+}
+
+/// Writes the trees for `files`, given as path, mode and content, and
+/// returns the top one.
+fn write_tree(repo: &FileRepository<OsFs>, files: &[(&str, FileMode, &str)]) -> Id {
+    let mut entries = vec![];
+    let mut dirs: Vec<&str> = vec![];
+    for (path, mode, content) in files {
+        match path.find('/') {
+            Some(slash) => if !dirs.contains(&&path[..slash]) {
+                dirs.push(&path[..slash]);
+            },
+            None => {
+                let id = match mode {
+                    FileMode::SUBMODULE => content.parse().unwrap(),
+                    _ => repo.write_object(ObjectType::BLOB, content.as_bytes()).unwrap(),
+                };
+                entries.push(TreeEntry::new(mode.clone(), id, *path));
+            }
+        }
+    }
+    for dir in dirs {
+        let prefix = format!("{}/", dir);
+        let below: Vec<(&str, FileMode, &str)> = files.iter()
+            .filter_map(|(path, mode, content)| path.strip_prefix(&prefix).map(|rest| (rest, mode.clone(), *content)))
+            .collect();
+        entries.push(TreeEntry::new(FileMode::DIR, write_tree(repo, &below), dir));
+    }
+    entries.sort_by_key(|e| if e.mode() == FileMode::DIR { format!("{}/", e.name()) } else { e.name().to_owned() });
+    repo.write_tree(&entries).unwrap()
+}
+
+fn set_head(repo: &FileRepository<OsFs>, id: &Id) {
+    let mut tx = repo.transaction();
+    tx.push(RefUpdate::new("HEAD", RefChange::Update(id.clone())));
+    tx.commit().unwrap();
+}
+
+#[test]
+fn test_checkout_tree_keeps_local_changes() {
+    let dir = tempfile::tempdir().unwrap();
+    let (repo, _) = FileRepository::init(dir.path(), &InitOptions::default()).unwrap();
+    let (one, one_tree) = commit(&repo, &[("a.txt", "a\n"), ("b.txt", "b\n")]);
+    let (two, two_tree) = commit(&repo, &[("a.txt", "changed\n"), ("b.txt", "b\n"), ("c.txt", "c\n")]);
+
+    let stats = repo.checkout_tree(&one_tree, &CheckoutOptions::default()).unwrap();
+    assert_eq!(stats.written, 2);
+    set_head(&repo, &one);
+    assert_eq!(repo.read_index().unwrap().entries().len(), 2);
+
+    // b.txt is the same in both trees, so its local change is carried over
+    fs::write(dir.path().join("b.txt"), "local\n").unwrap();
+    repo.checkout_tree(&two_tree, &CheckoutOptions::default()).unwrap();
+    assert_eq!(fs::read_to_string(dir.path().join("a.txt")).unwrap(), "changed\n");
+    assert_eq!(fs::read_to_string(dir.path().join("b.txt")).unwrap(), "local\n");
+    assert_eq!(fs::read_to_string(dir.path().join("c.txt")).unwrap(), "c\n");
+    set_head(&repo, &two);
+
+    // a.txt differs between the trees, so its local change blocks the checkout
+    fs::write(dir.path().join("a.txt"), "mine\n").unwrap();
+    let err = repo.checkout_tree(&one_tree, &CheckoutOptions::default()).unwrap_err();
+    match err.kind() {
+        ErrorKind::WouldOverwrite(paths) => assert_eq!(paths, &["a.txt"]),
+        kind => panic!("unexpected error {:?}", kind),
+    }
+    assert_eq!(fs::read_to_string(dir.path().join("a.txt")).unwrap(), "mine\n");
+}
+
+#[test]
+fn test_parallel_checkout() {
+    let dir = tempfile::tempdir().unwrap();
+    let (repo, _) = FileRepository::init(dir.path(), &InitOptions::default()).unwrap();
+    let names: Vec<(String, String)> = (0..150)
+        .map(|n| (format!("d{}/f{}.txt", n % 7, n), format!("file {}\n", n)))
+        .collect();
+    let files: Vec<(&str, FileMode, &str)> = names.iter()
+        .map(|(path, content)| (path.as_str(), FileMode::FILE, content.as_str()))
+        .collect();
+    let tree = write_tree(&repo, &files);
+
+    let options = CheckoutOptions { workers: 4, ..CheckoutOptions::default() };
+    let stats = repo.checkout_tree(&tree, &options).unwrap();
+    assert_eq!(stats.written, 150);
+    for (path, content) in &names {
+        assert_eq!(&fs::read_to_string(dir.path().join(path)).unwrap(), content);
+    }
+    let index = repo.read_index().unwrap();
+    assert_eq!(index.entries().len(), 150);
+    assert!(index.entries().iter().all(|e| e.size as usize == fs::metadata(dir.path().join(&e.path)).unwrap().len() as usize));
+    set_head(&repo, &commit_tree(&repo, &tree));
+    assert!(!repo.has_local_changes().unwrap());
+
+    // back to an empty tree, and the emptied directories go too
+    let stats = repo.checkout_tree(&repo.write_tree(&[]).unwrap(), &options).unwrap();
+    assert_eq!(stats.removed, 150);
+    assert!(!dir.path().join("d0").exists());
+    assert!(repo.read_index().unwrap().entries().is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_checkout_symlinks_executables_and_gitlinks() {
+    use std::os::unix::fs::PermissionsExt;
+    let dir = tempfile::tempdir().unwrap();
+    let (repo, _) = FileRepository::init(dir.path(), &InitOptions::default()).unwrap();
+    let submodule = "1234567890123456789012345678901234567890";
+    let tree = write_tree(&repo, &[
+        ("bin/run.sh", FileMode::EXE, "#!/bin/sh\n"),
+        ("link", FileMode::LINK, "bin/run.sh"),
+        ("lib", FileMode::SUBMODULE, submodule),
+        ("plain", FileMode::FILE, "text\n"),
+    ]);
+    repo.checkout_tree(&tree, &CheckoutOptions::default()).unwrap();
+    set_head(&repo, &commit_tree(&repo, &tree));
+
+    let mode = |path: &str| fs::symlink_metadata(dir.path().join(path)).unwrap().permissions().mode();
+    assert_ne!(mode("bin/run.sh") & 0o100, 0);
+    assert_eq!(mode("plain") & 0o111, 0);
+    assert_eq!(fs::read_link(dir.path().join("link")).unwrap(), Path::new("bin/run.sh"));
+    assert!(dir.path().join("lib").is_dir());
+    let index = repo.read_index().unwrap();
+    assert_eq!(index.entry("bin/run.sh").unwrap().mode, 0o100755);
+    assert_eq!(index.entry("link").unwrap().mode, 0o120000);
+    assert_eq!(index.entry("lib").unwrap().mode, 0o160000);
+    assert_eq!(index.entry("lib").unwrap().id.to_string(), submodule);
+    assert!(!repo.has_local_changes().unwrap());
+
+    // losing the execute bit is a local change to the file
+    fs::set_permissions(dir.path().join("bin/run.sh"), fs::Permissions::from_mode(0o644)).unwrap();
+    assert!(repo.has_local_changes().unwrap());
+}
+
+#[test]
+fn test_sparse_checkout() {
+    let dir = tempfile::tempdir().unwrap();
+    let (repo, _) = FileRepository::init(dir.path(), &InitOptions::default()).unwrap();
+    let git_dir = repo.git_dir().to_path_buf();
+    let config = fs::read_to_string(git_dir.join("config")).unwrap();
+    fs::write(git_dir.join("config"), config + "\tsparseCheckout = true\n").unwrap();
+    fs::create_dir_all(git_dir.join("info")).unwrap();
+    fs::write(git_dir.join("info/sparse-checkout"), "/*\n!/*/\n/docs/\n").unwrap();
+
+    let tree = write_tree(&repo, &[
+        ("README", FileMode::FILE, "top\n"),
+        ("docs/guide.md", FileMode::FILE, "guide\n"),
+        ("src/main.rs", FileMode::FILE, "fn main() {}\n"),
+    ]);
+    let stats = repo.checkout_tree(&tree, &CheckoutOptions::default()).unwrap();
+    assert_eq!(stats.written, 2);
+    assert!(dir.path().join("README").is_file());
+    assert!(dir.path().join("docs/guide.md").is_file());
+    assert!(!dir.path().join("src").exists());
+    let index = repo.read_index().unwrap();
+    assert!(index.entry("src/main.rs").unwrap().skip_worktree);
+    assert!(!index.entry("README").unwrap().skip_worktree);
+    set_head(&repo, &commit_tree(&repo, &tree));
+    assert!(!repo.has_local_changes().unwrap());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_checkout_does_not_write_through_symlinks() {
+    let dir = tempfile::tempdir().unwrap();
+    let outside = tempfile::tempdir().unwrap();
+    let (repo, _) = FileRepository::init(dir.path(), &InitOptions::default()).unwrap();
+    let (one, one_tree) = commit(&repo, &[("a.txt", "a\n")]);
+    repo.checkout_tree(&one_tree, &CheckoutOptions::default()).unwrap();
+    set_head(&repo, &one);
+    std::os::unix::fs::symlink(outside.path(), dir.path().join("d")).unwrap();
+
+    let tree = write_tree(&repo, &[("a.txt", FileMode::FILE, "a\n"), ("d/x", FileMode::FILE, "x\n")]);
+    let err = repo.checkout_tree(&tree, &CheckoutOptions::default()).unwrap_err();
+    match err.kind() {
+        ErrorKind::UntrackedWouldBeOverwritten(paths) => assert_eq!(paths, &["d"]),
+        kind => panic!("unexpected error {:?}", kind),
+    }
+    assert!(fs::symlink_metadata(dir.path().join("d")).unwrap().file_type().is_symlink());
+
+    // forced, the symlink makes way for the directory
+    repo.checkout_tree(&tree, &CheckoutOptions { force: true, ..CheckoutOptions::default() }).unwrap();
+    assert!(fs::symlink_metadata(dir.path().join("d")).unwrap().is_dir());
+    assert_eq!(fs::read_to_string(dir.path().join("d/x")).unwrap(), "x\n");
+    assert_eq!(fs::read_dir(outside.path()).unwrap().count(), 0);
+}
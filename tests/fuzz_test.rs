@@ -0,0 +1,109 @@
+//! Replays a fixed set of mutations of valid inputs through the fuzz entry
+//! points, so the read path stays panic free without a fuzzer installed.
+//! `cargo fuzz` under `fuzz/` explores the same targets much further.
+
+use rust_git::fuzz;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::io::Write;
+
+const ROUNDS: usize = 300;
+
+/// xorshift64, enough to pick mutations reproducibly.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n.max(1) as u64) as usize
+    }
+}
+
+fn mutate(rng: &mut Rng, seed: &[u8]) -> Vec<u8> {
+    let mut data = seed.to_vec();
+    for _ in 0..=rng.below(4) {
+        let at = rng.below(data.len());
+        match rng.below(5) {
+            0 if !data.is_empty() => data[at] ^= 1 << rng.below(8),
+            1 if !data.is_empty() => data[at] = [0x00, 0x7f, 0x80, 0xff][rng.below(4)],
+            2 => data.truncate(at),
+            3 => data.insert(at, rng.next() as u8),
+            _ if !data.is_empty() => data[at] = rng.next() as u8,
+            _ => {}
+        }
+    }
+    data
+}
+
+fn run(seeds: &[Vec<u8>], target: fn(&[u8])) {
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    for seed in seeds {
+        target(seed);
+        for _ in 0..ROUNDS {
+            target(&mutate(&mut rng, seed));
+        }
+    }
+}
+
+fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+const COMMIT: &[u8] = b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904
+parent 1a8f251087b9d51b18f1a821cd87dae0acee2936
+author A U Thor <a@example.com> 1500000000 +0800
+committer A U Thor <a@example.com> 1500000000 +0800
+
+message
+";
+
+const TAG: &[u8] = b"object a541069eb298c4969982721adea07e526d899351
+type commit
+tag v0.1
+tagger A U Thor <a@example.com> 1565707955 +0800
+
+a tag
+";
+
+#[test]
+fn fuzz_pack_entries() {
+    let pack = std::fs::read("tests/fixture/objects/pack/pack-1dba36995240d4e37eb9c1aae367accc94169fc4.pack").unwrap();
+    run(&[pack[..4096].to_vec()], fuzz::pack_entries);
+}
+
+#[test]
+fn fuzz_delta() {
+    // base "hello world", then copy "hello", insert " there" and copy " world"
+    let mut seed = vec![0, 11];
+    seed.extend_from_slice(b"hello world");
+    seed.extend_from_slice(&[11, 17, 0x90, 5, 6]);
+    seed.extend_from_slice(b" there");
+    seed.extend_from_slice(&[0x91, 5, 6]);
+    run(&[seed], fuzz::delta);
+}
+
+#[test]
+fn fuzz_object_parsers() {
+    let idx = std::fs::read("tests/fixture_sha256/objects/pack/pack-172d0426685853d10e65a0088280212011ce25785566fc6a9e53b5661acce041.idx").unwrap();
+    let mut tree = b"100644 README.md\0".to_vec();
+    tree.extend_from_slice(&[0x91; 20]);
+    tree.extend_from_slice(b"40000 src\0");
+    tree.extend_from_slice(&[0x17; 20]);
+    let reflog = b"1a8f251087b9d51b18f1a821cd87dae0acee2936 4b825dc642cb6eb9a060e54bf8d69288fbee4904 A <a@x> 1500000000 +0000\tcommit: x\n";
+    run(&[COMMIT.to_vec(), TAG.to_vec(), tree, reflog.to_vec(), idx], fuzz::object_parsers);
+}
+
+#[test]
+fn fuzz_loose_object() {
+    let mut commit = format!("commit {}\0", COMMIT.len()).into_bytes();
+    commit.extend_from_slice(COMMIT);
+    run(&[zlib(b"blob 11\0hello world"), zlib(&commit)], fuzz::loose_object);
+}
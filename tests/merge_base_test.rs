@@ -0,0 +1,36 @@
+use rust_git::model::repository::FileRepository;
+use rust_git::model::id::Id;
+
+// criss-cross history with a commit-graph: X2 and Y2 both merge B and C
+const A: &str = "7c83c34b51e2cf9a6b9ba2a6828d35ec83235031";
+const B: &str = "8b04b07bcbb719e7c01ed06e28b9a32d6e005bc5";
+const C: &str = "391853c40ea1bf267ac56d1888af8a03b10f46d1";
+
+fn open() -> FileRepository<rust_git::fs::OsFs> {
+    FileRepository::open("./tests/fixture_graph").expect("open repo failed")
+}
+
+fn ids(repo: &FileRepository<rust_git::fs::OsFs>, specs: &[&str]) -> Vec<Id> {
+    specs.iter().map(|spec| repo.rev_parse(spec).unwrap()).collect()
+}
+
+#[test]
+fn test_merge_bases_with_commit_graph() {
+    let repo = open();
+    let x = ids(&repo, &["x", "y", "z", "w", "master"]);
+    let expected: Vec<Id> = [C, B].iter().map(|id| id.parse().unwrap()).collect();
+    assert_eq!(repo.merge_bases(&x[0], &x[1..2]).unwrap(), expected);
+    assert_eq!(repo.merge_base(&x[0], &x[2]).unwrap(), Some(A.parse().unwrap()));
+    assert_eq!(repo.octopus_merge_bases(&x[..3]).unwrap(), [A.parse::<Id>().unwrap()]);
+}
+
+#[test]
+fn test_independent_and_is_ancestor() {
+    let repo = open();
+    let commits = ids(&repo, &["x", "y", "z", "w", "master", "z~1", "x"]);
+    assert_eq!(repo.independent(&commits).unwrap(), &commits[..5]);
+    assert!(repo.is_ancestor(&commits[5], &commits[2]).unwrap());
+    assert!(repo.is_ancestor(&commits[5], &commits[3]).unwrap());
+    assert!(!repo.is_ancestor(&commits[4], &commits[0]).unwrap());
+    assert!(repo.is_ancestor(&ids(&repo, &["x~1"])[0], &commits[1]).unwrap());
+}
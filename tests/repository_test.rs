@@ -1,5 +1,5 @@
 use rust_git::model::repository::{FileRepository, Repository};
-use rust_git::model::id::Id;
+use rust_git::model::id::{Id, ObjectFormat};
 use rust_git::model::object::{ObjectType};
 
 use rust_git::model::commit::Commit;
@@ -147,4 +147,48 @@ fn test_load_deltified_object_from_pack() {
     let blob = Blob::from(&repo, &obj).expect("parse blob failed");
     let content = str::from_utf8(blob.content()).expect("parse content failed");
     assert!(content.len() > 0);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_fsck_fixture() {
+    let repo = FileRepository::open("./tests/fixture").expect("open repo failed");
+    let problems: Vec<String> = repo.fsck().expect("fsck failed").iter().map(|p| p.to_string()).collect();
+    assert_eq!(problems, ["dangling commit 775d6e601c5e0a0bf40efde68392eb3cb18ede7d"]);
+}
+
+#[test]
+fn test_pack_stats() {
+    let path = Path::new(".").join("tests").join("fixture").join("objects").join("pack")
+        .join("pack-1dba36995240d4e37eb9c1aae367accc94169fc4.pack");
+    let pack = PackFile::open(&OsFs::new("./tests"), &path, ObjectFormat::Sha1).expect("open pack failed");
+    pack.verify().expect("verify pack failed");
+    let stats = pack.stats().expect("pack stats failed");
+    assert_eq!(stats.entries.len(), 147);
+    assert_eq!(stats.chain_lengths.values().sum::<usize>(), 147);
+    assert_eq!(stats.chain_lengths.keys().last(), Some(&11));
+    let entry = stats.entries.iter()
+        .find(|e| e.id.to_string() == "d41d271e3ac0f2ba5c0471b5899f86787495a556").unwrap();
+    assert_eq!((entry.object_type.clone(), entry.size, entry.size_in_pack, entry.offset, entry.depth),
+               (ObjectType::TREE, 55, 69, 15188, 3));
+    assert_eq!(entry.base, Id::from_str("2ce78aa5582e396f16fe14c5caef8825ce37524b").ok());
+}
+
+#[test]
+fn test_delta_base_outside_pack() {
+    // a thin pack: the tree and a blob are deltas against objects it lacks
+    let repo = FileRepository::open("./tests/fixture_thin").expect("open repo failed");
+    let tree = Id::from_str("6144c7299fb8c6344379d2e54462c32ccdb4c9c7").unwrap();
+    let err = repo.find_object(&tree).err().expect("delta typed without its base");
+    assert!(err.to_string().contains("delta base 0d69200a9cbe5c071d59382b656633b9534f1762 is not in the pack"), "{}", err);
+    assert_eq!(repo.find_object(&Id::from_str("3527c74a4a322ad8bacdefdc48736684284a9137").unwrap()).unwrap().object_type(),
+               ObjectType::COMMIT);
+
+    let path = Path::new(".").join("tests").join("fixture_thin").join("objects").join("pack")
+        .join("pack-9f3a20a07447470856898dc116364a9b9ec00ef6.pack");
+    let pack = PackFile::open(&OsFs::new("./tests"), &path, ObjectFormat::Sha1).expect("open pack failed");
+    assert!(pack.stats().is_err());
+    let corrupt = repo.fsck().expect("fsck failed").iter()
+        .filter(|p| p.to_string().starts_with("error: packed "))
+        .count();
+    assert_eq!(corrupt, 2);
+}
@@ -0,0 +1,122 @@
+use rust_git::model::repository::{FileRepository, Repository};
+use rust_git::model::id::{Id, ObjectFormat};
+use rust_git::model::object::{Object, ObjectType};
+use rust_git::model::commit::Commit;
+use rust_git::model::blob::Blob;
+use rust_git::model::tree::*;
+use rust_git::model::tag::Tag;
+use std::str::FromStr;
+use std::io::{Read, Seek, SeekFrom};
+
+const HEAD: &str = "f05ee31c7c233f5d847ffdc91f7d6777abc004a33b3c0cd4c7d25de5f1e80c17";
+const PACKED_COMMIT: &str = "b699e04280776e76f3d5d597e3502356c033ca85780296c2f7721d1b8884d39d";
+
+fn open() -> FileRepository<rust_git::fs::OsFs> {
+    FileRepository::open("./tests/fixture_sha256").expect("open repo failed")
+}
+
+#[test]
+fn test_open_sha256_repo() {
+    let repo = open();
+    assert_eq!(repo.object_format(), ObjectFormat::Sha256);
+    assert_eq!(repo.rev_parse("HEAD").unwrap(), Id::from_str(HEAD).unwrap());
+    assert_eq!(repo.rev_parse("HEAD~1").unwrap(), Id::from_str(PACKED_COMMIT).unwrap());
+    // 40 hex digits are only an abbreviation of a SHA-256 id
+    assert_eq!(repo.rev_parse(&HEAD[..40]).unwrap(), Id::from_str(HEAD).unwrap());
+    assert!(!Id::parse(&HEAD[..40], ObjectFormat::Sha256).unwrap().is_full());
+    assert!(Id::parse(&HEAD[..40], ObjectFormat::Sha1).unwrap().is_full());
+    assert!(Id::parse(HEAD, ObjectFormat::Sha1).is_err());
+}
+
+#[test]
+fn test_read_loose_sha256_commit() {
+    let repo = open();
+    let obj = repo.lookup(HEAD).expect("loose commit not found");
+    assert_eq!(obj.object_type(), ObjectType::COMMIT);
+    let commit = Commit::from(&repo, &obj).expect("parse commit failed");
+    assert_eq!(commit.tree(), &Id::from_str("bea2f4f7a5b25bada957d645d3c7416d560cc14e4ad2b57cfe67c362ae97f79e").unwrap());
+    assert_eq!(commit.parent(), &[Id::from_str(PACKED_COMMIT).unwrap()]);
+    assert_eq!(commit.message(), "loose commit");
+}
+
+#[test]
+fn test_read_packed_sha256_objects() {
+    let repo = open();
+    let obj = repo.lookup("1104186e").expect("packed tree not found");
+    let tree = Tree::from(&repo, &obj).expect("parse tree failed");
+    let names: Vec<&str> = tree.entries().iter().map(|e| e.name()).collect();
+    assert_eq!(names, ["dir", "numbers.txt"]);
+    assert_eq!(tree.entries()[0].mode(), FileMode::DIR);
+    assert_eq!(tree.entries()[1].id().to_string(),
+               "3a41bce81245c1ce1875ed352b9bb823dae5c5c1977ecab57274d924cd2348a6");
+
+    let obj = repo.lookup("31a81bee").expect("packed tag not found");
+    let tag = Tag::from(&repo, &obj).expect("parse tag failed");
+    assert_eq!(tag.object(), &Id::from_str(PACKED_COMMIT).unwrap());
+    assert_eq!(tag.tag(), "v1");
+}
+
+#[test]
+fn test_read_deltified_sha256_blob() {
+    let repo = open();
+    let obj = repo.lookup("437dd9d653be2b8864fc1fec221e1b6ecd6cc5f2941afd8a21641aa6366d01bc")
+        .expect("deltified blob not found");
+    let blob = Blob::from(&repo, &obj).expect("parse blob failed");
+    let expected: String = (1..=200).map(|n| format!("{}\n", n)).collect();
+    assert_eq!(blob.content(), expected.as_bytes());
+}
+
+#[test]
+fn test_read_object_and_peel() {
+    let repo = open();
+    let tag = Id::from_str("31a81bee06937da555d0d946f96e903f18e0e83e9088b7e8a975a0aa1b2268a2").unwrap();
+    let tree = Id::from_str("1104186e5bff9f586172a25c8edaee3f239de46d59d4e5cd703bc319b5a3dba4").unwrap();
+    match repo.read_object(&tag).unwrap() {
+        Object::Tag(t) => assert_eq!(t.tag(), "v1"),
+        other => panic!("expected a tag, got {:?}", other.kind()),
+    }
+    assert_eq!(repo.peel_to_commit(&tag).unwrap().id(), &Id::from_str(PACKED_COMMIT).unwrap());
+    assert_eq!(repo.peel_to_tree(&tag).unwrap().id(), &tree);
+    assert_eq!(repo.peel_to_kind(&tag, ObjectType::TAG).unwrap().id(), &tag);
+    assert!(repo.peel_to_commit(&tree).is_err());
+    assert!(repo.peel_to_kind(&tag, ObjectType::BLOB).is_err());
+
+    assert_eq!(repo.find_tree(&tree).unwrap().entries().len(), 2);
+    assert!(repo.find_tree(&tag).is_err());
+    assert!(repo.find_commit(&tag).is_err());
+    assert!(repo.find_commit(&Id::from_str(&"0".repeat(64)).unwrap()).is_err());
+}
+
+#[test]
+fn test_open_blob() {
+    let repo = open();
+    let packed = Id::from_str("3a41bce81245c1ce1875ed352b9bb823dae5c5c1977ecab57274d924cd2348a6").unwrap();
+    let deltified = Id::from_str("437dd9d653be2b8864fc1fec221e1b6ecd6cc5f2941afd8a21641aa6366d01bc").unwrap();
+    for id in &[packed, deltified] {
+        let content = Blob::from(&repo, &repo.get_object(id).unwrap()).unwrap().content().to_vec();
+        let mut blob = repo.open_blob(id).expect("open blob failed");
+        assert_eq!(blob.size(), content.len() as u64);
+        let mut all = vec![];
+        blob.read_to_end(&mut all).unwrap();
+        assert_eq!(all, content);
+        blob.seek(SeekFrom::Start(3)).unwrap();
+        let mut buf = [0u8; 6];
+        blob.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, &content[3..9]);
+    }
+
+    let loose = Id::from_str("5883ae349f6168a0446be46bbf0910f01caadbdddd3bf73ab4b85f14c2120ad7").unwrap();
+    let mut blob = repo.open_blob(&loose).unwrap();
+    blob.seek(SeekFrom::End(-3)).unwrap();
+    let mut tail = String::new();
+    blob.read_to_string(&mut tail).unwrap();
+    assert_eq!(tail, "se\n");
+
+    assert!(repo.open_blob(&Id::from_str(HEAD).unwrap()).is_err());
+}
+
+#[test]
+fn test_fsck_sha256_repo() {
+    let repo = open();
+    assert_eq!(repo.fsck().expect("fsck failed"), vec![]);
+}